@@ -0,0 +1,147 @@
+//! ADC convenience wrappers
+//!
+//! Frames the start/channel bits for common SPI ADCs and extracts the sample from the
+//! response, so users don't have to work out where the conversion result lands in the
+//! read phase.
+//!
+//! # Framing
+//!
+//! Both devices ignore MOSI until they see a start bit, so the command is placed in the
+//! **last** bits of the write phase (leading zeros are padding). The conversion result is
+//! then clocked out during the read phase:
+//!
+//! - **MCP3004/MCP3008**: 5 command bits + 12 response clocks (17-bit conversation)
+//!   - Response: 1 sample clock, 1 null bit, 10 data bits (MSB first)
+//! - **ADS7846**: 8 command bits + 16 response clocks (24-bit conversation)
+//!   - Response: 1 busy clock, 12 data bits (MSB first), 3 trailing zeros
+//!
+//! Any read clocks beyond the response are shifted off the returned value.
+//!
+//! # Notes
+//! - Frames assume MSB-first bit order on the wire
+//! - The master's `message_size` must cover the response (12 bits for MCP3008, 16 bits for ADS7846)
+//! - The master must use [`Duplex::Half`] frames: on full-duplex frames the response
+//!   overlaps the command and the sample would come back shifted
+//! - Chip select is not driven by these helpers; assert it around each call if not tied low
+
+use embassy_rp::pio::Instance;
+
+use crate::{Duplex, PioSpiMaster};
+
+/// Input configuration for an ADC conversion
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum InputMode {
+    /// Channel measured against ground (or the reference on ADS7846)
+    SingleEnded,
+    /// Channel measured against its paired channel
+    Differential,
+}
+
+/// MCP3004/MCP3008 10-bit ADC
+pub struct Mcp3008<'d, PIO: Instance, const SM: usize> {
+    spi: PioSpiMaster<'d, PIO, SM>,
+}
+
+impl<'d, PIO: Instance, const SM: usize> Mcp3008<'d, PIO, SM> {
+    /// Response clocks after the command (sample + null + 10 data bits)
+    const RESPONSE_BITS: usize = 12;
+
+    /// Wraps an SPI master for MCP3008 conversions
+    ///
+    /// # Panics
+    /// If the master's `message_size` is shorter than the 12-bit response or its frames
+    /// are not [`Duplex::Half`]
+    pub fn new(spi: PioSpiMaster<'d, PIO, SM>) -> Self {
+        assert!(
+            spi.message_size() >= Self::RESPONSE_BITS,
+            "message_size too small for MCP3008 response"
+        );
+        assert_eq!(
+            spi.duplex(),
+            Duplex::Half,
+            "MCP3008 frames must be write-then-read"
+        );
+        Self { spi }
+    }
+
+    /// Performs a conversion on `channel` (0-7) and returns the 10-bit sample
+    ///
+    /// # Behavior
+    /// 1. Write phase: start bit, SGL/DIFF bit, D2..D0 channel bits
+    /// 2. Read phase: skips the sample and null clocks, captures B9..B0
+    pub fn read(&mut self, channel: u8, mode: InputMode) -> u16 {
+        let sgl = matches!(mode, InputMode::SingleEnded) as u64;
+        let command = (1 << 4) | (sgl << 3) | (channel as u64 & 0x7);
+
+        let response = self.spi.transfer(command);
+        let trailing = self.spi.message_size() - Self::RESPONSE_BITS;
+        ((response >> trailing) & 0x3FF) as u16
+    }
+
+    /// Releases the underlying SPI master
+    pub fn release(self) -> PioSpiMaster<'d, PIO, SM> {
+        self.spi
+    }
+}
+
+/// ADS7846 power-down mode (PD1/PD0 bits)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum PowerDown {
+    /// Power down between conversions, PENIRQ enabled
+    BetweenConversions = 0b00,
+    /// Reference off, ADC on
+    ReferenceOff = 0b01,
+    /// Reference on, ADC off
+    AdcOff = 0b10,
+    /// Always powered
+    AlwaysOn = 0b11,
+}
+
+/// ADS7846 12-bit touch screen ADC
+pub struct Ads7846<'d, PIO: Instance, const SM: usize> {
+    spi: PioSpiMaster<'d, PIO, SM>,
+    power_down: PowerDown,
+}
+
+impl<'d, PIO: Instance, const SM: usize> Ads7846<'d, PIO, SM> {
+    /// Response clocks after the command (busy + 12 data bits + 3 zeros)
+    const RESPONSE_BITS: usize = 16;
+
+    /// Wraps an SPI master for ADS7846 conversions
+    ///
+    /// # Panics
+    /// If the master's `message_size` is shorter than the 16-bit response or its frames
+    /// are not [`Duplex::Half`]
+    pub fn new(spi: PioSpiMaster<'d, PIO, SM>, power_down: PowerDown) -> Self {
+        assert!(
+            spi.message_size() >= Self::RESPONSE_BITS,
+            "message_size too small for ADS7846 response"
+        );
+        assert_eq!(
+            spi.duplex(),
+            Duplex::Half,
+            "ADS7846 frames must be write-then-read"
+        );
+        Self { spi, power_down }
+    }
+
+    /// Performs a 12-bit conversion on `channel` (A2..A0, 0-7) and returns the sample
+    ///
+    /// # Behavior
+    /// 1. Write phase: control byte `S A2 A1 A0 MODE SER/DFR PD1 PD0` (MODE = 12-bit)
+    /// 2. Read phase: skips the busy clock, captures the 12 data bits
+    pub fn read(&mut self, channel: u8, mode: InputMode) -> u16 {
+        let ser = matches!(mode, InputMode::SingleEnded) as u64;
+        let command =
+            (1 << 7) | ((channel as u64 & 0x7) << 4) | (ser << 2) | self.power_down as u64;
+
+        let response = self.spi.transfer(command);
+        let trailing = self.spi.message_size() - Self::RESPONSE_BITS + 3;
+        ((response >> trailing) & 0xFFF) as u16
+    }
+
+    /// Releases the underlying SPI master
+    pub fn release(self) -> PioSpiMaster<'d, PIO, SM> {
+        self.spi
+    }
+}
@@ -0,0 +1,371 @@
+//! Logic-analyzer capture of the bus
+//!
+//! Failures that only show up in the field (a slave that misses one frame in ten thousand,
+//! a CS glitch under load) are hard to catch with a bench analyzer. [`LogicAnalyzer`] turns
+//! a spare state machine of the same PIO block into one: it samples CLK, MOSI, MISO and a
+//! PIO-managed CS at up to the system clock rate, a DMA channel moves the samples into a
+//! buffer while the transaction runs, and [`Capture::dump`] prints every level change
+//! through defmt:
+//!
+//! ```ignore
+//! let mut analyzer = LogicAnalyzer::new(&mut common, sm1, &spi, 1);
+//! let mut buffer = [0u32; 1024];
+//!
+//! let (status, capture) = analyzer.capture(p.DMA_CH3.reborrow(), &mut buffer, || {
+//!     spi.transfer(READ_STATUS)
+//! });
+//! if status & ERROR != 0 {
+//!     capture.dump();
+//! }
+//! ```
+//!
+//! # Sampling
+//!
+//! The state machine runs `in pins, n` every cycle, where `n` is the smallest power of two
+//! covering the GPIOs from the lowest to the highest bus pin, so the pins must lie within
+//! 32 GPIOs of each other. Samples are packed into the buffer words; a 1024-word buffer
+//! holds 8192 samples of bus pins within 4 GPIOs, about 55 µs at 150 MHz. Recording
+//! stops when the buffer is full or the transaction returns, whichever is first.
+//!
+//! # Notes
+//! - The capture is read at the pads, after the CLK inversion, so it shows the levels on
+//!   the wires
+//! - The analyzer only reads the pins; it takes nothing from the master, which keeps
+//!   running unchanged
+//! - A CS driven by the application is not known to the analyzer and is not recorded
+
+use core::future::Future;
+use core::sync::atomic::{compiler_fence, Ordering};
+
+use embassy_rp::dma::Channel;
+use embassy_rp::pac;
+use embassy_rp::pac::dma::vals::{DataSize, TreqSel};
+use embassy_rp::pio::{
+    Common, Config, FifoJoin, Instance, LoadedProgram, ShiftDirection, StateMachine,
+};
+use embassy_rp::Peri;
+use fixed::traits::ToFixed;
+
+use crate::claim::pio_index;
+use crate::irq::pio_regs;
+use crate::program::get_capture_program;
+use crate::PioSpiMaster;
+
+/// Bus levels at one sample
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Sample {
+    /// CLK level
+    pub clk: bool,
+    /// MOSI level
+    pub mosi: bool,
+    /// MISO level
+    pub miso: bool,
+    /// CS level, if the master manages CS
+    pub cs: Option<bool>,
+}
+
+/// Bit of each bus line within a sample
+#[derive(Clone, Copy)]
+struct Channels {
+    clk: u8,
+    mosi: u8,
+    miso: u8,
+    cs: Option<u8>,
+}
+
+impl Channels {
+    /// Extracts the bus levels from a sample
+    fn decode(self, sample: u32) -> Sample {
+        let level = |bit: u8| sample & 1 << bit != 0;
+        Sample {
+            clk: level(self.clk),
+            mosi: level(self.mosi),
+            miso: level(self.miso),
+            cs: self.cs.map(level),
+        }
+    }
+}
+
+/// Samples recorded by [`LogicAnalyzer::capture`]
+pub struct Capture<'b> {
+    words: &'b [u32],
+    channels: Channels,
+    sample_bits: u8,
+    /// Time between two samples in picoseconds
+    period_ps: u64,
+}
+
+impl Capture<'_> {
+    /// Returns the number of samples recorded
+    pub fn len(&self) -> usize {
+        self.words.len() * (32 / self.sample_bits as usize)
+    }
+
+    /// Returns `true` if nothing was recorded
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    /// Returns the time between two samples in nanoseconds, rounded down
+    pub fn sample_period_ns(&self) -> u32 {
+        (self.period_ps / 1000) as u32
+    }
+
+    /// Returns the samples in the order they were taken
+    pub fn samples(&self) -> impl Iterator<Item = Sample> + '_ {
+        let bits = self.sample_bits as u32;
+        let mask = u32::MAX >> (32 - bits);
+        self.words.iter().flat_map(move |&word| {
+            (0..32 / bits).map(move |i| self.channels.decode(word >> (i * bits) & mask))
+        })
+    }
+
+    /// Prints the capture through defmt: the first sample, then every sample whose levels
+    /// differ from the one before, each with its time since the start of the capture
+    ///
+    /// # Notes
+    /// - Output is proportional to the number of edges, not samples: a 32-bit frame
+    ///   prints roughly 100 lines
+    pub fn dump(&self) {
+        defmt::info!(
+            "bus capture: {=usize} samples, {=u32} ns apart",
+            self.len(),
+            self.sample_period_ns()
+        );
+        let mut previous = None;
+        for (i, sample) in self.samples().enumerate() {
+            if previous != Some(sample) {
+                let ns = i as u64 * self.period_ps / 1000;
+                defmt::info!("{=u64} ns: {}", ns, sample);
+                previous = Some(sample);
+            }
+        }
+    }
+}
+
+/// Spare state machine recording the bus of a [`PioSpiMaster`]
+pub struct LogicAnalyzer<'d, PIO: Instance, const SM: usize> {
+    sm: StateMachine<'d, PIO, SM>,
+    program: LoadedProgram<'d, PIO>,
+    channels: Channels,
+    sample_bits: u8,
+    sample_div: u16,
+}
+
+impl<'d, PIO: Instance, const SM: usize> LogicAnalyzer<'d, PIO, SM> {
+    /// Loads the sampling program for the pins of `spi` on a spare state machine
+    ///
+    /// # Arguments
+    /// * `common` - The PIO peripheral's common interface (for program loading)
+    /// * `sm` - Spare state machine of the master's PIO block (takes ownership)
+    /// * `spi` - Master whose CLK, MOSI, MISO and PIO-managed CS are recorded
+    /// * `sample_div` - System clock cycles per sample (1 = every cycle)
+    ///
+    /// # Panics
+    /// - If `sample_div` is 0
+    /// - If the bus pins are not within 32 GPIOs of each other, or outside the GPIOs the
+    ///   PIO block sees
+    /// - If the program (1 instruction) does not fit the free instruction memory
+    pub fn new<const BUS_SM: usize>(
+        common: &mut Common<'d, PIO>,
+        sm: StateMachine<'d, PIO, SM>,
+        spi: &PioSpiMaster<'d, PIO, BUS_SM>,
+        sample_div: u16,
+    ) -> Self {
+        assert!(sample_div >= 1, "sample_div must be at least 1");
+        let pins = [
+            Some(spi.clk_pin),
+            Some(spi.mosi_pin),
+            Some(spi.miso_pin),
+            spi.cs_pin,
+        ];
+        let lowest = pins.into_iter().flatten().min().unwrap_or(0);
+        let highest = pins.into_iter().flatten().max().unwrap_or(0);
+        let span = highest - lowest + 1;
+        assert!(span <= 32, "bus pins must lie within 32 GPIOs");
+        let gpio_base = if pio_regs::<PIO>().gpiobase().read().gpiobase() {
+            16
+        } else {
+            0
+        };
+        assert!(
+            lowest >= gpio_base && highest < gpio_base + 32,
+            "bus pins outside the PIO block's GPIO window"
+        );
+        let sample_bits = span.next_power_of_two();
+        let program = common.load_program(&get_capture_program(sample_bits));
+
+        let mut cfg = Config::default();
+        cfg.use_program(&program, &[]);
+        cfg.clock_divider = (sample_div as u32).to_fixed();
+        cfg.fifo_join = FifoJoin::RxOnly;
+        cfg.shift_in.auto_fill = true;
+        cfg.shift_in.threshold = 32;
+        // The first sample of a word ends up in its lowest bits
+        cfg.shift_in.direction = ShiftDirection::Right;
+        let mut sm = sm;
+        sm.set_config(&cfg);
+        pio_regs::<PIO>()
+            .sm(SM)
+            .pinctrl()
+            .modify(|w| w.set_in_base(lowest - gpio_base));
+
+        Self {
+            sm,
+            program,
+            channels: Channels {
+                clk: spi.clk_pin - lowest,
+                mosi: spi.mosi_pin - lowest,
+                miso: spi.miso_pin - lowest,
+                cs: spi.cs_pin.map(|pin| pin - lowest),
+            },
+            sample_bits,
+            sample_div,
+        }
+    }
+
+    /// Records the bus while `transaction` runs
+    ///
+    /// # Arguments
+    /// * `dma` - DMA channel moving the samples; busy until this returns
+    /// * `buffer` - Receives the samples, packed `32 / n` to a word (see
+    ///   [Sampling](crate::analyzer#sampling))
+    /// * `transaction` - Runs the transfers to record
+    ///
+    /// # Returns
+    /// * `(R, Capture)` - What `transaction` returned, and the samples recorded
+    ///
+    /// # Behavior
+    /// 1. Starts `dma` moving samples into `buffer`, then the state machine sampling
+    /// 2. Runs `transaction`
+    /// 3. Stops the state machine, lets the DMA drain the samples already taken and stops
+    ///    it; a buffer that filled first holds the start of the transaction
+    pub fn capture<'b, R>(
+        &mut self,
+        dma: Peri<'_, impl Channel>,
+        buffer: &'b mut [u32],
+        transaction: impl FnOnce() -> R,
+    ) -> (R, Capture<'b>) {
+        let recording = self.start(dma.number(), buffer);
+        let result = transaction();
+        (result, recording.finish())
+    }
+
+    /// Records the bus while the `transaction` future runs
+    ///
+    /// Same behavior as [`capture`](Self::capture), for async transfers.
+    ///
+    /// # Cancel Safety
+    /// Dropping the future stops the state machine and the DMA; the samples are lost.
+    pub async fn capture_async<'b, F: Future>(
+        &mut self,
+        dma: Peri<'_, impl Channel>,
+        buffer: &'b mut [u32],
+        transaction: F,
+    ) -> (F::Output, Capture<'b>) {
+        let recording = self.start(dma.number(), buffer);
+        let result = transaction.await;
+        (result, recording.finish())
+    }
+
+    /// Stops the state machine and frees the program's instruction memory
+    ///
+    /// # Arguments
+    /// * `common` - The PIO peripheral's common interface the program was loaded with
+    ///
+    /// # Returns
+    /// * `StateMachine` - The stopped state machine, ready to be reused by another driver
+    pub fn free(mut self, common: &mut Common<'d, PIO>) -> StateMachine<'d, PIO, SM> {
+        self.sm.set_enable(false);
+        self.sm.clear_fifos();
+        // SAFETY: the program is private to this analyzer, whose state machine is stopped
+        unsafe { common.free_instr(self.program.used_memory) };
+        self.sm
+    }
+
+    /// Starts DMA channel `channel` filling `buffer` from the RX FIFO, then sampling
+    fn start<'a, 'b>(
+        &'a mut self,
+        channel: u8,
+        buffer: &'b mut [u32],
+    ) -> Recording<'a, 'b, 'd, PIO, SM> {
+        self.sm.set_enable(false);
+        self.sm.clear_fifos();
+        self.sm.restart();
+
+        let ch = pac::DMA.ch(channel as usize);
+        ch.read_addr()
+            .write_value(pio_regs::<PIO>().rxf(SM).as_ptr() as u32);
+        ch.write_addr().write_value(buffer.as_mut_ptr() as u32);
+        ch.trans_count().write(|w| w.set_count(buffer.len() as u32));
+        compiler_fence(Ordering::SeqCst);
+        ch.ctrl_trig().write(|w| {
+            // RX DREQs follow the four TX DREQs of the block
+            w.set_treq_sel(TreqSel::from((pio_index::<PIO>() * 8 + 4 + SM) as u8));
+            w.set_data_size(DataSize::SIZE_WORD);
+            w.set_incr_read(false);
+            w.set_incr_write(true);
+            w.set_chain_to(channel);
+            w.set_irq_quiet(true);
+            w.set_en(true);
+        });
+        self.sm.set_enable(true);
+
+        Recording {
+            analyzer: self,
+            channel,
+            buffer: Some(buffer),
+        }
+    }
+}
+
+/// A capture in progress; dropping it (also on cancellation) stops the sampling and DMA
+struct Recording<'a, 'b, 'd, PIO: Instance, const SM: usize> {
+    analyzer: &'a mut LogicAnalyzer<'d, PIO, SM>,
+    channel: u8,
+    /// Taken by [`finish`](Self::finish)
+    buffer: Option<&'b mut [u32]>,
+}
+
+impl<'b, PIO: Instance, const SM: usize> Recording<'_, 'b, '_, PIO, SM> {
+    /// Stops sampling, waits for the samples taken to reach the buffer and returns them
+    fn finish(mut self) -> Capture<'b> {
+        self.analyzer.sm.set_enable(false);
+        let ch = pac::DMA.ch(self.channel as usize);
+        // The DMA keeps up with the RX FIFO unless the buffer is full
+        while !self.analyzer.sm.rx().empty() && ch.ctrl_trig().read().busy() {}
+        self.stop();
+        compiler_fence(Ordering::SeqCst);
+
+        let buffer: &'b [u32] = self.buffer.take().unwrap_or_default();
+        let written = buffer.len() - ch.trans_count().read().count() as usize;
+        let analyzer = &self.analyzer;
+        Capture {
+            words: &buffer[..written],
+            channels: analyzer.channels,
+            sample_bits: analyzer.sample_bits,
+            period_ps: analyzer.sample_div as u64 * 1_000_000_000_000
+                / embassy_rp::clocks::clk_sys_freq() as u64,
+        }
+    }
+
+    /// Stops the state machine and the DMA channel
+    fn stop(&mut self) {
+        self.analyzer.sm.set_enable(false);
+        let ch = pac::DMA.ch(self.channel as usize);
+        // Clear EN first so the abort cannot be followed by a re-trigger
+        ch.al1_ctrl().modify(|w| *w &= !1);
+        pac::DMA
+            .chan_abort()
+            .write(|w| w.set_chan_abort(1 << self.channel));
+        while pac::DMA.chan_abort().read().chan_abort() & (1 << self.channel) != 0 {}
+    }
+}
+
+impl<PIO: Instance, const SM: usize> Drop for Recording<'_, '_, '_, PIO, SM> {
+    fn drop(&mut self) {
+        if self.buffer.is_some() {
+            self.stop();
+        }
+    }
+}
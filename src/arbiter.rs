@@ -0,0 +1,156 @@
+//! Bus sharing between two masters
+//!
+//! On boards where the RP2350 and another controller share one SPI bus, [`BusArbiter`]
+//! negotiates ownership over a pair of GPIOs and keeps the RP2350's bus pins
+//! high-impedance whenever it does not own the bus.
+//!
+//! # Handshake
+//!
+//! - **Request** (output, active HIGH): the RP2350 wants the bus
+//! - **Grant** (input, active HIGH): the other side (or an external arbiter) has released
+//!   the bus to the RP2350
+//!
+//! 1. [`acquire_bus`](BusArbiter::acquire_bus) drives request HIGH and waits for grant
+//! 2. Once granted, CLK, MOSI and a PIO-managed CS are driven again
+//! 3. [`release_bus`](BusArbiter::release_bus) waits for queued frames, tri-states the
+//!    pins and drives request LOW; the other side may then deassert grant and take over
+//!
+//! ```ignore
+//! let mut arbiter = BusArbiter::new(spi, request, grant, ArbiterConfig::default());
+//! arbiter.acquire_bus().await?;
+//! let response = arbiter.spi().unwrap().transfer(0x1234);
+//! arbiter.release_bus();
+//! ```
+//!
+//! # Notes
+//! - Create the master with `new_with_cs` so CS is released with the other pins; an
+//!   application-managed CS has to be tri-stated by the application
+//! - Lines need pull resistors (CLK and CS HIGH) to hold their idle levels while no master
+//!   drives them
+
+use embassy_rp::gpio::{Input, Output};
+use embassy_rp::pio::Instance;
+use embassy_time::{with_timeout, Duration};
+
+use crate::PioSpiMaster;
+
+/// Arbitration settings
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct ArbiterConfig {
+    /// Longest wait for grant after raising request
+    pub grant_timeout: Duration,
+}
+
+impl Default for ArbiterConfig {
+    fn default() -> Self {
+        Self {
+            grant_timeout: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Arbitration failure
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum ArbiterError {
+    /// Grant was not asserted within [`ArbiterConfig::grant_timeout`]; request is dropped
+    /// again and the pins stay released
+    Timeout,
+}
+
+/// SPI master that only drives the bus while granted
+pub struct BusArbiter<'d, PIO: Instance, const SM: usize> {
+    spi: PioSpiMaster<'d, PIO, SM>,
+    request: Output<'d>,
+    grant: Input<'d>,
+    config: ArbiterConfig,
+    granted: bool,
+}
+
+impl<'d, PIO: Instance, const SM: usize> BusArbiter<'d, PIO, SM> {
+    /// Wraps `spi` and releases its pins until the bus is acquired
+    ///
+    /// # Arguments
+    /// * `spi` - SPI master on the shared bus (takes ownership)
+    /// * `request` - Bus request output; driven LOW here
+    /// * `grant` - Bus grant input, HIGH when the bus is ours
+    /// * `config` - Grant timeout
+    pub fn new(
+        mut spi: PioSpiMaster<'d, PIO, SM>,
+        mut request: Output<'d>,
+        grant: Input<'d>,
+        config: ArbiterConfig,
+    ) -> Self {
+        spi.release_pins();
+        request.set_low();
+        Self {
+            spi,
+            request,
+            grant,
+            config,
+            granted: false,
+        }
+    }
+
+    /// Requests the bus and waits until it is granted
+    ///
+    /// # Returns
+    /// * `Ok(())` - The bus is ours and the pins are driven
+    /// * `Err(ArbiterError::Timeout)` - No grant in time; request is deasserted again
+    ///
+    /// # Notes
+    /// - Returns at once if the bus is already held
+    pub async fn acquire_bus(&mut self) -> Result<(), ArbiterError> {
+        if self.granted {
+            return Ok(());
+        }
+        self.request.set_high();
+        if with_timeout(self.config.grant_timeout, self.grant.wait_for_high())
+            .await
+            .is_err()
+        {
+            self.request.set_low();
+            return Err(ArbiterError::Timeout);
+        }
+        self.spi.reclaim_pins();
+        self.granted = true;
+        Ok(())
+    }
+
+    /// Finishes queued frames, tri-states the pins and drops the request
+    ///
+    /// # Notes
+    /// - Read the responses of queued frames first: a full RX FIFO stalls the state
+    ///   machine mid-frame, and this waits forever
+    pub fn release_bus(&mut self) {
+        if !self.granted {
+            return;
+        }
+        self.spi.release_pins();
+        self.request.set_low();
+        self.granted = false;
+    }
+
+    /// Returns whether the bus is currently held
+    pub fn is_granted(&self) -> bool {
+        self.granted
+    }
+
+    /// Returns the master while the bus is held
+    ///
+    /// # Returns
+    /// * `Some(&mut PioSpiMaster)` - The bus is ours
+    /// * `None` - Call [`acquire_bus`](Self::acquire_bus) first
+    pub fn spi(&mut self) -> Option<&mut PioSpiMaster<'d, PIO, SM>> {
+        self.granted.then_some(&mut self.spi)
+    }
+
+    /// Releases the bus, then the master and pins
+    ///
+    /// # Notes
+    /// - The master's pins stay tri-stated, as the bus is no longer ours; call
+    ///   [`PioSpiMaster::reclaim_pins`] once it may drive them again
+    pub fn release(mut self) -> (PioSpiMaster<'d, PIO, SM>, Output<'d>, Input<'d>) {
+        self.release_bus();
+        (self.spi, self.request, self.grant)
+    }
+}
@@ -0,0 +1,62 @@
+//! Batched response collection
+//!
+//! The PIO's RX-not-empty condition fires for every word, so an async reader that awaits
+//! each response wakes once per frame. For high-rate small frames that wakeup cost
+//! dominates. [`PioSpiMaster::read_batch`] instead waits until a watermark of complete
+//! responses has accumulated in the RX FIFO, sleeping for the time the missing frames
+//! take to shift and checking the FIFO level, then drains them in one go.
+//!
+//! ```ignore
+//! for frame in frames {
+//!     spi.write(frame); // queue up to 4 frames of <= 32 bits
+//! }
+//! let mut responses = [0u64; 4];
+//! let count = spi.read_batch(&mut responses, 4).await;
+//! ```
+
+use embassy_rp::pio::Instance;
+use embassy_time::Timer;
+
+use crate::PioSpiMaster;
+
+impl<PIO: Instance, const SM: usize> PioSpiMaster<'_, PIO, SM> {
+    /// Waits until `watermark` responses are available, then drains every complete one
+    ///
+    /// # Arguments
+    /// * `out` - Destination for the responses, oldest first
+    /// * `watermark` - Responses to wait for; clamped to `out.len()` and to what the RX
+    ///   FIFO can hold (4 frames of up to 32 bits, 2 longer frames)
+    ///
+    /// # Returns
+    /// * `usize` - Number of responses stored in `out` (at least the clamped watermark)
+    ///
+    /// # Behavior
+    /// While fewer than `watermark` responses are in the RX FIFO, sleeps for the
+    /// [`frame_duration`](Self::frame_duration) of each missing frame and checks the FIFO
+    /// level again, so the task wakes about once per batch rather than once per word.
+    ///
+    /// # Notes
+    /// - The frames must already be queued (e.g. with [`write`](Self::write)); waiting for
+    ///   more responses than were started never returns
+    /// - Frames complete while the task sleeps, so the FIFO never overflows: the state
+    ///   machine simply stalls on a full RX FIFO until it is drained
+    pub async fn read_batch(&mut self, out: &mut [u64], watermark: usize) -> usize {
+        let words = self.message_size.div_ceil(32);
+        let capacity = self.pipeline_depth().min(out.len());
+        let watermark = watermark.min(capacity);
+
+        loop {
+            let ready = self.sm.rx().level() as usize / words;
+            if ready >= watermark {
+                break;
+            }
+            Timer::after(self.frame_duration() * (watermark - ready) as u32).await;
+        }
+
+        let ready = (self.sm.rx().level() as usize / words).min(capacity);
+        for response in &mut out[..ready] {
+            *response = self.pull_frame();
+        }
+        ready
+    }
+}
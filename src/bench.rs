@@ -0,0 +1,202 @@
+//! Throughput measurement
+//!
+//! Times a burst of transfers with the Cortex-M33 cycle counter (DWT `CYCCNT`) and reports
+//! the effective data rate, so the blocking, async and DMA paths can be compared under the
+//! exact clock configuration of the application.
+//!
+//! ```ignore
+//! let blocking = spi.benchmark(1000);
+//! let dma = bus.benchmark_dma(100, 256);
+//! info!("{} vs {} bit/s", blocking.bits_per_sec, dma.bits_per_sec);
+//! ```
+//!
+//! # Notes
+//! - The cycle counter is enabled on first use and left running
+//! - A run must finish within 2^32 system clock cycles (about 28 s at 150 MHz)
+//! - Async results include whatever else the executor ran during the burst
+
+use embassy_rp::pac;
+use embassy_rp::pio::Instance;
+
+use crate::stats::cycle_count;
+use crate::transaction::{Phase, PioSpiBus};
+use crate::{ClkPolarity, PioSpiMaster};
+
+/// Largest frame the [`PioSpiBus`] benchmarks can send, in bytes
+pub const BENCH_MAX_SIZE: usize = 1024;
+
+/// Source of bus benchmark frames (alternating bits, kept in flash for the DMA path)
+static BENCH_DATA: [u8; BENCH_MAX_SIZE] = [0x55; BENCH_MAX_SIZE];
+
+/// Result of a benchmark run
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Throughput {
+    /// Payload bits moved per second of wall-clock time
+    pub bits_per_sec: u32,
+    /// System clock cycles elapsed per frame, including CPU and FIFO wait time
+    pub cpu_cycles_per_frame: u32,
+}
+
+impl Throughput {
+    /// Computes the throughput of `frames` frames of `bits_per_frame` bits that took
+    /// `cycles` system clock cycles
+    fn from_cycles(frames: u32, bits_per_frame: usize, cycles: u32) -> Self {
+        let cycles = cycles.max(1) as u64;
+        let bits = frames as u64 * bits_per_frame as u64;
+        let sys_hz = embassy_rp::clocks::clk_sys_freq() as u64;
+        Self {
+            bits_per_sec: (bits * sys_hz / cycles).min(u32::MAX as u64) as u32,
+            cpu_cycles_per_frame: (cycles / frames.max(1) as u64) as u32,
+        }
+    }
+}
+
+/// Call-to-first-edge latency of the short-frame paths (see [`crate::short`])
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct ShortLatency {
+    /// Worst system clock cycles from a [`transfer_short`](PioSpiMaster::transfer_short)
+    /// call to the first CLK edge
+    pub transfer_cycles: u32,
+    /// Worst system clock cycles from a [`fire_short`](PioSpiMaster::fire_short) call to
+    /// the first CLK edge
+    pub armed_cycles: u32,
+}
+
+impl<PIO: Instance, const SM: usize> PioSpiMaster<'_, PIO, SM> {
+    /// Measures the blocking [`transfer`](Self::transfer) path
+    ///
+    /// # Arguments
+    /// * `frames` - Number of back-to-back transfers to time
+    ///
+    /// # Returns
+    /// * `Throughput` - Data rate counting `message_size` payload bits per frame
+    ///
+    /// # Notes
+    /// - Frames carry an alternating bit pattern; responses are discarded
+    pub fn benchmark(&mut self, frames: u32) -> Throughput {
+        let pattern = 0x5555_5555_5555_5555;
+        let start = cycle_count();
+        for _ in 0..frames {
+            let _ = self.transfer(pattern);
+        }
+        let cycles = cycle_count().wrapping_sub(start);
+        Throughput::from_cycles(frames, self.message_size, cycles)
+    }
+
+    /// Measures the async [`transfer_async`](Self::transfer_async) path
+    ///
+    /// Same as [`benchmark`](Self::benchmark), awaiting each transfer.
+    pub async fn benchmark_async(&mut self, frames: u32) -> Throughput {
+        let pattern = 0x5555_5555_5555_5555;
+        let start = cycle_count();
+        for _ in 0..frames {
+            let _ = self.transfer_async(pattern).await;
+        }
+        let cycles = cycle_count().wrapping_sub(start);
+        Throughput::from_cycles(frames, self.message_size, cycles)
+    }
+
+    /// Measures how soon the short-frame paths put the first CLK edge on the wire
+    ///
+    /// # Arguments
+    /// * `samples` - Frames to time on each path
+    ///
+    /// # Returns
+    /// * `ShortLatency` - Worst case over the samples, in system clock cycles
+    ///
+    /// # Behavior
+    /// Starts each frame and busy-polls the CLK pad through SIO until it leaves its idle
+    /// level, once as [`transfer_short`](Self::transfer_short) pushes it and once through
+    /// [`arm_short`](Self::arm_short) / [`fire_short`](Self::fire_short). The poll loop
+    /// adds a few cycles of its own.
+    ///
+    /// # Notes
+    /// - Frames carry an alternating bit pattern; responses are discarded
+    /// - Requires `message_size` of at most 8 and a running master
+    pub fn benchmark_short_latency(&mut self, samples: u32) -> ShortLatency {
+        let pattern = 0x55;
+        let mut latency = ShortLatency {
+            transfer_cycles: 0,
+            armed_cycles: 0,
+        };
+        for _ in 0..samples {
+            let start = cycle_count();
+            self.push_short(pattern);
+            self.wait_first_edge();
+            let cycles = cycle_count().wrapping_sub(start);
+            latency.transfer_cycles = latency.transfer_cycles.max(cycles);
+            let _ = self.finish_short();
+
+            self.arm_short(pattern);
+            let start = cycle_count();
+            self.fire_short();
+            self.wait_first_edge();
+            let cycles = cycle_count().wrapping_sub(start);
+            latency.armed_cycles = latency.armed_cycles.max(cycles);
+            let _ = self.finish_short();
+        }
+        latency
+    }
+
+    /// Spins until the CLK pad leaves its idle level
+    fn wait_first_edge(&self) {
+        let idle = self.clk_polarity() == ClkPolarity::IdleHigh;
+        let mask = 1 << self.clk_pin;
+        while (pac::SIO.gpio_in(0).read() & mask != 0) == idle {}
+    }
+}
+
+impl<PIO: Instance, const SM: usize> PioSpiBus<'_, PIO, SM> {
+    /// Measures the blocking [`write`](Self::write) path
+    ///
+    /// # Arguments
+    /// * `frames` - Number of write phases to time
+    /// * `size` - Bytes per write phase
+    ///
+    /// # Returns
+    /// * `Throughput` - Data rate counting `size * 8` payload bits per frame
+    ///
+    /// # Panics
+    /// If `size` exceeds [`BENCH_MAX_SIZE`]
+    pub fn benchmark(&mut self, frames: u32, size: usize) -> Throughput {
+        let data = &BENCH_DATA[..size];
+        let start = cycle_count();
+        for _ in 0..frames {
+            self.write(data);
+        }
+        let cycles = cycle_count().wrapping_sub(start);
+        Throughput::from_cycles(frames, size * 8, cycles)
+    }
+
+    /// Measures the async [`transaction_async`](Self::transaction_async) path
+    ///
+    /// Same as [`benchmark`](Self::benchmark), awaiting each write phase.
+    pub async fn benchmark_async(&mut self, frames: u32, size: usize) -> Throughput {
+        let data = &BENCH_DATA[..size];
+        let start = cycle_count();
+        for _ in 0..frames {
+            self.transaction_async(&mut [Phase::Write(data)]).await;
+        }
+        let cycles = cycle_count().wrapping_sub(start);
+        Throughput::from_cycles(frames, size * 8, cycles)
+    }
+
+    /// Measures the DMA [`write_static`](Self::write_static) path
+    ///
+    /// Same as [`benchmark`](Self::benchmark), streaming each frame from flash by DMA and
+    /// flushing once at the end, so consecutive frames overlap with their setup.
+    ///
+    /// # Panics
+    /// - If no DMA channel is attached (see [`with_dma`](Self::with_dma))
+    /// - If `size` exceeds [`BENCH_MAX_SIZE`]
+    pub fn benchmark_dma(&mut self, frames: u32, size: usize) -> Throughput {
+        let data = &BENCH_DATA[..size];
+        let start = cycle_count();
+        for _ in 0..frames {
+            self.write_static(data);
+        }
+        self.flush();
+        let cycles = cycle_count().wrapping_sub(start);
+        Throughput::from_cycles(frames, size * 8, cycles)
+    }
+}
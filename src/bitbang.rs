@@ -0,0 +1,290 @@
+//! Bit-banged fallback for configs the PIO programs cannot realize
+//!
+//! The frame programs fit their timing into PIO instruction delays, so CLK stretching,
+//! start delay, lead-in and trailer are capped (see [`SpiMasterConfig::fits_pio`]).
+//! [`BitBangSpi`] shifts the same frames from the CPU over plain GPIOs with no such caps, and
+//! [`FrameSpi`] is the transfer surface both implement, so drivers are written once:
+//!
+//! ```ignore
+//! async fn read_sensor(spi: &mut impl FrameSpi) -> u16 {
+//!     spi.transfer_async(0x8000).await as u16
+//! }
+//!
+//! let mut spi = if config.fits_pio() {
+//!     AnySpi::Pio(PioSpiMaster::new(&mut common, sm0, &clk, &mosi, &miso, config))
+//! } else {
+//!     AnySpi::BitBang(BitBangSpi::new(clk_out, mosi_out, miso_in, None, config))
+//! };
+//! let value = read_sensor(&mut spi).await;
+//! ```
+//!
+//! # Timing
+//!
+//! The bit-banged frames follow the PIO frame timing with one state machine cycle taken as
+//! `clk_div - 1` system clock cycles (as [`PioSpiMaster`] divides it), busy-waited with
+//! `cortex_m::asm::delay`. GPIO accesses come on top, so SCK runs somewhat slower than the
+//! PIO master's and edges jitter with interrupts; slaves clocked by the master do not mind.
+//! `start_delay_cycles` and `rx_overflow` have no meaning without a state machine and are
+//! ignored. A `ready_pin` is polled before every bit, like the PIO programs' wait.
+
+use embassy_rp::gpio::{Input, Level, Output};
+use embassy_rp::pac;
+use embassy_rp::pio::Instance;
+
+use crate::bits::reverse_bits;
+use crate::{
+    Alignment, BitOrder, ClkPolarity, Duplex, PioSpiMaster, SpiMasterConfig, WordOrder,
+    CYCLES_PER_BIT,
+};
+
+/// Frame transfers shared by [`PioSpiMaster`] and [`BitBangSpi`]
+#[allow(async_fn_in_trait)]
+pub trait FrameSpi {
+    /// Returns the frame size in bits
+    fn message_size(&self) -> usize;
+
+    /// Shifts one frame out and returns the response (see [`PioSpiMaster::transfer`])
+    fn transfer(&mut self, data: u64) -> u64;
+
+    /// Shifts one frame out, discarding the response (see [`PioSpiMaster::write`])
+    fn write(&mut self, data: u64);
+
+    /// Shifts one frame out and returns the response, yielding where the implementation
+    /// can (see [`PioSpiMaster::transfer_async`])
+    async fn transfer_async(&mut self, data: u64) -> u64;
+}
+
+impl<PIO: Instance, const SM: usize> FrameSpi for PioSpiMaster<'_, PIO, SM> {
+    fn message_size(&self) -> usize {
+        PioSpiMaster::message_size(self)
+    }
+
+    fn transfer(&mut self, data: u64) -> u64 {
+        PioSpiMaster::transfer(self, data)
+    }
+
+    fn write(&mut self, data: u64) {
+        PioSpiMaster::write(self, data)
+    }
+
+    async fn transfer_async(&mut self, data: u64) -> u64 {
+        PioSpiMaster::transfer_async(self, data).await
+    }
+}
+
+/// SPI master shifting frames from the CPU over GPIOs
+pub struct BitBangSpi<'d> {
+    clk: Output<'d>,
+    mosi: Output<'d>,
+    miso: Input<'d>,
+    cs: Option<Output<'d>>,
+    config: SpiMasterConfig,
+}
+
+impl<'d> BitBangSpi<'d> {
+    /// Creates a bit-banged master and drives CLK and CS to their idle levels
+    ///
+    /// # Arguments
+    /// * `clk` - Clock output
+    /// * `mosi` - MOSI output
+    /// * `miso` - MISO input
+    /// * `cs` - Active-low chip select output, asserted around every frame with the
+    ///   `cs_*_cycles` and `lead_in_cycles` timing; `None` leaves CS to the application
+    /// * `config` - SPI configuration, with no limits on the cycle counts
+    pub fn new(
+        clk: Output<'d>,
+        mosi: Output<'d>,
+        miso: Input<'d>,
+        cs: Option<Output<'d>>,
+        config: SpiMasterConfig,
+    ) -> Self {
+        let mut spi = Self {
+            clk,
+            mosi,
+            miso,
+            cs,
+            config,
+        };
+        spi.set_clk(true);
+        if let Some(cs) = &mut spi.cs {
+            cs.set_high();
+        }
+        spi
+    }
+
+    /// Returns the configuration frames are shifted with
+    pub fn config(&self) -> &SpiMasterConfig {
+        &self.config
+    }
+
+    /// Releases the pins
+    pub fn release(self) -> (Output<'d>, Output<'d>, Input<'d>, Option<Output<'d>>) {
+        (self.clk, self.mosi, self.miso, self.cs)
+    }
+
+    /// Shifts one frame, returning the MISO bits in frame order
+    fn frame(&mut self, data: u64) -> u64 {
+        let size = self.config.message_size;
+        let mask = u64::MAX >> (64 - size);
+        let pad = size.div_ceil(32) * 32 - size;
+        let data = match self.config.tx_alignment {
+            Alignment::Right => data,
+            Alignment::Left => data >> pad,
+        };
+        let data = match self.config.tx_bit_order {
+            BitOrder::MsbFirst => data & mask,
+            BitOrder::LsbFirst => reverse_bits(data, size),
+        };
+
+        if let Some(cs) = &mut self.cs {
+            cs.set_low();
+            self.wait(2 + self.config.cs_setup_cycles as u32);
+            for _ in 0..self.config.lead_in_cycles {
+                self.clock_bit(false);
+            }
+        }
+
+        let mut response = 0;
+        let rx_order = self.config.rx_word_order();
+        for i in 0..size {
+            let mosi = data >> wire_bit(size, self.config.word_order, i) & 1 != 0;
+            let miso = self.clock_bit(mosi);
+            if self.config.duplex == Duplex::Full && miso {
+                response |= 1 << wire_bit(size, rx_order, i);
+            }
+        }
+        if self.config.duplex == Duplex::Half {
+            // MOSI keeps the last data bit during the read phase, as in the PIO program
+            let mosi = self.mosi.is_set_high();
+            self.wait(1);
+            for i in 0..size {
+                if self.clock_bit(mosi) {
+                    response |= 1 << wire_bit(size, rx_order, i);
+                }
+            }
+        }
+        if self.config.trailer_cycles > 0 {
+            let mosi = self.mosi.is_set_high();
+            self.wait(1);
+            for _ in 0..self.config.trailer_cycles {
+                self.clock_bit(mosi);
+            }
+        }
+
+        if self.cs.is_some() {
+            self.wait(2 + self.config.cs_hold_cycles as u32);
+            if let Some(cs) = &mut self.cs {
+                cs.set_high();
+            }
+            self.wait(2 + self.config.cs_high_time_cycles as u32);
+        }
+
+        let response = match self.config.rx_bit_order {
+            BitOrder::MsbFirst => response,
+            BitOrder::LsbFirst => reverse_bits(response, size),
+        };
+        match self.config.rx_alignment {
+            Alignment::Right => response,
+            Alignment::Left => response << pad,
+        }
+    }
+
+    /// Clocks one bit: drives MOSI on the leading edge and samples MISO on the trailing
+    /// edge, holding each CLK phase for its configured cycles
+    fn clock_bit(&mut self, mosi: bool) -> bool {
+        if let Some(pin) = self.config.ready_pin {
+            while pac::SIO.gpio_in(0).read() & 1 << pin == 0 {}
+        }
+        self.set_clk(false);
+        self.mosi.set_level(Level::from(mosi));
+        self.wait(1 + self.config.clk_low_cycles as u32);
+        self.set_clk(true);
+        let sample_delay = self.config.miso_sample_delay_cycles as u32;
+        self.wait(sample_delay);
+        let miso = self.miso.is_high();
+        self.wait(CYCLES_PER_BIT - 1 + self.config.clk_high_cycles as u32);
+        miso
+    }
+
+    /// Drives CLK to its idle level (`true`) or away from it
+    fn set_clk(&mut self, idle: bool) {
+        let high = idle == (self.config.clk_polarity == ClkPolarity::IdleHigh);
+        self.clk.set_level(Level::from(high));
+    }
+
+    /// Busy-waits for `cycles` state machine cycles
+    fn wait(&self, cycles: u32) {
+        let divider = self.config.clk_div.saturating_sub(1).max(1) as u32;
+        cortex_m::asm::delay(cycles * divider);
+    }
+}
+
+impl FrameSpi for BitBangSpi<'_> {
+    fn message_size(&self) -> usize {
+        self.config.message_size
+    }
+
+    fn transfer(&mut self, data: u64) -> u64 {
+        self.frame(data)
+    }
+
+    fn write(&mut self, data: u64) {
+        self.frame(data);
+    }
+
+    /// Shifts the frame without yielding: the CPU is busy for the whole frame
+    async fn transfer_async(&mut self, data: u64) -> u64 {
+        self.frame(data)
+    }
+}
+
+/// Either kind of master, for code that picks one at runtime
+// Usually the PIO variant; with the `event-log` feature it carries the whole ring
+#[allow(clippy::large_enum_variant)]
+pub enum AnySpi<'d, PIO: Instance, const SM: usize> {
+    /// State machine master
+    Pio(PioSpiMaster<'d, PIO, SM>),
+    /// CPU fallback
+    BitBang(BitBangSpi<'d>),
+}
+
+impl<PIO: Instance, const SM: usize> FrameSpi for AnySpi<'_, PIO, SM> {
+    fn message_size(&self) -> usize {
+        match self {
+            Self::Pio(spi) => FrameSpi::message_size(spi),
+            Self::BitBang(spi) => spi.message_size(),
+        }
+    }
+
+    fn transfer(&mut self, data: u64) -> u64 {
+        match self {
+            Self::Pio(spi) => FrameSpi::transfer(spi, data),
+            Self::BitBang(spi) => spi.transfer(data),
+        }
+    }
+
+    fn write(&mut self, data: u64) {
+        match self {
+            Self::Pio(spi) => FrameSpi::write(spi, data),
+            Self::BitBang(spi) => spi.write(data),
+        }
+    }
+
+    async fn transfer_async(&mut self, data: u64) -> u64 {
+        match self {
+            Self::Pio(spi) => FrameSpi::transfer_async(spi, data).await,
+            Self::BitBang(spi) => spi.transfer_async(data).await,
+        }
+    }
+}
+
+/// Returns the bit of a `size`-bit frame shifted as the `i`th bit on the wire, after any
+/// LSB-first reversal: MSB first, except that [`WordOrder::LowFirst`] puts bits [31:0]
+/// before the high bits of frames longer than 32 bits
+fn wire_bit(size: usize, order: WordOrder, i: usize) -> usize {
+    match order {
+        WordOrder::LowFirst if size > 32 && i < 32 => 31 - i,
+        WordOrder::LowFirst if size > 32 => size - 1 - (i - 32),
+        _ => size - 1 - i,
+    }
+}
@@ -0,0 +1,91 @@
+//! Bit-reversal and decoding helpers
+//!
+//! The transaction program always shifts MSB first, and the frame program can only flip
+//! its shift direction for frames of up to 32 bits. Everywhere else LSB-first data is
+//! reversed by the CPU, so these helpers are written around Rust's `reverse_bits`, which
+//! compiles to a single `RBIT` per 32-bit word on the RP2350's Cortex-M33 (and a short
+//! shift/mask sequence elsewhere) rather than a per-bit loop.
+//!
+//! The decoding helpers turn raw responses into the values devices mean by them: Gray
+//! codes from absolute encoders ([`gray_to_binary`]), two's complement readings from
+//! bipolar ADCs ([`sign_extend`]) and fields packed next to each other ([`extract_bits`]).
+//! [`crate::decode`] applies them on the master's read path.
+
+#[cfg(all(test, feature = "std"))]
+mod tests;
+
+/// Reverses the order of the low `bits` bits of `value`
+///
+/// # Arguments
+/// * `value` - Frame whose bits [bits-1:0] are reversed; higher bits are ignored
+/// * `bits` - Frame size (1-64)
+///
+/// # Returns
+/// * `u64` - Bit 0 of `value` moved to bit `bits - 1` and so on, higher bits cleared
+pub fn reverse_bits(value: u64, bits: usize) -> u64 {
+    value.reverse_bits() >> (64 - bits)
+}
+
+/// Reverses the bit order within every byte of `buf`, keeping the byte order
+///
+/// Processes four bytes per step (`RBIT` reverses the word, `REV` restores the byte order).
+pub fn reverse_bits_in_bytes(buf: &mut [u8]) {
+    let mut chunks = buf.chunks_exact_mut(4);
+    for chunk in &mut chunks {
+        let word = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        chunk.copy_from_slice(&word.reverse_bits().swap_bytes().to_le_bytes());
+    }
+    for byte in chunks.into_remainder() {
+        *byte = byte.reverse_bits();
+    }
+}
+
+/// Converts a reflected Gray code, as absolute encoders send it, to binary
+///
+/// # Arguments
+/// * `gray` - Code in bits [bits-1:0]; higher bits are ignored
+/// * `bits` - Code width (1-64)
+///
+/// # Returns
+/// * `u64` - Position in bits [bits-1:0]: every bit is the XOR of the code bits at and
+///   above it
+pub const fn gray_to_binary(gray: u64, bits: usize) -> u64 {
+    let mut value = gray & mask(bits);
+    let mut shift = 1;
+    while shift < 64 {
+        value ^= value >> shift;
+        shift *= 2;
+    }
+    value
+}
+
+/// Sign-extends a two's complement number, as bipolar ADCs send it, to 64 bits
+///
+/// # Arguments
+/// * `value` - Number in bits [bits-1:0], bit `bits - 1` the sign; higher bits are ignored
+/// * `bits` - Number width (1-64)
+///
+/// # Returns
+/// * `i64` - The number, negative when its sign bit is set
+pub const fn sign_extend(value: u64, bits: usize) -> i64 {
+    let unused = 64 - bits as u32;
+    ((value << unused) as i64) >> unused
+}
+
+/// Extracts a bit field, e.g. a status flag or a channel number next to a reading
+///
+/// # Arguments
+/// * `value` - Frame holding the field
+/// * `lsb` - Position of the field's lowest bit (0-63)
+/// * `width` - Field width in bits (1-64, with `lsb + width` at most 64)
+///
+/// # Returns
+/// * `u64` - The field in bits [width-1:0]
+pub const fn extract_bits(value: u64, lsb: usize, width: usize) -> u64 {
+    (value >> lsb) & mask(width)
+}
+
+/// Returns a mask of the low `bits` bits (1-64)
+const fn mask(bits: usize) -> u64 {
+    u64::MAX >> (64 - bits)
+}
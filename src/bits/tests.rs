@@ -0,0 +1,108 @@
+//! Decoding helpers checked against bit-by-bit and wide-integer models, exhaustively for
+//! narrow widths and on random values for every width up to 64 bits.
+
+use super::*;
+
+/// Random values per width, on top of the fixed edge patterns
+const RANDOM_VALUES: usize = 256;
+
+/// Deterministic xorshift64 generator, so failures reproduce
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+/// Returns edge patterns and random values spanning all 64 bits, so every width sees bits
+/// set above it
+fn values(rng: &mut Rng) -> impl Iterator<Item = u64> + '_ {
+    let edges = [
+        0,
+        1,
+        u64::MAX,
+        1 << 63,
+        u64::MAX >> 1,
+        0x5555_5555_5555_5555,
+    ];
+    edges
+        .into_iter()
+        .chain((0..RANDOM_VALUES).map(|_| rng.next()))
+}
+
+/// Encodes a binary number as a reflected Gray code
+fn binary_to_gray(value: u64) -> u64 {
+    value ^ (value >> 1)
+}
+
+#[test]
+fn gray_codes_decode_to_their_position() {
+    for bits in 1..=12 {
+        for position in 0..1u64 << bits {
+            let gray = binary_to_gray(position);
+            assert_eq!(
+                gray_to_binary(gray, bits),
+                position,
+                "{bits}-bit code {gray:#b}"
+            );
+        }
+    }
+    let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+    for bits in 1..=64 {
+        let mask = u64::MAX >> (64 - bits);
+        for value in values(&mut rng) {
+            let position = value & mask;
+            // Bits above the code must not leak into the position
+            let gray = binary_to_gray(position) | (value & !mask);
+            assert_eq!(
+                gray_to_binary(gray, bits),
+                position,
+                "{bits}-bit code {gray:#x}"
+            );
+        }
+    }
+}
+
+#[test]
+fn sign_extension_matches_twos_complement() {
+    let mut rng = Rng(0xD1B5_4A32_D192_ED03);
+    for bits in 1..=64 {
+        for value in values(&mut rng) {
+            let field = (value as u128) & ((1u128 << bits) - 1);
+            let expected = if field >> (bits - 1) & 1 != 0 {
+                field as i128 - (1i128 << bits)
+            } else {
+                field as i128
+            };
+            assert_eq!(
+                sign_extend(value, bits) as i128,
+                expected,
+                "{bits}-bit {value:#x}"
+            );
+        }
+    }
+}
+
+#[test]
+fn extracted_fields_hold_their_bits() {
+    let mut rng = Rng(0x2545_F491_4F6C_DD1D);
+    for width in 1..=64 {
+        for lsb in 0..=64 - width {
+            for value in values(&mut rng).take(16) {
+                let field = extract_bits(value, lsb, width);
+                for bit in 0..64 {
+                    let expected = bit < width && value >> (lsb + bit) & 1 != 0;
+                    assert_eq!(
+                        field >> bit & 1 != 0,
+                        expected,
+                        "bit {bit} of {width} bits at {lsb} in {value:#x}"
+                    );
+                }
+            }
+        }
+    }
+}
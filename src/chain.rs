@@ -0,0 +1,135 @@
+//! Daisy-chained device support
+//!
+//! Devices that pass MOSI through to a MISO/DOUT pin (DACs, shift registers, LED drivers)
+//! can be chained so one long shift updates all of them, followed by a shared latch pulse.
+//! [`DaisyChain`] concatenates per-device frames and handles the bit offsets so each frame
+//! lands in the right device.
+//!
+//! # Bit Layout
+//!
+//! Device 0 is the one wired to the master's MOSI. The first bits shifted travel furthest,
+//! so frames are shifted in reverse device order (last device first), each MSB first:
+//!
+//! ```text
+//! [pad zeros][frame N-1] ... [frame 1][frame 0]  →  MOSI
+//! ```
+//!
+//! Leading zero padding brings the total to a whole number of bytes; those bits are shifted
+//! out past the last device and ignored.
+
+use embassy_rp::gpio::Output;
+use embassy_rp::pio::Instance;
+
+use crate::transaction::{Phase, PioSpiBus};
+
+/// Latch pulse width in CPU cycles (~100 ns at 150 MHz)
+const LATCH_PULSE_CYCLES: u32 = 16;
+
+/// Bytes staged before a write phase is issued
+const CHUNK_BYTES: usize = 32;
+
+/// Chain of `N` devices sharing MOSI→MISO links and a latch line
+pub struct DaisyChain<'d, PIO: Instance, const SM: usize, const N: usize> {
+    bus: PioSpiBus<'d, PIO, SM>,
+    latch: Output<'d>,
+    widths: [u8; N],
+}
+
+impl<'d, PIO: Instance, const SM: usize, const N: usize> DaisyChain<'d, PIO, SM, N> {
+    /// Creates a chain over `bus`
+    ///
+    /// # Arguments
+    /// * `bus` - Phase bus driving the chain (takes ownership)
+    /// * `latch` - Shared latch output; its current level is the idle level
+    /// * `widths` - Frame width in bits of each device, device 0 first (1-64 bits)
+    ///
+    /// # Panics
+    /// If any width is 0 or greater than 64
+    pub fn new(bus: PioSpiBus<'d, PIO, SM>, latch: Output<'d>, widths: [u8; N]) -> Self {
+        assert!(
+            widths.iter().all(|&w| (1..=64).contains(&w)),
+            "device frame width must be 1-64 bits"
+        );
+        Self { bus, latch, widths }
+    }
+
+    /// Shifts one frame to every device and pulses the latch
+    ///
+    /// # Arguments
+    /// * `frames` - One frame per device, device 0 first (only the low `width` bits are used)
+    ///
+    /// # Panics
+    /// If `frames.len()` differs from the chain length
+    pub fn update_all(&mut self, frames: &[u64]) {
+        assert!(frames.len() == N, "one frame per chained device required");
+
+        let total: usize = self.widths.iter().map(|&w| w as usize).sum();
+        let mut writer = BitWriter::new();
+        // One transaction for the whole shift, however many chunks it takes
+        self.bus.begin();
+
+        for _ in 0..(8 - total % 8) % 8 {
+            writer.push_bit(&mut self.bus, false);
+        }
+        for (&frame, &width) in frames.iter().zip(self.widths.iter()).rev() {
+            for bit in (0..width).rev() {
+                writer.push_bit(&mut self.bus, (frame >> bit) & 1 != 0);
+            }
+        }
+        writer.flush(&mut self.bus);
+        self.bus.end();
+
+        self.pulse_latch();
+    }
+
+    /// Drives the latch away from its idle level and back
+    fn pulse_latch(&mut self) {
+        self.latch.toggle();
+        cortex_m::asm::delay(LATCH_PULSE_CYCLES);
+        self.latch.toggle();
+    }
+
+    /// Releases the bus and latch pin
+    pub fn release(self) -> (PioSpiBus<'d, PIO, SM>, Output<'d>) {
+        (self.bus, self.latch)
+    }
+}
+
+/// Packs bits MSB first into bytes, writing full chunks to the bus as they fill
+struct BitWriter {
+    buf: [u8; CHUNK_BYTES],
+    len: usize,
+    bits: u8,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        Self {
+            buf: [0; CHUNK_BYTES],
+            len: 0,
+            bits: 0,
+        }
+    }
+
+    fn push_bit<PIO: Instance, const SM: usize>(
+        &mut self,
+        bus: &mut PioSpiBus<'_, PIO, SM>,
+        bit: bool,
+    ) {
+        self.buf[self.len] = (self.buf[self.len] << 1) | bit as u8;
+        self.bits += 1;
+        if self.bits == 8 {
+            self.bits = 0;
+            self.len += 1;
+            if self.len == CHUNK_BYTES {
+                self.flush(bus);
+            }
+        }
+    }
+
+    fn flush<PIO: Instance, const SM: usize>(&mut self, bus: &mut PioSpiBus<'_, PIO, SM>) {
+        bus.run_phase(&mut Phase::Write(&self.buf[..self.len]));
+        self.buf = [0; CHUNK_BYTES];
+        self.len = 0;
+    }
+}
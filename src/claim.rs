@@ -0,0 +1,77 @@
+//! Output pin bookkeeping across state machines
+//!
+//! Two state machines of one PIO block driving the same pin OR their outputs together, so
+//! a second driver built on an already-driven CLK, MOSI or CS pin produces garbage on both
+//! buses. Every driver records the pins it drives here when it is built, and construction
+//! fails if another state machine of the same block already drives one of them.
+//!
+//! Claims are kept per state machine: building a driver replaces whatever the previous
+//! driver on that state machine claimed (it cannot still exist, as it owned the state
+//! machine), and `free` clears them.
+
+use core::cell::Cell;
+
+use critical_section::Mutex;
+use embassy_rp::interrupt::typelevel::Interrupt;
+use embassy_rp::pac;
+use embassy_rp::pio::Instance;
+
+/// Output pins driven by each state machine of each PIO block, one bit per GPIO
+static CLAIMS: Mutex<Cell<[[u64; 4]; 3]>> = Mutex::new(Cell::new([[0; 4]; 3]));
+
+/// A pin is already driven by another state machine of the same PIO block
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct PinConflict {
+    /// GPIO number claimed twice
+    pub pin: u8,
+    /// State machine that already drives it
+    pub sm: usize,
+}
+
+/// Records `pins` as driven by state machine `SM` of `PIO`; `None` entries are skipped
+///
+/// # Returns
+/// * `Ok(())` - The pins are free (or only claimed by `SM` itself) and now belong to `SM`
+/// * `Err(PinConflict)` - The first pin another state machine drives; nothing is recorded
+pub(crate) fn claim_pins<PIO: Instance, const SM: usize, const N: usize>(
+    pins: [Option<u8>; N],
+) -> Result<(), PinConflict> {
+    let mask = pins
+        .into_iter()
+        .flatten()
+        .fold(0u64, |mask, pin| mask | 1 << pin);
+    critical_section::with(|cs| {
+        let claims = CLAIMS.borrow(cs);
+        let mut all = claims.get();
+        let block = &mut all[pio_index::<PIO>()];
+        for (sm, &claimed) in block.iter().enumerate() {
+            let overlap = claimed & mask;
+            if sm != SM && overlap != 0 {
+                let pin = overlap.trailing_zeros() as u8;
+                return Err(PinConflict { pin, sm });
+            }
+        }
+        block[SM] = mask;
+        claims.set(all);
+        Ok(())
+    })
+}
+
+/// Clears the pins claimed by state machine `SM` of `PIO`
+pub(crate) fn release_claim<PIO: Instance, const SM: usize>() {
+    critical_section::with(|cs| {
+        let claims = CLAIMS.borrow(cs);
+        let mut all = claims.get();
+        all[pio_index::<PIO>()][SM] = 0;
+        claims.set(all);
+    });
+}
+
+/// Returns the index of the `PIO` block, recovered from its interrupt number
+pub(crate) fn pio_index<PIO: Instance>() -> usize {
+    match <PIO::Interrupt as Interrupt>::IRQ {
+        pac::Interrupt::PIO0_IRQ_0 => 0,
+        pac::Interrupt::PIO1_IRQ_0 => 1,
+        _ => 2,
+    }
+}
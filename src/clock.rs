@@ -0,0 +1,188 @@
+//! Clock-only output
+//!
+//! [`ClockOut`] drives a free-running clock on the CLK pin with no data pins, for clocking
+//! external logic or measuring the clock path (edge rates, jitter, the fastest rate a
+//! buffer or level shifter passes) with a scope or frequency counter. It uses the same
+//! `clk_div` setting as [`SpiMasterConfig`](crate::SpiMasterConfig), so a rate found here
+//! carries over to the SPI master unchanged.
+//!
+//! ```ignore
+//! // 1 LOW + 2 HIGH cycles: the same rate and duty as the SPI master's SCK
+//! let mut clk = ClockOut::new(&mut common, sm1, &clk_pin, ClockOutConfig::default());
+//! info!("CLK at {} Hz", clk.frequency());
+//! clk.stop(); // CLK parks HIGH
+//! ```
+
+use embassy_rp::gpio::Level;
+use embassy_rp::pio::{Common, Config, Direction, Instance, LoadedProgram, Pin, StateMachine};
+
+use crate::claim::{claim_pins, release_claim};
+use crate::master::{clock_divider, reset_to_origin, sm_frequency};
+use crate::program::{get_clock_program, MAX_CLOCK_PHASE_CYCLES};
+use crate::CYCLES_PER_BIT;
+
+/// Clock output configuration
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct ClockOutConfig {
+    /// Clock divider setting, as in [`SpiMasterConfig::clk_div`](crate::SpiMasterConfig)
+    pub clk_div: u16,
+    /// State machine cycles CLK stays LOW per period (1-16)
+    pub low_cycles: u8,
+    /// State machine cycles CLK stays HIGH per period (1-16)
+    pub high_cycles: u8,
+}
+
+impl Default for ClockOutConfig {
+    /// Matches the SPI master's SCK: `clk_div` 8, 1 LOW + 2 HIGH cycles
+    fn default() -> Self {
+        Self {
+            clk_div: 8,
+            low_cycles: 1,
+            high_cycles: CYCLES_PER_BIT as u8 - 1,
+        }
+    }
+}
+
+impl ClockOutConfig {
+    /// Returns the state machine cycles per clock period
+    pub fn period_cycles(&self) -> u32 {
+        self.low_cycles as u32 + self.high_cycles as u32
+    }
+
+    /// Returns the clock frequency in Hz at the current system clock
+    pub fn frequency(&self) -> u32 {
+        sm_frequency(self.clk_div) / self.period_cycles()
+    }
+
+    /// Returns the HIGH share of each period in percent
+    pub fn duty_percent(&self) -> u8 {
+        (self.high_cycles as u32 * 100 / self.period_cycles()) as u8
+    }
+}
+
+/// Free-running clock on one PIO pin
+pub struct ClockOut<'d, PIO: Instance, const SM: usize> {
+    sm: StateMachine<'d, PIO, SM>,
+    program: LoadedProgram<'d, PIO>,
+    /// Side-set layout of the loaded program, for parking CLK
+    side_set: pio::SideSet,
+    config: ClockOutConfig,
+}
+
+impl<'d, PIO: Instance, const SM: usize> ClockOut<'d, PIO, SM> {
+    /// Loads the clock program and starts the clock
+    ///
+    /// # Arguments
+    /// * `common` - The PIO peripheral's common interface (for program loading and pin setup)
+    /// * `sm` - State machine (takes ownership)
+    /// * `clk_pin` - Clock pin (side-set/output)
+    /// * `config` - Rate and duty cycle
+    ///
+    /// # Panics
+    /// If `clk_div` is below 2, `low_cycles` or `high_cycles` is outside 1-16, or another
+    /// state machine of the same PIO block already drives the pin
+    /// (see [`PinConflict`](crate::PinConflict))
+    pub fn new(
+        common: &mut Common<'d, PIO>,
+        sm: StateMachine<'d, PIO, SM>,
+        clk_pin: &Pin<'d, PIO>,
+        config: ClockOutConfig,
+    ) -> Self {
+        assert!(config.clk_div >= 2, "clk_div must be at least 2");
+        let phases = 1..=MAX_CLOCK_PHASE_CYCLES;
+        assert!(
+            phases.contains(&config.low_cycles) && phases.contains(&config.high_cycles),
+            "low_cycles and high_cycles must be 1-16"
+        );
+        claim_pins::<PIO, SM, 1>([Some(clk_pin.pin())])
+            .expect("pin already driven by another state machine");
+        let code = get_clock_program(config.low_cycles, config.high_cycles);
+        let program = common.load_program(&code);
+
+        let mut cfg = Config::default();
+        cfg.use_program(&program, &[clk_pin]);
+        cfg.clock_divider = clock_divider(config.clk_div);
+
+        let mut sm = sm;
+        sm.set_config(&cfg);
+        sm.set_pins(Level::High, &[clk_pin]);
+        sm.set_pin_dirs(Direction::Out, &[clk_pin]);
+        sm.set_enable(true);
+
+        Self {
+            sm,
+            program,
+            side_set: code.side_set,
+            config,
+        }
+    }
+
+    /// Returns the active configuration
+    pub fn config(&self) -> ClockOutConfig {
+        self.config
+    }
+
+    /// Returns the clock frequency in Hz at the current system clock
+    pub fn frequency(&self) -> u32 {
+        self.config.frequency()
+    }
+
+    /// Returns whether the clock is running
+    pub fn is_running(&self) -> bool {
+        self.sm.is_enabled()
+    }
+
+    /// Stops the clock with CLK parked HIGH
+    ///
+    /// # Notes
+    /// - A HIGH phase in progress is cut short, so the last pulse may be narrower
+    pub fn stop(&mut self) {
+        self.sm.set_enable(false);
+        let park_high = pio::Instruction {
+            operands: pio::InstructionOperands::MOV {
+                destination: pio::MovDestination::Y,
+                op: pio::MovOperation::None,
+                source: pio::MovSource::Y,
+            },
+            delay: 0,
+            side_set: Some(1),
+        };
+        // SAFETY: the state machine is stopped and `mov y, y` only drives the side-set
+        unsafe { self.sm.exec_instr(park_high.encode(self.side_set)) };
+    }
+
+    /// Restarts the clock from the start of a LOW phase
+    pub fn start(&mut self) {
+        reset_to_origin(&mut self.sm, self.program.origin);
+        self.sm.set_enable(true);
+    }
+
+    /// Changes the clock rate, keeping the duty cycle
+    ///
+    /// # Arguments
+    /// * `clk_div` - New clock divider setting (minimum 2)
+    ///
+    /// # Notes
+    /// - Takes effect immediately; the period in progress may be stretched or shortened
+    pub fn set_clk_div(&mut self, clk_div: u16) {
+        assert!(clk_div >= 2, "clk_div must be at least 2");
+        self.sm.set_clock_divider(clock_divider(clk_div));
+        self.sm.clkdiv_restart();
+        self.config.clk_div = clk_div;
+    }
+
+    /// Stops the clock and frees the program's instruction memory
+    ///
+    /// # Arguments
+    /// * `common` - The PIO peripheral's common interface the program was loaded with
+    ///
+    /// # Returns
+    /// * `StateMachine` - The stopped state machine, ready to be reused by another driver
+    pub fn free(mut self, common: &mut Common<'d, PIO>) -> StateMachine<'d, PIO, SM> {
+        self.stop();
+        release_claim::<PIO, SM>();
+        // SAFETY: the program is private to this clock, whose state machine is stopped
+        unsafe { common.free_instr(self.program.used_memory) };
+        self.sm
+    }
+}
@@ -0,0 +1,116 @@
+//! Command builder for flash, FRAM and PSRAM style devices
+//!
+//! Memory devices share a common transaction shape: an opcode byte, an optional address,
+//! optional dummy clocks, then a data phase. [`Cmd`] describes that shape fluently and
+//! compiles to a [`Phase`] sequence executed by [`PioSpiBus`].
+//!
+//! # Example
+//!
+//! ```ignore
+//! // W25Q fast read: 0x0B, 24-bit address, 8 dummy clocks, data
+//! let mut buf = [0u8; 256];
+//! Cmd::new(0x0B).addr24(0x01_0000).dummy(8).read(&mut buf).run(&mut bus);
+//!
+//! // Write enable: opcode only
+//! Cmd::new(0x06).run(&mut bus);
+//! ```
+
+use embassy_rp::pio::Instance;
+
+use crate::transaction::{Phase, PhaseSpeeds, PioSpiBus};
+
+/// Data phase of a command
+enum Data<'a> {
+    None,
+    Read(&'a mut [u8]),
+    Write(&'a [u8]),
+}
+
+/// Fluent description of an opcode/address/dummy/data command
+pub struct Cmd<'a> {
+    opcode: [u8; 1],
+    addr: [u8; 4],
+    addr_len: usize,
+    dummy: u16,
+    data: Data<'a>,
+}
+
+impl<'a> Cmd<'a> {
+    /// Starts a command with the given opcode
+    pub fn new(opcode: u8) -> Self {
+        Self {
+            opcode: [opcode],
+            addr: [0; 4],
+            addr_len: 0,
+            dummy: 0,
+            data: Data::None,
+        }
+    }
+
+    /// Adds a 24-bit address phase (MSB first)
+    pub fn addr24(self, addr: u32) -> Self {
+        self.addr(addr, 3)
+    }
+
+    /// Adds a 32-bit address phase (MSB first)
+    pub fn addr32(self, addr: u32) -> Self {
+        self.addr(addr, 4)
+    }
+
+    fn addr(mut self, addr: u32, len: usize) -> Self {
+        self.addr = addr.to_be_bytes();
+        self.addr_len = len;
+        self
+    }
+
+    /// Adds dummy clock cycles between the address and data phases
+    pub fn dummy(mut self, cycles: u16) -> Self {
+        self.dummy = cycles;
+        self
+    }
+
+    /// Ends the command with a read data phase filling `buf`
+    pub fn read(mut self, buf: &'a mut [u8]) -> Self {
+        self.data = Data::Read(buf);
+        self
+    }
+
+    /// Ends the command with a write data phase sending `data`
+    pub fn write(mut self, data: &'a [u8]) -> Self {
+        self.data = Data::Write(data);
+        self
+    }
+
+    /// Compiles the command to its phase sequence
+    ///
+    /// Absent parts compile to empty phases, which [`PioSpiBus::transaction`] skips.
+    pub fn phases(&mut self) -> [Phase<'_>; 4] {
+        let data = match &mut self.data {
+            Data::None => Phase::Write(&[]),
+            Data::Read(buf) => Phase::Read(buf),
+            Data::Write(data) => Phase::Write(data),
+        };
+        [
+            Phase::Write(&self.opcode),
+            Phase::Write(&self.addr[4 - self.addr_len..]),
+            Phase::Dummy(self.dummy),
+            data,
+        ]
+    }
+
+    /// Executes the command on `bus`
+    pub fn run<PIO: Instance, const SM: usize>(mut self, bus: &mut PioSpiBus<'_, PIO, SM>) {
+        bus.transaction(&mut self.phases());
+    }
+
+    /// Executes the command on `bus`, clocking the opcode, address and write data at one
+    /// speed and dummy cycles and read data at another
+    /// (see [`PioSpiBus::transaction_with_speeds`])
+    pub fn run_with_speeds<PIO: Instance, const SM: usize>(
+        mut self,
+        bus: &mut PioSpiBus<'_, PIO, SM>,
+        speeds: PhaseSpeeds,
+    ) {
+        bus.transaction_with_speeds(&mut self.phases(), speeds);
+    }
+}
@@ -0,0 +1,206 @@
+//! Digital potentiometer and DAC write helpers
+//!
+//! Typed writers for common write-only devices, built on [`PioSpiMaster::write`] so
+//! users don't hand-pack command bits.
+//!
+//! # Supported Devices
+//!
+//! - **MCP41xx/MCP42xx** digital potentiometers: 16-bit frame (command byte + wiper byte)
+//! - **MCP4902/MCP4912/MCP4922** DACs: 16-bit frame (4 config bits + 12 data bits)
+//! - **DAC8552** DAC: 24-bit frame (control byte + 16 data bits)
+//!
+//! # Notes
+//! - The master's `message_size` must match the device frame width exactly, with
+//!   [`Duplex::Full`] frames: these devices act on the last bits clocked in before CS
+//!   rises, so the extra read clocks of a sequential frame would overwrite the command
+//! - Frames assume MSB-first bit order on the wire
+//! - Responses clocked in alongside each frame are discarded
+//! - Chip select is not driven by these helpers; the devices latch on CS rising edge
+
+use embassy_rp::pio::Instance;
+
+use crate::{Duplex, PioSpiMaster};
+
+/// Pushes a frame on the write-only path, discarding any responses left by previous writes
+///
+/// Draining first keeps the RX FIFO from filling up and stalling the state machine when
+/// the helper is used for long runs of writes.
+fn send<PIO: Instance, const SM: usize>(spi: &mut PioSpiMaster<'_, PIO, SM>, frame: u64) {
+    spi.discard_completed();
+    spi.write(frame);
+}
+
+fn check_frame_size<PIO: Instance, const SM: usize>(
+    spi: &PioSpiMaster<'_, PIO, SM>,
+    frame_bits: usize,
+) {
+    assert!(
+        spi.message_size() == frame_bits,
+        "message_size must match the device frame width"
+    );
+    assert!(
+        spi.duplex() == Duplex::Full,
+        "device frames must be full-duplex"
+    );
+}
+
+/// MCP42xx potentiometer selection (P1/P0 bits)
+///
+/// Single-pot MCP41xx parts only respond to `P0`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Potentiometer {
+    /// Potentiometer 0
+    P0 = 0b01,
+    /// Potentiometer 1
+    P1 = 0b10,
+    /// Both potentiometers
+    Both = 0b11,
+}
+
+/// MCP41xx/MCP42xx digital potentiometer (16-bit frame)
+pub struct Mcp41xx<'d, PIO: Instance, const SM: usize> {
+    spi: PioSpiMaster<'d, PIO, SM>,
+}
+
+impl<'d, PIO: Instance, const SM: usize> Mcp41xx<'d, PIO, SM> {
+    const FRAME_BITS: usize = 16;
+    const CMD_WRITE: u64 = 0b01;
+    const CMD_SHUTDOWN: u64 = 0b10;
+
+    /// Wraps an SPI master configured for 16-bit full-duplex frames
+    ///
+    /// # Panics
+    /// If the master's `message_size` is not 16 or its frames are not [`Duplex::Full`]
+    pub fn new(spi: PioSpiMaster<'d, PIO, SM>) -> Self {
+        check_frame_size(&spi, Self::FRAME_BITS);
+        Self { spi }
+    }
+
+    /// Sets the wiper position (0-255) of the selected potentiometer
+    pub fn set_wiper(&mut self, pot: Potentiometer, value: u8) {
+        self.command(Self::CMD_WRITE, pot, value);
+    }
+
+    /// Shuts down the selected potentiometer (terminal A open, wiper tied to B)
+    pub fn shutdown(&mut self, pot: Potentiometer) {
+        self.command(Self::CMD_SHUTDOWN, pot, 0);
+    }
+
+    /// Frame layout: `x x C1 C0 x x P1 P0 | D7..D0`
+    fn command(&mut self, command: u64, pot: Potentiometer, data: u8) {
+        let frame = (command << 12) | ((pot as u64) << 8) | data as u64;
+        send(&mut self.spi, frame);
+    }
+
+    /// Releases the underlying SPI master
+    pub fn release(self) -> PioSpiMaster<'d, PIO, SM> {
+        self.spi
+    }
+}
+
+/// DAC output channel
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum DacChannel {
+    /// Channel A
+    A,
+    /// Channel B
+    B,
+}
+
+/// MCP49x2 output gain (GA bit)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Gain {
+    /// Vout = Vref * D / 4096
+    X1,
+    /// Vout = 2 * Vref * D / 4096
+    X2,
+}
+
+/// MCP4902/MCP4912/MCP4922 dual DAC (16-bit frame)
+///
+/// Values are always given as 12-bit codes; the 8/10-bit parts ignore the low bits.
+pub struct Mcp4922<'d, PIO: Instance, const SM: usize> {
+    spi: PioSpiMaster<'d, PIO, SM>,
+    gain: Gain,
+    buffered: bool,
+}
+
+impl<'d, PIO: Instance, const SM: usize> Mcp4922<'d, PIO, SM> {
+    const FRAME_BITS: usize = 16;
+
+    /// Wraps an SPI master configured for 16-bit full-duplex frames
+    ///
+    /// # Arguments
+    /// * `spi` - SPI master (takes ownership)
+    /// * `gain` - Output gain applied to every write
+    /// * `buffered` - Buffer the Vref input (BUF bit)
+    ///
+    /// # Panics
+    /// If the master's `message_size` is not 16 or its frames are not [`Duplex::Full`]
+    pub fn new(spi: PioSpiMaster<'d, PIO, SM>, gain: Gain, buffered: bool) -> Self {
+        check_frame_size(&spi, Self::FRAME_BITS);
+        Self {
+            spi,
+            gain,
+            buffered,
+        }
+    }
+
+    /// Sets the output code (0-4095) of `channel` and enables its output
+    pub fn set_output(&mut self, channel: DacChannel, value: u16) {
+        self.command(channel, true, value);
+    }
+
+    /// Shuts down `channel` (output goes high-impedance)
+    pub fn shutdown(&mut self, channel: DacChannel) {
+        self.command(channel, false, 0);
+    }
+
+    /// Frame layout: `A/B BUF GA SHDN | D11..D0`
+    fn command(&mut self, channel: DacChannel, active: bool, value: u16) {
+        let frame = ((matches!(channel, DacChannel::B) as u64) << 15)
+            | ((self.buffered as u64) << 14)
+            | ((matches!(self.gain, Gain::X1) as u64) << 13)
+            | ((active as u64) << 12)
+            | (value as u64 & 0xFFF);
+        send(&mut self.spi, frame);
+    }
+
+    /// Releases the underlying SPI master
+    pub fn release(self) -> PioSpiMaster<'d, PIO, SM> {
+        self.spi
+    }
+}
+
+/// DAC8552 dual 16-bit DAC (24-bit frame)
+pub struct Dac8552<'d, PIO: Instance, const SM: usize> {
+    spi: PioSpiMaster<'d, PIO, SM>,
+}
+
+impl<'d, PIO: Instance, const SM: usize> Dac8552<'d, PIO, SM> {
+    const FRAME_BITS: usize = 24;
+
+    /// Wraps an SPI master configured for 24-bit full-duplex frames
+    ///
+    /// # Panics
+    /// If the master's `message_size` is not 24 or its frames are not [`Duplex::Full`]
+    pub fn new(spi: PioSpiMaster<'d, PIO, SM>) -> Self {
+        check_frame_size(&spi, Self::FRAME_BITS);
+        Self { spi }
+    }
+
+    /// Writes `value` to the data buffer of `channel` and loads it to the output immediately
+    pub fn set_output(&mut self, channel: DacChannel, value: u16) {
+        // Control byte: `0 0 LDB LDA x BufSel PD1 PD0`
+        let control: u64 = match channel {
+            DacChannel::A => 0b0001_0000,
+            DacChannel::B => 0b0010_0100,
+        };
+        send(&mut self.spi, (control << 16) | value as u64);
+    }
+
+    /// Releases the underlying SPI master
+    pub fn release(self) -> PioSpiMaster<'d, PIO, SM> {
+        self.spi
+    }
+}
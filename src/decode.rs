@@ -0,0 +1,79 @@
+//! Decoded reads
+//!
+//! Responses come back as masked `u64` frames, while encoders send Gray codes and bipolar
+//! ADCs two's complement numbers, often next to status bits. These transfers run the
+//! [`crate::bits`] decoding helpers on the response, taking the frame bits wherever the
+//! [`rx_alignment`](crate::SpiMasterConfig::rx_alignment) puts them:
+//!
+//! ```ignore
+//! let angle = encoder.transfer_gray(0) as f32 * 360.0 / 4096.0; // 12-bit absolute encoder
+//! let microvolts = adc.transfer_signed(READ) * UV_PER_LSB;      // 24-bit bipolar ADC
+//! let channel = adc.transfer_field(READ_STATUS, 20, 3);
+//! ```
+//!
+//! # Notes
+//! - Bit positions and widths count from bit 0 of the right-justified frame, whatever the
+//!   alignment
+//! - For responses from other paths (async, pipelined, DMA), call the [`crate::bits`]
+//!   helpers directly
+
+use embassy_rp::pio::Instance;
+
+use crate::bits::{extract_bits, gray_to_binary, sign_extend};
+use crate::{Alignment, PioSpiMaster};
+
+impl<PIO: Instance, const SM: usize> PioSpiMaster<'_, PIO, SM> {
+    /// Transfers a frame and decodes the response as a Gray code
+    ///
+    /// # Arguments
+    /// * `data` - Data to shift out, as [`transfer`](Self::transfer) takes it
+    ///
+    /// # Returns
+    /// * `u64` - The position the `message_size`-bit Gray code stands for
+    pub fn transfer_gray(&mut self, data: u64) -> u64 {
+        let response = self.transfer_frame_bits(data);
+        gray_to_binary(response, self.message_size)
+    }
+
+    /// Transfers a frame and sign-extends the response from `message_size` bits
+    ///
+    /// # Arguments
+    /// * `data` - Data to shift out, as [`transfer`](Self::transfer) takes it
+    ///
+    /// # Returns
+    /// * `i64` - The two's complement number the response holds
+    pub fn transfer_signed(&mut self, data: u64) -> i64 {
+        let response = self.transfer_frame_bits(data);
+        sign_extend(response, self.message_size)
+    }
+
+    /// Transfers a frame and extracts a bit field of the response
+    ///
+    /// # Arguments
+    /// * `data` - Data to shift out, as [`transfer`](Self::transfer) takes it
+    /// * `lsb` - Position of the field's lowest bit in the frame
+    /// * `width` - Field width in bits (at least 1)
+    ///
+    /// # Returns
+    /// * `u64` - The field in bits [width-1:0]
+    ///
+    /// # Panics
+    /// If the field does not lie within the frame (`lsb + width` above `message_size`)
+    pub fn transfer_field(&mut self, data: u64, lsb: usize, width: usize) -> u64 {
+        assert!(
+            width > 0 && lsb + width <= self.message_size,
+            "field must lie within the frame"
+        );
+        let response = self.transfer_frame_bits(data);
+        extract_bits(response, lsb, width)
+    }
+
+    /// Transfers a frame and returns the response in bits [message_size-1:0]
+    fn transfer_frame_bits(&mut self, data: u64) -> u64 {
+        let response = self.transfer(data);
+        match self.config.rx_alignment {
+            Alignment::Right => response,
+            Alignment::Left => response >> self.format.padding_bits(),
+        }
+    }
+}
@@ -0,0 +1,211 @@
+//! Several slaves on one bus, each with its own chip select
+//!
+//! A single [`PioSpiMaster`] drives CLK and MOSI for every slave on the bus; the chip
+//! selects are plain GPIOs. [`DeviceBus`] owns the master and all of them, so every frame
+//! names its slave by [`DeviceId`] and at most one CS is ever asserted:
+//!
+//! ```ignore
+//! const ADC: DeviceId = DeviceId(0);
+//! const DAC: DeviceId = DeviceId(1);
+//!
+//! let mut bus = DeviceBus::new(spi, [cs_adc, cs_dac]);
+//! let sample = bus.transfer(ADC, READ_CH0);
+//! bus.transfer(DAC, WRITE_OUT | sample); // ADC deselected first
+//! ```
+//!
+//! # Sequencing
+//!
+//! A device's CS stays asserted after its frame, so consecutive frames to the same device
+//! run back to back. Switching to another device (or [`DeviceBus::deselect`]):
+//! 1. Waits `2 + cs_hold_cycles` state machine cycles after the last frame, then
+//!    deasserts the current CS
+//! 2. Keeps every CS HIGH for `2 + cs_high_time_cycles` cycles
+//! 3. Asserts the new CS and waits `2 + cs_setup_cycles` cycles before its first frame
+//!
+//! The cycle counts come from the master's [`SpiMasterConfig`](crate::SpiMasterConfig),
+//! as with a PIO-managed CS, and are busy-waited by the CPU.
+//!
+//! # Idle Timeout
+//!
+//! Some slaves misbehave when CS is held low for long without clocks. With
+//! [`DeviceBus::set_idle_timeout`], CS is deasserted once no frame has been sent for the
+//! timeout: [`DeviceBus::deselect_when_idle`] does it in the background of a task that
+//! is otherwise waiting for work, and a frame to a device whose CS outlived the timeout
+//! reselects it first.
+//!
+//! ```ignore
+//! bus.set_idle_timeout(Some(Duration::from_micros(500)));
+//! loop {
+//!     match select(REQUESTS.receive(), bus.deselect_when_idle()).await {
+//!         Either::First((device, frame)) => reply(bus.transfer(device, frame)),
+//!         Either::Second(()) => {}
+//!     }
+//! }
+//! ```
+
+use embassy_rp::gpio::Output;
+use embassy_rp::pio::Instance;
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::PioSpiMaster;
+
+/// Index of a slave's chip select in [`DeviceBus::new`]'s `cs` array
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct DeviceId(pub u8);
+
+/// SPI master shared by slaves with separate, application-pin chip selects
+///
+/// # Type Parameters
+/// * `N` - Number of chip selects
+pub struct DeviceBus<'d, PIO: Instance, const SM: usize, const N: usize> {
+    spi: PioSpiMaster<'d, PIO, SM>,
+    cs: [Output<'d>; N],
+    selected: Option<DeviceId>,
+    /// Inactivity after which the selected device's CS is deasserted
+    idle_timeout: Option<Duration>,
+    /// Selection of the current device or end of its last frame
+    last_activity: Instant,
+}
+
+impl<'d, PIO: Instance, const SM: usize, const N: usize> DeviceBus<'d, PIO, SM, N> {
+    /// Takes over a master and the chip selects, deasserting all of them
+    ///
+    /// # Arguments
+    /// * `spi` - Master built without a PIO-managed CS
+    /// * `cs` - Active-low chip select outputs, indexed by [`DeviceId`]
+    ///
+    /// # Panics
+    /// If the master manages a CS pin itself
+    pub fn new(spi: PioSpiMaster<'d, PIO, SM>, mut cs: [Output<'d>; N]) -> Self {
+        assert!(spi.cs_pin.is_none(), "master must not manage CS itself");
+        for cs in &mut cs {
+            cs.set_high();
+        }
+        Self {
+            spi,
+            cs,
+            selected: None,
+            idle_timeout: None,
+            last_activity: Instant::MIN,
+        }
+    }
+
+    /// Transfers a frame with `device` selected
+    ///
+    /// # Arguments
+    /// * `device` - Slave to address
+    /// * `data` - Data to shift out on MOSI (only bits [message_size-1:0] are used)
+    ///
+    /// # Returns
+    /// * `u64` - Response bits read from MISO
+    ///
+    /// # Panics
+    /// If `device` is not below `N`
+    pub fn transfer(&mut self, device: DeviceId, data: u64) -> u64 {
+        self.select(device);
+        let response = self.spi.transfer(data);
+        self.last_activity = Instant::now();
+        response
+    }
+
+    /// Transfers a frame with `device` selected, awaiting FIFO space and the response
+    ///
+    /// Same behavior as [`transfer`](Self::transfer), with the frame itself sent by
+    /// [`PioSpiMaster::transfer_async`]; the CS sequencing still busy-waits.
+    ///
+    /// # Cancel Safety
+    /// If the future is dropped mid-frame, the device stays selected and the next
+    /// transfer recovers the master as [`PioSpiMaster::transfer_async`] describes
+    pub async fn transfer_async(&mut self, device: DeviceId, data: u64) -> u64 {
+        self.select(device);
+        let response = self.spi.transfer_async(data).await;
+        self.last_activity = Instant::now();
+        response
+    }
+
+    /// Returns the device whose CS is asserted, if any
+    pub fn selected(&self) -> Option<DeviceId> {
+        self.selected
+    }
+
+    /// Deasserts the current device's CS, observing the hold and CS-high times
+    pub fn deselect(&mut self) {
+        let Some(device) = self.selected.take() else {
+            return;
+        };
+        // Frames queued through `master()` must finish before CS rises
+        self.spi.wait_idle();
+        let config = self.spi.config;
+        self.wait_cycles(2 + config.cs_hold_cycles as u32);
+        self.cs[device.0 as usize].set_high();
+        self.wait_cycles(2 + config.cs_high_time_cycles as u32);
+    }
+
+    /// Sets how long CS may stay asserted without frames (see the [module docs](self))
+    ///
+    /// # Arguments
+    /// * `timeout` - Inactivity after which CS is deasserted; `None` keeps the device
+    ///   selected until another one is addressed or [`deselect`](Self::deselect) is called
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        self.idle_timeout = timeout;
+    }
+
+    /// Waits until the selected device has been idle for the timeout, then deselects it
+    ///
+    /// # Behavior
+    /// Never completes while no device is selected or no timeout is set, so it can be
+    /// raced against the task's source of work with `select`.
+    ///
+    /// # Cancel Safety
+    /// Cancel-safe: dropping the future before the timeout leaves the device selected
+    pub async fn deselect_when_idle(&mut self) {
+        match (self.selected, self.idle_timeout) {
+            (Some(_), Some(timeout)) => {
+                Timer::at(self.last_activity + timeout).await;
+                self.deselect();
+            }
+            _ => core::future::pending().await,
+        }
+    }
+
+    /// Returns the master, for configuration changes between frames
+    ///
+    /// # Notes
+    /// - Frames sent through it go to whichever device is [`selected`](Self::selected)
+    /// - Write-only frames sent through it must leave room in the RX FIFO (e.g. be
+    ///   followed by [`discard_stale`](PioSpiMaster::discard_stale)): deselecting waits
+    ///   for the state machine to idle
+    pub fn master(&mut self) -> &mut PioSpiMaster<'d, PIO, SM> {
+        &mut self.spi
+    }
+
+    /// Deselects the current device and returns the master and chip selects
+    pub fn release(mut self) -> (PioSpiMaster<'d, PIO, SM>, [Output<'d>; N]) {
+        self.deselect();
+        (self.spi, self.cs)
+    }
+
+    /// Switches the asserted CS to `device`, deselecting the previous device first
+    fn select(&mut self, device: DeviceId) {
+        assert!((device.0 as usize) < N, "device id out of range");
+        if self.selected == Some(device) && !self.idle_expired() {
+            return;
+        }
+        self.deselect();
+        self.cs[device.0 as usize].set_low();
+        self.selected = Some(device);
+        self.wait_cycles(2 + self.spi.config.cs_setup_cycles as u32);
+        self.last_activity = Instant::now();
+    }
+
+    /// Returns `true` if the selected device has been idle longer than the timeout
+    fn idle_expired(&self) -> bool {
+        self.idle_timeout
+            .is_some_and(|timeout| self.last_activity.elapsed() > timeout)
+    }
+
+    /// Busy-waits for `cycles` state machine cycles
+    fn wait_cycles(&self, cycles: u32) {
+        cortex_m::asm::delay(cycles * self.spi.clk_div() as u32);
+    }
+}
@@ -0,0 +1,219 @@
+//! `embedded-hal` SPI bus for the embassy shared-bus devices
+//!
+//! Device drivers written against `embedded_hal(_async)::spi::SpiDevice` share a bus
+//! through `embassy_embedded_hal::shared_bus`, which locks a mutex around an
+//! `SpiBus`, asserts the device's CS pin and runs the driver's operations. [`ByteBus`]
+//! is that `SpiBus` for a [`PioSpiMaster`] with 8-bit full-duplex frames, and implements
+//! `SetConfig` so `SpiDeviceWithConfig` switches mode and rate per device:
+//!
+//! ```ignore
+//! let config = SpiMasterConfig { message_size: 8, duplex: Duplex::Full, ..Default::default() };
+//! static BUS: StaticCell<Mutex<NoopRawMutex, ByteBus<'static, PIO0, 0>>> = StaticCell::new();
+//! let bus = BUS.init(Mutex::new(ByteBus::new(PioSpiMaster::new(&mut common, sm0, &clk, &mosi, &miso, config))));
+//!
+//! let flash = SpiDevice::new(bus, flash_cs);
+//! let adc = SpiDeviceWithConfig::new(bus, adc_cs, SpiMasterConfig {
+//!     clk_div: 32,
+//!     clk_polarity: ClkPolarity::IdleLow,
+//!     ..config
+//! });
+//! ```
+//!
+//! # Notes
+//! - [`PioSpiMaster`] implements `SetConfig` as well, so code generic over embassy's
+//!   config pattern can reconfigure a master of any frame size without the adapter
+//! - CS comes from the shared-bus device, so build the master without a PIO-managed CS
+//! - Per-device configs go through [`PioSpiMaster::apply_config`]: they may change the
+//!   clock divider, CLK polarity and bit orders, but must keep 8-bit full-duplex frames and
+//!   the rest of the program; otherwise the transaction fails with
+//!   `SpiDeviceError::Config`
+//! - The blocking bus streams bytes through
+//!   [`try_transfer_bytes`](PioSpiMaster::try_transfer_bytes); the async bus pipelines them
+//!   and yields while they shift
+
+use embassy_embedded_hal::SetConfig;
+use embassy_rp::pio::Instance;
+use embedded_hal::spi::{Error, ErrorKind, ErrorType};
+
+use crate::{ConfigError, Duplex, PioSpiMaster, SpiMasterConfig};
+
+/// Bytes moved per chunk of a write, or per pipelined burst of the async bus
+const CHUNK: usize = 16;
+
+/// Error reported by [`ByteBus`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum BusError {
+    /// The master no longer has 8-bit frames or, on the blocking bus, an
+    /// [ISR handle](PioSpiMaster::isr_handle) was issued or MOSI is
+    /// [mirrored](PioSpiMaster::set_mirror)
+    Unavailable,
+}
+
+impl Error for BusError {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Other
+    }
+}
+
+/// `embedded-hal` SPI bus over a [`PioSpiMaster`] with 8-bit full-duplex frames
+pub struct ByteBus<'d, PIO: Instance, const SM: usize> {
+    spi: PioSpiMaster<'d, PIO, SM>,
+}
+
+impl<'d, PIO: Instance, const SM: usize> ByteBus<'d, PIO, SM> {
+    /// Wraps a master whose frames are single bytes (takes ownership)
+    ///
+    /// # Panics
+    /// If `message_size` is not 8 or frames are not [`Duplex::Full`]
+    pub fn new(spi: PioSpiMaster<'d, PIO, SM>) -> Self {
+        assert_eq!(spi.message_size(), 8, "ByteBus requires 8-bit frames");
+        assert_eq!(
+            spi.duplex(),
+            Duplex::Full,
+            "ByteBus requires full-duplex frames"
+        );
+        Self { spi }
+    }
+
+    /// Returns the master, e.g. for its diagnostics
+    pub fn master(&mut self) -> &mut PioSpiMaster<'d, PIO, SM> {
+        &mut self.spi
+    }
+
+    /// Unwraps the master
+    pub fn into_inner(self) -> PioSpiMaster<'d, PIO, SM> {
+        self.spi
+    }
+
+    /// Transfers `buf` in place, blocking
+    fn exchange(&mut self, buf: &mut [u8]) -> Result<(), BusError> {
+        if buf.is_empty() || self.spi.try_transfer_bytes(buf) {
+            Ok(())
+        } else {
+            Err(BusError::Unavailable)
+        }
+    }
+
+    /// Transfers `buf` in place in pipelined bursts, awaiting the responses
+    async fn exchange_async(&mut self, buf: &mut [u8]) -> Result<(), BusError> {
+        if self.spi.message_size() != 8 {
+            return Err(BusError::Unavailable);
+        }
+        let mut frames = [0u64; CHUNK];
+        let mut responses = [0u64; CHUNK];
+        for chunk in buf.chunks_mut(CHUNK) {
+            for (frame, &byte) in frames.iter_mut().zip(chunk.iter()) {
+                *frame = byte as u64;
+            }
+            let count = chunk.len();
+            self.spi
+                .pipeline_async(&frames[..count], &mut responses[..count])
+                .await;
+            for (byte, &response) in chunk.iter_mut().zip(responses.iter()) {
+                *byte = response as u8;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<PIO: Instance, const SM: usize> ErrorType for ByteBus<'_, PIO, SM> {
+    type Error = BusError;
+}
+
+impl<PIO: Instance, const SM: usize> embedded_hal::spi::SpiBus for ByteBus<'_, PIO, SM> {
+    /// Clocks in `words`, sending zeros
+    fn read(&mut self, words: &mut [u8]) -> Result<(), BusError> {
+        words.fill(0);
+        self.exchange(words)
+    }
+
+    /// Sends `words` in chunks, discarding the responses
+    fn write(&mut self, words: &[u8]) -> Result<(), BusError> {
+        let mut buf = [0u8; CHUNK];
+        for chunk in words.chunks(CHUNK) {
+            buf[..chunk.len()].copy_from_slice(chunk);
+            self.exchange(&mut buf[..chunk.len()])?;
+        }
+        Ok(())
+    }
+
+    /// Sends `write` while reading into `read`; the shorter side is padded with zeros or
+    /// its extra responses discarded
+    fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), BusError> {
+        let common = read.len().min(write.len());
+        read[..common].copy_from_slice(&write[..common]);
+        self.exchange(&mut read[..common])?;
+        embedded_hal::spi::SpiBus::write(self, &write[common..])?;
+        embedded_hal::spi::SpiBus::read(self, &mut read[common..])
+    }
+
+    fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), BusError> {
+        self.exchange(words)
+    }
+
+    /// Transfers are complete when they return
+    fn flush(&mut self) -> Result<(), BusError> {
+        Ok(())
+    }
+}
+
+impl<PIO: Instance, const SM: usize> embedded_hal_async::spi::SpiBus for ByteBus<'_, PIO, SM> {
+    /// Async variant of the blocking `read`
+    async fn read(&mut self, words: &mut [u8]) -> Result<(), BusError> {
+        words.fill(0);
+        self.exchange_async(words).await
+    }
+
+    /// Async variant of the blocking `write`
+    async fn write(&mut self, words: &[u8]) -> Result<(), BusError> {
+        let mut buf = [0u8; CHUNK];
+        for chunk in words.chunks(CHUNK) {
+            buf[..chunk.len()].copy_from_slice(chunk);
+            self.exchange_async(&mut buf[..chunk.len()]).await?;
+        }
+        Ok(())
+    }
+
+    /// Async variant of the blocking `transfer`
+    async fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), BusError> {
+        let common = read.len().min(write.len());
+        read[..common].copy_from_slice(&write[..common]);
+        self.exchange_async(&mut read[..common]).await?;
+        embedded_hal_async::spi::SpiBus::write(self, &write[common..]).await?;
+        embedded_hal_async::spi::SpiBus::read(self, &mut read[common..]).await
+    }
+
+    async fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), BusError> {
+        self.exchange_async(words).await
+    }
+
+    /// Responses are collected before each call returns
+    async fn flush(&mut self) -> Result<(), BusError> {
+        Ok(())
+    }
+}
+
+impl<PIO: Instance, const SM: usize> SetConfig for ByteBus<'_, PIO, SM> {
+    type Config = SpiMasterConfig;
+    type ConfigError = ConfigError;
+
+    /// Applies a device's config to the master, refusing any other frame size or duplex
+    /// mode
+    fn set_config(&mut self, config: &SpiMasterConfig) -> Result<(), ConfigError> {
+        if config.message_size != 8 || config.duplex != Duplex::Full {
+            return Err(ConfigError::ProgramChange);
+        }
+        self.spi.set_config(config)
+    }
+}
+
+impl<PIO: Instance, const SM: usize> SetConfig for PioSpiMaster<'_, PIO, SM> {
+    type Config = SpiMasterConfig;
+    type ConfigError = ConfigError;
+
+    /// Switches to `config` through [`apply_config`](PioSpiMaster::apply_config)
+    fn set_config(&mut self, config: &SpiMasterConfig) -> Result<(), ConfigError> {
+        self.apply_config(config)
+    }
+}
@@ -0,0 +1,212 @@
+//! Event log for post-mortem reports
+//!
+//! When an SPI-attached device misbehaves in the field, the report that reaches the
+//! developer rarely says what the bus was doing beforehand. With the `event-log` feature,
+//! every master keeps its last [`EVENT_LOG_CAPACITY`] events — setting changes, frames
+//! queued, errors and stalls, each with its timestamp — in a fixed-size ring, and
+//! `PioSpiMaster::drain_events` hands them out oldest first:
+//!
+//! ```ignore
+//! if let Err(fault) = sensor.read(&mut spi) {
+//!     for event in spi.drain_events() {
+//!         defmt::error!("{}", event);
+//!     }
+//!     defmt::error!("{} older events lost", spi.events_lost());
+//! }
+//! ```
+//!
+//! # Recorded events
+//! - [`EventKind::Config`]: the clock divider, CLK polarity, bit order, frame size,
+//!   duplex mode, pins or mirrors changed
+//! - [`EventKind::Frame`]: a frame was queued by one of the master's own `transfer*` or
+//!   `write*` methods; the extension modules' fast paths (short frames, ISR handles, DMA
+//!   streams) are not logged
+//! - [`EventKind::Error`]: a cancelled transfer was recovered, responses were dropped or
+//!   frames rejected under the [`RxOverflowPolicy`](crate::RxOverflowPolicy), or the FIFOs
+//!   were found out of step
+//! - [`EventKind::Stall`]: the clock paused mid-frame during
+//!   [`transfer_checked`](crate::PioSpiMaster::transfer_checked)
+//!
+//! # Notes
+//! - A full log overwrites its oldest event; `events_lost` counts them
+//! - Without the feature, nothing is recorded and the log takes no memory; the event types
+//!   stay available
+//! - Recording costs a timestamp read and a copy into the ring per event, frames included
+
+#[cfg(feature = "event-log")]
+use embassy_rp::pio::Instance;
+use embassy_time::Instant;
+
+#[cfg(feature = "event-log")]
+use crate::PioSpiMaster;
+use crate::{BitOrder, ClkPolarity, Desync, Duplex};
+
+/// Events the log holds before overwriting the oldest
+pub const EVENT_LOG_CAPACITY: usize = 32;
+
+/// A logged event and when it happened
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Event {
+    /// Time the event was recorded
+    pub at: Instant,
+    /// What happened
+    pub kind: EventKind,
+}
+
+/// What a logged event records
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum EventKind {
+    /// A bus setting changed
+    Config(Setting),
+    /// A frame was queued, with its data as passed in (masked to the frame size)
+    Frame(u64),
+    /// Something went wrong
+    Error(ErrorEvent),
+    /// The clock paused mid-frame: the state machine waited on a full RX FIFO or for the
+    /// second word of a frame
+    Stall,
+}
+
+/// A bus setting change, with the new value
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Setting {
+    /// Clock divider
+    ClkDiv(u16),
+    /// CLK idle level
+    ClkPolarity(ClkPolarity),
+    /// Bit order of MOSI and MISO
+    BitOrder {
+        /// MOSI bit order
+        tx: BitOrder,
+        /// MISO bit order
+        rx: BitOrder,
+    },
+    /// Frame size in bits
+    MessageSize(u8),
+    /// Write-then-read or simultaneous frames
+    Duplex(Duplex),
+    /// CLK, MOSI and MISO moved to other GPIOs
+    Pins {
+        /// New CLK GPIO
+        clk: u8,
+        /// New MOSI GPIO
+        mosi: u8,
+        /// New MISO GPIO
+        miso: u8,
+    },
+    /// CLK and MOSI mirrors turned on or off
+    Mirror {
+        /// CLK is mirrored
+        clk: bool,
+        /// MOSI is mirrored
+        mosi: bool,
+    },
+}
+
+/// An error the master ran into
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum ErrorEvent {
+    /// A cancelled async transfer left a partial frame; the state machine was reset
+    Recovered,
+    /// A response was dropped under [`RxOverflowPolicy::DropOldest`](crate::RxOverflowPolicy)
+    ResponseDropped,
+    /// A frame was rejected under [`RxOverflowPolicy::Error`](crate::RxOverflowPolicy)
+    FrameRejected,
+    /// [`check_sync`](crate::PioSpiMaster::check_sync) found frames and responses out of step
+    Desync(Desync),
+}
+
+/// Fixed-size ring of the most recent events
+#[cfg(feature = "event-log")]
+pub(crate) struct EventLog {
+    events: [Option<Event>; EVENT_LOG_CAPACITY],
+    /// Index of the oldest event
+    head: usize,
+    len: usize,
+    /// Events overwritten since last read
+    lost: u32,
+}
+
+#[cfg(feature = "event-log")]
+impl EventLog {
+    /// Returns an empty log
+    pub(crate) const fn new() -> Self {
+        Self {
+            events: [None; EVENT_LOG_CAPACITY],
+            head: 0,
+            len: 0,
+            lost: 0,
+        }
+    }
+
+    /// Appends an event, overwriting the oldest one if the log is full
+    fn push(&mut self, event: Event) {
+        let tail = (self.head + self.len) % EVENT_LOG_CAPACITY;
+        self.events[tail] = Some(event);
+        if self.len == EVENT_LOG_CAPACITY {
+            self.head = (self.head + 1) % EVENT_LOG_CAPACITY;
+            self.lost = self.lost.saturating_add(1);
+        } else {
+            self.len += 1;
+        }
+    }
+
+    /// Removes and returns the oldest event
+    fn pop(&mut self) -> Option<Event> {
+        if self.len == 0 {
+            return None;
+        }
+        let event = self.events[self.head].take();
+        self.head = (self.head + 1) % EVENT_LOG_CAPACITY;
+        self.len -= 1;
+        event
+    }
+}
+
+/// Iterator over the logged events, oldest first, removing each one it returns
+///
+/// Events not iterated over stay in the log.
+#[cfg(feature = "event-log")]
+pub struct Drain<'a> {
+    log: &'a mut EventLog,
+}
+
+#[cfg(feature = "event-log")]
+impl Iterator for Drain<'_> {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        self.log.pop()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.log.len, Some(self.log.len))
+    }
+}
+
+#[cfg(feature = "event-log")]
+impl<PIO: Instance, const SM: usize> PioSpiMaster<'_, PIO, SM> {
+    /// Takes the logged events, oldest first
+    ///
+    /// # Returns
+    /// * `Drain` - Iterator removing each event it returns
+    pub fn drain_events(&mut self) -> Drain<'_> {
+        Drain {
+            log: &mut self.events,
+        }
+    }
+
+    /// Returns how many events were overwritten before being drained, and resets the count
+    pub fn events_lost(&mut self) -> u32 {
+        core::mem::take(&mut self.events.lost)
+    }
+}
+
+/// Records `kind` in `log` with the current time
+#[cfg(feature = "event-log")]
+pub(crate) fn record(log: &mut EventLog, kind: EventKind) {
+    log.push(Event {
+        at: Instant::now(),
+        kind,
+    });
+}
@@ -0,0 +1,215 @@
+//! Failsafe output states
+//!
+//! A master feeding an SPI-controlled power stage (gate drivers, motor controllers,
+//! programmable supplies) must leave the bus in a known state when the firmware gives up on
+//! it: a slave that sees CLK toggling or its CS asserted while the controller hangs or
+//! resets can latch a half-shifted command. [`PioSpiMaster::enter_failsafe`] stops the state
+//! machine and forces the pads to the levels in [`SpiMasterConfig::failsafe`] through the
+//! GPIO output overrides, which hold regardless of what the PIO does afterwards.
+//!
+//! Fault paths rarely own the master, so [`FailsafeHandle`] does the same from a watchdog
+//! task, a HardFault handler or a panic handler:
+//!
+//! ```ignore
+//! let config = SpiMasterConfig {
+//!     failsafe: FailsafeStates {
+//!         mosi: FailsafeLevel::Float,
+//!         ..FailsafeStates::default()
+//!     },
+//!     ..SpiMasterConfig::default()
+//! };
+//! let spi = PioSpiMaster::new_with_cs(&mut common, sm0, &clk, &mosi, &miso, &cs, config);
+//! critical_section::with(|cs| FAILSAFE.borrow(cs).set(Some(spi.failsafe_handle())));
+//!
+//! // panic handler
+//! if let Some(failsafe) = critical_section::with(|cs| FAILSAFE.borrow(cs).get()) {
+//!     failsafe.engage();
+//! }
+//! ```
+//!
+//! # Notes
+//! - Mirror pins (see [`set_mirror`](PioSpiMaster::set_mirror)) follow their originals
+//! - An application-managed CS is not known to the master; force it with its own GPIO
+//! - [`leave_failsafe`](PioSpiMaster::leave_failsafe) hands the pads back to the PIO
+//!
+//! [`SpiMasterConfig::failsafe`]: crate::SpiMasterConfig::failsafe
+
+use core::marker::PhantomData;
+
+use embassy_rp::pac;
+use embassy_rp::pio::Instance;
+
+use crate::irq::pio_regs;
+use crate::master::set_clk_inversion;
+use crate::PioSpiMaster;
+
+/// Level a bus line is forced to in failsafe
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub enum FailsafeLevel {
+    /// The line's resting level: CLK at its idle level, MOSI LOW, CS deasserted (HIGH)
+    #[default]
+    Idle,
+    /// Driven LOW
+    Low,
+    /// Driven HIGH
+    High,
+    /// Not driven, left to the pad's pulls and external resistors
+    Float,
+}
+
+/// Levels of the bus lines in failsafe; the default rests every line
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct FailsafeStates {
+    /// CLK (and its mirror)
+    pub clk: FailsafeLevel,
+    /// MOSI (and its mirror)
+    pub mosi: FailsafeLevel,
+    /// A PIO-managed CS
+    pub cs: FailsafeLevel,
+}
+
+/// Pad override resolved from a [`FailsafeLevel`]
+#[derive(Clone, Copy)]
+enum Force {
+    Low,
+    High,
+    Float,
+    /// CLK's idle level, read back from the pad's inversion when engaged
+    ClkIdle,
+}
+
+impl Force {
+    /// Resolves `level` for a line resting at `idle`
+    fn of(level: FailsafeLevel, idle: Force) -> Self {
+        match level {
+            FailsafeLevel::Idle => idle,
+            FailsafeLevel::Low => Force::Low,
+            FailsafeLevel::High => Force::High,
+            FailsafeLevel::Float => Force::Float,
+        }
+    }
+}
+
+/// Forces the bus of one master into its failsafe states
+///
+/// Obtained from [`PioSpiMaster::failsafe_handle`]; it is `Copy`, only writes registers and
+/// never blocks or takes a lock, so it can be stored in a `static` and engaged from any
+/// interrupt priority or a panic handler.
+#[derive(Clone, Copy)]
+pub struct FailsafeHandle<PIO: Instance, const SM: usize> {
+    /// Output pins in use and the override each gets
+    outputs: [Option<(u8, Force)>; 5],
+    _pio: PhantomData<PIO>,
+}
+
+impl<PIO: Instance, const SM: usize> FailsafeHandle<PIO, SM> {
+    /// Forces the pads to their failsafe states and stops the state machine
+    ///
+    /// # Behavior
+    /// 1. Overrides the output of every pin the master drives, which takes effect at once
+    ///    even mid-frame: CLK cannot produce another edge and CS is released
+    /// 2. Disables the state machine, leaving its FIFOs and program counter as they were
+    ///
+    /// # Notes
+    /// - Safe to call repeatedly: engaging again keeps the levels already forced
+    pub fn engage(&self) {
+        for (pin, force) in self.outputs.into_iter().flatten() {
+            force_pad(pin, force);
+        }
+        let ctrl = pio_regs::<PIO>().ctrl().as_ptr() as usize;
+        // SAFETY: the atomic clear alias of CTRL (+0x3000) clears only this state machine's
+        // enable bit, so concurrent writers to the other bits need no lock
+        unsafe { ((ctrl + 0x3000) as *mut u32).write_volatile(1 << SM) };
+    }
+}
+
+/// Overrides the output of `pin` for `force`
+fn force_pad(pin: u8, force: Force) {
+    use pac::io::vals::{Oeover, Outover};
+
+    let ctrl = pac::IO_BANK0.gpio(pin as usize).ctrl();
+    let (outover, oeover) = match force {
+        Force::Low => (Outover::LOW, Oeover::ENABLE),
+        Force::High => (Outover::HIGH, Oeover::ENABLE),
+        Force::Float => (ctrl.read().outover(), Oeover::DISABLE),
+        // An inverted pad idles LOW; one already forced keeps its level
+        Force::ClkIdle => match ctrl.read().outover() {
+            Outover::INVERT => (Outover::LOW, Oeover::ENABLE),
+            Outover::NORMAL => (Outover::HIGH, Oeover::ENABLE),
+            forced => (forced, Oeover::ENABLE),
+        },
+    };
+    ctrl.modify(|w| {
+        w.set_outover(outover);
+        w.set_oeover(oeover);
+    });
+}
+
+impl<PIO: Instance, const SM: usize> PioSpiMaster<'_, PIO, SM> {
+    /// Returns a handle that forces this master's bus into its failsafe states
+    ///
+    /// # Notes
+    /// - The handle captures the pins and failsafe states as they are now; take a new one
+    ///   after [`remap_pins`](Self::remap_pins) or [`set_mirror`](Self::set_mirror). CLK
+    ///   polarity changes need no new handle
+    pub fn failsafe_handle(&self) -> FailsafeHandle<PIO, SM> {
+        let states = self.config.failsafe;
+        let clk = Force::of(states.clk, Force::ClkIdle);
+        let mosi = Force::of(states.mosi, Force::Low);
+        let [clk_mirror, mosi_mirror] = self.mirror_pins();
+        FailsafeHandle {
+            outputs: [
+                Some((self.clk_pin, clk)),
+                clk_mirror.map(|pin| (pin, clk)),
+                Some((self.mosi_pin, mosi)),
+                mosi_mirror.map(|pin| (pin, mosi)),
+                self.cs_pin
+                    .map(|pin| (pin, Force::of(states.cs, Force::High))),
+            ],
+            _pio: PhantomData,
+        }
+    }
+
+    /// Forces the bus into the states of
+    /// [`SpiMasterConfig::failsafe`](crate::SpiMasterConfig::failsafe) and stops the state
+    /// machine, as [`FailsafeHandle::engage`] does
+    ///
+    /// # Notes
+    /// - Frames in flight are cut off; their responses are lost
+    /// - The pads stay forced until [`leave_failsafe`](Self::leave_failsafe): transfers
+    ///   made meanwhile restart the state machine but reach no slave, and their responses
+    ///   are meaningless
+    pub fn enter_failsafe(&mut self) {
+        self.failsafe_handle().engage();
+        self.interrupted = true;
+    }
+
+    /// Hands the pads back to the PIO and restarts the master after
+    /// [`enter_failsafe`](Self::enter_failsafe) or a [`FailsafeHandle`]
+    ///
+    /// # Behavior
+    /// Restores the pads' CLK inversion and output enables, then resets the state machine
+    /// to the program start with empty FIFOs and CS deasserted, as
+    /// [`resync`](Self::resync) does, and starts it.
+    ///
+    /// # Notes
+    /// - The slaves saw truncated frames if failsafe was entered mid-frame; reinitialize
+    ///   them before trusting their state
+    pub fn leave_failsafe(&mut self) {
+        let [clk_mirror, mosi_mirror] = self.mirror_pins();
+        for pin in [Some(self.clk_pin), clk_mirror].into_iter().flatten() {
+            set_clk_inversion(pin, self.clk_polarity);
+        }
+        for pin in [Some(self.mosi_pin), mosi_mirror, self.cs_pin]
+            .into_iter()
+            .flatten()
+        {
+            pac::IO_BANK0
+                .gpio(pin as usize)
+                .ctrl()
+                .modify(|w| w.set_outover(pac::io::vals::Outover::NORMAL));
+        }
+        self.reclaim_pins();
+        self.reset_frames(true);
+    }
+}
@@ -0,0 +1,143 @@
+//! FIFO debug flags as events
+//!
+//! The PIO block latches four sticky flags per state machine in its `FDEBUG` register:
+//! the state machine stalled on an empty TX FIFO or a full RX FIFO, the CPU pushed into a
+//! full TX FIFO, or the CPU pulled from an empty RX FIFO. Each one is a silent performance
+//! or correctness problem (CLK paused mid-frame, a frame lost, a garbage response);
+//! [`PioSpiMaster::fifo_events`] and friends make them observable:
+//!
+//! ```ignore
+//! spi.clear_fifo_events();
+//! run_burst(&mut spi).await;
+//! let events = spi.take_fifo_events();
+//! if events.rx_stall {
+//!     defmt::warn!("responses were collected too late; CLK paused");
+//! }
+//!
+//! // Or watch from a task
+//! let events = spi
+//!     .wait_fifo_events(FifoEvents::ERRORS, Duration::from_millis(10))
+//!     .await;
+//! ```
+//!
+//! # Notes
+//! - `tx_stall` is also set every time the state machine runs out of frames, which is its
+//!   normal idle state; it means a gap mid-frame only while frames were queued back to back
+//! - The master's own idle and health checks (e.g. [`transfer_checked`]) read and clear
+//!   `tx_stall` and `rx_stall`, so those two are best-effort between them; `tx_overflow`
+//!   and `rx_underflow` are only cleared here
+//!
+//! [`transfer_checked`]: PioSpiMaster::transfer_checked
+
+use embassy_rp::pio::Instance;
+use embassy_time::{Duration, Timer};
+
+use crate::irq::pio_regs;
+use crate::PioSpiMaster;
+
+/// Sticky FIFO debug flags of one state machine
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct FifoEvents {
+    /// The state machine waited on an empty TX FIFO
+    pub tx_stall: bool,
+    /// The state machine waited on a full RX FIFO (CLK paused until a response was pulled)
+    pub rx_stall: bool,
+    /// A word was pushed into a full TX FIFO and lost
+    pub tx_overflow: bool,
+    /// A word was pulled from an empty RX FIFO, returning garbage
+    pub rx_underflow: bool,
+}
+
+impl FifoEvents {
+    /// Every flag set
+    pub const ALL: Self = Self {
+        tx_stall: true,
+        rx_stall: true,
+        tx_overflow: true,
+        rx_underflow: true,
+    };
+
+    /// The flags that always indicate a problem (everything but `tx_stall`)
+    pub const ERRORS: Self = Self {
+        tx_stall: false,
+        ..Self::ALL
+    };
+
+    /// Returns `true` if any flag is set
+    pub fn any(&self) -> bool {
+        self.tx_stall || self.rx_stall || self.tx_overflow || self.rx_underflow
+    }
+
+    /// Returns the flags set in both `self` and `mask`
+    pub fn intersect(&self, mask: Self) -> Self {
+        Self {
+            tx_stall: self.tx_stall && mask.tx_stall,
+            rx_stall: self.rx_stall && mask.rx_stall,
+            tx_overflow: self.tx_overflow && mask.tx_overflow,
+            rx_underflow: self.rx_underflow && mask.rx_underflow,
+        }
+    }
+}
+
+impl<PIO: Instance, const SM: usize> PioSpiMaster<'_, PIO, SM> {
+    /// Returns the state machine's FIFO debug flags without clearing them
+    pub fn fifo_events(&self) -> FifoEvents {
+        let fdebug = pio_regs::<PIO>().fdebug().read();
+        let bit = 1 << SM;
+        FifoEvents {
+            tx_stall: fdebug.txstall() & bit != 0,
+            rx_stall: fdebug.rxstall() & bit != 0,
+            tx_overflow: fdebug.txover() & bit != 0,
+            rx_underflow: fdebug.rxunder() & bit != 0,
+        }
+    }
+
+    /// Returns the state machine's FIFO debug flags and clears the ones that were set
+    ///
+    /// # Notes
+    /// - Flags raised between the read and the clear are kept for the next call
+    pub fn take_fifo_events(&mut self) -> FifoEvents {
+        let events = self.fifo_events();
+        self.clear_events(events);
+        events
+    }
+
+    /// Clears all FIFO debug flags of the state machine, e.g. before a section to watch
+    pub fn clear_fifo_events(&mut self) {
+        self.clear_events(FifoEvents::ALL);
+    }
+
+    /// Waits until any flag of `mask` is raised, then clears and returns those flags
+    ///
+    /// # Arguments
+    /// * `mask` - Flags to wait for (e.g. [`FifoEvents::ERRORS`])
+    /// * `interval` - Time between checks; `FDEBUG` raises no interrupt, so it is polled
+    ///
+    /// # Returns
+    /// * `FifoEvents` - The flags of `mask` that were set; others are left untouched
+    ///
+    /// # Cancel Safety
+    /// Cancel-safe: flags are only cleared once they are returned.
+    pub async fn wait_fifo_events(&mut self, mask: FifoEvents, interval: Duration) -> FifoEvents {
+        loop {
+            let events = self.fifo_events().intersect(mask);
+            if events.any() {
+                self.clear_events(events);
+                return events;
+            }
+            Timer::after(interval).await;
+        }
+    }
+
+    /// Clears the given flags of this state machine (write 1 to clear, so other state
+    /// machines' flags are not touched)
+    fn clear_events(&mut self, events: FifoEvents) {
+        let bit = |set: bool| if set { 1 << SM } else { 0 };
+        pio_regs::<PIO>().fdebug().write(|w| {
+            w.set_txstall(bit(events.tx_stall));
+            w.set_rxstall(bit(events.rx_stall));
+            w.set_txover(bit(events.tx_overflow));
+            w.set_rxunder(bit(events.rx_underflow));
+        });
+    }
+}
@@ -0,0 +1,230 @@
+//! Frame layout: packing frames into FIFO words and reassembling responses
+//!
+//! Every frame program shifts whole FIFO words, so the master turns each `u64` frame into
+//! one or two left-justified TX words and each response's RX words back into a `u64`,
+//! honoring the bit order, alignment and word order settings. That arithmetic is all
+//! shifts and masks whose edge cases sit at the sizes where a frame crosses a word (17,
+//! 31, 32, 33, 47 bits): [`FrameFormat`] keeps it free of any hardware, so the `std` host
+//! tests run it over every size and setting.
+//!
+//! ```ignore
+//! let format = config.frame_format();
+//! let (words, count) = format.pack(frame);
+//! let response = format.unpack(rx_words);
+//! ```
+
+use crate::bits::reverse_bits;
+use crate::program::double_bits;
+
+#[cfg(all(test, feature = "std"))]
+mod tests;
+
+/// Order of the two FIFO words of a frame longer than 32 bits
+///
+/// Each word is always shifted MSB first; this only selects which part of the frame
+/// is transmitted (and, unless [`SpiMasterConfig::rx_word_order`] says otherwise,
+/// received) first.
+///
+/// [`SpiMasterConfig::rx_word_order`]: crate::SpiMasterConfig::rx_word_order
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "hal", derive(defmt::Format))]
+pub enum WordOrder {
+    /// High bits first: MSB first across the whole frame
+    #[default]
+    HighFirst,
+    /// Bits [31:0] first, then the remaining high bits
+    LowFirst,
+}
+
+/// Position of a frame's bits in the `u64` passed to or returned from a transfer
+///
+/// Frames are carried in one 32-bit FIFO word (up to 32 bits) or two (longer frames); the
+/// alignment picks which end of that 32- or 64-bit container the frame sits at.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "hal", derive(defmt::Format))]
+pub enum Alignment {
+    /// Frame in bits [message_size-1:0], as a number
+    #[default]
+    Right,
+    /// Frame in the top bits of the container (bits [31:32-message_size], or
+    /// [63:64-message_size] beyond 32 bits), padding bits zero: the layout of devices that
+    /// define, say, a 12-bit frame as the high bits of a 16- or 32-bit register
+    Left,
+}
+
+/// Bit order of one direction of a frame
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "hal", derive(defmt::Format))]
+pub enum BitOrder {
+    /// Bit `message_size - 1` of the frame is on the wire first
+    #[default]
+    MsbFirst,
+    /// Bit 0 of the frame is on the wire first
+    LsbFirst,
+}
+
+/// The settings that decide how a frame maps onto FIFO words
+///
+/// The frame programs shift OUT and IN left for MSB-first frames of any size and right
+/// for LSB-first frames of up to 32 bits; longer LSB-first frames are bit-reversed by the
+/// CPU and shifted left.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameFormat {
+    /// Frame size in bits (1-60)
+    pub message_size: usize,
+    /// Order in which frame bits are shifted out on MOSI
+    pub tx_bit_order: BitOrder,
+    /// Order in which MISO bits are assembled into the response
+    pub rx_bit_order: BitOrder,
+    /// Position of the frame bits in data passed to a transfer
+    pub tx_alignment: Alignment,
+    /// Position of the frame bits in a response
+    pub rx_alignment: Alignment,
+    /// Part of a frame longer than 32 bits that is sent first
+    pub tx_word_order: WordOrder,
+    /// Part of a response longer than 32 bits that arrives first
+    pub rx_word_order: WordOrder,
+    /// MOSI is mirrored: every bit of a frame of up to 16 bits is shifted out twice
+    pub mirror_mosi: bool,
+}
+
+impl FrameFormat {
+    /// Returns the number of FIFO words a frame takes in each direction
+    pub fn words(&self) -> usize {
+        self.message_size.div_ceil(32)
+    }
+
+    /// Returns the bits of the 32- or 64-bit frame container beyond message_size
+    pub fn padding_bits(&self) -> usize {
+        self.words() * 32 - self.message_size
+    }
+
+    /// Returns the mask of the frame bits in data passed to a transfer
+    pub fn tx_mask(&self) -> u64 {
+        self.mask(self.tx_alignment)
+    }
+
+    /// Returns the mask of the frame bits in a response
+    pub fn rx_mask(&self) -> u64 {
+        self.mask(self.rx_alignment)
+    }
+
+    /// Packs a frame into its TX FIFO words
+    ///
+    /// # Arguments
+    /// * `data` - Frame at its [`tx_alignment`](Self::tx_alignment); bits outside
+    ///   [`tx_mask`](Self::tx_mask) must be zero
+    ///
+    /// # Returns
+    /// * `([u32; 2], usize)` - The words and how many are used; unused words are zero
+    ///
+    /// # Behavior
+    /// OUT shifts from the MSB of the OSR, so each word is left-justified:
+    /// - **<=32 bits**: One word, data in bits [31:32-message_size]; LSB-first frames
+    ///   shift right and stay right-justified. With MOSI mirrored, every bit is doubled
+    /// - **>32 bits**: A full 32-bit word, then the remaining (message_size - 32) bits
+    ///   left-justified; [`WordOrder`] selects whether the high or low part goes first.
+    ///   LSB-first frames are bit-reversed before being split
+    pub fn pack(&self, data: u64) -> ([u32; 2], usize) {
+        let data = match self.tx_alignment {
+            Alignment::Right => data,
+            Alignment::Left => data >> self.padding_bits(),
+        };
+        if self.message_size <= 32 {
+            let word = match self.tx_bit_order {
+                BitOrder::MsbFirst => data << (32 - self.message_size),
+                BitOrder::LsbFirst => data,
+            };
+            return ([self.mirror_word(word as u32), 0], 1);
+        }
+
+        let data = match self.tx_bit_order {
+            BitOrder::MsbFirst => data,
+            BitOrder::LsbFirst => reverse_bits(data, self.message_size),
+        };
+        let rest = self.message_size - 32;
+        let (first, second) = match self.tx_word_order {
+            WordOrder::HighFirst => (data >> rest, data << (64 - self.message_size)),
+            WordOrder::LowFirst => (data, (data >> 32) << (32 - rest)),
+        };
+        ([first as u32, second as u32], 2)
+    }
+
+    /// Doubles the frame bits of a single TX word when MOSI is mirrored, so every
+    /// `out pins, 2` drives the same bit on both pins
+    pub fn mirror_word(&self, word: u32) -> u32 {
+        if !self.mirror_mosi {
+            return word;
+        }
+        // Frames of up to 16 bits sit in the half the OSR shifts out first
+        match self.tx_bit_order {
+            BitOrder::MsbFirst => double_bits((word >> 16) as u16),
+            BitOrder::LsbFirst => double_bits(word as u16),
+        }
+    }
+
+    /// Reassembles a response from its RX FIFO words
+    ///
+    /// # Arguments
+    /// * `words` - The RX words in the order they were pulled; only the first is used for
+    ///   frames of up to 32 bits
+    ///
+    /// # Returns
+    /// * `u64` - Response at its [`rx_alignment`](Self::rx_alignment)
+    ///
+    /// # Behavior
+    /// IN shifts into the LSB of the ISR, so each word is right-justified:
+    /// - **<=32 bits**: One word holding the whole frame; LSB-first frames shift right and
+    ///   arrive left-justified
+    /// - **>32 bits**: A full 32-bit word, then the remaining (message_size - 32) bits;
+    ///   [`WordOrder`] selects whether the first word is the high or low part.
+    ///   LSB-first frames are bit-reversed after being reassembled
+    pub fn unpack(&self, words: [u32; 2]) -> u64 {
+        if self.message_size <= 32 {
+            self.unpack_word(words[0])
+        } else {
+            self.unpack_words(words[0], words[1])
+        }
+    }
+
+    /// Extracts the response from the single RX FIFO word of a frame of up to 32 bits
+    pub fn unpack_word(&self, word: u32) -> u64 {
+        let data = match self.rx_bit_order {
+            BitOrder::MsbFirst => word as u64,
+            BitOrder::LsbFirst => (word >> (32 - self.message_size)) as u64,
+        };
+        self.align_response(data)
+    }
+
+    /// Reassembles the two RX FIFO words of a frame longer than 32 bits
+    pub fn unpack_words(&self, first: u32, second: u32) -> u64 {
+        let rest = self.message_size - 32;
+        let (first, second) = (first as u64, second as u64);
+        let data = match self.rx_word_order {
+            WordOrder::HighFirst => (first << rest) | second,
+            WordOrder::LowFirst => first | (second << 32),
+        };
+        let data = match self.rx_bit_order {
+            BitOrder::MsbFirst => data,
+            BitOrder::LsbFirst => reverse_bits(data, self.message_size),
+        };
+        self.align_response(data)
+    }
+
+    /// Returns the mask of the frame bits at `alignment`
+    fn mask(&self, alignment: Alignment) -> u64 {
+        let mask = (1u64 << self.message_size) - 1;
+        match alignment {
+            Alignment::Right => mask,
+            Alignment::Left => mask << self.padding_bits(),
+        }
+    }
+
+    /// Moves a right-justified response to its configured alignment
+    fn align_response(&self, data: u64) -> u64 {
+        match self.rx_alignment {
+            Alignment::Right => data,
+            Alignment::Left => data << self.padding_bits(),
+        }
+    }
+}
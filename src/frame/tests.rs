@@ -0,0 +1,232 @@
+//! Frame layout properties, checked for every frame size and every combination of bit
+//! order, alignment and word order against a bit-level model of the shift registers.
+
+use std::vec::Vec;
+
+use super::*;
+
+/// Sizes the properties run over: every size the frame programs support
+const SIZES: core::ops::RangeInclusive<usize> = 1..=60;
+
+/// Random frames per size and setting combination, on top of the fixed edge patterns
+const RANDOM_FRAMES: usize = 64;
+
+/// Deterministic xorshift64 generator, so failures reproduce
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+/// Returns the format of every combination of the settings for `message_size`
+fn formats(message_size: usize) -> Vec<FrameFormat> {
+    let orders = [BitOrder::MsbFirst, BitOrder::LsbFirst];
+    let alignments = [Alignment::Right, Alignment::Left];
+    let word_orders = [WordOrder::HighFirst, WordOrder::LowFirst];
+    let mut formats = Vec::new();
+    for tx_bit_order in orders {
+        for rx_bit_order in orders {
+            for tx_alignment in alignments {
+                for rx_alignment in alignments {
+                    for tx_word_order in word_orders {
+                        for rx_word_order in word_orders {
+                            formats.push(FrameFormat {
+                                message_size,
+                                tx_bit_order,
+                                rx_bit_order,
+                                tx_alignment,
+                                rx_alignment,
+                                tx_word_order,
+                                rx_word_order,
+                                mirror_mosi: false,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+    }
+    formats
+}
+
+/// Returns edge patterns and random values, all masked to `bits` bits
+fn frames(bits: usize, rng: &mut Rng) -> Vec<u64> {
+    let mask = u64::MAX >> (64 - bits);
+    let mut frames = vec![0, mask, 0x5555_5555_5555_5555, 0xAAAA_AAAA_AAAA_AAAA];
+    frames.extend((0..bits).map(|bit| 1 << bit));
+    frames.extend((0..bits).map(|bit| !(1 << bit)));
+    frames.extend((0..RANDOM_FRAMES).map(|_| rng.next()));
+    frames.into_iter().map(|frame| frame & mask).collect()
+}
+
+/// Places a right-justified frame at `alignment`
+fn align(format: &FrameFormat, frame: u64, alignment: Alignment) -> u64 {
+    match alignment {
+        Alignment::Right => frame,
+        Alignment::Left => frame << format.padding_bits(),
+    }
+}
+
+/// Returns a frame's bits in the order they cross the wire, derived from the settings
+/// alone: bit order first, then, beyond 32 bits, which 32-bit part goes first
+fn wire_bits(frame: u64, size: usize, bit_order: BitOrder, word_order: WordOrder) -> Vec<bool> {
+    let sequence: Vec<usize> = match bit_order {
+        BitOrder::MsbFirst => (0..size).rev().collect(),
+        BitOrder::LsbFirst => (0..size).collect(),
+    };
+    let sequence = if size > 32 && word_order == WordOrder::LowFirst {
+        // The part holding frame bits [31:0] of the (possibly reversed) sequence first
+        let (high, low) = sequence.split_at(size - 32);
+        [low, high].concat()
+    } else {
+        sequence
+    };
+    sequence.iter().map(|&bit| frame >> bit & 1 != 0).collect()
+}
+
+/// Models the OUT shifts of the frame programs: the bits a program puts on MOSI for the
+/// TX words of one frame
+fn shift_out(format: &FrameFormat, words: [u32; 2], count: usize) -> Vec<bool> {
+    let size = format.message_size;
+    if size <= 32 {
+        assert_eq!(count, 1, "{size}-bit frames take one TX word");
+        let word = words[0];
+        return match format.tx_bit_order {
+            // Left shift: from bit 31 down
+            BitOrder::MsbFirst => (0..size).map(|i| word >> (31 - i) & 1 != 0).collect(),
+            // Right shift: from bit 0 up
+            BitOrder::LsbFirst => (0..size).map(|i| word >> i & 1 != 0).collect(),
+        };
+    }
+    assert_eq!(count, 2, "{size}-bit frames take two TX words");
+    let first = (0..32).map(|i| words[0] >> (31 - i) & 1 != 0);
+    let second = (0..size - 32).map(|i| words[1] >> (31 - i) & 1 != 0);
+    first.chain(second).collect()
+}
+
+/// Models the IN shifts and autopush of the frame programs: the RX words a program pushes
+/// for the MISO bits of one frame
+fn shift_in(format: &FrameFormat, bits: &[bool]) -> [u32; 2] {
+    let size = format.message_size;
+    if size <= 32 {
+        let word = bits
+            .iter()
+            .fold(0u32, |isr, &bit| match format.rx_bit_order {
+                BitOrder::MsbFirst => isr << 1 | bit as u32,
+                BitOrder::LsbFirst => isr >> 1 | (bit as u32) << 31,
+            });
+        return [word, 0];
+    }
+    let word = |bits: &[bool]| bits.iter().fold(0u32, |isr, &bit| isr << 1 | bit as u32);
+    [word(&bits[..32]), word(&bits[32..])]
+}
+
+#[test]
+fn packed_frames_shift_out_in_wire_order() {
+    let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+    for size in SIZES {
+        for format in formats(size) {
+            for frame in frames(size, &mut rng) {
+                let data = align(&format, frame, format.tx_alignment);
+                assert_eq!(
+                    data & !format.tx_mask(),
+                    0,
+                    "{format:?}: frame outside tx_mask"
+                );
+                let (words, count) = format.pack(data);
+                assert!(
+                    words[count..].iter().all(|&word| word == 0),
+                    "{format:?}: unused TX word set"
+                );
+                assert_eq!(
+                    shift_out(&format, words, count),
+                    wire_bits(frame, size, format.tx_bit_order, format.tx_word_order),
+                    "{format:?}: frame {frame:#x}"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn shifted_in_responses_unpack_to_the_frame() {
+    let mut rng = Rng(0xD1B5_4A32_D192_ED03);
+    for size in SIZES {
+        for format in formats(size) {
+            for frame in frames(size, &mut rng) {
+                let bits = wire_bits(frame, size, format.rx_bit_order, format.rx_word_order);
+                let words = shift_in(&format, &bits);
+                let response = format.unpack(words);
+                assert_eq!(
+                    response,
+                    align(&format, frame, format.rx_alignment),
+                    "{format:?}: response {frame:#x} from {words:#x?}"
+                );
+                assert_eq!(
+                    response & !format.rx_mask(),
+                    0,
+                    "{format:?}: response outside rx_mask"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn masks_cover_exactly_the_frame() {
+    for size in SIZES {
+        for format in formats(size) {
+            for (mask, alignment) in [
+                (format.tx_mask(), format.tx_alignment),
+                (format.rx_mask(), format.rx_alignment),
+            ] {
+                assert_eq!(mask.count_ones() as usize, size, "{format:?}");
+                let expected = align(&format, u64::MAX >> (64 - size), alignment);
+                assert_eq!(mask, expected, "{format:?}");
+            }
+            let container = format.words() * 32;
+            assert_eq!(format.padding_bits(), container - size);
+            assert!(
+                matches!((size, format.words()), (1..=32, 1) | (33..=60, 2)),
+                "{size} bits"
+            );
+        }
+    }
+}
+
+#[test]
+fn mirrored_frames_drive_every_bit_twice() {
+    let mut rng = Rng(0x2545_F491_4F6C_DD1D);
+    for size in 1..=16 {
+        for format in formats(size) {
+            let mirrored = FrameFormat {
+                mirror_mosi: true,
+                ..format
+            };
+            for frame in frames(size, &mut rng) {
+                let data = align(&format, frame, format.tx_alignment);
+                let ([word, _], _) = mirrored.pack(data);
+                // `out pins, 2` shifts two bits at a time in the program's direction
+                let pairs: Vec<u32> = match format.tx_bit_order {
+                    BitOrder::MsbFirst => (0..size).map(|i| word >> (30 - 2 * i) & 0b11).collect(),
+                    BitOrder::LsbFirst => (0..size).map(|i| word >> (2 * i) & 0b11).collect(),
+                };
+                assert!(
+                    pairs.iter().all(|&pair| pair == 0b00 || pair == 0b11),
+                    "{format:?}: both pins carry the same bit"
+                );
+                let bits: Vec<bool> = pairs.iter().map(|&pair| pair != 0).collect();
+                assert_eq!(
+                    bits,
+                    wire_bits(frame, size, format.tx_bit_order, format.tx_word_order),
+                    "{format:?}: frame {frame:#x}"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,179 @@
+//! Sharing the bus pins with a hardware SPI block
+//!
+//! Boards that run most of their SPI traffic on the RP2350's SPI0/SPI1 peripheral
+//! sometimes have one device with a frame size or timing the hardware block cannot produce
+//! (e.g. a 20-bit DAC). Rather than wiring it to separate pins, the same CLK, MOSI and MISO
+//! can alternate between the two controllers:
+//! [`hand_to_hardware_spi`](PioSpiMaster::hand_to_hardware_spi) parks the state machine and
+//! switches the pins' function select to the SPI block, and
+//! [`take_from_hardware_spi`](PioSpiMaster::take_from_hardware_spi) switches them back:
+//!
+//! ```ignore
+//! // embassy_rp::spi::Spi on SPI0 built on GPIO 18/19/16, PIO master on the same pins
+//! let spi_block = spi.hand_to_hardware_spi()?;
+//! flash.read(addr, &mut buf)?; // hardware SPI
+//! spi.take_from_hardware_spi();
+//! let level = spi.transfer(DAC_WRITE | code); // odd-sized frame on the PIO
+//! ```
+//!
+//! # Handover
+//!
+//! The pins must be the SPI block's own: CLK on an SCK pin, MOSI on TX and MISO on RX of
+//! the same block (GPIO n with n % 4 = 2, 3 and 0; SPI0 where n / 8 is even, SPI1 where it
+//! is odd). On every switch CLK is first forced to the level the outgoing controller idles
+//! at, the function select is changed underneath, and the override is then dropped, so
+//! the only CLK edge is the single transition between the two idle levels when their
+//! polarities differ, as with [`set_clk_polarity`](PioSpiMaster::set_clk_polarity).
+//!
+//! # Notes
+//! - Chip selects are the application's: deassert every slave before switching. A
+//!   PIO-managed CS and any mirror pins stay with the state machine and hold their idle
+//!   levels while the hardware block owns the bus
+//! - The pad settings (pulls, drive strength, input enable) are shared and kept
+
+use embassy_rp::pac;
+use embassy_rp::pio::Instance;
+
+use crate::claim::pio_index;
+use crate::master::set_clk_inversion;
+use crate::{ClkPolarity, PioSpiMaster};
+
+/// Hardware SPI block sharing the master's pins
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum HardwareSpi {
+    /// `SPI0`, on GPIO 0-7, 16-23 and 32-39
+    Spi0,
+    /// `SPI1`, on GPIO 8-15, 24-31 and 40-47
+    Spi1,
+}
+
+impl HardwareSpi {
+    /// Returns the block's registers
+    fn regs(self) -> pac::spi::Spi {
+        match self {
+            HardwareSpi::Spi0 => pac::SPI0,
+            HardwareSpi::Spi1 => pac::SPI1,
+        }
+    }
+
+    /// Returns the level CLK idles at while the block drives it
+    fn idle_polarity(self) -> ClkPolarity {
+        if self.regs().cr0().read().spo() {
+            ClkPolarity::IdleHigh
+        } else {
+            ClkPolarity::IdleLow
+        }
+    }
+}
+
+/// A bus handover that cannot be made
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum HandoverError {
+    /// CLK, MOSI and MISO are not the SCK, TX and RX pins of one hardware SPI block
+    NotSpiPins,
+    /// The hardware SPI block is not enabled, so its idle levels are undefined
+    SpiDisabled,
+}
+
+/// GPIO function select of the hardware SPI blocks
+const FUNCSEL_SPI: u8 = 1;
+
+/// GPIO function select of PIO0; PIO1 and PIO2 follow
+const FUNCSEL_PIO0: u8 = 6;
+
+impl<PIO: Instance, const SM: usize> PioSpiMaster<'_, PIO, SM> {
+    /// Returns the hardware SPI block whose SCK, TX and RX pins are CLK, MOSI and MISO
+    pub fn hardware_spi(&self) -> Option<HardwareSpi> {
+        let block = |pin: u8, function: u8| {
+            (pin % 4 == function).then_some(if (pin / 8).is_multiple_of(2) {
+                HardwareSpi::Spi0
+            } else {
+                HardwareSpi::Spi1
+            })
+        };
+        let sck = block(self.clk_pin, 2)?;
+        let tx = block(self.mosi_pin, 3)?;
+        let rx = block(self.miso_pin, 0)?;
+        (sck == tx && tx == rx).then_some(sck)
+    }
+
+    /// Hands CLK, MOSI and MISO to the hardware SPI block wired to them
+    ///
+    /// # Returns
+    /// * `Ok(HardwareSpi)` - The block that now drives the bus
+    /// * `Err(HandoverError)` - The pins are not a hardware SPI block's, or the block is
+    ///   not enabled; nothing was changed
+    ///
+    /// # Behavior
+    /// 1. Waits until every queued frame has been shifted out and stops the state machine
+    /// 2. Forces CLK to the hardware block's idle level (its `SPO` setting)
+    /// 3. Switches the three pins' function select to the SPI block, then drops the force
+    ///
+    /// # Notes
+    /// - Read the responses of queued frames first: a full RX FIFO stalls the state
+    ///   machine mid-frame, and this waits forever
+    /// - Configure the hardware block (rate, mode, frame size) before calling this; a
+    ///   polarity change afterwards produces a CLK edge
+    /// - Transfers on this master wait forever until
+    ///   [`take_from_hardware_spi`](Self::take_from_hardware_spi)
+    pub fn hand_to_hardware_spi(&mut self) -> Result<HardwareSpi, HandoverError> {
+        let spi = self.hardware_spi().ok_or(HandoverError::NotSpiPins)?;
+        if !spi.regs().cr1().read().sse() {
+            return Err(HandoverError::SpiDisabled);
+        }
+        self.wait_idle();
+        self.sm.set_enable(false);
+        self.switch_pins(spi.idle_polarity(), FUNCSEL_SPI);
+        set_normal_output(self.clk_pin);
+        Ok(spi)
+    }
+
+    /// Takes CLK, MOSI and MISO back from the hardware SPI block and restarts the master
+    ///
+    /// # Behavior
+    /// 1. Waits until the hardware block has finished its last frame
+    /// 2. Forces CLK to the level it idles at, switches the pins' function select back to
+    ///    the PIO and restores this master's CLK polarity
+    /// 3. Resets the state machine to the program start with empty FIFOs, as
+    ///    [`resync`](Self::resync) does, and starts it
+    ///
+    /// # Notes
+    /// - Does nothing to the pins if they are not a hardware SPI block's; the state
+    ///   machine is still restarted
+    pub fn take_from_hardware_spi(&mut self) {
+        if let Some(spi) = self.hardware_spi() {
+            while spi.regs().sr().read().bsy() {}
+            let funcsel = FUNCSEL_PIO0 + pio_index::<PIO>() as u8;
+            self.switch_pins(spi.idle_polarity(), funcsel);
+            set_clk_inversion(self.clk_pin, self.clk_polarity);
+        }
+        self.reset_frames(true);
+    }
+
+    /// Forces CLK to the idle level of `polarity`, then moves CLK, MOSI and MISO to
+    /// `funcsel`, leaving CLK forced
+    fn switch_pins(&self, polarity: ClkPolarity, funcsel: u8) {
+        let outover = match polarity {
+            ClkPolarity::IdleHigh => pac::io::vals::Outover::HIGH,
+            ClkPolarity::IdleLow => pac::io::vals::Outover::LOW,
+        };
+        pac::IO_BANK0
+            .gpio(self.clk_pin as usize)
+            .ctrl()
+            .modify(|w| w.set_outover(outover));
+        for pin in [self.clk_pin, self.mosi_pin, self.miso_pin] {
+            pac::IO_BANK0
+                .gpio(pin as usize)
+                .ctrl()
+                .modify(|w| w.set_funcsel(funcsel));
+        }
+    }
+}
+
+/// Drops the output override of `pin`, so its function drives it unchanged
+fn set_normal_output(pin: u8) {
+    pac::IO_BANK0
+        .gpio(pin as usize)
+        .ctrl()
+        .modify(|w| w.set_outover(pac::io::vals::Outover::NORMAL));
+}
@@ -0,0 +1,103 @@
+//! Display and radio init tables
+//!
+//! Most display controllers (ST7789, ILI9341, SSD1306) and many radios are brought up by
+//! a fixed table of commands, parameter bytes and pauses copied from the datasheet.
+//! [`PioSpiBus::run_init_sequence`] executes such a table with the data/command (D/C)
+//! and chip select lines handled for every entry.
+//!
+//! ```ignore
+//! const ST7789_INIT: &[InitOp] = &[
+//!     InitOp::Cmd(0x01),             // SWRESET
+//!     InitOp::DelayMs(150),
+//!     InitOp::Cmd(0x11),             // SLPOUT
+//!     InitOp::DelayMs(10),
+//!     InitOp::Cmd(0x3A),             // COLMOD
+//!     InitOp::Data(&[0x55]),         // 16 bits per pixel
+//!     InitOp::Cmd(0x29),             // DISPON
+//! ];
+//! bus.run_init_sequence(&mut cs, &mut dc, ST7789_INIT).await;
+//! ```
+
+use embassy_rp::gpio::Output;
+use embassy_rp::pio::Instance;
+use embassy_time::Timer;
+
+use crate::transaction::{Phase, PioSpiBus};
+
+/// One entry of an init table
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum InitOp<'a> {
+    /// Command byte, sent with D/C LOW in a new chip-select window
+    Cmd(u8),
+    /// Parameter bytes of the preceding command, sent with D/C HIGH in its window
+    Data(&'a [u8]),
+    /// Pause in milliseconds with chip select deasserted
+    DelayMs(u16),
+}
+
+impl<PIO: Instance, const SM: usize> PioSpiBus<'_, PIO, SM> {
+    /// Executes an init table
+    ///
+    /// # Arguments
+    /// * `cs` - Active-low chip select output
+    /// * `dc` - Data/command output (LOW = command, HIGH = data)
+    /// * `ops` - Table entries, executed in order
+    ///
+    /// # Behavior
+    /// - Each [`InitOp::Cmd`] closes the previous chip-select window and opens a new one,
+    ///   so a command and its [`InitOp::Data`] entries share one window
+    /// - D/C only changes once the preceding bytes have left the bus
+    /// - [`InitOp::DelayMs`] closes the window before waiting
+    /// - Chip select is deasserted when the table ends
+    /// - Each chip-select window is one transaction: the `before`
+    ///   [hook](crate::transaction::TransactionHooks) runs before chip select is asserted
+    ///   and the `after` hook once it is deasserted
+    pub async fn run_init_sequence(
+        &mut self,
+        cs: &mut Output<'_>,
+        dc: &mut Output<'_>,
+        ops: &[InitOp<'_>],
+    ) {
+        let mut open = false;
+        for op in ops {
+            match *op {
+                InitOp::Cmd(command) => {
+                    self.close_window(cs, &mut open);
+                    dc.set_low();
+                    self.open_window(cs, &mut open);
+                    self.run_phases_async(&mut [Phase::Write(&[command])]).await;
+                }
+                InitOp::Data(data) => {
+                    self.wait_idle();
+                    dc.set_high();
+                    self.open_window(cs, &mut open);
+                    self.run_phases_async(&mut [Phase::Write(data)]).await;
+                }
+                InitOp::DelayMs(ms) => {
+                    self.close_window(cs, &mut open);
+                    Timer::after_millis(ms as u64).await;
+                }
+            }
+        }
+        self.close_window(cs, &mut open);
+    }
+
+    /// Asserts chip select, starting a transaction unless the window is already open
+    fn open_window(&mut self, cs: &mut Output<'_>, open: &mut bool) {
+        if !*open {
+            self.begin_async();
+            *open = true;
+        }
+        cs.set_low();
+    }
+
+    /// Deasserts chip select once the bytes have left the bus, then ends the open window's
+    /// transaction
+    fn close_window(&mut self, cs: &mut Output<'_>, open: &mut bool) {
+        self.wait_idle();
+        cs.set_high();
+        if core::mem::take(open) {
+            self.end();
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! Interrupt-driven transfers without an async executor
+//!
+//! embassy-rp's PIO driver owns each block's `PIOx_IRQ_0` line for its futures. This module
+//! routes a state machine's FIFO conditions to the otherwise unused `PIOx_IRQ_1` line instead,
+//! so frameworks such as RTIC can bind a hardware task to it and drive transfers from the
+//! interrupt handler.
+//!
+//! ```ignore
+//! // init
+//! spi.listen(FifoInterrupt::RxNotEmpty);
+//! spi.try_start(frame);
+//!
+//! // #[task(binds = PIO0_IRQ_1, shared = [spi])]
+//! if let Some(response) = spi.on_interrupt() {
+//!     // handle response, optionally `try_start` the next frame
+//! }
+//! ```
+//!
+//! # Notes
+//! - Both conditions are level-triggered: the interrupt stays pending while the RX FIFO
+//!   holds data or the TX FIFO has room, so the handler must drain the FIFO or `unlisten`
+//! - The `PIOx_IRQ_1` interrupt must be unmasked in the NVIC (RTIC does this for bound tasks)
+
+use embassy_rp::interrupt::typelevel::Interrupt;
+use embassy_rp::pac;
+use embassy_rp::pio::Instance;
+
+use crate::PioSpiMaster;
+
+/// Depth of each (unjoined) state machine FIFO in words
+pub(crate) const FIFO_DEPTH: u8 = 4;
+
+/// State machine FIFO condition that can raise `PIOx_IRQ_1`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum FifoInterrupt {
+    /// TX FIFO has room for at least one word
+    TxNotFull,
+    /// RX FIFO holds at least one word
+    RxNotEmpty,
+}
+
+impl FifoInterrupt {
+    /// Returns the INTE/INTS bit of this condition for state machine `sm`
+    pub(crate) fn mask(self, sm: usize) -> u32 {
+        match self {
+            FifoInterrupt::RxNotEmpty => 1 << sm,
+            FifoInterrupt::TxNotFull => 1 << (4 + sm),
+        }
+    }
+}
+
+/// Returns the register block of `PIO`
+///
+/// The crate-facing `Instance` trait hides the register block, so it is recovered from the
+/// block's interrupt number.
+pub(crate) fn pio_regs<PIO: Instance>() -> pac::pio::Pio {
+    match <PIO::Interrupt as Interrupt>::IRQ {
+        pac::Interrupt::PIO0_IRQ_0 => pac::PIO0,
+        pac::Interrupt::PIO1_IRQ_0 => pac::PIO1,
+        _ => pac::PIO2,
+    }
+}
+
+impl<PIO: Instance, const SM: usize> PioSpiMaster<'_, PIO, SM> {
+    /// Enables `PIOx_IRQ_1` for a FIFO condition of this state machine
+    pub fn listen(&mut self, irq: FifoInterrupt) {
+        // INTE is shared by all state machines of the block, so update it atomically
+        critical_section::with(|_| {
+            pio_regs::<PIO>()
+                .irqs(1)
+                .inte()
+                .modify(|m| m.0 |= irq.mask(SM));
+        });
+    }
+
+    /// Disables `PIOx_IRQ_1` for a FIFO condition of this state machine
+    pub fn unlisten(&mut self, irq: FifoInterrupt) {
+        critical_section::with(|_| {
+            pio_regs::<PIO>()
+                .irqs(1)
+                .inte()
+                .modify(|m| m.0 &= !irq.mask(SM));
+        });
+    }
+
+    /// Returns `true` if `irq` is enabled and its condition is currently asserted
+    pub fn is_pending(&self, irq: FifoInterrupt) -> bool {
+        pio_regs::<PIO>().irqs(1).ints().read().0 & irq.mask(SM) != 0
+    }
+
+    /// Queues a frame if the TX FIFO has room for all of its words, without waiting
+    ///
+    /// # Arguments
+    /// * `data` - Data to shift out on MOSI (only bits [message_size-1:0] are used)
+    ///
+    /// # Returns
+    /// * `bool` - `true` if the frame was queued, `false` if the TX FIFO is too full or
+    ///   [`RxOverflowPolicy::Error`](crate::RxOverflowPolicy::Error) rejected it
+    pub fn try_start(&mut self, data: u64) -> bool {
+        let words = self.message_size.div_ceil(32) as u8;
+        if FIFO_DEPTH - self.sm.tx().level() < words || !self.admit_frame() {
+            return false;
+        }
+        self.push_frame(data);
+        true
+    }
+
+    /// Interrupt handler body: collects a completed response if one is available
+    ///
+    /// # Returns
+    /// * `Some(u64)` - Response of the oldest frame started with [`try_start`](Self::try_start)
+    /// * `None` - No complete response in the RX FIFO yet
+    ///
+    /// # Notes
+    /// - Never blocks, so it is safe to call from any interrupt priority
+    /// - Call it repeatedly (or loop until `None`) to drain several responses
+    pub fn on_interrupt(&mut self) -> Option<u64> {
+        let words = self.message_size.div_ceil(32) as u8;
+        if self.sm.rx().level() < words {
+            return None;
+        }
+        Some(self.pull_frame())
+    }
+}
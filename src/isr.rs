@@ -0,0 +1,185 @@
+//! Frames pushed from interrupt handlers
+//!
+//! Some frames are due at an event rather than when a task gets to run, e.g. a DAC update
+//! on every encoder tick. [`PioSpiIsrHandle`] lets an interrupt handler queue such frames
+//! on a state machine whose [`PioSpiMaster`] stays with a task for configuration and its
+//! own transfers.
+//!
+//! ```ignore
+//! static DAC: Mutex<Cell<Option<PioSpiIsrHandle<PIO0, 0>>>> = ...;
+//!
+//! // task
+//! let handle = spi.isr_handle();
+//! critical_section::with(|cs| DAC.borrow(cs).set(Some(handle)));
+//!
+//! // encoder interrupt
+//! if let Some(dac) = critical_section::with(|cs| DAC.borrow(cs).get()) {
+//!     dac.try_write(DAC_WRITE | position);
+//! }
+//! ```
+//!
+//! # Arbitration
+//!
+//! Both sides push into the same FIFOs, so each access runs in a critical section and
+//! the FIFOs belong to one side at a time:
+//! - While the task has frames in flight (from their push until their response is
+//!   pulled), the handle refuses frames. Responses of [`write`](PioSpiMaster::write)
+//!   frames count until [`discard_stale`](PioSpiMaster::discard_stale) or
+//!   [`drain_responses`](PioSpiMaster::drain_responses) collects them
+//! - Handle frames are write-only: the handle discards their responses on its next call,
+//!   and the task discards any still outstanding before its next frame
+//! - Resetting the state machine (a cancelled async transfer, `resync`, switching duplex
+//!   or polarity) drops handle frames that have not reached the wire yet
+
+use core::cell::Cell;
+use core::marker::PhantomData;
+
+use critical_section::Mutex;
+use embassy_rp::pio::Instance;
+
+use crate::claim::pio_index;
+use crate::frame::FrameFormat;
+use crate::irq::{pio_regs, FIFO_DEPTH};
+use crate::PioSpiMaster;
+
+/// FIFO ownership of one state machine
+#[derive(Clone, Copy)]
+struct Slot {
+    /// A handle was issued and the master still exists
+    shared: bool,
+    /// The task has frames in flight; the handle must not touch the FIFOs
+    task_busy: bool,
+    /// RX words the handle's frames produced or will produce that nobody pulled yet
+    rx_words: u8,
+}
+
+impl Slot {
+    const IDLE: Slot = Slot {
+        shared: false,
+        task_busy: false,
+        rx_words: 0,
+    };
+}
+
+/// FIFO ownership of each state machine of each PIO block
+static SLOTS: Mutex<Cell<[[Slot; 4]; 3]>> = Mutex::new(Cell::new([[Slot::IDLE; 4]; 3]));
+
+/// Runs `f` on the slot of state machine `SM` of `PIO` inside a critical section
+fn with_slot<PIO: Instance, const SM: usize, R>(f: impl FnOnce(&mut Slot) -> R) -> R {
+    critical_section::with(|cs| {
+        let slots = SLOTS.borrow(cs);
+        let mut all = slots.get();
+        let result = f(&mut all[pio_index::<PIO>()][SM]);
+        slots.set(all);
+        result
+    })
+}
+
+/// Takes the FIFOs for the task
+///
+/// # Returns
+/// * `usize` - RX words of handle frames still to come, which the task must discard before
+///   the response of its own next frame
+pub(crate) fn claim<PIO: Instance, const SM: usize>() -> usize {
+    with_slot::<PIO, SM, _>(|slot| {
+        slot.task_busy = true;
+        core::mem::take(&mut slot.rx_words) as usize
+    })
+}
+
+/// Hands the FIFOs back to the handle
+pub(crate) fn release<PIO: Instance, const SM: usize>() {
+    with_slot::<PIO, SM, _>(|slot| slot.task_busy = false);
+}
+
+/// Invalidates the handles of a master being freed
+pub(crate) fn retire<PIO: Instance, const SM: usize>() {
+    with_slot::<PIO, SM, _>(|slot| *slot = Slot::IDLE);
+}
+
+/// Handle for queuing write-only frames from interrupt handlers
+///
+/// Obtained from [`PioSpiMaster::isr_handle`]; it is `Copy` and can be stored in a
+/// `static` for the handler. See the [module docs](self) for how it shares the FIFOs with
+/// the task.
+#[derive(Clone, Copy)]
+pub struct PioSpiIsrHandle<PIO: Instance, const SM: usize> {
+    /// Frame format of the master, as it was when the handle was issued
+    format: FrameFormat,
+    _pio: PhantomData<PIO>,
+}
+
+impl<PIO: Instance, const SM: usize> PioSpiIsrHandle<PIO, SM> {
+    /// Queues a frame if the FIFOs are free and have room for it, without waiting
+    ///
+    /// # Arguments
+    /// * `data` - Frame in the master's format (size, alignment, bit and word order); bits
+    ///   outside the frame are ignored
+    ///
+    /// # Returns
+    /// * `bool` - `true` if the frame was queued; `false` if the task has frames in
+    ///   flight, the TX FIFO is too full or the master was freed
+    ///
+    /// # Notes
+    /// - Never blocks, so it is safe to call from any interrupt priority
+    /// - Responses of earlier handle frames that have completed are discarded first, so a
+    ///   steady stream of handle frames cannot stall the state machine on a full RX FIFO
+    pub fn try_write(&self, data: u64) -> bool {
+        let (words, count) = self.format.pack(data & self.format.tx_mask());
+        with_slot::<PIO, SM, _>(|slot| {
+            if !slot.shared || slot.task_busy {
+                return false;
+            }
+            let regs = pio_regs::<PIO>();
+            // The task is idle, so every RX word belongs to a handle frame
+            while regs.fstat().read().rxempty() & (1 << SM) == 0 {
+                let _ = regs.rxf(SM).read();
+                slot.rx_words = slot.rx_words.saturating_sub(1);
+            }
+            let level = (regs.flevel().read().0 >> (8 * SM)) as u8 & 0x0F;
+            if FIFO_DEPTH - level < count as u8 {
+                return false;
+            }
+            for &word in &words[..count] {
+                regs.txf(SM).write_value(word);
+            }
+            slot.rx_words += count as u8;
+            true
+        })
+    }
+
+    /// Returns the frame size in bits
+    pub fn message_size(&self) -> usize {
+        self.format.message_size
+    }
+}
+
+impl<PIO: Instance, const SM: usize> PioSpiMaster<'_, PIO, SM> {
+    /// Returns a handle for queuing frames from interrupt handlers
+    ///
+    /// # Returns
+    /// * `PioSpiIsrHandle` - Handle sharing this state machine's FIFOs with the master
+    ///   until [`free`](Self::free)
+    ///
+    /// # Notes
+    /// - From now on every frame of the master takes the FIFOs in a critical section first
+    /// - Not available together with [`transfer_bytes`](Self::transfer_bytes), whose packed
+    ///   FIFO words handle frames would corrupt
+    pub fn isr_handle(&mut self) -> PioSpiIsrHandle<PIO, SM> {
+        if !self.isr_shared {
+            self.isr_shared = true;
+            let busy = self.in_flight > 0 || self.interrupted;
+            with_slot::<PIO, SM, _>(|slot| {
+                *slot = Slot {
+                    shared: true,
+                    task_busy: busy,
+                    rx_words: 0,
+                }
+            });
+        }
+        PioSpiIsrHandle {
+            format: self.format,
+            _pio: PhantomData,
+        }
+    }
+}
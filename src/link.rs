@@ -0,0 +1,297 @@
+//! Reliable frame link between two MCUs
+//!
+//! [`Link`] runs a small stop-and-wait protocol over a [`PioSpiBus`], a chip-select output
+//! and a slave-ready input, so two boards (e.g. a pair of RP2350s) can exchange messages
+//! with length, sequence numbers, CRC and acknowledgements without writing a protocol.
+//!
+//! # Frames
+//!
+//! Every frame is sent in its own chip-select window:
+//!
+//! ```text
+//! [kind] [seq] [len] [payload: len bytes] [CRC-16 high] [CRC-16 low]
+//! ```
+//!
+//! The CRC is CRC-16/CCITT-FALSE over kind, seq, len and payload. Kinds:
+//! - `DATA` (0x01): Message payload
+//! - `POLL` (0x05): Master asks the slave for a message
+//! - `ACK` (0x06) / `NAK` (0x15): Receipt of the `DATA` frame with sequence number `seq`
+//! - `NONE` (0x00): Slave has nothing to send in response to a `POLL`
+//!
+//! # Handshake
+//!
+//! The slave drives the ready line HIGH when it is armed for the next chip-select window
+//! and must drive it LOW before chip select is deasserted (e.g. from its CS falling-edge
+//! interrupt), keeping it LOW until it has processed the frame and is armed again. The
+//! master waits for ready before every window.
+//!
+//! - **Send**: master writes `DATA`, then reads the slave's `ACK`/`NAK` frame; it resends
+//!   the same sequence number on `NAK`, CRC error or mismatch
+//! - **Receive**: master writes `POLL`, then reads a `DATA` or `NONE` frame and answers a
+//!   `DATA` frame with `ACK` or `NAK`; a repeated sequence number (lost `ACK`) is
+//!   acknowledged again but not delivered twice
+//!
+//! ```ignore
+//! let mut link = Link::new(bus, cs, ready, LinkConfig::default());
+//! link.send(b"hello").await?;
+//! let len = link.receive(&mut buf).await?;
+//! ```
+//!
+//! # Notes
+//! - A frame is only judged once its CRC has been checked, so a corrupted length byte is
+//!   retried rather than taken for an oversized message
+//! - Dropping a `send` or `receive` future mid-window still deasserts chip select; the
+//!   slave sees a truncated frame, which its CRC check rejects
+
+use embassy_rp::gpio::{Input, Output};
+use embassy_rp::pio::Instance;
+use embassy_time::{with_timeout, Duration};
+
+use crate::transaction::{Phase, PioSpiBus};
+
+/// Frame kind of a message
+const KIND_DATA: u8 = 0x01;
+/// Frame kind of a master request for a message
+const KIND_POLL: u8 = 0x05;
+/// Frame kind acknowledging a `DATA` frame
+const KIND_ACK: u8 = 0x06;
+/// Frame kind rejecting a corrupted `DATA` frame
+const KIND_NAK: u8 = 0x15;
+/// Frame kind of an empty answer to a `POLL`
+const KIND_NONE: u8 = 0x00;
+
+/// Largest payload of one frame in bytes
+pub const MAX_PAYLOAD: usize = 255;
+
+/// Link settings
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct LinkConfig {
+    /// Resends after the first attempt before giving up
+    pub retries: u8,
+    /// Longest wait for the slave-ready line
+    pub ready_timeout: Duration,
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        Self {
+            retries: 3,
+            ready_timeout: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Link failure
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum LinkError {
+    /// The slave did not signal ready within [`LinkConfig::ready_timeout`]
+    Timeout,
+    /// No valid acknowledgement after all retries
+    NoAck,
+    /// No valid frame received after all retries
+    Corrupted,
+    /// The message does not fit (payload over [`MAX_PAYLOAD`] or larger than the buffer)
+    TooLong(usize),
+}
+
+/// Master side of the link
+pub struct Link<'d, PIO: Instance, const SM: usize> {
+    bus: PioSpiBus<'d, PIO, SM>,
+    cs: Output<'d>,
+    ready: Input<'d>,
+    config: LinkConfig,
+    /// Sequence number of the next message sent
+    tx_seq: u8,
+    /// Sequence number of the last message delivered by `receive`
+    rx_seq: Option<u8>,
+}
+
+impl<'d, PIO: Instance, const SM: usize> Link<'d, PIO, SM> {
+    /// Creates a link over `bus`
+    ///
+    /// # Arguments
+    /// * `bus` - Phase bus wired to the slave (takes ownership)
+    /// * `cs` - Active-low chip select output; driven HIGH here
+    /// * `ready` - Slave-ready input, HIGH when the slave is armed
+    /// * `config` - Retry and timeout settings
+    pub fn new(
+        bus: PioSpiBus<'d, PIO, SM>,
+        mut cs: Output<'d>,
+        ready: Input<'d>,
+        config: LinkConfig,
+    ) -> Self {
+        cs.set_high();
+        Self {
+            bus,
+            cs,
+            ready,
+            config,
+            tx_seq: 0,
+            rx_seq: None,
+        }
+    }
+
+    /// Sends a message and waits for the slave to acknowledge it
+    ///
+    /// # Arguments
+    /// * `payload` - Message bytes (up to [`MAX_PAYLOAD`])
+    ///
+    /// # Returns
+    /// * `Ok(())` - The slave acknowledged the message
+    /// * `Err(LinkError)` - Timeout, no valid acknowledgement after all retries, or the
+    ///   payload is too long
+    pub async fn send(&mut self, payload: &[u8]) -> Result<(), LinkError> {
+        if payload.len() > MAX_PAYLOAD {
+            return Err(LinkError::TooLong(payload.len()));
+        }
+
+        let seq = self.tx_seq;
+        for _ in 0..=self.config.retries {
+            self.write_frame(KIND_DATA, seq, payload).await?;
+
+            let mut reply = [0u8; 5];
+            self.wait_ready().await?;
+            let cs = Selected::new(&mut self.cs);
+            self.bus
+                .transaction_async(&mut [Phase::Read(&mut reply)])
+                .await;
+            drop(cs);
+
+            let [kind, reply_seq, len, crc_hi, crc_lo] = reply;
+            let valid = len == 0 && crc16(&reply[..3]) == u16::from_be_bytes([crc_hi, crc_lo]);
+            if valid && kind == KIND_ACK && reply_seq == seq {
+                self.tx_seq = seq.wrapping_add(1);
+                return Ok(());
+            }
+        }
+        Err(LinkError::NoAck)
+    }
+
+    /// Asks the slave for a message
+    ///
+    /// # Arguments
+    /// * `buf` - Destination for the message payload
+    ///
+    /// # Returns
+    /// * `Ok(usize)` - Payload length (0 if the slave had nothing to send)
+    /// * `Err(LinkError)` - Timeout, no valid frame after all retries, or the message is
+    ///   larger than `buf` (it is rejected with `NAK` and stays queued on the slave)
+    pub async fn receive(&mut self, buf: &mut [u8]) -> Result<usize, LinkError> {
+        for _ in 0..=self.config.retries {
+            self.write_frame(KIND_POLL, 0, &[]).await?;
+
+            let mut header = [0u8; 3];
+            let mut body = [0u8; MAX_PAYLOAD + 2];
+            self.wait_ready().await?;
+            let cs = Selected::new(&mut self.cs);
+            // Payload and CRC are read in full before the length is judged, so a corrupted
+            // length byte shows up as a CRC error
+            let read = self
+                .bus
+                .read_prefixed(
+                    &mut [],
+                    &mut header,
+                    |header| header[2] as usize + 2,
+                    &mut body,
+                    &mut [],
+                )
+                .await;
+            drop(cs);
+            let [kind, seq, len] = header;
+            let len = len as usize;
+            let (payload, crc) = body[..read].split_at(len);
+
+            let expected = crc16_update(crc16(&header), payload);
+            if expected != u16::from_be_bytes([crc[0], crc[1]]) {
+                self.write_frame(KIND_NAK, seq, &[]).await?;
+                continue;
+            }
+            if len > buf.len() {
+                self.write_frame(KIND_NAK, seq, &[]).await?;
+                return Err(LinkError::TooLong(len));
+            }
+            buf[..len].copy_from_slice(payload);
+            match kind {
+                KIND_NONE => return Ok(0),
+                KIND_DATA => {
+                    self.write_frame(KIND_ACK, seq, &[]).await?;
+                    if self.rx_seq == Some(seq) {
+                        // Our previous ACK was lost and the slave resent the message
+                        continue;
+                    }
+                    self.rx_seq = Some(seq);
+                    return Ok(len);
+                }
+                _ => continue,
+            }
+        }
+        Err(LinkError::Corrupted)
+    }
+
+    /// Releases the bus and pins
+    pub fn release(self) -> (PioSpiBus<'d, PIO, SM>, Output<'d>, Input<'d>) {
+        (self.bus, self.cs, self.ready)
+    }
+
+    /// Writes one frame in its own chip-select window
+    async fn write_frame(&mut self, kind: u8, seq: u8, payload: &[u8]) -> Result<(), LinkError> {
+        let header = [kind, seq, payload.len() as u8];
+        let crc = crc16_update(crc16(&header), payload).to_be_bytes();
+
+        self.wait_ready().await?;
+        let cs = Selected::new(&mut self.cs);
+        self.bus
+            .transaction_async(&mut [
+                Phase::Write(&header),
+                Phase::Write(payload),
+                Phase::Write(&crc),
+            ])
+            .await;
+        drop(cs);
+        Ok(())
+    }
+
+    /// Waits for the slave-ready line
+    async fn wait_ready(&mut self) -> Result<(), LinkError> {
+        with_timeout(self.config.ready_timeout, self.ready.wait_for_high())
+            .await
+            .map_err(|_| LinkError::Timeout)
+    }
+}
+
+/// Chip select held LOW until dropped, so a cancelled window still deasserts it
+struct Selected<'a, 'd>(&'a mut Output<'d>);
+
+impl<'a, 'd> Selected<'a, 'd> {
+    /// Asserts chip select
+    fn new(cs: &'a mut Output<'d>) -> Self {
+        cs.set_low();
+        Self(cs)
+    }
+}
+
+impl Drop for Selected<'_, '_> {
+    fn drop(&mut self) {
+        self.0.set_high();
+    }
+}
+
+/// Returns the CRC-16/CCITT-FALSE of `data`
+fn crc16(data: &[u8]) -> u16 {
+    crc16_update(0xFFFF, data)
+}
+
+/// Continues a CRC-16/CCITT-FALSE (polynomial 0x1021) over more data
+fn crc16_update(mut crc: u16, data: &[u8]) -> u16 {
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
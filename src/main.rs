@@ -1,94 +1,628 @@
+//! Hardware-in-the-loop test suite
+//!
+//! Flash this binary to validate the crate on a real board; progress and results are
+//! reported over defmt/RTT. A second state machine on the same PIO block acts as the SPI
+//! slave: it records every frame it receives and answers with a frame queued by the test,
+//! so both directions are checked bit for bit.
+//!
+//! # Wiring
+//! - GPIO 2: CLK, GPIO 3: MOSI, GPIO 4: MISO, GPIO 6: CS (may be left unconnected)
+//! - Jumper GPIO 5 (test slave output) to GPIO 4 (MISO)
+//!
+//! # Suites
+//! - `loopback`: Every frame size variant, mode and several speeds, plus a speed sweep
+//! - `stress`: Thousands of random frames through the blocking, async and checked paths
+//! - `recovery`: Cancelled transfers and stalled state machines followed by clean frames
+//!
+//! All suites run by default. Set `PIO_SPI_HIL` at build time to a comma-separated list
+//! of suite names to run only those:
+//!
+//! ```text
+//! PIO_SPI_HIL=loopback,recovery cargo run --release
+//! ```
+
 #![no_std]
 #![no_main]
 
-use defmt::info;
+use defmt::{error, info};
 use embassy_executor::Spawner;
 use embassy_rp::bind_interrupts;
 use embassy_rp::peripherals::PIO0;
-use embassy_rp::pio::Pio;
-use embassy_time::Timer;
-use pio_spi::{PioSpiMaster, SpiMasterConfig};
+use embassy_rp::pio::{
+    Common, Config, Direction, LoadedProgram, Pin, Pio, ShiftDirection, StateMachine,
+};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use fixed::traits::ToFixed;
+use pio::pio_asm;
+use pio_spi::{PioSpiMaster, SpiMasterConfig, WordOrder};
 use {defmt_rtt as _, panic_probe as _};
 
 bind_interrupts!(struct Irqs {
     PIO0_IRQ_0 => embassy_rp::pio::InterruptHandler<PIO0>;
 });
 
-#[embassy_executor::main]
-async fn main(_spawner: Spawner) {
-    info!("PIO SPI Example Starting");
+/// Suites to run, from the `PIO_SPI_HIL` build-time variable (all when unset)
+const SELECTED: Option<&str> = option_env!("PIO_SPI_HIL");
 
-    let p = embassy_rp::init(Default::default());
+/// Frame sizes of the loopback matrix: both program variants and the edges between them
+const SIZES: [usize; 9] = [1, 8, 16, 24, 31, 32, 33, 50, 63];
 
-    // Initialize PIO with pins
-    let Pio {
-        mut common,
-        sm0,
-        sm1,
-        sm2,
-        ..
-    } = Pio::new(p.PIO0, Irqs);
+/// Dividers of the loopback matrix
+///
+/// The test slave answers a falling CLK edge about 6 system clocks later, so it cannot
+/// keep up below a divider of roughly 8; faster rates are only probed by the speed sweep.
+const CLK_DIVS: [u16; 3] = [250, 32, 16];
 
-    // Create PIO pins from GPIO pins
-    let clk_pin = common.make_pio_pin(p.PIN_2);
-    let mosi_pin = common.make_pio_pin(p.PIN_3);
-    let miso_pin = common.make_pio_pin(p.PIN_4);
+/// Divider of the stress and recovery suites
+const TEST_CLK_DIV: u16 = 16;
 
-    // Demo 1: 16-bit transfer
-    {
-        info!("=== 16-bit Transfer Demo ===");
-        let config = SpiMasterConfig {
-            clk_div: 8,
-            message_size: 16,
+/// Slowest divider, used to keep a frame in flight long enough to cancel it
+const SLOW_CLK_DIV: u16 = u16::MAX;
+
+/// Random frames per size and path in the stress suite
+const STRESS_FRAMES: u32 = 5000;
+
+/// Master configurations covered by the loopback matrix
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+enum Mode {
+    /// Plain frames, high word first
+    HighFirst,
+    /// Plain frames, low word first (only differs from `HighFirst` above 32 bits)
+    LowFirst,
+    /// PIO-managed chip select
+    Cs,
+}
+
+const MODES: [Mode; 3] = [Mode::HighFirst, Mode::LowFirst, Mode::Cs];
+
+/// Returns `true` if `suite` was selected at build time
+fn selected(suite: &str) -> bool {
+    SELECTED.is_none_or(|list| list.split(',').any(|name| name.trim() == suite))
+}
+
+/// Returns the mask of the low `size` bits
+fn mask(size: usize) -> u64 {
+    u64::MAX >> (64 - size)
+}
+
+/// Xorshift generator for reproducible random frames
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+/// PIO-based SPI slave answering the master under test
+///
+/// Mirrors the frame program: it samples `message_size` bits from MOSI on the rising CLK
+/// edges, then drives `message_size` bits on its output after each falling edge. Frames
+/// travel MSB first in both directions, in the same FIFO word layout as the master.
+struct TestSlave<'d> {
+    sm: StateMachine<'d, PIO0, 1>,
+    program: LoadedProgram<'d, PIO0>,
+    message_size: usize,
+}
+
+impl<'d> TestSlave<'d> {
+    /// Loads the slave program and starts it
+    ///
+    /// # Arguments
+    /// * `common` - The PIO peripheral's common interface
+    /// * `sm` - State machine to run the slave on
+    /// * `mosi_pin` - The master's MOSI pin, sampled by the slave
+    /// * `out_pin` - Slave output, jumpered to the master's MISO pin
+    /// * `message_size` - Frame size in bits (1-63)
+    fn new(
+        common: &mut Common<'d, PIO0>,
+        sm: StateMachine<'d, PIO0, 1>,
+        mosi_pin: &Pin<'d, PIO0>,
+        out_pin: &Pin<'d, PIO0>,
+        message_size: usize,
+    ) -> Self {
+        // CLK is GPIO 2 (see the wiring notes above)
+        let program = if message_size <= 32 {
+            pio_asm!(
+                "pull block", // Load loop count (message_size - 1)
+                "out y, 32",  // Y = loop count; leaves the OSR empty
+                ".wrap_target",
+                "mov x, y",
+                "receive:",
+                "  wait 0 gpio 2",
+                "  wait 1 gpio 2", // Master's rising edge: MOSI is stable
+                "  in pins, 1",    // Last bit auto-pushes the received frame
+                "  jmp x--, receive",
+                "mov x, y",
+                "answer:",
+                "  wait 0 gpio 2",
+                "  out pins, 1", // Drive the next bit while CLK is LOW
+                "  wait 1 gpio 2",
+                "  jmp x--, answer",
+                ".wrap",
+            )
+            .program
+        } else {
+            pio_asm!(
+                "pull block", // Load loop count (message_size - 1)
+                "out y, 32",  // Y = loop count; leaves the OSR empty
+                ".wrap_target",
+                "mov x, y",
+                "receive:",
+                "  wait 0 gpio 2",
+                "  wait 1 gpio 2", // Master's rising edge: MOSI is stable
+                "  in pins, 1",    // First 32 bits auto-push
+                "  jmp x--, receive",
+                "push block", // Push the (message_size - 32) remaining bits
+                "mov x, y",
+                "answer:",
+                "  wait 0 gpio 2",
+                "  out pins, 1", // Drive the next bit while CLK is LOW
+                "  wait 1 gpio 2",
+                "  jmp x--, answer",
+                "out null, 32", // Discard unused OSR bits before the next frame
+                ".wrap",
+            )
+            .program
         };
+        let program = common.load_program(&program);
 
-        let mut spi =
-            PioSpiMaster::<PIO0, 0>::new(&mut common, sm0, &clk_pin, &mosi_pin, &miso_pin, config);
+        let mut cfg = Config::default();
+        cfg.use_program(&program, &[]);
+        cfg.set_in_pins(&[mosi_pin]);
+        cfg.set_out_pins(&[out_pin]);
+        cfg.clock_divider = 1u8.to_fixed();
+        let threshold = message_size.min(32) as u8;
+        cfg.shift_out.auto_fill = true;
+        cfg.shift_out.threshold = threshold;
+        cfg.shift_out.direction = ShiftDirection::Left;
+        cfg.shift_in.auto_fill = true;
+        cfg.shift_in.threshold = threshold;
+        cfg.shift_in.direction = ShiftDirection::Left;
 
-        let data = 0xABCD_u16 as u64;
-        info!("Sending: 0x{:04x}", data);
-        let response = spi.transfer(data);
-        info!("Received: 0x{:04x}", response & 0xFFFF);
-        Timer::after_millis(100).await;
+        let mut sm = sm;
+        sm.set_config(&cfg);
+        sm.set_pin_dirs(Direction::Out, &[out_pin]);
+
+        let mut slave = Self {
+            sm,
+            program,
+            message_size,
+        };
+        slave.start();
+        slave
     }
 
-    // Demo 2: 50-bit transfer
-    {
-        info!("=== 50-bit Transfer Demo ===");
-        let config = SpiMasterConfig {
-            clk_div: 8,
-            message_size: 50,
+    /// Hands the slave its loop count and enables it
+    fn start(&mut self) {
+        self.sm.tx().push(self.message_size as u32 - 1);
+        self.sm.set_enable(true);
+    }
+
+    /// Returns the slave to the start of a frame with empty FIFOs
+    ///
+    /// Only call it while the master is idle.
+    fn reset(&mut self) {
+        self.sm.set_enable(false);
+        self.sm.clear_fifos();
+        self.sm.restart();
+        let jmp = pio::InstructionOperands::JMP {
+            condition: pio::JmpCondition::Always,
+            address: self.program.origin,
         };
+        // SAFETY: the state machine is disabled, so jumping to the program start is harmless
+        unsafe { self.sm.exec_instr(jmp.encode()) };
+        self.start();
+    }
 
-        let mut spi =
-            PioSpiMaster::<PIO0, 1>::new(&mut common, sm1, &clk_pin, &mosi_pin, &miso_pin, config);
+    /// Queues the frame the slave answers the next master frame with
+    fn respond(&mut self, frame: u64) {
+        let size = self.message_size;
+        if size <= 32 {
+            self.sm.tx().push((frame << (32 - size)) as u32);
+        } else {
+            self.sm.tx().push((frame >> (size - 32)) as u32);
+            self.sm.tx().push((frame << (64 - size)) as u32);
+        }
+    }
+
+    /// Returns the last frame the slave received, or `None` if none arrived within 1 ms
+    fn received(&mut self) -> Option<u64> {
+        let first = self.pull()?;
+        if self.message_size <= 32 {
+            return Some(first as u64);
+        }
+        let second = self.pull()?;
+        Some(((first as u64) << (self.message_size - 32)) | second as u64)
+    }
 
-        let data = 0x0000_0000_0001_2345_6789_u64;
-        info!("Sending: 0x{:012x}", data);
-        let response = spi.transfer(data);
-        info!("Received: 0x{:012x}", response);
-        Timer::after_millis(100).await;
+    fn pull(&mut self) -> Option<u32> {
+        let deadline = Instant::now() + Duration::from_millis(1);
+        loop {
+            if let Some(word) = self.sm.rx().try_pull() {
+                return Some(word);
+            }
+            if Instant::now() > deadline {
+                return None;
+            }
+        }
     }
 
-    // Demo 3: 60-bit transfer
-    {
-        info!("=== 60-bit Transfer Demo ===");
+    /// Stops the slave and frees its instruction memory
+    fn free(self, common: &mut Common<'d, PIO0>) -> StateMachine<'d, PIO0, 1> {
+        let mut sm = self.sm;
+        sm.set_enable(false);
+        sm.clear_fifos();
+        // SAFETY: the program is private to this slave, whose state machine is stopped
+        unsafe { common.free_instr(self.program.used_memory) };
+        sm
+    }
+}
+
+/// Pass/fail counters of a run
+#[derive(Default)]
+struct Tally {
+    passed: u32,
+    failed: u32,
+}
+
+impl Tally {
+    fn record(&mut self, ok: bool) {
+        if ok {
+            self.passed += 1;
+        } else {
+            self.failed += 1;
+        }
+    }
+}
+
+/// Board resources shared by all suites
+struct Bench<'d> {
+    common: Common<'d, PIO0>,
+    master_sm: Option<StateMachine<'d, PIO0, 0>>,
+    slave_sm: Option<StateMachine<'d, PIO0, 1>>,
+    clk_pin: Pin<'d, PIO0>,
+    mosi_pin: Pin<'d, PIO0>,
+    miso_pin: Pin<'d, PIO0>,
+    cs_pin: Pin<'d, PIO0>,
+    slave_pin: Pin<'d, PIO0>,
+    tally: Tally,
+    rng: Rng,
+}
+
+impl<'d> Bench<'d> {
+    /// Creates a master and a matching test slave
+    fn setup(
+        &mut self,
+        mode: Mode,
+        message_size: usize,
+        clk_div: u16,
+    ) -> (PioSpiMaster<'d, PIO0, 0>, TestSlave<'d>) {
         let config = SpiMasterConfig {
-            clk_div: 8,
-            message_size: 60,
+            clk_div,
+            message_size,
+            word_order: match mode {
+                Mode::LowFirst => WordOrder::LowFirst,
+                _ => WordOrder::HighFirst,
+            },
+            ..Default::default()
         };
+        let sm = self.master_sm.take().unwrap();
+        let spi = match mode {
+            Mode::Cs => PioSpiMaster::try_new_with_cs(
+                &mut self.common,
+                sm,
+                &self.clk_pin,
+                &self.mosi_pin,
+                &self.miso_pin,
+                &self.cs_pin,
+                config,
+            ),
+            _ => PioSpiMaster::try_new(
+                &mut self.common,
+                sm,
+                &self.clk_pin,
+                &self.mosi_pin,
+                &self.miso_pin,
+                config,
+            ),
+        }
+        .expect("PIO SPI master could not be built");
+        let slave = TestSlave::new(
+            &mut self.common,
+            self.slave_sm.take().unwrap(),
+            &self.mosi_pin,
+            &self.slave_pin,
+            message_size,
+        );
+        (spi, slave)
+    }
 
-        let mut spi =
-            PioSpiMaster::<PIO0, 2>::new(&mut common, sm2, &clk_pin, &mosi_pin, &miso_pin, config);
+    /// Frees a master and slave created by [`setup`](Self::setup)
+    fn teardown(&mut self, spi: PioSpiMaster<'d, PIO0, 0>, slave: TestSlave<'d>) {
+        self.master_sm = Some(spi.free(&mut self.common));
+        self.slave_sm = Some(slave.free(&mut self.common));
+    }
+
+    /// Records a named check, logging it if it failed
+    fn check(&mut self, name: &str, ok: bool) {
+        self.tally.record(ok);
+        if !ok {
+            error!("FAIL {}", name);
+        }
+    }
+}
+
+/// Sends `frame` while the slave answers `response`; `true` if both arrived intact
+///
+/// Resets the slave after a mismatch so the next exchange starts in sync.
+fn exchange(
+    spi: &mut PioSpiMaster<'_, PIO0, 0>,
+    slave: &mut TestSlave<'_>,
+    frame: u64,
+    response: u64,
+) -> bool {
+    slave.respond(response);
+    let got = spi.transfer(frame);
+    let seen = slave.received();
+    let ok = got == response && seen == Some(frame);
+    if !ok {
+        slave.reset();
+    }
+    ok
+}
+
+/// Async variant of [`exchange`]
+async fn exchange_async(
+    spi: &mut PioSpiMaster<'_, PIO0, 0>,
+    slave: &mut TestSlave<'_>,
+    frame: u64,
+    response: u64,
+) -> bool {
+    slave.respond(response);
+    let got = spi.transfer_async(frame).await;
+    let seen = slave.received();
+    let ok = got == response && seen == Some(frame);
+    if !ok {
+        slave.reset();
+    }
+    ok
+}
+
+/// Checks every size, mode and divider with edge-case and random patterns
+fn loopback(bench: &mut Bench<'_>) {
+    info!("=== loopback ===");
+    for mode in MODES {
+        for size in SIZES {
+            for clk_div in CLK_DIVS {
+                let (mut spi, mut slave) = bench.setup(mode, size, clk_div);
+                let m = mask(size);
+                let patterns = [
+                    0,
+                    m,
+                    0xAAAA_AAAA_AAAA_AAAA & m,
+                    0x5555_5555_5555_5555 & m,
+                    1,
+                    1 << (size - 1),
+                    bench.rng.next() & m,
+                    bench.rng.next() & m,
+                ];
+                let mut ok = true;
+                for frame in patterns {
+                    // Answer with the complement so a stuck or shorted line cannot pass
+                    let response = !frame & m;
+                    if !exchange(&mut spi, &mut slave, frame, response) {
+                        error!(
+                            "{} bits, {}, clk_div {}: frame 0x{:x} failed",
+                            size, mode, clk_div, frame
+                        );
+                        ok = false;
+                    }
+                }
+                bench.check("loopback", ok);
+                bench.teardown(spi, slave);
+            }
+        }
+    }
+
+    for mode in MODES {
+        for size in [32, 50] {
+            let (mut spi, mut slave) = bench.setup(mode, size, TEST_CLK_DIV);
+            let mut rng = Rng(0x1234_5678 + size as u64);
+            let best = spi.find_max_frequency(|spi| {
+                (0..64).all(|_| {
+                    let frame = rng.next() & mask(size);
+                    exchange(spi, &mut slave, frame, !frame & mask(size))
+                })
+            });
+            match best {
+                Some(clk_div) => info!(
+                    "{} bits, {}: fastest passing clk_div {} ({} Hz)",
+                    size,
+                    mode,
+                    clk_div,
+                    SpiMasterConfig {
+                        clk_div,
+                        ..Default::default()
+                    }
+                    .frequency()
+                ),
+                None => error!("{} bits, {}: no passing clk_div", size, mode),
+            }
+            bench.check("speed sweep", best.is_some());
+            bench.teardown(spi, slave);
+        }
+    }
+}
+
+/// Runs long random sequences through each transfer path
+async fn stress(bench: &mut Bench<'_>) {
+    info!("=== stress ===");
+    for size in [32, 50] {
+        let m = mask(size);
+        let (mut spi, mut slave) = bench.setup(Mode::HighFirst, size, TEST_CLK_DIV);
+
+        let start = Instant::now();
+        let mut failures = 0;
+        for _ in 0..STRESS_FRAMES {
+            let frame = bench.rng.next() & m;
+            let response = bench.rng.next() & m;
+            if !exchange(&mut spi, &mut slave, frame, response) {
+                failures += 1;
+            }
+        }
+        info!(
+            "{} bits blocking: {} failures in {} frames ({} ms)",
+            size,
+            failures,
+            STRESS_FRAMES,
+            start.elapsed().as_millis()
+        );
+        bench.check("stress blocking", failures == 0);
+
+        let start = Instant::now();
+        let mut failures = 0;
+        for _ in 0..STRESS_FRAMES {
+            let frame = bench.rng.next() & m;
+            let response = bench.rng.next() & m;
+            if !exchange_async(&mut spi, &mut slave, frame, response).await {
+                failures += 1;
+            }
+        }
+        info!(
+            "{} bits async: {} failures in {} frames ({} ms)",
+            size,
+            failures,
+            STRESS_FRAMES,
+            start.elapsed().as_millis()
+        );
+        bench.check("stress async", failures == 0);
+
+        let mut failures = 0;
+        for _ in 0..STRESS_FRAMES {
+            let frame = bench.rng.next() & m;
+            let response = bench.rng.next() & m;
+            slave.respond(response);
+            let result = spi.transfer_checked(frame);
+            if !result.is_ok() || result.data != response || slave.received() != Some(frame) {
+                failures += 1;
+                slave.reset();
+            }
+        }
+        info!(
+            "{} bits checked: {} failures in {} frames",
+            size, failures, STRESS_FRAMES
+        );
+        bench.check("stress checked", failures == 0);
+
+        bench.teardown(spi, slave);
+    }
+}
+
+/// Interrupts and stalls the master, then checks that the next frames are clean
+async fn recovery(bench: &mut Bench<'_>) {
+    info!("=== recovery ===");
 
-        let data = 0x0FEDCBA987654321_u64;
-        info!("Sending: 0x{:015x}", data);
-        let response = spi.transfer(data);
-        info!("Received: 0x{:015x}", response);
-        Timer::after_millis(100).await;
+    // A transfer cancelled mid-frame must not leak into the next one
+    for size in [16, 50] {
+        let m = mask(size);
+        let (mut spi, mut slave) = bench.setup(Mode::HighFirst, size, SLOW_CLK_DIV);
+        slave.respond(0);
+        let cancelled = with_timeout(Duration::from_millis(5), spi.transfer_async(m))
+            .await
+            .is_err();
+        bench.check("transfer cancelled", cancelled);
+
+        // Let the abandoned frame finish quickly, then resynchronize the slave
+        spi.try_set_clk_div(TEST_CLK_DIV);
+        Timer::after_millis(1).await;
+        slave.reset();
+        let ok = (0..16).all(|_| {
+            let frame = bench.rng.next() & m;
+            exchange(&mut spi, &mut slave, frame, !frame & m)
+        });
+        info!("{} bits: frames after cancellation ok: {}", size, ok);
+        bench.check("recover after cancel", ok);
+        bench.teardown(spi, slave);
+    }
+
+    // A response left unread is reported as stale instead of silently returned
+    let (mut spi, mut slave) = bench.setup(Mode::HighFirst, 16, TEST_CLK_DIV);
+    slave.respond(0x1111);
+    spi.write(0xAAAA);
+    Timer::after_millis(1).await;
+    let _ = slave.received();
+    slave.respond(0x2222);
+    let result = spi.transfer_checked(0xBBBB);
+    info!("stale response: {}", result);
+    bench.check("rx_overflow", result.rx_overflow && result.data == 0x1111);
+    bench.teardown(spi, slave);
+
+    // Filling the RX FIFO stalls the state machine; the stall is reported
+    let (mut spi, mut slave) = bench.setup(Mode::HighFirst, 16, TEST_CLK_DIV);
+    for i in 0..5 {
+        slave.respond(i);
+        spi.write(i);
+        Timer::after_millis(1).await;
+        let _ = slave.received();
     }
+    slave.respond(5);
+    let result = spi.transfer_checked(5);
+    info!("stalled state machine: {}", result);
+    bench.check("stalled", result.stalled && result.rx_overflow);
+    bench.teardown(spi, slave);
+
+    // A fresh master on the same state machine starts clean
+    let (mut spi, mut slave) = bench.setup(Mode::HighFirst, 16, TEST_CLK_DIV);
+    let ok = exchange(&mut spi, &mut slave, 0xC3C3, 0x3C3C);
+    bench.check("clean after free", ok);
+    bench.teardown(spi, slave);
+}
 
-    info!("Demo complete");
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    info!("PIO SPI hardware-in-the-loop tests");
+
+    let p = embassy_rp::init(Default::default());
+    let Pio {
+        mut common,
+        sm0,
+        sm1,
+        ..
+    } = Pio::new(p.PIO0, Irqs);
+
+    let mut bench = Bench {
+        clk_pin: common.make_pio_pin(p.PIN_2),
+        mosi_pin: common.make_pio_pin(p.PIN_3),
+        miso_pin: common.make_pio_pin(p.PIN_4),
+        slave_pin: common.make_pio_pin(p.PIN_5),
+        cs_pin: common.make_pio_pin(p.PIN_6),
+        common,
+        master_sm: Some(sm0),
+        slave_sm: Some(sm1),
+        tally: Tally::default(),
+        rng: Rng(0x9E37_79B9_7F4A_7C15),
+    };
+
+    if selected("loopback") {
+        loopback(&mut bench);
+    }
+    if selected("stress") {
+        stress(&mut bench).await;
+    }
+    if selected("recovery") {
+        recovery(&mut bench).await;
+    }
+
+    let tally = &bench.tally;
+    if tally.failed == 0 {
+        info!("ALL PASSED ({} checks)", tally.passed);
+    } else {
+        error!("{} FAILED, {} passed", tally.failed, tally.passed);
+    }
     loop {
         Timer::after_millis(1000).await;
     }
@@ -0,0 +1,296 @@
+//! Many devices with their own bus settings, shared fairly between tasks
+//!
+//! [`DeviceBus`] selects one of several slaves per frame, but every slave has to accept the
+//! master's frame size, rate, CLK polarity and bit order, and only one task can own it.
+//! [`BusManager`] adds both halves for boards with many SPI peripherals (mixers, synth
+//! voice boards): each of up to 16 devices registers its own [`DeviceSettings`], which are
+//! applied when the bus switches to it, and tasks addressing different devices take turns
+//! in round-robin order, so a chatty device cannot starve the others.
+//!
+//! ```ignore
+//! static BUS: StaticCell<BusManager<'static, CriticalSectionRawMutex, PIO0, 0, 3>> =
+//!     StaticCell::new();
+//! let bus = BUS.init(BusManager::new(
+//!     DeviceBus::new(spi, [cs_mixer, cs_dac, cs_adc]),
+//!     [
+//!         DeviceSettings { message_size: 16, ..DeviceSettings::of(&config) },
+//!         DeviceSettings { message_size: 24, clk_div: 4, ..DeviceSettings::of(&config) },
+//!         DeviceSettings { clk_polarity: ClkPolarity::IdleLow, ..DeviceSettings::of(&config) },
+//!     ],
+//! ));
+//!
+//! // Any task holding `bus: &'static BusManager<..>`:
+//! let level = bus.transfer(MIXER, READ_LEVEL).await;
+//! ```
+//!
+//! # Scheduling
+//!
+//! The bus is granted one request at a time. When it is released, the next device after
+//! the last one served (by [`DeviceId`], wrapping around) that has a request waiting gets
+//! it, whatever order the requests arrived in.
+//!
+//! # Notes
+//! - Settings are cached: switching between devices with equal settings changes nothing
+//!   on the master, and only the settings that differ are applied otherwise
+//! - One task per device at a time: a second task waiting on the same device takes over
+//!   its turn and the two are served one after the other
+//! - The other state machines of the PIO block can run further managers on their own pins
+
+use core::cell::RefCell;
+use core::future::poll_fn;
+use core::task::Poll;
+
+use embassy_rp::pio::Instance;
+use embassy_sync::blocking_mutex;
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::mutex::Mutex;
+use embassy_sync::waitqueue::WakerRegistration;
+
+use crate::devices::{DeviceBus, DeviceId};
+use crate::{BitOrder, ClkPolarity, SpiMasterConfig, MAX_MESSAGE_SIZE};
+
+/// Most devices one [`BusManager`] schedules
+pub const MAX_DEVICES: usize = 16;
+
+/// Bus settings of one device, applied when the bus switches to it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct DeviceSettings {
+    /// Frame size (1-60); all devices must be on the same side of 32 bits as the master
+    pub message_size: usize,
+    /// Clock divider (minimum 2), as in [`SpiMasterConfig::clk_div`]
+    pub clk_div: u16,
+    /// CLK idle level
+    pub clk_polarity: ClkPolarity,
+    /// Order in which frame bits are shifted out on MOSI
+    pub tx_bit_order: BitOrder,
+    /// Order in which MISO bits are assembled into the response
+    pub rx_bit_order: BitOrder,
+}
+
+impl DeviceSettings {
+    /// Returns the settings a master built from `config` starts with
+    pub fn of(config: &SpiMasterConfig) -> Self {
+        Self {
+            message_size: config.message_size,
+            clk_div: config.clk_div,
+            clk_polarity: config.clk_polarity,
+            tx_bit_order: config.tx_bit_order,
+            rx_bit_order: config.rx_bit_order,
+        }
+    }
+}
+
+/// The device bus with the settings currently applied to its master
+struct Cached<'d, PIO: Instance, const SM: usize, const N: usize> {
+    bus: DeviceBus<'d, PIO, SM, N>,
+    settings: [DeviceSettings; N],
+    applied: DeviceSettings,
+}
+
+impl<PIO: Instance, const SM: usize, const N: usize> Cached<'_, PIO, SM, N> {
+    /// Deselects the previous device and applies `device`'s settings where they differ
+    fn switch_to(&mut self, device: DeviceId) {
+        let wanted = self.settings[device.0 as usize];
+        if self.bus.selected() == Some(device) || wanted == self.applied {
+            return;
+        }
+        // Polarity and bit order changes must not reach a selected slave
+        self.bus.deselect();
+        let spi = self.bus.master();
+        if wanted.clk_div != self.applied.clk_div {
+            spi.try_set_clk_div(wanted.clk_div);
+        }
+        spi.set_clk_polarity(wanted.clk_polarity);
+        spi.set_bit_order(wanted.tx_bit_order, wanted.rx_bit_order);
+        // Checked against the master's program variant in `BusManager::new`
+        let _ = spi.set_message_size(wanted.message_size);
+        self.applied = wanted;
+    }
+}
+
+/// Round-robin grant state
+struct Schedule<const N: usize> {
+    /// Device holding the bus
+    owner: Option<u8>,
+    /// Devices with a request waiting, one bit each
+    waiting: u16,
+    /// Device served last, where the round-robin search continues
+    last: u8,
+    wakers: [WakerRegistration; N],
+}
+
+impl<const N: usize> Schedule<N> {
+    /// Returns the waiting device next in turn after the last one served
+    fn next(&self) -> Option<u8> {
+        (1..=N as u8)
+            .map(|offset| (self.last + offset) % N as u8)
+            .find(|&device| self.waiting & 1 << device != 0)
+    }
+}
+
+/// Device bus shared by tasks, with per-device settings and fair turns
+///
+/// # Type Parameters
+/// * `M` - Mutex kind guarding the bus (see [`SharedSpi`](crate::shared::SharedSpi))
+/// * `N` - Number of devices (1-16)
+pub struct BusManager<'d, M: RawMutex, PIO: Instance, const SM: usize, const N: usize> {
+    bus: Mutex<M, Cached<'d, PIO, SM, N>>,
+    schedule: blocking_mutex::Mutex<M, RefCell<Schedule<N>>>,
+}
+
+impl<'d, M: RawMutex, PIO: Instance, const SM: usize, const N: usize>
+    BusManager<'d, M, PIO, SM, N>
+{
+    /// Takes over a device bus and the settings of each device
+    ///
+    /// # Arguments
+    /// * `bus` - Master and chip selects
+    /// * `settings` - Settings of each device, indexed by [`DeviceId`]
+    ///
+    /// # Panics
+    /// - If `N` is 0 or above [`MAX_DEVICES`]
+    /// - If a device's `clk_div` is below 2, or its `message_size` is out of range or on
+    ///   the other side of 32 bits from the master's (that takes another program)
+    pub fn new(mut bus: DeviceBus<'d, PIO, SM, N>, settings: [DeviceSettings; N]) -> Self {
+        assert!((1..=MAX_DEVICES).contains(&N), "1-16 devices");
+        let spi = bus.master();
+        let applied = DeviceSettings {
+            clk_div: spi.clk_div(),
+            clk_polarity: spi.clk_polarity(),
+            ..DeviceSettings::of(&spi.config)
+        };
+        for device in &settings {
+            assert!(device.clk_div >= 2, "clk_div must be at least 2");
+            assert!(
+                (1..=MAX_MESSAGE_SIZE).contains(&device.message_size)
+                    && (device.message_size > 32) == (applied.message_size > 32),
+                "device frame size needs another frame program"
+            );
+        }
+        Self {
+            bus: Mutex::new(Cached {
+                bus,
+                settings,
+                applied,
+            }),
+            schedule: blocking_mutex::Mutex::new(RefCell::new(Schedule {
+                owner: None,
+                waiting: 0,
+                last: N as u8 - 1,
+                wakers: core::array::from_fn(|_| WakerRegistration::new()),
+            })),
+        }
+    }
+
+    /// Transfers a frame to `device` once it is its turn
+    ///
+    /// # Arguments
+    /// * `device` - Slave to address
+    /// * `data` - Data to shift out on MOSI (only bits [message_size-1:0] of the device's
+    ///   frame size are used)
+    ///
+    /// # Returns
+    /// * `u64` - Response bits read from MISO
+    ///
+    /// # Panics
+    /// If `device` is not below `N`
+    ///
+    /// # Cancel Safety
+    /// Dropping the future while waiting gives up the turn; dropping it mid-frame is
+    /// recovered by the next transfer as [`DeviceBus::transfer_async`] describes.
+    pub async fn transfer(&self, device: DeviceId, data: u64) -> u64 {
+        let _turn = self.turn(device).await;
+        let mut cached = self.bus.lock().await;
+        cached.switch_to(device);
+        cached.bus.transfer_async(device, data).await
+    }
+
+    /// Transfers several frames to `device` in one turn, its CS held throughout
+    ///
+    /// # Arguments
+    /// * `device` - Slave to address
+    /// * `frames` - Frames to send, replaced by their responses
+    ///
+    /// # Panics
+    /// If `device` is not below `N`
+    ///
+    /// # Cancel Safety
+    /// As [`transfer`](Self::transfer); frames already replaced hold valid responses.
+    pub async fn transfer_in_place(&self, device: DeviceId, frames: &mut [u64]) {
+        let _turn = self.turn(device).await;
+        let mut cached = self.bus.lock().await;
+        cached.switch_to(device);
+        for frame in frames {
+            *frame = cached.bus.transfer_async(device, *frame).await;
+        }
+    }
+
+    /// Returns the settings registered for `device`
+    ///
+    /// # Panics
+    /// If `device` is not below `N`
+    pub async fn settings(&self, device: DeviceId) -> DeviceSettings {
+        self.bus.lock().await.settings[device.0 as usize]
+    }
+
+    /// Deselects the current device and returns the device bus
+    pub fn release(self) -> DeviceBus<'d, PIO, SM, N> {
+        let mut cached = self.bus.into_inner();
+        cached.bus.deselect();
+        cached.bus
+    }
+
+    /// Waits until the round-robin order grants `device` the bus
+    async fn turn(&self, device: DeviceId) -> Turn<'_, M, N> {
+        assert!((device.0 as usize) < N, "device id out of range");
+        let mut turn = Turn {
+            schedule: &self.schedule,
+            device: device.0,
+            granted: false,
+        };
+        poll_fn(|cx| {
+            self.schedule.lock(|schedule| {
+                let mut schedule = schedule.borrow_mut();
+                schedule.waiting |= 1 << turn.device;
+                if schedule.owner.is_none() && schedule.next() == Some(turn.device) {
+                    schedule.waiting &= !(1 << turn.device);
+                    schedule.owner = Some(turn.device);
+                    turn.granted = true;
+                    return Poll::Ready(());
+                }
+                schedule.wakers[turn.device as usize].register(cx.waker());
+                Poll::Pending
+            })
+        })
+        .await;
+        turn
+    }
+}
+
+/// A request's place in the schedule; dropping it (also on cancellation) releases the bus
+/// or withdraws the request, and wakes the device next in turn
+struct Turn<'a, M: RawMutex, const N: usize> {
+    schedule: &'a blocking_mutex::Mutex<M, RefCell<Schedule<N>>>,
+    device: u8,
+    /// The bus was granted, rather than still being waited for
+    granted: bool,
+}
+
+impl<M: RawMutex, const N: usize> Drop for Turn<'_, M, N> {
+    fn drop(&mut self) {
+        self.schedule.lock(|schedule| {
+            let mut schedule = schedule.borrow_mut();
+            if self.granted {
+                schedule.owner = None;
+                schedule.last = self.device;
+            } else {
+                schedule.waiting &= !(1 << self.device);
+            }
+            if schedule.owner.is_none() {
+                if let Some(next) = schedule.next() {
+                    schedule.wakers[next as usize].wake();
+                }
+            }
+        });
+    }
+}
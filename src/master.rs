@@ -0,0 +1,2533 @@
+//! Frame-based SPI master
+//!
+//! [`PioSpiMaster`] runs the frame program from [`crate::program`]: every transfer shifts
+//! `message_size` bits out and then the same number of bits in.
+
+use core::future::poll_fn;
+use core::mem::MaybeUninit;
+use core::task::Poll;
+
+use embassy_rp::gpio::Level;
+use embassy_rp::pac;
+use embassy_rp::pio::{
+    Common, Config, Direction, Instance, LoadedProgram, Pin, ShiftDirection, StateMachine,
+};
+use fixed::traits::ToFixed;
+use fixed::types::extra::U8;
+use fixed::FixedU32;
+use pio::SetDestination;
+
+use crate::claim::{claim_pins, release_claim, PinConflict};
+#[cfg(feature = "event-log")]
+use crate::events::EventLog;
+use crate::events::{ErrorEvent, EventKind, Setting};
+use crate::failsafe::FailsafeStates;
+use crate::frame::{Alignment, BitOrder, FrameFormat, WordOrder};
+use crate::isr;
+use crate::program::{
+    add_trailer, delay_sampling, delay_start, get_full_duplex_program, get_pio_program,
+    mirror_outputs, stretch_clock, wait_for_ready, MAX_CLK_STRETCH, MAX_LEAD_IN_CYCLES,
+    MAX_MIRRORED_MESSAGE_SIZE, MAX_SAMPLE_DELAY, MAX_START_DELAY, MAX_TRAILER_CYCLES,
+};
+#[cfg(feature = "cs")]
+use crate::program::{get_cs_pio_program, CsTiming};
+#[cfg(feature = "cycle-stats")]
+use crate::stats::{cycle_count, CycleStats};
+
+/// Idle level of CLK
+///
+/// The frame programs always change MOSI on the leading edge and sample MISO on the
+/// trailing edge (CPHA=1); the polarity is applied by inverting the CLK pad output.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub enum ClkPolarity {
+    /// CLK idles HIGH: SPI Mode 3 (CPOL=1, CPHA=1)
+    #[default]
+    IdleHigh,
+    /// CLK idles LOW: SPI Mode 1 (CPOL=0, CPHA=1)
+    IdleLow,
+}
+
+/// How a frame's MOSI and MISO bits share the clock
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub enum Duplex {
+    /// `message_size` clocks shifting MOSI out, then `message_size` clocks sampling MISO,
+    /// giving the slave a whole frame to turn its answer around
+    #[default]
+    Half,
+    /// `message_size` clocks shifting MOSI out and sampling MISO in the same period, for
+    /// devices that answer while the command is still arriving
+    Full,
+}
+
+/// What a write-only frame does when the RX FIFO has no room left for its response
+///
+/// Applies to [`PioSpiMaster::write`], [`PioSpiMaster::write_capture_later`] and
+/// [`PioSpiMaster::try_start`]; every transfer that reads its own response makes room
+/// itself.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub enum RxOverflowPolicy {
+    /// Queue the frame anyway: the state machine stalls with CLK paused once the RX FIFO
+    /// is full, until responses are read
+    #[default]
+    Block,
+    /// Discard the oldest unread response (waiting for its frame to finish if needed), then
+    /// queue the frame; every discarded response is counted
+    DropOldest,
+    /// Reject the frame without sending it and count the overflow; `try_start` returns
+    /// `false`
+    Error,
+}
+
+/// Outputs copied onto a second pin, see [`PioSpiMaster::set_mirror`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct Mirror {
+    /// The GPIO above CLK carries a copy of CLK
+    pub clk: bool,
+    /// The GPIO above MOSI carries a copy of MOSI
+    pub mosi: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SpiMasterConfig {
+    pub clk_div: u16,
+    pub message_size: usize,
+    pub word_order: WordOrder,
+    /// Part of the response that arrives first in frames longer than 32 bits, for slaves
+    /// that answer in a different order than they are addressed; `None` follows
+    /// `word_order`
+    pub rx_word_order: Option<WordOrder>,
+    /// Order in which frame bits are shifted out on MOSI
+    pub tx_bit_order: BitOrder,
+    /// Order in which MISO bits are assembled into the response
+    pub rx_bit_order: BitOrder,
+    /// Position of the frame in the data passed to transfers
+    pub tx_alignment: Alignment,
+    /// Position of the frame in the responses returned by transfers
+    pub rx_alignment: Alignment,
+    /// Extra state machine cycles between CS falling and the first CLK edge
+    /// (PIO-managed CS only; 2 cycles are always present)
+    pub cs_setup_cycles: u8,
+    /// Extra state machine cycles between the last CLK rising edge and CS rising
+    /// (PIO-managed CS only; at least 2 cycles are always present)
+    pub cs_hold_cycles: u8,
+    /// Extra state machine cycles CS stays HIGH before the next frame may assert it
+    /// (PIO-managed CS only; 2 cycles are always present)
+    pub cs_high_time_cycles: u8,
+    /// Dummy CLK periods between the CS setup time and the first data bit, clocked by the
+    /// state machine like data bits with MOSI held (PIO-managed CS only; 0-32)
+    pub lead_in_cycles: u8,
+    /// Extra CLK periods after the last data bit, clocked like data bits with MOSI held and
+    /// MISO ignored, for slaves that latch on clocks beyond the data (0-32). A PIO-managed
+    /// CS stays asserted until they are done
+    pub trailer_cycles: u8,
+    /// CLK idle level
+    pub clk_polarity: ClkPolarity,
+    /// Extra state machine cycles CLK stays LOW per bit, lengthening data setup time
+    /// before each rising edge (0-7; 1 cycle is always present)
+    pub clk_low_cycles: u8,
+    /// Extra state machine cycles CLK stays HIGH per bit (0-7; 2 cycles are always present)
+    pub clk_high_cycles: u8,
+    /// State machine cycles between each CLK rising edge and the MISO sample, for slaves
+    /// whose data arrives late over long or isolated links (0-8). The HIGH phase of every
+    /// sampling bit grows by the same amount
+    pub miso_sample_delay_cycles: u8,
+    /// State machine cycles the master idles after being started, delaying all of its CLK
+    /// edges against masters started in the same cycle by
+    /// [`start_synchronized`](crate::sync::start_synchronized) (0-8)
+    pub start_delay_cycles: u8,
+    /// Handling of write-only frames when their responses would overflow the RX FIFO
+    pub rx_overflow: RxOverflowPolicy,
+    /// Sequential write-then-read frames or full-duplex frames
+    pub duplex: Duplex,
+    /// GPIO (0-31) a slave holds HIGH while it can take clocks; while it is LOW the state
+    /// machine holds CLK HIGH before the next bit, pausing mid-frame if need be. Costs one
+    /// cycle per bit. The pin must be configured as an input (e.g. a live
+    /// `embassy_rp::gpio::Input`); `None` clocks freely
+    pub ready_pin: Option<u8>,
+    /// CLK and MOSI copies driven by the same instructions as the originals. Only
+    /// [`PioSpiMaster::set_mirror`] turns them on, as it takes the mirror pins; the
+    /// constructors reject a config with either set ([`InitError::InvalidConfig`])
+    pub mirror: Mirror,
+    /// Levels [`PioSpiMaster::enter_failsafe`] forces the bus lines to
+    pub failsafe: FailsafeStates,
+}
+
+impl Default for SpiMasterConfig {
+    fn default() -> Self {
+        Self {
+            clk_div: 8,
+            message_size: 16,
+            word_order: WordOrder::default(),
+            rx_word_order: None,
+            tx_bit_order: BitOrder::default(),
+            rx_bit_order: BitOrder::default(),
+            tx_alignment: Alignment::default(),
+            rx_alignment: Alignment::default(),
+            cs_setup_cycles: 0,
+            cs_hold_cycles: 0,
+            cs_high_time_cycles: 0,
+            lead_in_cycles: 0,
+            trailer_cycles: 0,
+            clk_polarity: ClkPolarity::default(),
+            clk_low_cycles: 0,
+            clk_high_cycles: 0,
+            miso_sample_delay_cycles: 0,
+            start_delay_cycles: 0,
+            rx_overflow: RxOverflowPolicy::default(),
+            duplex: Duplex::default(),
+            ready_pin: None,
+            mirror: Mirror::default(),
+            failsafe: FailsafeStates::default(),
+        }
+    }
+}
+
+impl SpiMasterConfig {
+    /// Reports the SCK rate nearest to `requested_hz` that the frame program can produce
+    ///
+    /// # Arguments
+    /// * `requested_hz` - Desired SCK frequency
+    ///
+    /// # Returns
+    /// * `(u32, i32)` - Achievable SCK frequency in Hz and its error relative to the request
+    ///   in parts per million (negative when slower than requested)
+    ///
+    /// # Notes
+    /// - Uses the current system clock and [`CYCLES_PER_BIT`] state machine cycles per bit,
+    ///   i.e. no `clk_low_cycles`/`clk_high_cycles` stretching
+    /// - Only integer dividers are used: a fractional divider would stretch individual
+    ///   cycles unevenly and jitter the SCK edges, so the error is reported instead
+    /// - The matching `clk_div` is returned by [`clk_div_for`](Self::clk_div_for)
+    pub fn closest_frequency(requested_hz: u32) -> (u32, i32) {
+        let actual_hz = sck_frequency(Self::clk_div_for(requested_hz));
+        let error_ppm =
+            (actual_hz as i64 - requested_hz as i64) * 1_000_000 / requested_hz.max(1) as i64;
+        (actual_hz, error_ppm as i32)
+    }
+
+    /// Returns the `clk_div` whose SCK rate is nearest to `requested_hz`
+    pub fn clk_div_for(requested_hz: u32) -> u16 {
+        clk_div_for_period(requested_hz, CYCLES_PER_BIT)
+    }
+
+    /// Returns the SCK frequency in Hz produced by this config's `clk_div` and CLK phases
+    pub fn frequency(&self) -> u32 {
+        sm_frequency(self.clk_div) / self.cycles_per_bit()
+    }
+
+    /// Returns the instruction slots [`PioSpiMaster::new`] and the other constructors
+    /// without PIO-managed CS load for this config
+    ///
+    /// # Notes
+    /// - Each PIO block has 32 slots shared by all four state machines; budget this against
+    ///   the programs of drivers sharing the block
+    ///
+    /// # Returns
+    /// * `Ok(usize)` - Instruction slots of the program
+    /// * `Err(InitError)` - The config is [invalid](Self::is_valid) or its program exceeds
+    ///   32 instructions ([`InitError::ProgramTooLong`])
+    pub fn program_len(&self) -> Result<usize, InitError> {
+        Ok(frame_program(self, false)?.code.len())
+    }
+
+    /// Returns the instruction slots [`PioSpiMaster::new_with_cs`] loads for this config,
+    /// with the same errors as [`program_len`](Self::program_len)
+    #[cfg(feature = "cs")]
+    pub fn cs_program_len(&self) -> Result<usize, InitError> {
+        Ok(frame_program(self, true)?.code.len())
+    }
+
+    /// Returns whether the frame programs can realize this config's timing
+    ///
+    /// # Returns
+    /// * `true` - CLK stretching, sample delay, start delay, lead-in and trailer are within
+    ///   the limits of the PIO instructions (see the field docs)
+    /// * `false` - The constructors would panic; use
+    ///   [`BitBangSpi`](crate::bitbang::BitBangSpi) for this config instead
+    pub fn fits_pio(&self) -> bool {
+        self.clk_low_cycles <= MAX_CLK_STRETCH
+            && self.clk_high_cycles <= MAX_CLK_STRETCH
+            && self.miso_sample_delay_cycles <= MAX_SAMPLE_DELAY
+            && self.start_delay_cycles <= MAX_START_DELAY
+            && self.lead_in_cycles <= MAX_LEAD_IN_CYCLES
+            && self.trailer_cycles <= MAX_TRAILER_CYCLES
+    }
+
+    /// Returns whether the constructors accept this config
+    ///
+    /// # Returns
+    /// * `true` - The config [fits the PIO programs](Self::fits_pio), `message_size` is
+    ///   1-[`MAX_MESSAGE_SIZE`] (1-16 with MOSI mirrored), `clk_div` is at least 2 and
+    ///   `ready_pin` (if any) is GPIO 0-31
+    /// * `false` - The `try_*` constructors return [`InitError::InvalidConfig`]
+    ///
+    /// # Notes
+    /// - A valid config can still need more than 32 instructions when many options are
+    ///   combined; [`program_len`](Self::program_len) reports that
+    /// - With CLK mirrored, no instruction may carry more than 3 delay cycles, which
+    ///   [`program_len`](Self::program_len) also reports as [`InitError::InvalidConfig`]
+    pub fn is_valid(&self) -> bool {
+        let max_size = if self.mirror.mosi {
+            MAX_MIRRORED_MESSAGE_SIZE
+        } else {
+            MAX_MESSAGE_SIZE
+        };
+        self.fits_pio()
+            && (1..=max_size).contains(&self.message_size)
+            && self.clk_div >= 2
+            && self.ready_pin.is_none_or(|pin| pin < 32)
+    }
+
+    /// Returns the part of a response longer than 32 bits that arrives first
+    pub fn rx_word_order(&self) -> WordOrder {
+        self.rx_word_order.unwrap_or(self.word_order)
+    }
+
+    /// Returns the layout of this config's frames in the FIFO words
+    pub fn frame_format(&self) -> FrameFormat {
+        FrameFormat {
+            message_size: self.message_size,
+            tx_bit_order: self.tx_bit_order,
+            rx_bit_order: self.rx_bit_order,
+            tx_alignment: self.tx_alignment,
+            rx_alignment: self.rx_alignment,
+            tx_word_order: self.word_order,
+            rx_word_order: self.rx_word_order(),
+            mirror_mosi: self.mirror.mosi,
+        }
+    }
+
+    /// Returns the state machine cycles per SCK period, including CLK phase stretching,
+    /// the slave-ready check and the MISO sample delay (which half-duplex write bits do not
+    /// have, so their periods may be shorter)
+    pub fn cycles_per_bit(&self) -> u32 {
+        let ready_wait = self.ready_pin.is_some() as u32;
+        CYCLES_PER_BIT
+            + self.clk_low_cycles as u32
+            + self.clk_high_cycles as u32
+            + self.miso_sample_delay_cycles as u32
+            + ready_wait
+    }
+
+    /// Returns the `start_delay_cycles` that put this master half an SCK period behind an
+    /// otherwise identical one, for ping-pong sampling or interleaved converters
+    ///
+    /// # Returns
+    /// * `Some(u8)` - Half of [`cycles_per_bit`](Self::cycles_per_bit)
+    /// * `None` - The period has an odd number of cycles; add a cycle to `clk_low_cycles`
+    ///   or `clk_high_cycles` (e.g. `clk_low_cycles: 1` for a 4-cycle period)
+    pub fn half_period_cycles(&self) -> Option<u8> {
+        let cycles = self.cycles_per_bit();
+        cycles.is_multiple_of(2).then_some((cycles / 2) as u8)
+    }
+}
+
+/// State machine cycles per SCK period in the frame program (1 LOW + 2 HIGH), before any
+/// `clk_low_cycles`/`clk_high_cycles` stretching
+pub const CYCLES_PER_BIT: u32 = 3;
+
+/// Returns the `clk_div` whose SCK rate is nearest to `requested_hz` for a period of
+/// `cycles_per_bit` state machine cycles
+fn clk_div_for_period(requested_hz: u32, cycles_per_bit: u32) -> u16 {
+    let per_bit = requested_hz.max(1) as u64 * cycles_per_bit as u64;
+    let sys_hz = embassy_rp::clocks::clk_sys_freq() as u64;
+    let divider = (sys_hz + per_bit / 2) / per_bit;
+    (divider.clamp(1, u16::MAX as u64 - 1) + 1) as u16
+}
+
+/// Returns the SCK frequency for a `clk_div` setting at the current system clock
+fn sck_frequency(clk_div: u16) -> u32 {
+    sm_frequency(clk_div) / CYCLES_PER_BIT
+}
+
+/// Returns the state machine clock rate for a `clk_div` setting at the current system clock
+pub(crate) fn sm_frequency(clk_div: u16) -> u32 {
+    let divider = clk_div.saturating_sub(1).max(1) as u32;
+    embassy_rp::clocks::clk_sys_freq() / divider
+}
+
+/// Reason a master could not be built
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum InitError {
+    /// An output pin is already driven by another state machine of the same PIO block
+    PinConflict(PinConflict),
+    /// The PIO block has no run of this many free instruction slots left; other drivers
+    /// (e.g. cyw43-pio or a WS2812 driver) occupy the rest
+    NoInstructionMemory { needed: usize },
+    /// A config value is outside the range the frame programs support (see
+    /// [`SpiMasterConfig::is_valid`])
+    InvalidConfig,
+    /// The configured options together need more than the 32 instruction slots of a PIO
+    /// block
+    ProgramTooLong,
+}
+
+impl From<PinConflict> for InitError {
+    fn from(conflict: PinConflict) -> Self {
+        InitError::PinConflict(conflict)
+    }
+}
+
+/// Largest frame the programs shift: 32 bits plus a second word whose unused bits still
+/// leave `out null, 32` room to empty the OSR
+pub const MAX_MESSAGE_SIZE: usize = 60;
+
+/// Reason [`PioSpiMaster::set_message_size`] refused a size
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum SizeError {
+    /// The size is 0 or above [`MAX_MESSAGE_SIZE`]
+    OutOfRange,
+    /// The size is on the other side of 32 bits than the current one, which needs a
+    /// different program
+    ProgramChange,
+}
+
+/// Reason [`PioSpiMaster::apply_config`] refused a config
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum ConfigError {
+    /// The config is not [valid](SpiMasterConfig::is_valid)
+    Invalid,
+    /// The config needs a different frame program: another duplex mode, CLK stretching,
+    /// sample, start or CS delays, lead-in, trailer, ready pin or mirrors, or a frame size
+    /// on the other side of 32 bits
+    ProgramChange,
+}
+
+/// Reason [`PioSpiMaster::remap_pins`] refused new pins
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum RemapError {
+    /// Another state machine of the same PIO block drives the new CLK or MOSI pin
+    PinConflict(PinConflict),
+    /// CLK or MOSI is [mirrored](PioSpiMaster::set_mirror); turn the mirrors off first
+    Mirrored,
+}
+
+impl From<PinConflict> for RemapError {
+    fn from(conflict: PinConflict) -> Self {
+        RemapError::PinConflict(conflict)
+    }
+}
+
+/// Response of [`PioSpiMaster::transfer_checked`] with FIFO health flags
+///
+/// Any flag set means `data` may not be the response to the frame that was sent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct TransferResult {
+    /// Response bits read from MISO
+    pub data: u64,
+    /// Responses of earlier frames were still unread (in the RX FIFO or not yet shifted)
+    /// when the frame was queued, so `data` is an older response (left by
+    /// [`PioSpiMaster::write`] or an abandoned read)
+    pub rx_overflow: bool,
+    /// The TX FIFO ran dry between the two words of a >32-bit frame, pausing CLK mid-frame
+    pub tx_underrun: bool,
+    /// The state machine stalled on a full RX FIFO, pausing CLK during a read phase
+    pub stalled: bool,
+}
+
+impl TransferResult {
+    /// Returns `true` if no error flag is set
+    pub fn is_ok(&self) -> bool {
+        !(self.rx_overflow || self.tx_underrun || self.stalled)
+    }
+}
+
+/// The FIFOs of a master no longer pair each response with the frame that produced it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Desync {
+    /// Responses of this many earlier frames are unread and would be returned first
+    /// (e.g. after [`PioSpiMaster::write`]); collect or discard them first
+    Stale(usize),
+    /// The RX FIFO holds more words than the frames in flight can have produced (e.g. words
+    /// pushed through [`PioSpiMaster::with_sm`]); reset the master with
+    /// [`PioSpiMaster::resync`]
+    Unaccounted,
+    /// The known-answer frame of [`PioSpiMaster::resync`] got this response instead
+    Mismatch(u64),
+}
+
+pub struct PioSpiMaster<'d, PIO: Instance, const SM: usize> {
+    pub(crate) sm: StateMachine<'d, PIO, SM>,
+    _program: LoadedProgram<'d, PIO>,
+    pub(crate) message_size: usize,
+    tx_bit_order: BitOrder,
+    rx_bit_order: BitOrder,
+    clk_div: u16,
+    /// Divider last chosen and the system clock in Hz it was chosen for, for rescaling it
+    /// to another clock
+    chosen_div: (u16, u32),
+    /// GPIO number of the CLK pin, for switching its polarity
+    pub(crate) clk_pin: u8,
+    /// GPIO numbers of MOSI and a PIO-managed CS, for tri-stating the bus
+    pub(crate) mosi_pin: u8,
+    pub(crate) cs_pin: Option<u8>,
+    /// GPIO number of MISO, for switching its pulls
+    pub(crate) miso_pin: u8,
+    pub(crate) clk_polarity: ClkPolarity,
+    /// State machine cycles per SCK period, CLK phase stretching included
+    cycles_per_bit: u32,
+    /// Set while an async transfer is in progress; still set on entry means it was cancelled
+    pub(crate) interrupted: bool,
+    /// Responses of `write_capture_later` frames not yet collected by `drain_responses`
+    captured: usize,
+    /// Frames pushed whose responses have not been pulled yet
+    pub(crate) in_flight: usize,
+    rx_overflow: RxOverflowPolicy,
+    /// Responses dropped or frames rejected under `rx_overflow` since last read
+    overflows: u32,
+    /// State machine configuration, for installing a different program
+    cfg: Config<'d, PIO>,
+    /// Configuration the master was built with, for regenerating its program
+    pub(crate) config: SpiMasterConfig,
+    /// Layout of frames in the FIFO words, kept in step with `config`
+    pub(crate) format: FrameFormat,
+    /// Set once an ISR handle was issued; frames then take the FIFOs from it first
+    pub(crate) isr_shared: bool,
+    /// Recent events, for [`drain_events`](Self::drain_events)
+    #[cfg(feature = "event-log")]
+    pub(crate) events: EventLog,
+    /// FIFO waits, for [`cycle_stats`](Self::cycle_stats)
+    #[cfg(feature = "cycle-stats")]
+    pub(crate) cycles: CycleStats,
+}
+
+impl<'d, PIO: Instance, const SM: usize> PioSpiMaster<'d, PIO, SM> {
+    /// Creates a new PIO SPI Master
+    ///
+    /// # Arguments
+    /// * `common` - The PIO peripheral's common interface (for program loading and pin setup)
+    /// * `sm` - State machine (takes ownership)
+    /// * `clk_pin` - Clock pin (side-set/output)
+    /// * `mosi_pin` - MOSI pin (output)
+    /// * `miso_pin` - MISO pin (input)
+    /// * `config` - SPI configuration
+    ///
+    /// # Panics
+    /// If another state machine of the same PIO block already drives CLK or MOSI, or the
+    /// program does not fit in the free instruction memory (see [`try_new`](Self::try_new))
+    pub fn new(
+        common: &mut Common<'d, PIO>,
+        sm: StateMachine<'d, PIO, SM>,
+        clk_pin: &Pin<'d, PIO>,
+        mosi_pin: &Pin<'d, PIO>,
+        miso_pin: &Pin<'d, PIO>,
+        config: SpiMasterConfig,
+    ) -> Self {
+        Self::try_new(common, sm, clk_pin, mosi_pin, miso_pin, config)
+            .expect("PIO SPI master could not be built")
+    }
+
+    /// Creates a new PIO SPI Master, reporting pin conflicts and a full instruction memory
+    /// instead of panicking
+    ///
+    /// # Arguments
+    /// Same as [`new`](Self::new)
+    ///
+    /// # Returns
+    /// * `Ok(PioSpiMaster)` - The master, running
+    /// * `Err(InitError::PinConflict)` - CLK or MOSI is already driven by another state
+    ///   machine of the same PIO block (a master, [`PioSpiBus`](crate::transaction::PioSpiBus)
+    ///   or [`ClockOut`](crate::clock::ClockOut)); nothing was loaded and `sm` is dropped
+    /// * `Err(InitError::NoInstructionMemory)` - Fewer than
+    ///   [`program_len`](SpiMasterConfig::program_len) consecutive instruction slots are
+    ///   free; `sm` is dropped
+    /// * `Err(InitError::InvalidConfig)` / `Err(InitError::ProgramTooLong)` - `config` is
+    ///   not [valid](SpiMasterConfig::is_valid) or its program exceeds 32 instructions;
+    ///   nothing was claimed and `sm` is dropped
+    pub fn try_new(
+        common: &mut Common<'d, PIO>,
+        sm: StateMachine<'d, PIO, SM>,
+        clk_pin: &Pin<'d, PIO>,
+        mosi_pin: &Pin<'d, PIO>,
+        miso_pin: &Pin<'d, PIO>,
+        config: SpiMasterConfig,
+    ) -> Result<Self, InitError> {
+        let mut spi = Self::init(common, sm, clk_pin, mosi_pin, miso_pin, None, config)?;
+        spi.start();
+        Ok(spi)
+    }
+
+    /// Creates a PIO SPI Master that is fully configured but not yet running
+    ///
+    /// # Arguments
+    /// Same as [`new`](Self::new)
+    ///
+    /// # Behavior
+    /// Everything [`new`](Self::new) does except enabling the state machine: CLK is
+    /// already driven at its idle level. Call [`start`](Self::start) to run it.
+    ///
+    /// # Notes
+    /// - Frames may be queued before [`start`](Self::start) (up to the TX FIFO depth);
+    ///   they are shifted once the master runs
+    /// - Transfers that wait for a response block until the master is started
+    ///
+    /// # Panics
+    /// Same as [`new`](Self::new)
+    pub fn new_disabled(
+        common: &mut Common<'d, PIO>,
+        sm: StateMachine<'d, PIO, SM>,
+        clk_pin: &Pin<'d, PIO>,
+        mosi_pin: &Pin<'d, PIO>,
+        miso_pin: &Pin<'d, PIO>,
+        config: SpiMasterConfig,
+    ) -> Self {
+        Self::try_new_disabled(common, sm, clk_pin, mosi_pin, miso_pin, config)
+            .expect("PIO SPI master could not be built")
+    }
+
+    /// Creates a PIO SPI Master that is not yet running, reporting errors instead of
+    /// panicking
+    ///
+    /// # Arguments
+    /// Same as [`new`](Self::new)
+    ///
+    /// # Returns
+    /// Same as [`try_new`](Self::try_new), except that the master is not running (see
+    /// [`new_disabled`](Self::new_disabled))
+    pub fn try_new_disabled(
+        common: &mut Common<'d, PIO>,
+        sm: StateMachine<'d, PIO, SM>,
+        clk_pin: &Pin<'d, PIO>,
+        mosi_pin: &Pin<'d, PIO>,
+        miso_pin: &Pin<'d, PIO>,
+        config: SpiMasterConfig,
+    ) -> Result<Self, InitError> {
+        Self::init(common, sm, clk_pin, mosi_pin, miso_pin, None, config)
+    }
+
+    /// Creates a new PIO SPI Master that drives chip select from the state machine
+    ///
+    /// # Arguments
+    /// * `common` - The PIO peripheral's common interface (for program loading and pin setup)
+    /// * `sm` - State machine (takes ownership)
+    /// * `clk_pin` - Clock pin (side-set/output)
+    /// * `mosi_pin` - MOSI pin (output)
+    /// * `miso_pin` - MISO pin (input)
+    /// * `cs_pin` - Active-low chip select pin (set/output)
+    /// * `config` - SPI configuration, including the `cs_*_cycles` timing
+    ///
+    /// # Behavior
+    /// CS is asserted when a frame is taken from the TX FIFO and deasserted after its read
+    /// phase, so every frame gets its own CS pulse with cycle-exact setup, hold and
+    /// minimum high times (see [`SpiMasterConfig`]).
+    ///
+    /// # Panics
+    /// If another state machine of the same PIO block already drives CLK, MOSI or CS, or
+    /// the program does not fit in the free instruction memory
+    /// (see [`try_new_with_cs`](Self::try_new_with_cs))
+    #[cfg(feature = "cs")]
+    pub fn new_with_cs(
+        common: &mut Common<'d, PIO>,
+        sm: StateMachine<'d, PIO, SM>,
+        clk_pin: &Pin<'d, PIO>,
+        mosi_pin: &Pin<'d, PIO>,
+        miso_pin: &Pin<'d, PIO>,
+        cs_pin: &Pin<'d, PIO>,
+        config: SpiMasterConfig,
+    ) -> Self {
+        Self::try_new_with_cs(common, sm, clk_pin, mosi_pin, miso_pin, cs_pin, config)
+            .expect("PIO SPI master could not be built")
+    }
+
+    /// Creates a new PIO SPI Master with PIO-managed chip select, reporting pin conflicts
+    /// and a full instruction memory instead of panicking
+    ///
+    /// # Arguments
+    /// Same as [`new_with_cs`](Self::new_with_cs)
+    ///
+    /// # Returns
+    /// * `Ok(PioSpiMaster)` - The master, running
+    /// * `Err(InitError::PinConflict)` - CLK, MOSI or CS is already driven by another
+    ///   state machine of the same PIO block; nothing was loaded and `sm` is dropped
+    /// * `Err(InitError::NoInstructionMemory)` - Fewer than
+    ///   [`cs_program_len`](SpiMasterConfig::cs_program_len) consecutive instruction slots
+    ///   are free; `sm` is dropped
+    /// * `Err(InitError::InvalidConfig)` / `Err(InitError::ProgramTooLong)` - As for
+    ///   [`try_new`](Self::try_new)
+    #[cfg(feature = "cs")]
+    pub fn try_new_with_cs(
+        common: &mut Common<'d, PIO>,
+        sm: StateMachine<'d, PIO, SM>,
+        clk_pin: &Pin<'d, PIO>,
+        mosi_pin: &Pin<'d, PIO>,
+        miso_pin: &Pin<'d, PIO>,
+        cs_pin: &Pin<'d, PIO>,
+        config: SpiMasterConfig,
+    ) -> Result<Self, InitError> {
+        let mut spi = Self::init(
+            common,
+            sm,
+            clk_pin,
+            mosi_pin,
+            miso_pin,
+            Some(cs_pin),
+            config,
+        )?;
+        spi.start();
+        Ok(spi)
+    }
+
+    /// Creates a PIO SPI Master with PIO-managed chip select that is fully configured but
+    /// not yet running
+    ///
+    /// # Arguments
+    /// Same as [`new_with_cs`](Self::new_with_cs)
+    ///
+    /// # Behavior
+    /// Everything [`new_with_cs`](Self::new_with_cs) does except enabling the state
+    /// machine: CLK and CS are already driven at their idle levels. See
+    /// [`new_disabled`](Self::new_disabled).
+    ///
+    /// # Panics
+    /// Same as [`new_with_cs`](Self::new_with_cs)
+    #[cfg(feature = "cs")]
+    pub fn new_with_cs_disabled(
+        common: &mut Common<'d, PIO>,
+        sm: StateMachine<'d, PIO, SM>,
+        clk_pin: &Pin<'d, PIO>,
+        mosi_pin: &Pin<'d, PIO>,
+        miso_pin: &Pin<'d, PIO>,
+        cs_pin: &Pin<'d, PIO>,
+        config: SpiMasterConfig,
+    ) -> Self {
+        Self::try_new_with_cs_disabled(common, sm, clk_pin, mosi_pin, miso_pin, cs_pin, config)
+            .expect("PIO SPI master could not be built")
+    }
+
+    /// Creates a PIO SPI Master with PIO-managed chip select that is not yet running,
+    /// reporting errors instead of panicking
+    ///
+    /// # Arguments
+    /// Same as [`new_with_cs`](Self::new_with_cs)
+    ///
+    /// # Returns
+    /// Same as [`try_new_with_cs`](Self::try_new_with_cs), except that the master is not
+    /// running (see [`new_disabled`](Self::new_disabled))
+    #[cfg(feature = "cs")]
+    pub fn try_new_with_cs_disabled(
+        common: &mut Common<'d, PIO>,
+        sm: StateMachine<'d, PIO, SM>,
+        clk_pin: &Pin<'d, PIO>,
+        mosi_pin: &Pin<'d, PIO>,
+        miso_pin: &Pin<'d, PIO>,
+        cs_pin: &Pin<'d, PIO>,
+        config: SpiMasterConfig,
+    ) -> Result<Self, InitError> {
+        Self::init(
+            common,
+            sm,
+            clk_pin,
+            mosi_pin,
+            miso_pin,
+            Some(cs_pin),
+            config,
+        )
+    }
+
+    /// Claims the output pins, loads the frame program for `config` (with CS if `cs_pin` is
+    /// given) and configures the state machine for it, leaving it disabled with the loop
+    /// count queued
+    fn init(
+        common: &mut Common<'d, PIO>,
+        sm: StateMachine<'d, PIO, SM>,
+        clk_pin: &Pin<'d, PIO>,
+        mosi_pin: &Pin<'d, PIO>,
+        miso_pin: &Pin<'d, PIO>,
+        cs_pin: Option<&Pin<'d, PIO>>,
+        config: SpiMasterConfig,
+    ) -> Result<Self, InitError> {
+        // Mirrors need their pins, which only `set_mirror` takes
+        if config.mirror != Mirror::default() {
+            return Err(InitError::InvalidConfig);
+        }
+        // Generate the program, then claim the driven pins before touching any hardware
+        let program = frame_program(&config, cs_pin.is_some())?;
+        let cs_pin_number = cs_pin.map(|pin| pin.pin());
+        claim_pins::<PIO, SM, 3>([Some(clk_pin.pin()), Some(mosi_pin.pin()), cs_pin_number])?;
+
+        // Load PIO program, giving the pins back if it does not fit
+        let Ok(_program) = common.try_load_program(&program) else {
+            release_claim::<PIO, SM>();
+            return Err(InitError::NoInstructionMemory {
+                needed: program.code.len(),
+            });
+        };
+
+        // Create configuration
+        let mut cfg = Config::default();
+
+        // Set pin configurations
+        // Side-set controls CLK (1 bit for state) - declared in PIO program
+        // OUT instructions shift MOSI (1 bit per state)
+        // IN instructions shift MISO (1 bit per state)
+        // SET instructions drive CS when it is PIO-managed
+        cfg.use_program(&_program, &[clk_pin]);
+        cfg.set_out_pins(&[mosi_pin]);
+        cfg.set_in_pins(&[miso_pin]);
+        if let Some(cs_pin) = cs_pin {
+            cfg.set_set_pins(&[cs_pin]);
+        }
+
+        // Configure clock divider
+        cfg.clock_divider = clock_divider(config.clk_div);
+
+        // Configure shift registers with auto-fill and dynamic thresholds
+        // Both thresholds equal message_size when it fits in one word, so each frame
+        // exactly empties the OSR and fills the ISR - no fixup instructions needed.
+        // Note: Hardware threshold is clamped to 0-32, so for message_size > 32,
+        // we clamp to 32: the first word is refilled/pushed automatically and the
+        // program handles the (message_size - 32)-bit remainder explicitly
+        let threshold = config.message_size.min(32) as u8;
+        cfg.shift_out.auto_fill = true;
+        cfg.shift_out.threshold = threshold;
+        cfg.shift_in.auto_fill = true;
+        cfg.shift_in.threshold = threshold;
+
+        // Shift left: OUT takes the MSB of the OSR first, IN leaves words right-justified.
+        // LSB-first frames of up to 32 bits shift right instead, so the hardware reverses
+        // them for free; longer frames keep shifting left and are reversed by the CPU
+        // (see `FrameFormat::pack` and `FrameFormat::unpack_words`).
+        cfg.shift_out.direction = shift_direction(config.tx_bit_order, config.message_size);
+        cfg.shift_in.direction = shift_direction(config.rx_bit_order, config.message_size);
+
+        // Apply configuration, CLK polarity, idle levels (CLK and CS HIGH) and pin
+        // directions, then enable
+        let mut sm = sm;
+        sm.set_config(&cfg);
+        set_clk_inversion(clk_pin.pin(), config.clk_polarity);
+        sm.set_pins(Level::High, &[clk_pin]);
+        sm.set_pin_dirs(Direction::Out, &[clk_pin, mosi_pin]);
+        sm.set_pin_dirs(Direction::In, &[miso_pin]);
+        if let Some(cs_pin) = cs_pin {
+            sm.set_pins(Level::High, &[cs_pin]);
+            sm.set_pin_dirs(Direction::Out, &[cs_pin]);
+        }
+
+        let mut spi = Self {
+            sm,
+            _program,
+            message_size: config.message_size,
+            tx_bit_order: config.tx_bit_order,
+            rx_bit_order: config.rx_bit_order,
+            clk_div: config.clk_div,
+            chosen_div: (config.clk_div, embassy_rp::clocks::clk_sys_freq()),
+            clk_pin: clk_pin.pin(),
+            mosi_pin: mosi_pin.pin(),
+            miso_pin: miso_pin.pin(),
+            cs_pin: cs_pin_number,
+            clk_polarity: config.clk_polarity,
+            cycles_per_bit: config.cycles_per_bit(),
+            interrupted: false,
+            captured: 0,
+            in_flight: 0,
+            rx_overflow: config.rx_overflow,
+            overflows: 0,
+            cfg,
+            format: config.frame_format(),
+            config,
+            isr_shared: false,
+            #[cfg(feature = "event-log")]
+            events: EventLog::new(),
+            #[cfg(feature = "cycle-stats")]
+            cycles: CycleStats::default(),
+        };
+        spi.push_loop_count();
+        Ok(spi)
+    }
+
+    /// Starts a master built with [`new_disabled`](Self::new_disabled) or
+    /// [`new_with_cs_disabled`](Self::new_with_cs_disabled)
+    ///
+    /// # Behavior
+    /// Restarts the clock divider so the first state machine cycle begins a full divider
+    /// period from now, then enables the state machine. Does nothing if already running.
+    pub fn start(&mut self) {
+        if self.sm.is_enabled() {
+            return;
+        }
+        self.sm.clkdiv_restart();
+        self.sm.set_enable(true);
+    }
+
+    /// Returns whether the state machine is running
+    pub fn is_running(&self) -> bool {
+        self.sm.is_enabled()
+    }
+
+    /// Restarts the state machine from the program start, re-establishing the loop count
+    ///
+    /// # Behavior
+    /// Without waiting for frames in flight: stops the state machine, clears both FIFOs and
+    /// its internal state, jumps to the program start, deasserts a PIO-managed CS, pushes
+    /// the loop count the program loads into Y first, and sets it running again. Frames
+    /// afterwards are paired with their responses as after [`new`](Self::new).
+    ///
+    /// # Notes
+    /// - A frame being shifted is cut short; toggle the slave's chip select before the
+    ///   next transfer
+    /// - Responses of frames in flight are lost; use [`resync`](Self::resync) to let them
+    ///   finish first
+    /// - Also starts a master built with [`new_disabled`](Self::new_disabled)
+    pub fn restart(&mut self) {
+        self.sm.set_clock_divider(clock_divider(self.clk_div));
+        self.reset_frames(true);
+    }
+
+    /// Pushes the loop count the program loads into Y at startup
+    ///
+    /// `jmp x--` runs the loop body X + 1 times, hence message_size - 1.
+    fn push_loop_count(&mut self) {
+        self.sm.tx().push(self.message_size as u32 - 1);
+    }
+
+    /// Aborts a frame left half-done by a cancelled async transfer
+    ///
+    /// # Behavior
+    /// If the previous [`transfer_async`](Self::transfer_async) future was dropped before
+    /// completing, the state machine may be stalled mid-frame with words still queued in
+    /// either FIFO. The state machine is reset to the start of the program, both FIFOs are
+    /// flushed and the loop count is reloaded, so the next frame starts from a clean state.
+    ///
+    /// # Notes
+    /// - The slave saw a truncated frame; toggle its chip select before the next transfer
+    /// - Responses queued by [`write_capture_later`](Self::write_capture_later) are lost
+    pub(crate) fn recover_if_interrupted(&mut self) {
+        if self.interrupted {
+            self.log_event(EventKind::Error(ErrorEvent::Recovered));
+            self.reset_frames(true);
+        }
+    }
+
+    /// Records `kind` in the event log (see [`crate::events`]); does nothing without the
+    /// `event-log` feature
+    pub(crate) fn log_event(&mut self, kind: EventKind) {
+        #[cfg(feature = "event-log")]
+        crate::events::record(&mut self.events, kind);
+        #[cfg(not(feature = "event-log"))]
+        let _ = kind;
+    }
+
+    /// Resets the state machine to the program start with empty FIFOs, CS deasserted, the
+    /// loop count reloaded and nothing in flight, then sets it running or not
+    pub(crate) fn reset_frames(&mut self, enable: bool) {
+        if self.isr_shared {
+            // The reset drops whatever the handle queued
+            isr::claim::<PIO, SM>();
+        }
+        reset_to_origin(&mut self.sm, self._program.origin);
+        // Deassert a PIO-managed CS (no-op without one, as no SET pins are mapped)
+        let cs_high = pio::InstructionOperands::SET {
+            destination: SetDestination::PINS,
+            data: 1,
+        };
+        // SAFETY: the state machine is disabled; SET only touches the CS pin
+        unsafe { self.sm.exec_instr(cs_high.encode()) };
+        self.push_loop_count();
+        self.sm.set_enable(enable);
+        self.interrupted = false;
+        self.captured = 0;
+        self.in_flight = 0;
+        self.release_fifos();
+    }
+
+    /// Takes the FIFOs from the ISR handle before the first frame in flight, discarding the
+    /// responses of its frames still to come
+    pub(crate) fn claim_fifos(&mut self) {
+        if !self.isr_shared || self.in_flight > 0 {
+            return;
+        }
+        for _ in 0..isr::claim::<PIO, SM>() {
+            self.pull_blocking();
+        }
+    }
+
+    /// Hands the FIFOs back to the ISR handle once no frame of the master is in flight
+    pub(crate) fn release_fifos(&mut self) {
+        if self.isr_shared && self.in_flight == 0 && !self.interrupted {
+            isr::release::<PIO, SM>();
+        }
+    }
+
+    /// Runs `f` with the raw state machine, then restores the invariants the master relies on
+    ///
+    /// # Arguments
+    /// * `f` - Closure given scoped access to the [`StateMachine`] (e.g. `exec_instr`, pin
+    ///   control or FIFO-level tweaks the crate does not wrap)
+    ///
+    /// # Returns
+    /// * `R` - Whatever `f` returns
+    ///
+    /// # Behavior
+    /// After `f` returns:
+    /// 1. The clock divider is rewritten from the master's `clk_div`
+    /// 2. If the program counter was moved outside the master's program, or back into the
+    ///    preamble that loads the loop count with none queued, the state machine is
+    ///    [restarted](Self::restart), as after a cancelled async transfer
+    /// 3. The state machine is re-enabled if `f` left it disabled
+    ///
+    /// # Notes
+    /// - Shift, pin mapping and wrap settings changed through `set_config` are not undone;
+    ///   a mismatch with the loaded program corrupts frames
+    /// - Use [`set_clk_div`](Self::set_clk_div) to change the speed; a divider set here is
+    ///   overwritten
+    pub fn with_sm<R>(&mut self, f: impl FnOnce(&mut StateMachine<'d, PIO, SM>) -> R) -> R {
+        let result = f(&mut self.sm);
+
+        self.sm.set_clock_divider(clock_divider(self.clk_div));
+        let origin = self._program.origin;
+        let len = self._program.wrap.source.wrapping_sub(origin) % 32 + 1;
+        let preamble = self._program.wrap.target.wrapping_sub(origin) % 32;
+        let offset = self.sm.get_addr().wrapping_sub(origin) % 32;
+        // Back in the preamble means the program was restarted, and its `pull` would take
+        // the next frame for the loop count
+        if offset >= len || (offset < preamble && self.sm.tx().empty()) {
+            self.restart();
+        }
+        if !self.sm.is_enabled() {
+            self.sm.set_enable(true);
+        }
+        result
+    }
+
+    /// Performs one transfer at a different SCK rate, then restores the current one
+    ///
+    /// # Arguments
+    /// * `hz` - SCK rate for this frame; the nearest integer divider is used, CLK phase
+    ///   stretching included
+    /// * `data` - Data to shift out on MOSI (only bits [message_size-1:0] are used)
+    ///
+    /// # Returns
+    /// * `u64` - Response bits read from MISO
+    ///
+    /// # Behavior
+    /// 1. Waits for frames queued earlier to be shifted, at the current rate
+    /// 2. Switches the divider, transfers the frame and switches back once its response
+    ///    is in
+    ///
+    /// # Notes
+    /// - For a slow status read between fast bursts, or the reverse, on a shared bus
+    /// - Responses of earlier write frames must fit the RX FIFO (see
+    ///   [`frames_in_flight`](Self::frames_in_flight)); a full RX FIFO stalls the state
+    ///   machine and step 1 never ends
+    /// - The CS hold and high times of a PIO-managed CS, which follow the last bit, run at
+    ///   the restored rate
+    pub fn transfer_at(&mut self, hz: u32, data: u64) -> u64 {
+        let previous = self.clk_div;
+        let clk_div = clk_div_for_period(hz, self.cycles_per_bit);
+        if clk_div == previous {
+            return self.transfer(data);
+        }
+        // A temporary speed: the divider `rescale_for_sysclk` works from stays as it was
+        let chosen = self.chosen_div;
+        self.wait_idle();
+        self.try_set_clk_div(clk_div);
+        let response = self.transfer(data);
+        self.try_set_clk_div(previous);
+        self.chosen_div = chosen;
+        response
+    }
+
+    /// Waits until the state machine has shifted every queued frame and stalls waiting
+    /// for the next one
+    ///
+    /// Both programs stall on the empty TX FIFO between frames (the CS program after
+    /// deasserting CS), which sets the sticky TX stall flag. A disabled state machine (not
+    /// yet [started](Self::start), or handed to the hardware SPI block) never stalls and
+    /// has nothing in flight, so this returns at once.
+    pub(crate) fn wait_idle(&mut self) {
+        if !self.sm.is_enabled() {
+            return;
+        }
+        while !self.sm.tx().empty() {}
+        let _ = self.sm.tx().stalled();
+        while !self.sm.tx().stalled() {}
+    }
+
+    /// Returns the configured message size in bits
+    pub fn message_size(&self) -> usize {
+        self.message_size
+    }
+
+    /// Returns the current clock divider setting
+    pub fn clk_div(&self) -> u16 {
+        self.clk_div
+    }
+
+    /// Changes the clock divider between transfers
+    ///
+    /// # Arguments
+    /// * `clk_div` - New divider, same meaning as [`SpiMasterConfig::clk_div`] (minimum 2)
+    ///
+    /// # Notes
+    /// - Takes effect immediately; call it only while no frame is being shifted
+    ///
+    /// # Panics
+    /// If `clk_div` is below 2 (see [`try_set_clk_div`](Self::try_set_clk_div))
+    pub fn set_clk_div(&mut self, clk_div: u16) {
+        assert!(self.try_set_clk_div(clk_div), "clk_div must be at least 2");
+    }
+
+    /// Changes the clock divider between transfers, refusing dividers below 2
+    ///
+    /// # Returns
+    /// * `bool` - `true` if the divider was applied, `false` if `clk_div` is below 2
+    pub fn try_set_clk_div(&mut self, clk_div: u16) -> bool {
+        if clk_div < 2 {
+            return false;
+        }
+        self.sm.set_clock_divider(clock_divider(clk_div));
+        self.sm.clkdiv_restart();
+        self.clk_div = clk_div;
+        self.chosen_div = (clk_div, embassy_rp::clocks::clk_sys_freq());
+        self.log_event(EventKind::Config(Setting::ClkDiv(clk_div)));
+        true
+    }
+
+    /// Recomputes the clock divider for a new system clock, keeping the SCK rate
+    ///
+    /// # Arguments
+    /// * `new_hz` - System clock in Hz the application switched (or is about to switch) to
+    ///
+    /// # Returns
+    /// * `u32` - SCK frequency in Hz at `new_hz`
+    ///
+    /// # Behavior
+    /// Scales the divider last chosen (when the master was built or
+    /// [`try_set_clk_div`](Self::try_set_clk_div) last ran) by the ratio of `new_hz` to the
+    /// system clock it was chosen for, rounding up so SCK never ends up faster than chosen,
+    /// and applies it as [`try_set_clk_div`](Self::try_set_clk_div) does. Every call scales
+    /// from that choice, so switching the clock back restores the chosen divider.
+    ///
+    /// # Notes
+    /// - embassy-rp has no hook for clock changes: call this on every master (and
+    ///   reconfigure other drivers) wherever the application reprograms `clk_sys`
+    /// - Call it between frames; a frame shifting across the clock change itself runs
+    ///   partly at the old rate scaled by the change
+    /// - A clock too slow for the chosen rate gives the smallest divider (2), so SCK drops
+    ///   below it
+    pub fn rescale_for_sysclk(&mut self, new_hz: u32) -> u32 {
+        let (chosen, sys_hz) = self.chosen_div;
+        let divider = (chosen - 1) as u64;
+        let scaled = (divider * new_hz as u64).div_ceil(sys_hz.max(1) as u64);
+        let clk_div = (scaled.clamp(1, u16::MAX as u64 - 1) + 1) as u16;
+        if clk_div != self.clk_div {
+            self.sm.set_clock_divider(clock_divider(clk_div));
+            self.sm.clkdiv_restart();
+            self.clk_div = clk_div;
+            self.log_event(EventKind::Config(Setting::ClkDiv(clk_div)));
+        }
+        new_hz / (clk_div - 1) as u32 / self.cycles_per_bit
+    }
+
+    /// Returns the current CLK idle level
+    pub fn clk_polarity(&self) -> ClkPolarity {
+        self.clk_polarity
+    }
+
+    /// Switches the CLK idle level between frames
+    ///
+    /// # Arguments
+    /// * `polarity` - New idle level
+    ///
+    /// # Behavior
+    /// 1. Waits until every queued frame has been shifted out and the state machine is
+    ///    waiting for the next one; a PIO-managed CS is deasserted at that point
+    /// 2. Inverts (or restores) the CLK pad output, so CLK moves to the new idle level in
+    ///    a single transition and no other edge is produced
+    ///
+    /// The new idle level is therefore only ever reached while no frame is in flight, so
+    /// the one edge it produces cannot clock a selected slave.
+    ///
+    /// # Notes
+    /// - With an application-managed CS, deassert every slave's CS before calling this
+    /// - Read the responses of queued frames first: a full RX FIFO stalls the state
+    ///   machine mid-frame, and this waits forever
+    pub fn set_clk_polarity(&mut self, polarity: ClkPolarity) {
+        if polarity == self.clk_polarity {
+            return;
+        }
+        self.wait_idle();
+        set_clk_inversion(self.clk_pin, polarity);
+        if let [Some(clk_mirror), _] = self.mirror_pins() {
+            set_clk_inversion(clk_mirror, polarity);
+        }
+        self.clk_polarity = polarity;
+        self.log_event(EventKind::Config(Setting::ClkPolarity(polarity)));
+    }
+
+    /// Tri-states CLK, MOSI, a PIO-managed CS and any mirror pins so another controller can
+    /// drive them
+    ///
+    /// # Behavior
+    /// 1. Waits until every queued frame has been shifted out (a PIO-managed CS is
+    ///    deasserted at that point)
+    /// 2. Overrides the pads' output enables off; the PIO keeps its pin directions and
+    ///    the state machine keeps running, so nothing has to be reconfigured afterwards
+    ///
+    /// Use it to hand the lines to a programmer (e.g. in-system flash programming) or a
+    /// second master; [`reclaim_pins`](Self::reclaim_pins) gives them back to the PIO.
+    ///
+    /// # Notes
+    /// - Frames transferred while released still run, but nothing is driven on the wire
+    /// - Read the responses of queued frames first: a full RX FIFO stalls the state
+    ///   machine mid-frame, and this waits forever
+    /// - An application-managed CS is not touched
+    pub fn release_pins(&mut self) {
+        self.wait_idle();
+        set_output_enable(self.output_pins(), false);
+    }
+
+    /// Resumes driving the pins released by [`release_pins`](Self::release_pins)
+    ///
+    /// # Notes
+    /// - The pins return to the levels the PIO holds for them (CLK and CS idle HIGH), so
+    ///   make sure the other controller has stopped driving them first
+    pub fn reclaim_pins(&mut self) {
+        set_output_enable(self.output_pins(), true);
+    }
+
+    /// Returns CLK, MOSI, a PIO-managed CS and the mirror pins in use
+    fn output_pins(&self) -> [Option<u8>; 5] {
+        let [clk_mirror, mosi_mirror] = self.mirror_pins();
+        [
+            Some(self.clk_pin),
+            Some(self.mosi_pin),
+            self.cs_pin,
+            clk_mirror,
+            mosi_mirror,
+        ]
+    }
+
+    /// Returns the GPIOs carrying the CLK and MOSI copies, if mirrored
+    pub(crate) fn mirror_pins(&self) -> [Option<u8>; 2] {
+        [
+            self.config.mirror.clk.then_some(self.clk_pin + 1),
+            self.config.mirror.mosi.then_some(self.mosi_pin + 1),
+        ]
+    }
+
+    /// Moves CLK, MOSI and MISO to other pins without rebuilding the master
+    ///
+    /// # Arguments
+    /// * `clk_pin` - New clock pin (side-set/output)
+    /// * `mosi_pin` - New MOSI pin (output)
+    /// * `miso_pin` - New MISO pin (input)
+    ///
+    /// # Returns
+    /// * `Ok(())` - Frames from now on use the new pins
+    /// * `Err(RemapError::PinConflict)` - Another state machine of the block drives
+    ///   `clk_pin` or `mosi_pin`; nothing was changed
+    /// * `Err(RemapError::Mirrored)` - CLK or MOSI is [mirrored](Self::set_mirror);
+    ///   nothing was changed
+    ///
+    /// # Behavior
+    /// 1. Waits until every queued frame has been shifted out and stops the state machine
+    /// 2. Turns the old CLK and MOSI pins that are not reused into inputs and drops the CLK
+    ///    inversion of the old CLK pin
+    /// 3. Re-executes the pin configuration with the new pins: side-set, OUT and IN bases,
+    ///    CLK polarity, idle levels and directions
+    /// 4. Restarts the program with empty FIFOs and the loop count reloaded, as
+    ///    [`restart`](Self::restart) does
+    ///
+    /// Useful behind an analog mux, or to try candidate wirings during bring-up. A
+    /// PIO-managed CS stays on its pin.
+    ///
+    /// # Notes
+    /// - Read the responses of queued frames first: a full RX FIFO stalls the state
+    ///   machine mid-frame, and this waits forever
+    /// - Pad settings (pulls, drive strength) are not carried over to the new pins
+    pub fn remap_pins(
+        &mut self,
+        clk_pin: &Pin<'d, PIO>,
+        mosi_pin: &Pin<'d, PIO>,
+        miso_pin: &Pin<'d, PIO>,
+    ) -> Result<(), RemapError> {
+        if self.config.mirror != Mirror::default() {
+            return Err(RemapError::Mirrored);
+        }
+        let (clk, mosi) = (clk_pin.pin(), mosi_pin.pin());
+        claim_pins::<PIO, SM, 3>([Some(clk), Some(mosi), self.cs_pin])?;
+        let running = self.sm.is_enabled();
+        self.wait_idle();
+        self.sm.set_enable(false);
+
+        set_clk_inversion(self.clk_pin, ClkPolarity::IdleHigh);
+        for old in [self.clk_pin, self.mosi_pin] {
+            if old != clk && old != mosi {
+                release_pin_dir(&mut self.sm, old);
+            }
+        }
+
+        self.cfg.use_program(&self._program, &[clk_pin]);
+        self.cfg.set_out_pins(&[mosi_pin]);
+        self.cfg.set_in_pins(&[miso_pin]);
+        self.cfg.clock_divider = clock_divider(self.clk_div);
+        // The stale origin `set_config` jumps to is replaced by `reset_frames`
+        self.sm.set_config(&self.cfg);
+        set_clk_inversion(clk, self.clk_polarity);
+        self.sm.set_pins(Level::High, &[clk_pin]);
+        self.sm.set_pin_dirs(Direction::Out, &[clk_pin, mosi_pin]);
+        self.sm.set_pin_dirs(Direction::In, &[miso_pin]);
+        (self.clk_pin, self.mosi_pin, self.miso_pin) = (clk, mosi, miso_pin.pin());
+        self.log_event(EventKind::Config(Setting::Pins {
+            clk,
+            mosi,
+            miso: self.miso_pin,
+        }));
+
+        self.reset_frames(running);
+        Ok(())
+    }
+
+    /// Returns the time the state machine takes to shift one frame (write and read phase)
+    /// at the current divider, rounded up to whole microseconds
+    pub fn frame_duration(&self) -> embassy_time::Duration {
+        // One or two phases of message_size bits plus their `mov x, y` setup cycles, and
+        // the trailer periods plus their `set x`
+        let phases = match self.config.duplex {
+            Duplex::Half => 2,
+            Duplex::Full => 1,
+        };
+        let trailer = match self.config.trailer_cycles {
+            0 => 0,
+            periods => periods as u64 * self.cycles_per_bit as u64 + 1,
+        };
+        let cycles = phases * (self.message_size as u64 * self.cycles_per_bit as u64 + 1) + trailer;
+        let sm_hz = sm_frequency(self.clk_div) as u64;
+        embassy_time::Duration::from_micros((cycles * 1_000_000).div_ceil(sm_hz))
+    }
+
+    /// Finds the fastest clock divider at which `test_fn` still passes
+    ///
+    /// # Arguments
+    /// * `test_fn` - Runs one or more verification transfers (e.g. reading a device ID or
+    ///   an echo register) and returns `true` if every response was correct
+    ///
+    /// # Returns
+    /// * `Some(u16)` - Fastest passing divider, which is left applied
+    /// * `None` - `test_fn` failed at the current divider; the divider is left unchanged
+    ///
+    /// # Behavior
+    /// 1. Runs `test_fn` at the current divider (the slowest candidate)
+    /// 2. Decreases the divider one step at a time, rerunning `test_fn` after each change
+    /// 3. Stops at the first failure (or at divider 2) and backs off to the last passing value
+    ///
+    /// ```ignore
+    /// let best = spi.find_max_frequency(|spi| (0..16).all(|_| spi.transfer(READ_ID) & 0xFF == ID));
+    /// ```
+    ///
+    /// # Notes
+    /// - Include enough transfers in `test_fn` to catch marginal timing; a single pass at the
+    ///   edge of reliability is not a guarantee
+    /// - Pick a slower divider than the result if the wiring or temperature may change
+    pub fn find_max_frequency<F>(&mut self, mut test_fn: F) -> Option<u16>
+    where
+        F: FnMut(&mut Self) -> bool,
+    {
+        let start = self.clk_div;
+        if !test_fn(self) {
+            return None;
+        }
+
+        let mut best = start;
+        while best > 2 {
+            self.try_set_clk_div(best - 1);
+            if !test_fn(self) {
+                break;
+            }
+            best -= 1;
+        }
+        self.try_set_clk_div(best);
+        Some(best)
+    }
+
+    /// Performs one SPI transfer: write then read, or both at once with [`Duplex::Full`]
+    ///
+    /// # Arguments
+    /// * `data` - Data to shift out on MOSI (only bits [message_size-1:0] are used)
+    ///
+    /// # Returns
+    /// * `u64` - Response bits read from MISO (padded to u64)
+    ///
+    /// # Behavior
+    /// 1. Splits the data into 32-bit words for TX FIFO (see [`WordOrder`])
+    /// 2. PIO write phase: Shifts out message_size bits to MOSI while toggling CLK
+    ///    - Auto-fill refills OSR from TX FIFO as bits are shifted
+    /// 3. PIO read phase: Shifts in message_size bits from MISO while toggling CLK
+    ///    ([`Duplex::Full`] frames sample MISO during the write phase instead)
+    /// 4. PIO pushes result to RX FIFO
+    /// 5. Combines RX FIFO reads into result
+    ///
+    /// # Notes
+    /// - Always clocks a read: a separate phase on [`Duplex::Half`] frames, the write phase
+    ///   itself on [`Duplex::Full`] frames
+    /// - Implements SPI Mode 3 timing (CPOL=1, CPHA=1), or Mode 1 with [`ClkPolarity::IdleLow`]
+    /// - Clock toggled for every bit shifted
+    /// - Auto-fill handles FIFO refilling during operation
+    pub fn transfer(&mut self, data: u64) -> u64 {
+        self.push_frame(data);
+        self.pull_frame()
+    }
+
+    /// Performs an SPI transfer, awaiting FIFO space and the response
+    ///
+    /// Same behavior as [`transfer`](Self::transfer), but yields to the executor while the
+    /// TX FIFO is full or the RX FIFO is empty. Requires the PIO interrupt handler to be bound.
+    ///
+    /// # Cancel Safety
+    /// Dropping the future before it completes (e.g. a `select` timeout) may leave part of
+    /// the frame queued or its response unread. The next call on this master detects this,
+    /// aborts the partial frame and resets the state machine before sending (see
+    /// `recover_if_interrupted`), so responses never get attributed to the wrong frame.
+    pub async fn transfer_async(&mut self, data: u64) -> u64 {
+        self.recover_if_interrupted();
+        self.claim_fifos();
+        self.interrupted = true;
+
+        self.log_event(EventKind::Frame(data & self.format.tx_mask()));
+        let (words, count) = self.format.pack(data & self.format.tx_mask());
+        for &word in &words[..count] {
+            self.sm.tx().wait_push(word).await;
+        }
+        self.in_flight += 1;
+
+        let first = self.sm.rx().wait_pull().await;
+        let response = if self.message_size <= 32 {
+            self.format.unpack_word(first)
+        } else {
+            let second = self.sm.rx().wait_pull().await;
+            self.format.unpack_words(first, second)
+        };
+        self.in_flight -= 1;
+
+        self.interrupted = false;
+        self.release_fifos();
+        response
+    }
+
+    /// Performs an SPI transfer, polling the FIFOs and yielding in between
+    ///
+    /// Same behavior as [`transfer_async`](Self::transfer_async), but instead of waiting
+    /// for the PIO interrupt it checks the FIFO status and yields to the executor while the
+    /// TX FIFO is full or the RX FIFO is empty. Use it where the PIO block's interrupt is
+    /// owned by another subsystem (e.g. cyw43 on the Pico W) or not bound at all.
+    ///
+    /// # Notes
+    /// - The task is polled again right away, so the executor never sleeps while a
+    ///   transfer is pending; prefer [`transfer_async`](Self::transfer_async) where the
+    ///   interrupt is available
+    ///
+    /// # Cancel Safety
+    /// Same as [`transfer_async`](Self::transfer_async)
+    pub async fn transfer_polling(&mut self, data: u64) -> u64 {
+        self.recover_if_interrupted();
+        self.claim_fifos();
+        self.interrupted = true;
+
+        self.log_event(EventKind::Frame(data & self.format.tx_mask()));
+        let (words, count) = self.format.pack(data & self.format.tx_mask());
+        for &word in &words[..count] {
+            while !self.sm.tx().try_push(word) {
+                yield_now().await;
+            }
+        }
+        self.in_flight += 1;
+
+        let mut rx = [0u32; 2];
+        for word in &mut rx[..count] {
+            *word = loop {
+                match self.sm.rx().try_pull() {
+                    Some(word) => break word,
+                    None => yield_now().await,
+                }
+            };
+        }
+        let response = if count == 1 {
+            self.format.unpack_word(rx[0])
+        } else {
+            self.format.unpack_words(rx[0], rx[1])
+        };
+        self.in_flight -= 1;
+
+        self.interrupted = false;
+        self.release_fifos();
+        response
+    }
+
+    /// Transfers a run of byte frames, four to a FIFO word
+    ///
+    /// # Arguments
+    /// * `buf` - Bytes to shift out on MOSI; each is replaced by the response to its frame
+    ///
+    /// # Behavior
+    /// 1. Discards the responses of frames still in flight (see
+    ///    [`discard_stale`](Self::discard_stale))
+    /// 2. Raises both shift thresholds to 32 bits, so every TX word feeds four frames and
+    ///    every RX word collects four responses, and restarts the program with them
+    /// 3. Streams the bytes in words, refilling the TX FIFO as responses are collected;
+    ///    frames follow each other with the usual 2-cycle gap
+    /// 4. Restores one frame per word and sends the 0-3 trailing bytes as single frames
+    ///
+    /// # Notes
+    /// - Requires `message_size = 8`; use [`Duplex::Full`] for the usual byte-stream
+    ///   devices, which answer while the command byte is still arriving
+    /// - CS is not touched between bytes: hold an application-managed CS across the call.
+    ///   A PIO-managed CS still pulses once per byte
+    /// - [`BitOrder::LsbFirst`] applies to each byte, as with single frames
+    ///
+    /// # Panics
+    /// If `message_size` is not 8, an [ISR handle](Self::isr_handle) was issued or MOSI is
+    /// mirrored (see [`try_transfer_bytes`](Self::try_transfer_bytes))
+    pub fn transfer_bytes(&mut self, buf: &mut [u8]) {
+        assert_eq!(self.message_size, 8, "transfer_bytes requires 8-bit frames");
+        assert!(
+            self.try_transfer_bytes(buf),
+            "transfer_bytes is not available with an ISR handle or mirrored MOSI"
+        );
+    }
+
+    /// Transfers a byte buffer in place like [`transfer_bytes`](Self::transfer_bytes),
+    /// refusing instead of panicking
+    ///
+    /// # Returns
+    /// * `bool` - `true` if the bytes were transferred, `false` (with `buf` untouched) if
+    ///   `message_size` is not 8, an [ISR handle](Self::isr_handle) was issued or MOSI is
+    ///   [mirrored](Self::set_mirror) (packed words carry single bits)
+    pub fn try_transfer_bytes(&mut self, buf: &mut [u8]) -> bool {
+        if self.message_size != 8 || self.isr_shared || self.config.mirror.mosi {
+            return false;
+        }
+        self.discard_stale();
+
+        let (packed, tail) = buf.split_at_mut(buf.len() / 4 * 4);
+        if !packed.is_empty() {
+            self.set_packed(true);
+            let words = packed.len() / 4;
+            let (mut sent, mut received) = (0, 0);
+            while received < words {
+                if sent < words {
+                    let b = &packed[sent * 4..sent * 4 + 4];
+                    let bytes = [b[0], b[1], b[2], b[3]];
+                    let word = match self.tx_bit_order {
+                        BitOrder::MsbFirst => u32::from_be_bytes(bytes),
+                        BitOrder::LsbFirst => u32::from_le_bytes(bytes),
+                    };
+                    if self.sm.tx().try_push(word) {
+                        sent += 1;
+                    }
+                }
+                if let Some(word) = self.sm.rx().try_pull() {
+                    let bytes = match self.rx_bit_order {
+                        BitOrder::MsbFirst => word.to_be_bytes(),
+                        BitOrder::LsbFirst => word.to_le_bytes(),
+                    };
+                    packed[received * 4..received * 4 + 4].copy_from_slice(&bytes);
+                    received += 1;
+                }
+            }
+            self.set_packed(false);
+        }
+
+        // Single frames honor the alignment settings, which bytes do not use
+        let pad = self.format.padding_bits();
+        for byte in tail {
+            let data = match self.config.tx_alignment {
+                Alignment::Right => *byte as u64,
+                Alignment::Left => (*byte as u64) << pad,
+            };
+            let response = self.transfer(data);
+            *byte = match self.config.rx_alignment {
+                Alignment::Right => response as u8,
+                Alignment::Left => (response >> pad) as u8,
+            };
+        }
+        true
+    }
+
+    /// Switches the shift thresholds between one byte frame per FIFO word and four
+    ///
+    /// Waits for the state machine to idle, then restarts the program so the OSR and ISR
+    /// start out empty under the new threshold.
+    fn set_packed(&mut self, packed: bool) {
+        let running = self.sm.is_enabled();
+        if running {
+            self.wait_idle_discarding();
+        }
+        self.sm.set_enable(false);
+        // A threshold of 0 means 32 bits
+        let threshold = if packed { 0 } else { self.message_size as u8 };
+        self.sm.set_thresholds(threshold);
+        self.reset_frames(running);
+    }
+
+    /// Performs an SPI transfer and reports FIFO conditions that corrupt it
+    ///
+    /// # Arguments
+    /// * `data` - Data to shift out on MOSI (only bits [message_size-1:0] are used)
+    ///
+    /// # Returns
+    /// * `TransferResult` - Response plus overflow/underrun/stall flags
+    ///
+    /// # Behavior
+    /// 1. Clears the sticky RX stall flag and notes whether responses of earlier frames are
+    ///    unread
+    /// 2. Pushes the frame; for >32-bit frames, waits for the first word to be taken and
+    ///    checks that the second word arrived before the state machine ran out of bits
+    /// 3. Pulls the response and reads the RX stall flag
+    ///
+    /// # Notes
+    /// - Slightly slower than [`transfer`](Self::transfer); use it where silent corruption
+    ///   must be caught
+    pub fn transfer_checked(&mut self, data: u64) -> TransferResult {
+        self.recover_if_interrupted();
+        self.claim_fifos();
+        let _ = self.sm.rx().stalled();
+        let rx_overflow = self.in_flight > 0 || self.sm.rx().level() > 0;
+
+        self.log_event(EventKind::Frame(data & self.format.tx_mask()));
+        let (words, count) = self.format.pack(data & self.format.tx_mask());
+        self.in_flight += 1;
+        self.push_word(words[0]);
+        let mut tx_underrun = false;
+        if count == 2 {
+            // Once the first word is in the OSR the state machine is mid-frame, so a TX
+            // stall from here on means the clock paused between the two words
+            while !self.sm.tx().empty() {}
+            let _ = self.sm.tx().stalled();
+            self.push_word(words[1]);
+            tx_underrun = self.sm.tx().stalled();
+        }
+
+        let data = self.pull_frame();
+        let stalled = self.sm.rx().stalled();
+        if stalled || tx_underrun {
+            self.log_event(EventKind::Stall);
+        }
+        TransferResult {
+            data,
+            rx_overflow,
+            tx_underrun,
+            stalled,
+        }
+    }
+
+    /// Performs a write-then-read transfer without masking input or output
+    ///
+    /// # Arguments
+    /// * `data` - Pre-packed frame; bits outside the frame (see [`Alignment`]) must be zero
+    ///
+    /// # Returns
+    /// * `u64` - Response bits exactly as reassembled from the RX FIFO
+    ///
+    /// # Notes
+    /// - Skips the mask step of [`transfer`](Self::transfer) for hot paths where the caller
+    ///   already guarantees the frame fits
+    /// - With the `raw-checks` feature, debug builds assert that no bits outside the frame
+    ///   are set
+    pub fn transfer_raw(&mut self, data: u64) -> u64 {
+        #[cfg(feature = "raw-checks")]
+        debug_assert!(
+            data & !self.format.tx_mask() == 0,
+            "transfer_raw data has bits outside the frame"
+        );
+        self.push_frame_raw(data);
+        self.pull_frame()
+    }
+
+    /// Splits a frame into TX FIFO words and pushes them
+    ///
+    /// OUT shifts from the MSB of the OSR, so each word is left-justified:
+    /// - **<=32 bits**: One word, data in bits [31:32-message_size]; LSB-first frames
+    ///   shift right and stay right-justified
+    /// - **>32 bits**: A full 32-bit word, then the remaining (message_size - 32) bits
+    ///   left-justified; [`WordOrder`] selects whether the high or low part goes first.
+    ///   LSB-first frames are bit-reversed before being split
+    pub(crate) fn push_frame(&mut self, data: u64) {
+        // Extract only the bits we need
+        self.push_frame_raw(data & self.format.tx_mask());
+    }
+
+    /// Packs and pushes a frame whose bits outside the frame are already clear
+    fn push_frame_raw(&mut self, data: u64) {
+        self.recover_if_interrupted();
+        self.claim_fifos();
+        self.log_event(EventKind::Frame(data));
+        let (words, count) = self.format.pack(data);
+        for &word in &words[..count] {
+            self.push_word(word);
+        }
+        self.in_flight += 1;
+    }
+
+    /// Pushes a TX word, spinning while the FIFO is full (timed with the `cycle-stats`
+    /// feature)
+    fn push_word(&mut self, word: u32) {
+        #[cfg(feature = "cycle-stats")]
+        if self.sm.tx().full() {
+            let start = cycle_count();
+            self.sm.tx().push(word);
+            self.cycles.push.blocked(cycle_count().wrapping_sub(start));
+            return;
+        }
+        #[cfg(feature = "cycle-stats")]
+        self.cycles.push.ready();
+        self.sm.tx().push(word);
+    }
+
+    /// Pulls a frame's RX FIFO words and reassembles them
+    ///
+    /// IN shifts into the LSB of the ISR, so each word is right-justified:
+    /// - **<=32 bits**: One word holding the whole frame; LSB-first frames shift right and
+    ///   arrive left-justified
+    /// - **>32 bits**: A full 32-bit word, then the remaining (message_size - 32) bits;
+    ///   [`WordOrder`] selects whether the first word is the high or low part.
+    ///   LSB-first frames are bit-reversed after being reassembled
+    pub(crate) fn pull_frame(&mut self) -> u64 {
+        self.in_flight = self.in_flight.saturating_sub(1);
+        let first = self.pull_blocking();
+        let response = if self.message_size <= 32 {
+            self.format.unpack_word(first)
+        } else {
+            let second = self.pull_blocking();
+            self.format.unpack_words(first, second)
+        };
+        self.release_fifos();
+        response
+    }
+
+    /// Pulls a word from the RX FIFO, waiting until the PIO has pushed one
+    pub(crate) fn pull_blocking(&mut self) -> u32 {
+        if let Some(word) = self.sm.rx().try_pull() {
+            #[cfg(feature = "cycle-stats")]
+            self.cycles.pull.ready();
+            return word;
+        }
+        #[cfg(feature = "cycle-stats")]
+        let start = cycle_count();
+        loop {
+            if let Some(word) = self.sm.rx().try_pull() {
+                #[cfg(feature = "cycle-stats")]
+                self.cycles.pull.blocked(cycle_count().wrapping_sub(start));
+                return word;
+            }
+        }
+    }
+
+    /// Performs a write-only SPI transfer
+    ///
+    /// # Arguments
+    /// * `data` - Data to shift out on MOSI (only bits [message_size-1:0] are used)
+    ///
+    /// # Behavior
+    /// Pushes data words to TX FIFO without waiting for RX response. The PIO will still
+    /// perform both write and read phases internally, but this method returns immediately
+    /// without consuming the RX FIFO.
+    ///
+    /// Useful for:
+    /// - Command sequences where response isn't needed
+    /// - Streaming data bursts
+    /// - Avoiding RX FIFO deadlock when multiple writes precede a read
+    ///
+    /// # Notes
+    /// - Does not read RX FIFO (caller responsible for draining if needed)
+    /// - PIO still samples MISO internally (its read phase, or during the write phase on
+    ///   [`Duplex::Full`] frames)
+    pub fn write(&mut self, data: u64) {
+        if self.admit_frame() {
+            self.push_frame(data);
+        }
+    }
+
+    /// Performs a write-only SPI transfer whose response is collected later
+    ///
+    /// # Arguments
+    /// * `data` - Data to shift out on MOSI (only bits [message_size-1:0] are used)
+    ///
+    /// # Behavior
+    /// Pushes the frame like [`write`](Self::write) and records that its response is
+    /// pending, so a burst of writes can be pipelined and the answers fetched afterwards
+    /// with [`drain_responses`](Self::drain_responses).
+    ///
+    /// # Notes
+    /// - Responses wait in the RX FIFO: once it is full (4 words, i.e. 4 frames of up to 32
+    ///   bits or 2 longer ones) the state machine stalls, and a few frames later pushing
+    ///   blocks forever. Drain between longer bursts
+    /// - Do not interleave [`write`](Self::write) or [`transfer`](Self::transfer) with
+    ///   pending responses: their frames share the RX FIFO in order
+    pub fn write_capture_later(&mut self, data: u64) {
+        if self.admit_frame() {
+            self.push_frame(data);
+            self.captured += 1;
+        }
+    }
+
+    /// Returns how many [`write_capture_later`](Self::write_capture_later) responses have
+    /// not been drained yet
+    pub fn pending_responses(&self) -> usize {
+        self.captured
+    }
+
+    /// Collects the responses of earlier [`write_capture_later`](Self::write_capture_later)
+    /// frames, oldest first
+    ///
+    /// # Arguments
+    /// * `out` - Destination for the responses
+    ///
+    /// # Returns
+    /// * `usize` - Responses written to `out`: the pending count or `out.len()`, whichever
+    ///   is smaller; the rest stay pending
+    ///
+    /// # Notes
+    /// - Blocks until each collected frame has completed
+    pub fn drain_responses(&mut self, out: &mut [u64]) -> usize {
+        let count = self.captured.min(out.len());
+        for response in &mut out[..count] {
+            *response = self.pull_frame();
+        }
+        self.captured -= count;
+        count
+    }
+
+    /// Applies the [`RxOverflowPolicy`] before a write-only frame is queued
+    ///
+    /// # Returns
+    /// * `bool` - `true` if the frame may be queued
+    pub(crate) fn admit_frame(&mut self) -> bool {
+        let capacity = self.pipeline_depth();
+        if self.in_flight < capacity {
+            return true;
+        }
+        match self.rx_overflow {
+            RxOverflowPolicy::Block => true,
+            RxOverflowPolicy::DropOldest => {
+                while self.in_flight >= capacity {
+                    self.pull_frame();
+                    self.overflows = self.overflows.saturating_add(1);
+                    self.log_event(EventKind::Error(ErrorEvent::ResponseDropped));
+                }
+                self.captured = self.captured.min(self.in_flight);
+                true
+            }
+            RxOverflowPolicy::Error => {
+                self.overflows = self.overflows.saturating_add(1);
+                self.log_event(EventKind::Error(ErrorEvent::FrameRejected));
+                false
+            }
+        }
+    }
+
+    /// Returns how many responses were dropped or frames rejected under the
+    /// [`RxOverflowPolicy`], and resets the count
+    pub fn rx_overflows(&mut self) -> u32 {
+        core::mem::take(&mut self.overflows)
+    }
+
+    /// Returns how many frames were queued whose responses have not been read yet
+    ///
+    /// # Notes
+    /// - Every frame's response is read in order, so a transfer started now returns the
+    ///   response of the oldest of these frames unless this is 0
+    pub fn frames_in_flight(&self) -> usize {
+        self.in_flight
+    }
+
+    /// Checks that the next response read belongs to the next frame queued
+    ///
+    /// # Returns
+    /// * `Ok(())` - No responses are outstanding and the RX FIFO is empty
+    /// * `Err(Desync)` - Earlier responses are unread, or the RX FIFO holds words no queued
+    ///   frame accounts for
+    pub fn check_sync(&mut self) -> Result<(), Desync> {
+        let words = self.message_size.div_ceil(32);
+        let result = if self.sm.rx().level() as usize > self.in_flight * words {
+            Err(Desync::Unaccounted)
+        } else {
+            match self.in_flight {
+                0 => Ok(()),
+                stale => Err(Desync::Stale(stale)),
+            }
+        };
+        if let Err(desync) = result {
+            self.log_event(EventKind::Error(ErrorEvent::Desync(desync)));
+        }
+        result
+    }
+
+    /// Performs an SPI transfer only if its response cannot be a stale one
+    ///
+    /// # Arguments
+    /// * `data` - Data to shift out on MOSI (only bits [message_size-1:0] are used)
+    ///
+    /// # Returns
+    /// * `Ok(u64)` - Response to this frame
+    /// * `Err(Desync)` - [`check_sync`](Self::check_sync) failed; nothing was sent
+    pub fn transfer_strict(&mut self, data: u64) -> Result<u64, Desync> {
+        self.check_sync()?;
+        Ok(self.transfer(data))
+    }
+
+    /// Waits for every frame in flight and discards its response, resynchronizing the FIFOs
+    ///
+    /// # Returns
+    /// * `usize` - Responses discarded, pending [`write_capture_later`](Self::write_capture_later)
+    ///   responses included
+    ///
+    /// # Notes
+    /// - Unaccounted RX words are flushed as well
+    pub fn discard_stale(&mut self) -> usize {
+        let stale = self.in_flight;
+        for _ in 0..stale {
+            self.pull_frame();
+        }
+        while self.sm.rx().try_pull().is_some() {}
+        self.captured = 0;
+        stale
+    }
+
+    /// Restores the pairing of frames and responses after an application bug or abort
+    ///
+    /// # Arguments
+    /// * `known_answer` - Optional `(frame, response)` pair whose response is fixed (e.g. an
+    ///   ID register read), sent afterwards to verify the pairing; pass `None` where no
+    ///   frame is safe to send
+    ///
+    /// # Returns
+    /// * `Ok(())` - Frames and responses are paired again
+    /// * `Err(Desync::Mismatch)` - The known-answer frame got a different response
+    ///
+    /// # Behavior
+    /// 1. Lets queued frames finish, discarding every RX word so a full RX FIFO cannot
+    ///    hold them up, until the state machine stalls waiting for the next frame
+    /// 2. Resets the state machine to the program start with empty FIFOs, as after a
+    ///    cancelled async transfer, which also ends a frame stuck waiting for its second
+    ///    word
+    /// 3. Transfers the known-answer frame, if any, and compares its response
+    ///
+    /// # Notes
+    /// - Responses of frames in flight, pending [`write_capture_later`](Self::write_capture_later)
+    ///   ones included, are discarded
+    /// - A master that is not running is only reset
+    pub fn resync(&mut self, known_answer: Option<(u64, u64)>) -> Result<(), Desync> {
+        let running = self.sm.is_enabled();
+        if running {
+            self.wait_idle_discarding();
+        }
+        self.reset_frames(running);
+
+        match known_answer {
+            Some((frame, expected)) if running => {
+                let response = self.transfer(frame);
+                if response == expected & self.format.rx_mask() {
+                    Ok(())
+                } else {
+                    Err(Desync::Mismatch(response))
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Waits until the state machine stalls waiting for the next frame, discarding every RX
+    /// word so a full RX FIFO cannot hold queued frames up
+    pub(crate) fn wait_idle_discarding(&mut self) {
+        while !self.sm.tx().empty() {
+            while self.sm.rx().try_pull().is_some() {}
+        }
+        let _ = self.sm.tx().stalled();
+        while !self.sm.tx().stalled() {
+            while self.sm.rx().try_pull().is_some() {}
+        }
+    }
+
+    /// Returns whether frames are sequential or full-duplex
+    pub fn duplex(&self) -> Duplex {
+        self.config.duplex
+    }
+
+    /// Switches between the sequential and the full-duplex frame program
+    ///
+    /// # Arguments
+    /// * `common` - The PIO peripheral's common interface the master was built with
+    /// * `duplex` - Frame shape to use from now on
+    ///
+    /// # Returns
+    /// * `Ok(())` - The new program is loaded (or `duplex` was already in use)
+    /// * `Err(InitError::NoInstructionMemory)` - The new program does not fit where the
+    ///   old one was; the old program is loaded again
+    ///
+    /// # Behavior
+    /// Lets queued frames finish, then unloads the current program, loads the other one
+    /// and restarts the state machine from it with empty FIFOs, as
+    /// [`resync`](Self::resync) does. Timing, CS and bit order settings carry over.
+    ///
+    /// # Notes
+    /// - Responses of frames in flight are discarded; collect them first
+    /// - The full-duplex program is one or two instructions shorter, so switching to it
+    ///   always fits; switching back can fail if another driver took the freed slots
+    pub fn set_duplex(
+        &mut self,
+        common: &mut Common<'d, PIO>,
+        duplex: Duplex,
+    ) -> Result<(), InitError> {
+        if duplex == self.config.duplex {
+            return Ok(());
+        }
+        let with_cs = self.cs_pin.is_some();
+        let config = SpiMasterConfig {
+            duplex,
+            ..self.config
+        };
+        let program = frame_program(&config, with_cs)?;
+        let old = frame_program(&self.config, with_cs)?;
+
+        let running = self.sm.is_enabled();
+        if running {
+            self.wait_idle_discarding();
+        }
+        self.sm.set_enable(false);
+        // SAFETY: the state machine is stopped and the program is private to this master.
+        // `InstanceMemory` is a plain mask without `Drop`, so the copy read out of
+        // `_program` only marks the slots free; `install` overwrites `_program` below
+        unsafe {
+            let used_memory = core::ptr::read(&self._program.used_memory);
+            common.free_instr(used_memory);
+        }
+        let result = match common.try_load_program(&program) {
+            Ok(loaded) => {
+                self.config = config;
+                self.format = config.frame_format();
+                self.install(loaded);
+                self.log_event(EventKind::Config(Setting::Duplex(duplex)));
+                Ok(())
+            }
+            Err(_) => {
+                // The old program fits back into the slots it was just freed from
+                if let Ok(loaded) = common.try_load_program(&old) {
+                    self.install(loaded);
+                }
+                Err(InitError::NoInstructionMemory {
+                    needed: program.code.len(),
+                })
+            }
+        };
+        self.reset_frames(running);
+        result
+    }
+
+    /// Drives copies of CLK and/or MOSI on the GPIOs above them, e.g. to feed two slaves
+    /// wired in different directions without an external buffer
+    ///
+    /// # Arguments
+    /// * `common` - The PIO peripheral's common interface the master was built with
+    /// * `clk_mirror` - Pin to carry a copy of CLK: the GPIO above CLK, or `None` to stop
+    ///   mirroring CLK
+    /// * `mosi_mirror` - Pin to carry a copy of MOSI: the GPIO above MOSI, or `None` to
+    ///   stop mirroring MOSI
+    ///
+    /// # Returns
+    /// * `Ok(())` - Frames from now on drive the mirrors (or nothing had to change)
+    /// * `Err(InitError::InvalidConfig)` - A mirror pin is not directly above its original
+    ///   or is one of the master's other pins, MOSI is mirrored with frames over 16 bits,
+    ///   or CLK is mirrored with more than 3 delay cycles on an instruction (e.g.
+    ///   `clk_low_cycles` above 3); nothing was changed
+    /// * `Err(InitError::PinConflict)` - Another state machine of the block drives a
+    ///   mirror pin; nothing was changed
+    /// * `Err(InitError::NoInstructionMemory)` - As [`set_duplex`](Self::set_duplex)
+    ///
+    /// # Behavior
+    /// Lets queued frames finish, then reloads the frame program re-encoded for the
+    /// mirrors and restarts it with empty FIFOs, as [`set_duplex`](Self::set_duplex) does:
+    /// - CLK: the side-set widens to two pins, so every CLK edge is driven on both by the
+    ///   same instruction. The mirror idles HIGH and follows
+    ///   [`set_clk_polarity`](Self::set_clk_polarity) like CLK
+    /// - MOSI: every data bit is shifted out twice, onto MOSI and the pin above it, by the
+    ///   same `out` instruction; the master doubles the bits of every TX word
+    ///
+    /// The copies switch in the same state machine cycle as the originals, so the skew
+    /// between them comes only from the pads and the traces. Mirror pins no longer in use
+    /// are turned into inputs.
+    ///
+    /// # Notes
+    /// - The mirror pins must be set up for the PIO (`common.make_pio_pin`) and kept alive
+    /// - The program keeps its length, so reloading fits unless another driver took the
+    ///   freed slots meanwhile
+    /// - While MOSI is mirrored, [`transfer_bytes`](Self::transfer_bytes) is unavailable
+    /// - [`remap_pins`](Self::remap_pins) needs the mirrors off
+    pub fn set_mirror(
+        &mut self,
+        common: &mut Common<'d, PIO>,
+        clk_mirror: Option<&Pin<'d, PIO>>,
+        mosi_mirror: Option<&Pin<'d, PIO>>,
+    ) -> Result<(), InitError> {
+        let (clk_mirror_pin, mosi_mirror_pin) = (
+            clk_mirror.map(|pin| pin.pin()),
+            mosi_mirror.map(|pin| pin.pin()),
+        );
+        let used = [
+            Some(self.clk_pin),
+            Some(self.mosi_pin),
+            Some(self.miso_pin),
+            self.cs_pin,
+        ];
+        let fits = |mirror: Option<u8>, original: u8| {
+            mirror.is_none_or(|pin| pin == original + 1 && !used.contains(&Some(pin)))
+        };
+        if !fits(clk_mirror_pin, self.clk_pin) || !fits(mosi_mirror_pin, self.mosi_pin) {
+            return Err(InitError::InvalidConfig);
+        }
+        let mirror = Mirror {
+            clk: clk_mirror.is_some(),
+            mosi: mosi_mirror.is_some(),
+        };
+        if mirror == self.config.mirror {
+            return Ok(());
+        }
+        let with_cs = self.cs_pin.is_some();
+        let config = SpiMasterConfig {
+            mirror,
+            ..self.config
+        };
+        if !config.is_valid() {
+            return Err(InitError::InvalidConfig);
+        }
+        let program = frame_program(&config, with_cs)?;
+        let old = frame_program(&self.config, with_cs)?;
+        claim_pins::<PIO, SM, 5>([
+            Some(self.clk_pin),
+            Some(self.mosi_pin),
+            self.cs_pin,
+            clk_mirror_pin,
+            mosi_mirror_pin,
+        ])?;
+
+        let running = self.sm.is_enabled();
+        if running {
+            self.wait_idle_discarding();
+        }
+        self.sm.set_enable(false);
+        // SAFETY: as in `set_duplex`
+        unsafe {
+            let used_memory = core::ptr::read(&self._program.used_memory);
+            common.free_instr(used_memory);
+        }
+        let result = match common.try_load_program(&program) {
+            Ok(loaded) => {
+                let [old_clk_mirror, old_mosi_mirror] = self.mirror_pins();
+                if let Some(pin) = old_clk_mirror.filter(|_| !mirror.clk) {
+                    set_clk_inversion(pin, ClkPolarity::IdleHigh);
+                    release_pin_dir(&mut self.sm, pin);
+                }
+                if let Some(pin) = old_mosi_mirror.filter(|_| !mirror.mosi) {
+                    release_pin_dir(&mut self.sm, pin);
+                }
+                let mut pins = self.cfg.get_pins();
+                pins.sideset_count = program.side_set.bits();
+                pins.out_count = 1 + mirror.mosi as u8;
+                // SAFETY: the groups only grow onto the claimed mirror pins, which are made
+                // outputs below
+                unsafe { self.cfg.set_pins(pins) };
+                self.config = config;
+                self.format = config.frame_format();
+                self.set_shift_thresholds();
+                self.install(loaded);
+                if let Some(pin) = clk_mirror {
+                    set_clk_inversion(pin.pin(), self.clk_polarity);
+                    self.sm.set_pins(Level::High, &[pin]);
+                    self.sm.set_pin_dirs(Direction::Out, &[pin]);
+                }
+                if let Some(pin) = mosi_mirror {
+                    self.sm.set_pin_dirs(Direction::Out, &[pin]);
+                }
+                self.log_event(EventKind::Config(Setting::Mirror {
+                    clk: mirror.clk,
+                    mosi: mirror.mosi,
+                }));
+                Ok(())
+            }
+            Err(_) => {
+                if let Ok(loaded) = common.try_load_program(&old) {
+                    self.install(loaded);
+                }
+                let _ = claim_pins::<PIO, SM, 5>(self.output_pins());
+                Err(InitError::NoInstructionMemory {
+                    needed: program.code.len(),
+                })
+            }
+        };
+        self.reset_frames(running);
+        result
+    }
+
+    /// Switches the bit order of both directions between frames
+    ///
+    /// # Arguments
+    /// * `tx` - Order in which frame bits are shifted out on MOSI
+    /// * `rx` - Order in which MISO bits are assembled into the response
+    ///
+    /// # Behavior
+    /// Lets queued frames finish (discarding their responses), then updates the shift
+    /// directions and restarts the program with empty FIFOs, as [`set_duplex`](Self::set_duplex)
+    /// does. Does nothing if both orders are already set.
+    pub fn set_bit_order(&mut self, tx: BitOrder, rx: BitOrder) {
+        if (tx, rx) == (self.tx_bit_order, self.rx_bit_order) {
+            return;
+        }
+        let running = self.sm.is_enabled();
+        if running {
+            self.wait_idle_discarding();
+        }
+        self.sm.set_enable(false);
+
+        (self.tx_bit_order, self.rx_bit_order) = (tx, rx);
+        (self.config.tx_bit_order, self.config.rx_bit_order) = (tx, rx);
+        self.format = self.config.frame_format();
+        self.cfg.shift_out.direction = shift_direction(tx, self.message_size);
+        self.cfg.shift_in.direction = shift_direction(rx, self.message_size);
+        self.cfg.clock_divider = clock_divider(self.clk_div);
+        self.sm.set_config(&self.cfg);
+        self.log_event(EventKind::Config(Setting::BitOrder { tx, rx }));
+        self.reset_frames(running);
+    }
+
+    /// Changes the frame size without reloading the program
+    ///
+    /// # Arguments
+    /// * `bits` - New message size (1-60)
+    ///
+    /// # Returns
+    /// * `Ok(())` - Frames from now on are `bits` long (or `bits` was already in use)
+    /// * `Err(SizeError)` - The size is out of range or needs the other program variant;
+    ///   nothing was changed
+    ///
+    /// # Behavior
+    /// Lets queued frames finish (discarding their responses) so the state machine stops at
+    /// a frame boundary, updates the shift thresholds, then restarts the program with empty
+    /// FIFOs and the new loop count, so its `pull` / `out y, 32` preamble loads the new size
+    /// into Y.
+    ///
+    /// # Notes
+    /// - Frames of up to 32 bits and longer ones use different programs (the longer ones
+    ///   shift a second word): crossing that boundary requires a new master
+    /// - With MOSI [mirrored](Self::set_mirror), sizes above 16 bits are out of range
+    pub fn set_message_size(&mut self, bits: usize) -> Result<(), SizeError> {
+        let config = SpiMasterConfig {
+            message_size: bits,
+            ..self.config
+        };
+        if !config.is_valid() {
+            return Err(SizeError::OutOfRange);
+        }
+        if (bits > 32) != (self.message_size > 32) {
+            return Err(SizeError::ProgramChange);
+        }
+        if bits == self.message_size {
+            return Ok(());
+        }
+        let running = self.sm.is_enabled();
+        if running {
+            self.wait_idle_discarding();
+        }
+        self.sm.set_enable(false);
+
+        self.message_size = bits;
+        self.config.message_size = bits;
+        self.format = self.config.frame_format();
+        self.set_shift_thresholds();
+        self.cfg.clock_divider = clock_divider(self.clk_div);
+        self.sm.set_config(&self.cfg);
+        self.log_event(EventKind::Config(Setting::MessageSize(bits as u8)));
+        self.reset_frames(running);
+        Ok(())
+    }
+
+    /// Switches to another config between frames, as far as the loaded program allows
+    ///
+    /// # Arguments
+    /// * `config` - Config to use from now on
+    ///
+    /// # Returns
+    /// * `Ok(())` - Frames from now on follow `config`
+    /// * `Err(ConfigError)` - The config is invalid or needs a different frame program;
+    ///   nothing was changed
+    ///
+    /// # Behavior
+    /// Checks that `config` generates the program already loaded (the frame size aside),
+    /// then applies what differs: the clock divider, CLK polarity, bit orders and frame size
+    /// through their setters, and the alignments, word orders, RX overflow policy and
+    /// failsafe states directly. Settings already in use are left alone, so applying the
+    /// current config costs no restart.
+    ///
+    /// # Notes
+    /// - Meant for buses shared by devices with different modes and rates; program changes
+    ///   go through [`set_duplex`](Self::set_duplex), [`set_mirror`](Self::set_mirror) or a
+    ///   new master
+    /// - A bit order or frame size change lets queued frames finish and discards their
+    ///   responses, as the setters do
+    pub fn apply_config(&mut self, config: &SpiMasterConfig) -> Result<(), ConfigError> {
+        if !config.is_valid() {
+            return Err(ConfigError::Invalid);
+        }
+        if config.mirror != self.config.mirror
+            || (config.message_size > 32) != (self.message_size > 32)
+        {
+            return Err(ConfigError::ProgramChange);
+        }
+        let with_cs = self.cs_pin.is_some();
+        let same_size = SpiMasterConfig {
+            message_size: self.message_size,
+            ..*config
+        };
+        let layout =
+            |program: pio::Program<32>| (program.code, program.wrap, program.side_set.bits());
+        let wanted = frame_program(&same_size, with_cs).map(layout);
+        if wanted.is_err() || wanted != frame_program(&self.config, with_cs).map(layout) {
+            return Err(ConfigError::ProgramChange);
+        }
+
+        self.config.word_order = config.word_order;
+        self.config.rx_word_order = config.rx_word_order;
+        self.config.tx_alignment = config.tx_alignment;
+        self.config.rx_alignment = config.rx_alignment;
+        self.config.failsafe = config.failsafe;
+        self.config.rx_overflow = config.rx_overflow;
+        self.format = self.config.frame_format();
+        self.rx_overflow = config.rx_overflow;
+        if config.clk_div != self.clk_div {
+            self.try_set_clk_div(config.clk_div);
+        }
+        self.set_clk_polarity(config.clk_polarity);
+        self.set_bit_order(config.tx_bit_order, config.rx_bit_order);
+        // In range and on this side of 32 bits, checked above
+        let _ = self.set_message_size(config.message_size);
+        Ok(())
+    }
+
+    /// Sets the OUT and IN thresholds to one frame per FIFO word (32 bits for longer
+    /// frames), the OUT threshold doubled while MOSI is mirrored
+    fn set_shift_thresholds(&mut self) {
+        let threshold = self.message_size.min(32) as u8;
+        self.cfg.shift_out.threshold = if self.config.mirror.mosi {
+            2 * threshold
+        } else {
+            threshold
+        };
+        self.cfg.shift_in.threshold = threshold;
+    }
+
+    /// Points the state machine at a newly loaded frame program
+    fn install(&mut self, loaded: LoadedProgram<'d, PIO>) {
+        let mut exec = self.cfg.get_exec();
+        exec.wrap_top = loaded.wrap.source;
+        exec.wrap_bottom = loaded.wrap.target;
+        // SAFETY: only the wrap window changes, to the bounds of the program just loaded
+        unsafe { self.cfg.set_exec(exec) };
+        self.cfg.clock_divider = clock_divider(self.clk_div);
+        // The stale origin `set_config` jumps to is replaced by `reset_frames`
+        self.sm.set_config(&self.cfg);
+        self._program = loaded;
+    }
+
+    /// Discards the responses that have already completed, without waiting for the rest
+    pub(crate) fn discard_completed(&mut self) {
+        let words = self.message_size.div_ceil(32);
+        while self.in_flight > 0 && self.sm.rx().level() as usize >= words {
+            self.pull_frame();
+        }
+        if self.in_flight == 0 {
+            while self.sm.rx().try_pull().is_some() {}
+        }
+    }
+
+    /// Stops the state machine and frees the program's instruction memory
+    ///
+    /// # Arguments
+    /// * `common` - The PIO peripheral's common interface the program was loaded with
+    ///
+    /// # Returns
+    /// * `StateMachine` - The stopped state machine, ready to be reused by another driver
+    ///
+    /// # Notes
+    /// - Pins keep their PIO function and last driven level, and CLK its polarity
+    /// - Unread responses are discarded
+    pub fn free(self, common: &mut Common<'d, PIO>) -> StateMachine<'d, PIO, SM> {
+        let mut sm = self.sm;
+        sm.set_enable(false);
+        sm.clear_fifos();
+        release_claim::<PIO, SM>();
+        isr::retire::<PIO, SM>();
+        // SAFETY: the program is private to this master, whose state machine is stopped
+        unsafe { common.free_instr(self._program.used_memory) };
+        sm
+    }
+}
+
+/// A master whose PIO block and state machine live for the whole program, as handed out by
+/// `embassy_rp::init`; it can be moved into or shared with spawned tasks
+pub type StaticPioSpiMaster<PIO, const SM: usize> = PioSpiMaster<'static, PIO, SM>;
+
+impl<PIO: Instance, const SM: usize> PioSpiMaster<'static, PIO, SM> {
+    /// Creates a new PIO SPI Master in a `'static` slot
+    ///
+    /// # Arguments
+    /// * `slot` - Uninitialized storage for the master, e.g. `StaticCell::uninit()` or
+    ///   `cortex_m::singleton!`
+    /// * Others - Same as [`new`](Self::new)
+    ///
+    /// # Returns
+    /// * `&'static mut PioSpiMaster` - The master, running, ready to be passed to
+    ///   `spawner.spawn`
+    ///
+    /// # Notes
+    /// - The master is built in place, without a copy on the stack
+    /// - Every other constructor works the same way with `slot.write(...)`
+    ///
+    /// # Panics
+    /// Same as [`new`](Self::new)
+    pub fn new_static(
+        slot: &'static mut MaybeUninit<Self>,
+        common: &mut Common<'static, PIO>,
+        sm: StateMachine<'static, PIO, SM>,
+        clk_pin: &Pin<'static, PIO>,
+        mosi_pin: &Pin<'static, PIO>,
+        miso_pin: &Pin<'static, PIO>,
+        config: SpiMasterConfig,
+    ) -> &'static mut Self {
+        slot.write(Self::new(common, sm, clk_pin, mosi_pin, miso_pin, config))
+    }
+}
+
+/// Returns the shift direction realizing `order` for frames of `message_size` bits
+fn shift_direction(order: BitOrder, message_size: usize) -> ShiftDirection {
+    match order {
+        BitOrder::LsbFirst if message_size <= 32 => ShiftDirection::Right,
+        _ => ShiftDirection::Left,
+    }
+}
+
+/// Generates the frame program for `config`'s frame size, duplex mode and timing, with
+/// PIO-managed CS if `with_cs` is set
+#[cfg_attr(not(feature = "cs"), allow(unused_variables))]
+fn frame_program(config: &SpiMasterConfig, with_cs: bool) -> Result<pio::Program<32>, InitError> {
+    if !config.is_valid() {
+        return Err(InitError::InvalidConfig);
+    }
+    let full_duplex = config.duplex == Duplex::Full;
+    #[cfg(feature = "cs")]
+    let mut program = if with_cs {
+        let timing = CsTiming {
+            setup_cycles: config.cs_setup_cycles,
+            hold_cycles: config.cs_hold_cycles,
+            high_time_cycles: config.cs_high_time_cycles,
+            lead_in_cycles: config.lead_in_cycles,
+        };
+        get_cs_pio_program(config.message_size, &timing, full_duplex)
+    } else if full_duplex {
+        get_full_duplex_program(config.message_size)
+    } else {
+        get_pio_program(config.message_size)
+    };
+    #[cfg(not(feature = "cs"))]
+    let mut program = if full_duplex {
+        get_full_duplex_program(config.message_size)
+    } else {
+        get_pio_program(config.message_size)
+    };
+    let too_long = |_| InitError::ProgramTooLong;
+    add_trailer(&mut program, config.trailer_cycles).map_err(too_long)?;
+    stretch_clock(&mut program, config.clk_low_cycles, config.clk_high_cycles);
+    delay_sampling(&mut program, config.miso_sample_delay_cycles).map_err(too_long)?;
+    if let Some(pin) = config.ready_pin {
+        wait_for_ready(&mut program, pin).map_err(too_long)?;
+    }
+    delay_start(&mut program, config.start_delay_cycles).map_err(too_long)?;
+    mirror_outputs(&mut program, config.mirror.clk, config.mirror.mosi)
+        .map_err(|_| InitError::InvalidConfig)?;
+    Ok(program)
+}
+
+/// Returns `Pending` once, waking the task right away, so other tasks get to run
+async fn yield_now() {
+    let mut yielded = false;
+    poll_fn(|cx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    })
+    .await
+}
+
+/// Converts the user-facing `clk_div` setting to the state machine clock divider
+///
+/// Clock divider uses FixedU32<U8> format (8.8 bits).
+/// Value is (clk_div - 1), converted to fixed-point.
+pub(crate) fn clock_divider(clk_div: u16) -> FixedU32<U8> {
+    (clk_div as u32 - 1).to_fixed()
+}
+
+/// Sets the CLK pad output inversion for `polarity`
+pub(crate) fn set_clk_inversion(pin: u8, polarity: ClkPolarity) {
+    let outover = match polarity {
+        ClkPolarity::IdleHigh => pac::io::vals::Outover::NORMAL,
+        ClkPolarity::IdleLow => pac::io::vals::Outover::INVERT,
+    };
+    pac::IO_BANK0
+        .gpio(pin as usize)
+        .ctrl()
+        .modify(|w| w.set_outover(outover));
+}
+
+/// Turns a pin the state machine no longer drives back into an input
+///
+/// `set_pin_dirs` needs the [`Pin`], which the master does not keep, so the `set pindirs`
+/// it would execute is run by hand with the SET group pointed at the pin. The state
+/// machine must be stopped; its pin configuration is rewritten afterwards.
+fn release_pin_dir<PIO: Instance, const SM: usize>(sm: &mut StateMachine<'_, PIO, SM>, pin: u8) {
+    crate::irq::pio_regs::<PIO>().sm(SM).pinctrl().modify(|w| {
+        w.set_set_base(pin);
+        w.set_set_count(1);
+    });
+    let input = pio::InstructionOperands::SET {
+        destination: SetDestination::PINDIRS,
+        data: 0,
+    };
+    // SAFETY: the state machine is stopped and SET only reaches `pin`
+    unsafe { sm.exec_instr(input.encode()) };
+}
+
+/// Overrides the output enable of `pins` off, or returns it to the PIO
+pub(crate) fn set_output_enable<const N: usize>(pins: [Option<u8>; N], enabled: bool) {
+    let oeover = if enabled {
+        pac::io::vals::Oeover::NORMAL
+    } else {
+        pac::io::vals::Oeover::DISABLE
+    };
+    for pin in pins.into_iter().flatten() {
+        pac::IO_BANK0
+            .gpio(pin as usize)
+            .ctrl()
+            .modify(|w| w.set_oeover(oeover));
+    }
+}
+
+/// Resets a state machine to the start of its program with empty FIFOs
+///
+/// Leaves the state machine disabled; the caller restores any startup handshake and
+/// re-enables it.
+pub(crate) fn reset_to_origin<PIO: Instance, const SM: usize>(
+    sm: &mut StateMachine<'_, PIO, SM>,
+    origin: u8,
+) {
+    sm.set_enable(false);
+    sm.clear_fifos();
+    sm.restart();
+    sm.clkdiv_restart();
+    let jmp = pio::InstructionOperands::JMP {
+        condition: pio::JmpCondition::Always,
+        address: origin,
+    };
+    // SAFETY: the state machine is disabled, so jumping to the program start is harmless
+    unsafe { sm.exec_instr(jmp.encode()) };
+}
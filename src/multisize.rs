@@ -0,0 +1,189 @@
+//! Several frame sizes on one state machine
+//!
+//! [`PioSpiMaster`](crate::PioSpiMaster) loads one loop count per state machine, so a
+//! 16-bit DAC and a 24-bit ADC on the same bus would need two state machines driving the
+//! same pins. [`MultiSizeSpi`] registers up to 8 sizes instead and picks one per frame: a
+//! header in each TX word makes the program jump into a table of loop counts.
+//!
+//! ```ignore
+//! let mut spi = MultiSizeSpi::new(&mut common, sm0, &clk, &mosi, &miso, 8, &[16, 24]);
+//!
+//! dac_cs.set_low();
+//! spi.transfer(0, 0x3FFF); // 16-bit DAC write
+//! dac_cs.set_high();
+//!
+//! adc_cs.set_low();
+//! let sample = spi.transfer(1, 0x80_0000); // 24-bit ADC read
+//! adc_cs.set_high();
+//! ```
+//!
+//! # Notes
+//! - Frames are sequential (write phase, then read phase, as
+//!   [`Duplex::Half`](crate::Duplex::Half)), MSB first in SPI Mode 3, and at most 27 bits:
+//!   the header takes the rest of the TX word
+//! - There is no PIO-managed chip select; drive each device's CS from a GPIO
+//! - Frames are 5 state machine cycles further apart than with the plain frame program
+
+use embassy_rp::gpio::Level;
+use embassy_rp::pio::{
+    Common, Config, Direction, Instance, LoadedProgram, Pin, ShiftDirection, StateMachine,
+};
+
+use crate::claim::{claim_pins, release_claim};
+use crate::master::{clock_divider, sm_frequency};
+use crate::program::{
+    get_multi_size_program, MAX_FRAME_SIZES, MAX_MULTI_SIZE_BITS, MULTI_SIZE_TABLE,
+};
+use crate::CYCLES_PER_BIT;
+
+/// SPI master whose frames each pick one of a few registered sizes
+pub struct MultiSizeSpi<'d, PIO: Instance, const SM: usize> {
+    sm: StateMachine<'d, PIO, SM>,
+    program: LoadedProgram<'d, PIO>,
+    /// Registered sizes in bits, by slot; the first `slots` entries are used
+    sizes: [u8; MAX_FRAME_SIZES],
+    slots: usize,
+    clk_div: u16,
+}
+
+impl<'d, PIO: Instance, const SM: usize> MultiSizeSpi<'d, PIO, SM> {
+    /// Loads the program for a set of frame sizes and starts the state machine
+    ///
+    /// # Arguments
+    /// * `common` - The PIO peripheral's common interface (for program loading and pin setup)
+    /// * `sm` - State machine (takes ownership)
+    /// * `clk_pin` - Clock pin (side-set/output)
+    /// * `mosi_pin` - MOSI pin (output)
+    /// * `miso_pin` - MISO pin (input)
+    /// * `clk_div` - Clock divider setting, as in
+    ///   [`SpiMasterConfig::clk_div`](crate::SpiMasterConfig)
+    /// * `sizes` - Frame sizes in bits (1-27), at most 8; frames name them by index
+    ///
+    /// # Panics
+    /// - If `clk_div` is below 2 or `sizes` is empty, too long or out of range
+    /// - If another state machine of the same PIO block already drives CLK or MOSI (see
+    ///   [`PinConflict`](crate::PinConflict))
+    /// - If the program (10 instructions plus 2 per size) does not fit the free
+    ///   instruction memory
+    pub fn new(
+        common: &mut Common<'d, PIO>,
+        sm: StateMachine<'d, PIO, SM>,
+        clk_pin: &Pin<'d, PIO>,
+        mosi_pin: &Pin<'d, PIO>,
+        miso_pin: &Pin<'d, PIO>,
+        clk_div: u16,
+        sizes: &[usize],
+    ) -> Self {
+        assert!(clk_div >= 2, "clk_div must be at least 2");
+        let code = get_multi_size_program(sizes);
+        claim_pins::<PIO, SM, 2>([Some(clk_pin.pin()), Some(mosi_pin.pin())])
+            .expect("pin already driven by another state machine");
+        let program = common.load_program(&code);
+
+        let mut cfg = Config::default();
+        cfg.use_program(&program, &[clk_pin]);
+        cfg.set_out_pins(&[mosi_pin]);
+        cfg.set_in_pins(&[miso_pin]);
+        cfg.clock_divider = clock_divider(clk_div);
+        // The program pulls and pushes itself, as frame lengths vary
+        cfg.shift_out.auto_fill = false;
+        cfg.shift_out.threshold = 32;
+        cfg.shift_out.direction = ShiftDirection::Left;
+        cfg.shift_in.auto_fill = false;
+        cfg.shift_in.threshold = 32;
+        cfg.shift_in.direction = ShiftDirection::Left;
+
+        let mut sm = sm;
+        sm.set_config(&cfg);
+        sm.set_pins(Level::High, &[clk_pin]);
+        sm.set_pin_dirs(Direction::Out, &[clk_pin, mosi_pin]);
+        sm.set_pin_dirs(Direction::In, &[miso_pin]);
+        sm.set_enable(true);
+
+        let mut table = [0; MAX_FRAME_SIZES];
+        for (entry, &size) in table.iter_mut().zip(sizes) {
+            *entry = size as u8;
+        }
+        Self {
+            sm,
+            program,
+            sizes: table,
+            slots: sizes.len(),
+            clk_div,
+        }
+    }
+
+    /// Returns the size in bits registered for `slot`
+    ///
+    /// # Panics
+    /// If `slot` is not a registered size
+    pub fn frame_size(&self, slot: usize) -> usize {
+        assert!(slot < self.slots, "frame size slot not registered");
+        self.sizes[slot] as usize
+    }
+
+    /// Returns the SCK frequency in Hz at the current system clock
+    pub fn sck_frequency(&self) -> u32 {
+        sm_frequency(self.clk_div) / CYCLES_PER_BIT
+    }
+
+    /// Shifts one frame of the size registered for `slot` out and returns the response
+    ///
+    /// # Arguments
+    /// * `slot` - Index into the sizes passed to [`new`](Self::new)
+    /// * `data` - Frame in bits [size-1:0]; higher bits are ignored
+    ///
+    /// # Returns
+    /// * `u32` - Response in bits [size-1:0], the rest zero
+    ///
+    /// # Panics
+    /// If `slot` is not a registered size
+    pub fn transfer(&mut self, slot: usize, data: u32) -> u32 {
+        let word = self.pack(slot, data);
+        self.sm.tx().push(word);
+        loop {
+            if let Some(response) = self.sm.rx().try_pull() {
+                return response;
+            }
+        }
+    }
+
+    /// Shifts one frame out and returns the response, awaiting FIFO space and the response
+    ///
+    /// Same behavior as [`transfer`](Self::transfer), but yields to the executor while the
+    /// frame shifts. Requires the PIO interrupt handler to be bound.
+    ///
+    /// # Cancel Safety
+    /// Not cancel-safe: a frame dropped after it was queued leaves its response in the RX
+    /// FIFO, where the next transfer would take it. Do not race this against a timeout.
+    pub async fn transfer_async(&mut self, slot: usize, data: u32) -> u32 {
+        let word = self.pack(slot, data);
+        self.sm.tx().wait_push(word).await;
+        self.sm.rx().wait_pull().await
+    }
+
+    /// Stops the state machine and frees the program's instruction memory
+    ///
+    /// # Arguments
+    /// * `common` - The PIO peripheral's common interface the program was loaded with
+    ///
+    /// # Returns
+    /// * `StateMachine` - The stopped state machine, ready to be reused by another driver
+    pub fn free(mut self, common: &mut Common<'d, PIO>) -> StateMachine<'d, PIO, SM> {
+        self.sm.set_enable(false);
+        self.sm.clear_fifos();
+        release_claim::<PIO, SM>();
+        // SAFETY: the program is private to this master, whose state machine is stopped
+        unsafe { common.free_instr(self.program.used_memory) };
+        self.sm
+    }
+
+    /// Builds a frame's TX word: the absolute address of its jump table entry in bits
+    /// [31:27], then the frame left-justified
+    fn pack(&self, slot: usize, data: u32) -> u32 {
+        let size = self.frame_size(slot);
+        let entry = self.program.origin as u32 + MULTI_SIZE_TABLE as u32 + 2 * slot as u32;
+        let data = data & (u32::MAX >> (32 - size));
+        entry << MAX_MULTI_SIZE_BITS | data << (MAX_MULTI_SIZE_BITS - size)
+    }
+}
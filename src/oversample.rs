@@ -0,0 +1,135 @@
+//! Oversampled and redundant reads
+//!
+//! Averaging several conversions of an ADC channel trades rate for noise, but a loop of
+//! [`transfer`](PioSpiMaster::transfer) calls leaves the bus idle between frames while the
+//! CPU handles each response. [`PioSpiMaster::read_average`] repeats one read frame with
+//! the FIFOs kept full, as [`pipeline`](PioSpiMaster::pipeline) does, and folds every
+//! response into a running sum as it arrives, so the frames follow each other back to back
+//! and no buffer is needed:
+//!
+//! ```ignore
+//! let reading = spi.read_average(READ_CH0, 16);
+//! let volts = reading.mean as f32 * LSB_VOLTS;
+//! let noise = reading.max - reading.min;
+//! ```
+//!
+//! Over noisy cables and cheap isolators a single read occasionally comes back with a
+//! glitched bit. [`PioSpiMaster::read_redundant`] repeats the read the same way and votes:
+//! the median of the responses, which is also the majority value whenever more than half
+//! of them agree, with a flag for any disagreement:
+//!
+//! ```ignore
+//! let vote = spi.read_redundant::<3>(READ_STATUS);
+//! if vote.disagreement {
+//!     glitches += 1;
+//! }
+//! if !vote.majority {
+//!     return Err(Error::Unreliable);
+//! }
+//! handle(vote.value);
+//! ```
+//!
+//! # Notes
+//! - Responses are averaged and ordered as unsigned numbers at their
+//!   [`rx_alignment`](crate::SpiMasterConfig::rx_alignment): the mean and median of signed
+//!   or Gray-coded readings are wrong, though a majority still holds
+//! - Responses of earlier [`write`](PioSpiMaster::write) frames still in flight would be
+//!   taken as samples; drain them first
+
+use embassy_rp::pio::Instance;
+
+use crate::vote::vote;
+pub use crate::vote::Vote;
+use crate::PioSpiMaster;
+
+/// Mean and spread of repeated reads, see [`PioSpiMaster::read_average`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Average {
+    /// Mean of the responses, rounded to nearest
+    pub mean: u64,
+    /// Smallest response
+    pub min: u64,
+    /// Largest response
+    pub max: u64,
+    /// Number of responses averaged
+    pub samples: u32,
+}
+
+impl<PIO: Instance, const SM: usize> PioSpiMaster<'_, PIO, SM> {
+    /// Reads `n` times with the same frame and averages the responses
+    ///
+    /// # Arguments
+    /// * `frame` - Read frame to repeat, as [`transfer`](Self::transfer) takes it
+    /// * `n` - Number of reads (at least 1)
+    ///
+    /// # Returns
+    /// * `Average` - Rounded mean, minimum and maximum of the `n` responses
+    ///
+    /// # Behavior
+    /// Keeps as many copies of `frame` in flight as the RX FIFO has room for responses,
+    /// adding each response to a 128-bit sum and topping the queue up, so only the final
+    /// division follows the last frame.
+    ///
+    /// # Panics
+    /// If `n` is 0
+    pub fn read_average(&mut self, frame: u64, n: u32) -> Average {
+        assert!(n > 0, "read_average needs at least one read");
+        let mut sum = 0u128;
+        let (mut min, mut max) = (u64::MAX, 0);
+        self.repeat_read(frame, n, |response| {
+            sum += response as u128;
+            min = min.min(response);
+            max = max.max(response);
+        });
+        Average {
+            mean: ((sum + n as u128 / 2) / n as u128) as u64,
+            min,
+            max,
+            samples: n,
+        }
+    }
+
+    /// Reads `N` times with the same frame and votes on the responses
+    ///
+    /// # Arguments
+    /// * `frame` - Read frame to repeat, as [`transfer`](Self::transfer) takes it
+    ///
+    /// # Type Parameters
+    /// * `N` - Number of reads (at least 1); odd counts always have a single median
+    ///
+    /// # Returns
+    /// * `Vote` - The median response, whether it holds a majority and whether any
+    ///   response differed
+    ///
+    /// # Behavior
+    /// Transfers the frames back to back as [`read_average`](Self::read_average) does,
+    /// keeping the responses, then settles on one with [`vote`](crate::vote::vote).
+    ///
+    /// # Panics
+    /// If `N` is 0
+    pub fn read_redundant<const N: usize>(&mut self, frame: u64) -> Vote {
+        assert!(N > 0, "read_redundant needs at least one read");
+        let mut responses = [0u64; N];
+        let mut slots = responses.iter_mut();
+        self.repeat_read(frame, N as u32, |response| {
+            if let Some(slot) = slots.next() {
+                *slot = response;
+            }
+        });
+        vote(&mut responses)
+    }
+
+    /// Transfers `frame` `n` times with the FIFOs kept full, passing each response to
+    /// `sample` in order
+    pub(crate) fn repeat_read(&mut self, frame: u64, n: u32, mut sample: impl FnMut(u64)) {
+        let depth = self.pipeline_depth() as u32;
+        let mut pushed = 0;
+        for pulled in 0..n {
+            while pushed < n && pushed - pulled < depth {
+                self.push_frame(frame);
+                pushed += 1;
+            }
+            sample(self.pull_frame());
+        }
+    }
+}
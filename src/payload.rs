@@ -0,0 +1,405 @@
+//! Typed frames
+//!
+//! Drivers usually think in registers and fields rather than `u64` frames. A type
+//! implementing [`Payload`] knows its wire form, so
+//! [`transfer_payload`](crate::PioSpiMaster::transfer_payload) sends and receives it
+//! without shifting and masking at the call site:
+//!
+//! ```ignore
+//! struct Command { write: bool, addr: u8, value: u16 }
+//!
+//! impl Payload for Command {
+//!     const BITS: usize = 24;
+//!
+//!     fn to_wire_bits(&self) -> u64 {
+//!         WireWriter::new()
+//!             .field(self.write as u64, 1)
+//!             .field(self.addr as u64, 7)
+//!             .field(self.value as u64, 16)
+//!             .finish()
+//!     }
+//!
+//!     fn from_wire_bits(bits: u64) -> Self {
+//!         let mut reader = WireReader::new(bits, Self::BITS);
+//!         Command {
+//!             write: reader.field(1) != 0,
+//!             addr: reader.field(7) as u8,
+//!             value: reader.field(16) as u16,
+//!         }
+//!     }
+//! }
+//!
+//! let status: u32 = spi.transfer_payload(&Command { write: true, addr: 0x12, value: 7 });
+//! ```
+//!
+//! # Wire Order
+//!
+//! Wire bits are a right-justified number whose most significant bit is on the wire
+//! first, as [`PioSpiMaster::transfer`](crate::PioSpiMaster::transfer) takes frames with
+//! the default settings. [`WireWriter`] and [`WireReader`] handle fields in wire order, and
+//! the provided impls cover:
+//! - `u8`, `u16`, `u32`, `u64` and `bool`: the number itself, most significant byte first
+//! - [`Le`]: a `u16`, `u32` or `u64` sent least significant byte first, as in most
+//!   little-endian register maps
+//! - `[u8; N]` (N up to 8): the bytes in array order
+//!
+//! # Declaring Frames
+//!
+//! [`wire_frame!`](crate::wire_frame) writes the struct and its [`Payload`] impl from a
+//! list of named fields and their widths, and checks the widths while compiling:
+//!
+//! ```ignore
+//! wire_frame! {
+//!     /// DAC write command
+//!     pub struct DacWrite: 40 {
+//!         command: u8 = 4,
+//!         address: u8 = 4,
+//!         data: u16 = 16,
+//!         pad: u16 = 16,
+//!     }
+//! }
+//!
+//! let _: u64 = spi.transfer_payload(&DacWrite { command: 0x3, address: 1, data: level, pad: 0 });
+//! ```
+
+#[cfg(all(test, feature = "std"))]
+mod tests;
+
+#[cfg(feature = "hal")]
+use embassy_rp::pio::Instance;
+
+#[cfg(feature = "hal")]
+use crate::{Alignment, PioSpiMaster};
+
+/// A value with a fixed-size wire form
+pub trait Payload: Sized {
+    /// Bits on the wire (1-64)
+    const BITS: usize;
+
+    /// Returns the wire form in bits [BITS-1:0], bit `BITS - 1` going out first
+    fn to_wire_bits(&self) -> u64;
+
+    /// Builds the value from its wire form in bits [BITS-1:0]; higher bits are ignored
+    fn from_wire_bits(bits: u64) -> Self;
+}
+
+/// Mask of the low `bits` bits
+const fn low_mask(bits: usize) -> u64 {
+    if bits >= 64 {
+        u64::MAX
+    } else {
+        (1 << bits) - 1
+    }
+}
+
+macro_rules! impl_payload_uint {
+    ($($ty:ty),*) => {$(
+        impl Payload for $ty {
+            const BITS: usize = <$ty>::BITS as usize;
+
+            fn to_wire_bits(&self) -> u64 {
+                *self as u64
+            }
+
+            fn from_wire_bits(bits: u64) -> Self {
+                bits as $ty
+            }
+        }
+    )*};
+}
+
+impl_payload_uint!(u8, u16, u32, u64);
+
+impl Payload for bool {
+    const BITS: usize = 1;
+
+    fn to_wire_bits(&self) -> u64 {
+        *self as u64
+    }
+
+    fn from_wire_bits(bits: u64) -> Self {
+        bits & 1 != 0
+    }
+}
+
+/// A number sent least significant byte first (bits within each byte still MSB first)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Le<T>(pub T);
+
+macro_rules! impl_payload_le {
+    ($($ty:ty),*) => {$(
+        impl Payload for Le<$ty> {
+            const BITS: usize = <$ty>::BITS as usize;
+
+            fn to_wire_bits(&self) -> u64 {
+                self.0.swap_bytes() as u64
+            }
+
+            fn from_wire_bits(bits: u64) -> Self {
+                Le((bits as $ty).swap_bytes())
+            }
+        }
+    )*};
+}
+
+impl_payload_le!(u16, u32, u64);
+
+impl<const N: usize> Payload for [u8; N] {
+    const BITS: usize = {
+        assert!(N >= 1 && N <= 8, "byte array payloads are 1-8 bytes");
+        N * 8
+    };
+
+    fn to_wire_bits(&self) -> u64 {
+        self.iter().fold(0, |bits, &byte| (bits << 8) | byte as u64)
+    }
+
+    fn from_wire_bits(bits: u64) -> Self {
+        core::array::from_fn(|i| (bits >> (8 * (N - 1 - i))) as u8)
+    }
+}
+
+/// Builds wire bits from fields in wire order
+///
+/// Each field is appended after the previous ones, so the first field goes out first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WireWriter {
+    bits: u64,
+    len: usize,
+}
+
+impl WireWriter {
+    /// Starts an empty frame
+    pub const fn new() -> Self {
+        Self { bits: 0, len: 0 }
+    }
+
+    /// Appends a field
+    ///
+    /// # Arguments
+    /// * `value` - Field in bits [width-1:0]; higher bits are ignored
+    /// * `width` - Field size in bits (0-64)
+    ///
+    /// # Panics
+    /// If the frame would exceed 64 bits
+    pub const fn field(self, value: u64, width: usize) -> Self {
+        assert!(self.len + width <= 64, "frame exceeds 64 bits");
+        let bits = if width == 0 {
+            self.bits
+        } else if width == 64 {
+            value
+        } else {
+            (self.bits << width) | (value & low_mask(width))
+        };
+        Self {
+            bits,
+            len: self.len + width,
+        }
+    }
+
+    /// Appends a nested payload as a field of `P::BITS` bits
+    ///
+    /// # Panics
+    /// If the frame would exceed 64 bits
+    pub fn payload<P: Payload>(self, payload: &P) -> Self {
+        self.field(payload.to_wire_bits(), P::BITS)
+    }
+
+    /// Returns the bits appended so far
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if no bits were appended
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the frame, the first field in the highest bits
+    pub const fn finish(self) -> u64 {
+        self.bits
+    }
+}
+
+/// Splits wire bits into fields in wire order
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WireReader {
+    bits: u64,
+    /// Bits not yet read, counted from the low end
+    remaining: usize,
+}
+
+impl WireReader {
+    /// Starts reading a frame
+    ///
+    /// # Arguments
+    /// * `bits` - Frame in bits [len-1:0]
+    /// * `len` - Frame size in bits (0-64)
+    pub const fn new(bits: u64, len: usize) -> Self {
+        Self {
+            bits: bits & low_mask(len),
+            remaining: len,
+        }
+    }
+
+    /// Takes the next field
+    ///
+    /// # Arguments
+    /// * `width` - Field size in bits (0-64)
+    ///
+    /// # Returns
+    /// * `u64` - The field, right-justified
+    ///
+    /// # Panics
+    /// If fewer than `width` bits are left
+    pub fn field(&mut self, width: usize) -> u64 {
+        assert!(width <= self.remaining, "field past the end of the frame");
+        self.remaining -= width;
+        let shifted = if self.remaining >= 64 {
+            0
+        } else {
+            self.bits >> self.remaining
+        };
+        shifted & low_mask(width)
+    }
+
+    /// Takes the next `P::BITS` bits as a nested payload
+    ///
+    /// # Panics
+    /// If fewer than `P::BITS` bits are left
+    pub fn payload<P: Payload>(&mut self) -> P {
+        P::from_wire_bits(self.field(P::BITS))
+    }
+
+    /// Returns the bits not read yet
+    pub const fn remaining(&self) -> usize {
+        self.remaining
+    }
+}
+
+#[cfg(feature = "hal")]
+impl<PIO: Instance, const SM: usize> PioSpiMaster<'_, PIO, SM> {
+    /// Transfers a typed frame and decodes the response
+    ///
+    /// # Arguments
+    /// * `data` - Value to send; its wire bits fill the low `T::BITS` bits of the frame
+    ///
+    /// # Returns
+    /// * `R` - The response, built from the low `R::BITS` bits of the frame
+    ///
+    /// # Notes
+    /// - Payload bits are right-justified whatever `tx_alignment` / `rx_alignment` say;
+    ///   a payload shorter than the frame goes out after leading zeros
+    ///
+    /// # Panics
+    /// In debug builds, if `T::BITS` or `R::BITS` exceeds `message_size`
+    pub fn transfer_payload<T: Payload, R: Payload>(&mut self, data: &T) -> R {
+        let response = self.transfer(self.payload_frame(data));
+        self.payload_response(response)
+    }
+
+    /// Transfers a typed frame and decodes the response, awaiting FIFO space and the
+    /// response
+    ///
+    /// Same behavior as [`transfer_payload`](Self::transfer_payload), with the frame sent
+    /// by [`transfer_async`](Self::transfer_async).
+    ///
+    /// # Cancel Safety
+    /// As [`transfer_async`](Self::transfer_async)
+    pub async fn transfer_payload_async<T: Payload, R: Payload>(&mut self, data: &T) -> R {
+        let response = self.transfer_async(self.payload_frame(data)).await;
+        self.payload_response(response)
+    }
+
+    /// Moves a payload's wire bits to where `transfer` expects the frame
+    fn payload_frame<T: Payload>(&self, data: &T) -> u64 {
+        debug_assert!(T::BITS <= self.message_size, "payload exceeds the frame");
+        let bits = data.to_wire_bits() & low_mask(T::BITS);
+        match self.config.tx_alignment {
+            Alignment::Right => bits,
+            Alignment::Left => bits << self.format.padding_bits(),
+        }
+    }
+
+    /// Decodes a response as returned by `transfer`
+    fn payload_response<R: Payload>(&self, response: u64) -> R {
+        debug_assert!(R::BITS <= self.message_size, "payload exceeds the frame");
+        let bits = match self.config.rx_alignment {
+            Alignment::Right => response,
+            Alignment::Left => response >> self.format.padding_bits(),
+        };
+        R::from_wire_bits(bits & low_mask(R::BITS))
+    }
+}
+
+/// Declares a struct of named bit fields that implements [`Payload`]
+///
+/// Fields are listed in wire order as `name: type = width`, after the frame's total width.
+/// Each becomes a public field of the given unsigned integer type.
+///
+/// # Compile-Time Checks
+/// - Every field fits its type (e.g. `addr: u8 = 9` is rejected)
+/// - The widths add up to the declared total, which is at most 64
+///
+/// # Notes
+/// - Field values wider than their field are truncated; debug builds assert instead
+///
+/// See the [module docs](crate::payload) for an example.
+#[macro_export]
+macro_rules! wire_frame {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident: $total:literal {
+            $(
+                $(#[$field_meta:meta])*
+                $field:ident: $ty:ty = $width:literal
+            ),+ $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+        $vis struct $name {
+            $(
+                $(#[$field_meta])*
+                pub $field: $ty,
+            )+
+        }
+
+        const _: () = {
+            $(
+                assert!(
+                    $width <= <$ty>::BITS as usize,
+                    concat!("field `", stringify!($field), "` is wider than its type"),
+                );
+            )+
+            assert!(
+                0 $(+ $width)+ == $total,
+                concat!("fields of `", stringify!($name), "` do not add up to its width"),
+            );
+            assert!($total <= 64, "frames are at most 64 bits");
+        };
+
+        impl $crate::payload::Payload for $name {
+            const BITS: usize = $total;
+
+            fn to_wire_bits(&self) -> u64 {
+                $(
+                    debug_assert!(
+                        $width == 64 || (self.$field as u64) >> $width == 0,
+                        concat!("`", stringify!($field), "` does not fit its field"),
+                    );
+                )+
+                $crate::payload::WireWriter::new()
+                    $(.field(self.$field as u64, $width))+
+                    .finish()
+            }
+
+            fn from_wire_bits(bits: u64) -> Self {
+                let mut reader = $crate::payload::WireReader::new(bits, $total);
+                Self {
+                    $($field: reader.field($width) as $ty,)+
+                }
+            }
+        }
+    };
+}
@@ -0,0 +1,244 @@
+//! Wire forms of the provided payloads, field packing round trips and frames declared
+//! with `wire_frame!`.
+
+use super::*;
+
+/// Deterministic xorshift64 generator, so failures reproduce
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+crate::wire_frame! {
+    /// DAC write command, as in the module docs
+    struct DacWrite: 40 {
+        command: u8 = 4,
+        address: u8 = 4,
+        data: u16 = 16,
+        pad: u16 = 16,
+    }
+}
+
+crate::wire_frame! {
+    /// Frame filling all 64 bits with fields
+    struct Full: 64 {
+        flag: u8 = 1,
+        id: u8 = 7,
+        value: u64 = 56,
+    }
+}
+
+crate::wire_frame! {
+    /// Frame of a single 64-bit field
+    struct Whole: 64 {
+        raw: u64 = 64,
+    }
+}
+
+#[test]
+fn writer_puts_the_first_field_highest() {
+    let bits = WireWriter::new()
+        .field(0b1, 1)
+        .field(0x12, 7)
+        .field(0xBEEF, 16)
+        .finish();
+    assert_eq!(bits, 0x92BEEF);
+}
+
+#[test]
+fn writer_ignores_bits_above_the_width() {
+    let bits = WireWriter::new().field(0xFF, 4).field(0x1F0, 4).finish();
+    assert_eq!(bits, 0xF0);
+}
+
+#[test]
+fn zero_width_fields_are_empty() {
+    let writer = WireWriter::new().field(u64::MAX, 0);
+    assert!(writer.is_empty());
+    assert_eq!(writer.finish(), 0);
+
+    let bits = WireWriter::new()
+        .field(0xA, 4)
+        .field(u64::MAX, 0)
+        .field(0x5, 4)
+        .finish();
+    assert_eq!(bits, 0xA5);
+
+    let mut reader = WireReader::new(bits, 8);
+    assert_eq!(reader.field(0), 0);
+    assert_eq!(reader.field(4), 0xA);
+    assert_eq!(reader.field(0), 0);
+    assert_eq!(reader.field(4), 0x5);
+    assert_eq!(reader.remaining(), 0);
+}
+
+#[test]
+fn sixty_four_bit_fields_round_trip() {
+    let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+    for value in [0, 1, u64::MAX, 1 << 63, rng.next(), rng.next()] {
+        let writer = WireWriter::new().field(value, 64);
+        assert_eq!(writer.len(), 64);
+        assert_eq!(writer.finish(), value);
+        assert_eq!(WireReader::new(value, 64).field(64), value);
+    }
+}
+
+#[test]
+fn fields_round_trip() {
+    let mut rng = Rng(0x2545_F491_4F6C_DD1D);
+    for _ in 0..256 {
+        // Split 64 bits at random points, some fields empty
+        let mut widths = [0usize; 6];
+        let mut left = 64;
+        for width in widths.iter_mut().take(5) {
+            *width = rng.next() as usize % (left + 1);
+            left -= *width;
+        }
+        widths[5] = left;
+
+        let values = widths.map(|width| rng.next() & low_mask(width));
+        let bits = widths
+            .iter()
+            .zip(values)
+            .fold(WireWriter::new(), |writer, (&width, value)| {
+                writer.field(value, width)
+            })
+            .finish();
+
+        let mut reader = WireReader::new(bits, 64);
+        for (&width, value) in widths.iter().zip(values) {
+            assert_eq!(reader.field(width), value, "widths {widths:?}");
+        }
+        assert_eq!(reader.remaining(), 0);
+    }
+}
+
+#[test]
+fn reader_ignores_bits_above_the_frame() {
+    let mut reader = WireReader::new(0xFFFF_FF00_0000_00A5, 8);
+    assert_eq!(reader.field(4), 0xA);
+    assert_eq!(reader.field(4), 0x5);
+}
+
+#[test]
+#[should_panic]
+fn writer_rejects_more_than_64_bits() {
+    let _ = WireWriter::new().field(0, 60).field(0, 5);
+}
+
+#[test]
+#[should_panic]
+fn reader_rejects_fields_past_the_end() {
+    let mut reader = WireReader::new(0, 8);
+    reader.field(4);
+    reader.field(5);
+}
+
+#[test]
+fn integers_go_most_significant_byte_first() {
+    assert_eq!(0x1234u16.to_wire_bits(), 0x1234);
+    assert_eq!(u16::from_wire_bits(0xAB_1234), 0x1234);
+    assert_eq!(u64::from_wire_bits(u64::MAX), u64::MAX);
+    assert!(bool::from_wire_bits(0b11));
+    assert!(!bool::from_wire_bits(0b10));
+}
+
+#[test]
+fn le_swaps_the_byte_order() {
+    assert_eq!(Le(0x1234u16).to_wire_bits(), 0x3412);
+    assert_eq!(Le(0x1234_5678u32).to_wire_bits(), 0x7856_3412);
+    assert_eq!(
+        Le(0x0102_0304_0506_0708u64).to_wire_bits(),
+        0x0807_0605_0403_0201
+    );
+    assert_eq!(Le::<u32>::from_wire_bits(0x7856_3412), Le(0x1234_5678));
+    assert_eq!(<Le<u16> as Payload>::BITS, 16);
+
+    let mut rng = Rng(0x0123_4567_89AB_CDEF);
+    for _ in 0..64 {
+        let value = rng.next();
+        assert_eq!(
+            Le::<u64>::from_wire_bits(Le(value).to_wire_bits()),
+            Le(value)
+        );
+    }
+}
+
+#[test]
+fn byte_arrays_keep_array_order() {
+    assert_eq!([0x12u8].to_wire_bits(), 0x12);
+    assert_eq!([0x12u8, 0x34, 0x56].to_wire_bits(), 0x12_3456);
+    assert_eq!(<[u8; 3]>::from_wire_bits(0xFF12_3456), [0x12, 0x34, 0x56]);
+    assert_eq!(<[u8; 3] as Payload>::BITS, 24);
+
+    let bytes = [1, 2, 3, 4, 5, 6, 7, 8];
+    assert_eq!(bytes.to_wire_bits(), 0x0102_0304_0506_0708);
+    assert_eq!(<[u8; 8]>::from_wire_bits(bytes.to_wire_bits()), bytes);
+}
+
+#[test]
+fn nested_payloads_take_their_width() {
+    let bits = WireWriter::new()
+        .payload(&true)
+        .payload(&Le(0x1234u16))
+        .payload(&[0xAAu8])
+        .finish();
+    assert_eq!(bits, 0x0134_12AA);
+
+    let mut reader = WireReader::new(bits, 25);
+    assert!(reader.payload::<bool>());
+    assert_eq!(reader.payload::<Le<u16>>(), Le(0x1234));
+    assert_eq!(reader.payload::<[u8; 1]>(), [0xAA]);
+}
+
+#[test]
+fn declared_frame_packs_in_field_order() {
+    let frame = DacWrite {
+        command: 0x3,
+        address: 0x1,
+        data: 0xABCD,
+        pad: 0,
+    };
+    assert_eq!(DacWrite::BITS, 40);
+    assert_eq!(frame.to_wire_bits(), 0x31_ABCD_0000);
+    assert_eq!(DacWrite::from_wire_bits(frame.to_wire_bits()), frame);
+}
+
+#[test]
+fn declared_frame_ignores_bits_above_its_width() {
+    let frame = DacWrite::from_wire_bits(0xFF00_0031_ABCD_0000);
+    assert_eq!(
+        frame,
+        DacWrite {
+            command: 0x3,
+            address: 0x1,
+            data: 0xABCD,
+            pad: 0,
+        }
+    );
+}
+
+#[test]
+fn declared_64_bit_frames_round_trip() {
+    let full = Full {
+        flag: 1,
+        id: 0x7F,
+        value: 0x00AB_CDEF_0123_4567,
+    };
+    assert_eq!(Full::BITS, 64);
+    assert_eq!(full.to_wire_bits(), 0xFFAB_CDEF_0123_4567);
+    assert_eq!(Full::from_wire_bits(full.to_wire_bits()), full);
+
+    let mut rng = Rng(0xDEAD_BEEF_CAFE_F00D);
+    for raw in [0, u64::MAX, rng.next()] {
+        let whole = Whole { raw };
+        assert_eq!(whole.to_wire_bits(), raw);
+        assert_eq!(Whole::from_wire_bits(raw), whole);
+    }
+}
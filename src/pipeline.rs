@@ -0,0 +1,105 @@
+//! Pipelined request bursts
+//!
+//! Reading a run of registers with [`transfer`](PioSpiMaster::transfer) leaves the bus
+//! idle between frames while the CPU pulls one response and pushes the next frame.
+//! [`PioSpiMaster::pipeline`] keeps frames queued ahead instead: it pushes as many frames
+//! as the RX FIFO can hold responses for, then pulls the oldest response and tops the
+//! queue up again, so the state machine shifts frames back to back while every response
+//! still lands in the slot of its request:
+//!
+//! ```ignore
+//! let requests = [0x8000, 0x8100, 0x8200, 0x8300, 0x8400, 0x8500];
+//! let mut responses = [0u64; 6];
+//! spi.pipeline(&requests, &mut responses);
+//! ```
+//!
+//! # Depth
+//!
+//! Frames are kept ahead only up to the responses the RX FIFO holds (4 frames of up to
+//! 32 bits, 2 longer ones). Queuing more would fill the RX FIFO and stall the state
+//! machine mid-burst, so the limit costs no throughput; it also means the TX FIFO never
+//! fills and pushing never waits.
+
+use embassy_rp::pio::Instance;
+
+use crate::irq::FIFO_DEPTH;
+use crate::PioSpiMaster;
+
+impl<PIO: Instance, const SM: usize> PioSpiMaster<'_, PIO, SM> {
+    /// Transfers a burst of frames with the FIFOs kept full, responses in request order
+    ///
+    /// # Arguments
+    /// * `requests` - Frames to send, as [`transfer`](Self::transfer) takes them
+    /// * `responses` - Destination for the responses; `responses[i]` answers `requests[i]`
+    ///
+    /// # Returns
+    /// * `usize` - Number of frames transferred: the shorter of the two slices
+    ///
+    /// # Behavior
+    /// 1. Pushes frames until as many are in flight as the RX FIFO has room for
+    /// 2. Blocks for the oldest response, stores it and pushes the next frame
+    /// 3. Once every frame is pushed, collects the remaining responses
+    ///
+    /// # Notes
+    /// - Responses of earlier [`write`](Self::write) frames still in flight share the RX
+    ///   FIFO in order; drain them first or they are taken as answers to `requests`
+    pub fn pipeline(&mut self, requests: &[u64], responses: &mut [u64]) -> usize {
+        let count = requests.len().min(responses.len());
+        let depth = self.pipeline_depth();
+        let mut pushed = 0;
+        for (i, response) in responses[..count].iter_mut().enumerate() {
+            while pushed < count && pushed - i < depth {
+                self.push_frame(requests[pushed]);
+                pushed += 1;
+            }
+            *response = self.pull_frame();
+        }
+        count
+    }
+
+    /// Transfers a burst of frames with the FIFOs kept full, awaiting each response
+    ///
+    /// Same behavior as [`pipeline`](Self::pipeline), but yields to the executor while the
+    /// oldest response is not ready. Requires the PIO interrupt handler to be bound.
+    ///
+    /// # Cancel Safety
+    /// As [`transfer_async`](Self::transfer_async): dropping the future mid-burst makes the
+    /// next call reset the state machine and discard the frames still queued. Responses
+    /// already stored in `responses` are valid.
+    pub async fn pipeline_async(&mut self, requests: &[u64], responses: &mut [u64]) -> usize {
+        self.recover_if_interrupted();
+        self.claim_fifos();
+        self.interrupted = true;
+
+        let count = requests.len().min(responses.len());
+        let depth = self.pipeline_depth();
+        let mut pushed = 0;
+        for (i, response) in responses[..count].iter_mut().enumerate() {
+            while pushed < count && pushed - i < depth {
+                let (words, used) = self.format.pack(requests[pushed] & self.format.tx_mask());
+                for &word in &words[..used] {
+                    self.sm.tx().wait_push(word).await;
+                }
+                self.in_flight += 1;
+                pushed += 1;
+            }
+            let first = self.sm.rx().wait_pull().await;
+            *response = if self.message_size <= 32 {
+                self.format.unpack_word(first)
+            } else {
+                let second = self.sm.rx().wait_pull().await;
+                self.format.unpack_words(first, second)
+            };
+            self.in_flight -= 1;
+        }
+
+        self.interrupted = false;
+        self.release_fifos();
+        count
+    }
+
+    /// Returns how many frames may be in flight before the RX FIFO is full
+    pub(crate) fn pipeline_depth(&self) -> usize {
+        FIFO_DEPTH as usize / self.message_size.div_ceil(32)
+    }
+}
@@ -0,0 +1,309 @@
+//! Hardware-paced status polling
+//!
+//! Waiting for a flash write to finish or an ADC conversion to complete usually means
+//! re-reading a status register until a flag changes, with the task woken for every read.
+//! [`PioSpiMaster::poll_status`] hands that loop to the hardware: a DMA channel paced by
+//! one of the DMA block's pacing timers pushes the status-read frame at a fixed interval,
+//! the state machine shifts it, and the `PIOx_IRQ_1` handler compares each response
+//! against a mask. The task is only woken by the response that matches:
+//!
+//! ```ignore
+//! // #[interrupt]
+//! fn PIO0_IRQ_1() {
+//!     pio_spi::poll::on_poll_interrupt::<PIO0, 0>();
+//! }
+//!
+//! // Wait for the flash's write-in-progress bit to clear
+//! let status = spi
+//!     .poll_status(
+//!         p.DMA_CH2.reborrow(),
+//!         StatusPoll {
+//!             frame: 0x0500, // READ STATUS, then one byte clocked in
+//!             mask: 0x01,
+//!             expected: 0x00,
+//!             interval: Duration::from_micros(100),
+//!             timer: 0,
+//!         },
+//!     )
+//!     .await;
+//! ```
+//!
+//! # Notes
+//! - Frames are limited to 32 bits, so every poll is a single FIFO word each way
+//! - The `PIOx_IRQ_1` interrupt must be unmasked in the NVIC and forward to
+//!   [`on_poll_interrupt`]; it can share the line with [`crate::irq`] users of other
+//!   state machines
+//! - The pacing timer counts in system clock cycles, so the interval is at most 65535 of
+//!   them (about 437 µs at 150 MHz)
+
+use core::cell::Cell;
+use core::future::poll_fn;
+use core::sync::atomic::{compiler_fence, Ordering};
+use core::task::Poll;
+
+use critical_section::Mutex;
+use embassy_rp::dma::Channel;
+use embassy_rp::pac;
+use embassy_rp::pac::dma::vals::{DataSize, TransCountMode, TreqSel};
+use embassy_rp::pio::Instance;
+use embassy_rp::Peri;
+use embassy_sync::waitqueue::AtomicWaker;
+use embassy_time::Duration;
+
+use crate::claim::pio_index;
+use crate::irq::{pio_regs, FifoInterrupt};
+use crate::{Alignment, BitOrder, PioSpiMaster};
+
+/// Number of DMA pacing timers
+const DMA_TIMERS: u8 = 4;
+
+/// Status poll run by [`PioSpiMaster::poll_status`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct StatusPoll {
+    /// Frame reading the status register, as passed to
+    /// [`transfer`](PioSpiMaster::transfer)
+    pub frame: u64,
+    /// Response bits compared, in the form [`transfer`](PioSpiMaster::transfer) returns
+    pub mask: u64,
+    /// Value the masked response bits must have to end the poll
+    pub expected: u64,
+    /// Time between the starts of two polls
+    pub interval: Duration,
+    /// DMA pacing timer used (0-3); nothing else may use it while the poll runs
+    pub timer: u8,
+}
+
+/// Comparison the interrupt handler applies for one state machine, in RX FIFO word form
+#[derive(Clone, Copy)]
+struct Watch {
+    mask: u32,
+    expected: u32,
+    /// DMA channel pushing the polls, stopped on a match
+    channel: u8,
+    /// Raw RX word of the matching response
+    matched: Option<u32>,
+}
+
+/// Running polls, indexed by PIO block and state machine
+static WATCHES: Mutex<Cell<[[Option<Watch>; 4]; 3]>> = Mutex::new(Cell::new([[None; 4]; 3]));
+
+/// Tasks waiting for a match, indexed like [`WATCHES`]
+static WAKERS: [[AtomicWaker; 4]; 3] = [const { [const { AtomicWaker::new() }; 4] }; 3];
+
+/// Interrupt handler body for `PIOx_IRQ_1`: checks the responses of a running poll
+///
+/// # Behavior
+/// Pulls every response from the RX FIFO of state machine `SM` and compares it. On a
+/// match, stops the polls, disables the interrupt and wakes the task in
+/// [`poll_status`](PioSpiMaster::poll_status). Does nothing if no poll is running on the
+/// state machine.
+///
+/// # Notes
+/// - Never blocks; responses that do not match are discarded
+pub fn on_poll_interrupt<PIO: Instance, const SM: usize>() {
+    let regs = pio_regs::<PIO>();
+    let pio = pio_index::<PIO>();
+    critical_section::with(|cs| {
+        let watches = WATCHES.borrow(cs);
+        let mut all = watches.get();
+        let Some(watch) = &mut all[pio][SM] else {
+            return;
+        };
+        while watch.matched.is_none() && regs.fstat().read().rxempty() & (1 << SM) == 0 {
+            let word = regs.rxf(SM).read();
+            if word & watch.mask == watch.expected {
+                stop_dma(watch.channel);
+                regs.irqs(1)
+                    .inte()
+                    .modify(|m| m.0 &= !FifoInterrupt::RxNotEmpty.mask(SM));
+                watch.matched = Some(word);
+                WAKERS[pio][SM].wake();
+            }
+        }
+        watches.set(all);
+    });
+}
+
+/// Stops a DMA channel, waiting for any write in progress to finish
+fn stop_dma(channel: u8) {
+    let regs = pac::DMA.ch(channel as usize);
+    // Clear EN first so the abort cannot be followed by a re-trigger
+    regs.al1_ctrl().modify(|w| *w &= !1);
+    pac::DMA
+        .chan_abort()
+        .write(|w| w.set_chan_abort(1 << channel));
+    while pac::DMA.chan_abort().read().chan_abort() & (1 << channel) != 0 {}
+}
+
+/// Stops a running poll when dropped, so a cancelled future leaves no DMA running
+struct PollGuard {
+    pio: usize,
+    sm: usize,
+    regs: pac::pio::Pio,
+    channel: u8,
+}
+
+impl Drop for PollGuard {
+    fn drop(&mut self) {
+        stop_dma(self.channel);
+        critical_section::with(|cs| {
+            self.regs
+                .irqs(1)
+                .inte()
+                .modify(|m| m.0 &= !FifoInterrupt::RxNotEmpty.mask(self.sm));
+            let watches = WATCHES.borrow(cs);
+            let mut all = watches.get();
+            all[self.pio][self.sm] = None;
+            watches.set(all);
+        });
+    }
+}
+
+impl<PIO: Instance, const SM: usize> PioSpiMaster<'_, PIO, SM> {
+    /// Re-reads a status register at a fixed interval until masked bits match
+    ///
+    /// # Arguments
+    /// * `dma` - DMA channel that pushes the polls; busy until this returns
+    /// * `poll` - Frame, comparison, interval and pacing timer
+    ///
+    /// # Returns
+    /// * `u64` - The matching response, as [`transfer`](Self::transfer) returns it
+    ///
+    /// # Behavior
+    /// 1. Points `poll.timer` at `poll.interval` and starts `dma` pushing `poll.frame`
+    ///    into the TX FIFO on every timer tick, with no end count
+    /// 2. Listens for [`FifoInterrupt::RxNotEmpty`] on `PIOx_IRQ_1`, whose handler
+    ///    ([`on_poll_interrupt`]) compares `response & mask` against `expected`
+    /// 3. Sleeps until the handler reports a match, then lets polls already queued finish
+    ///    and discards their responses
+    ///
+    /// # Notes
+    /// - The state machine must be running
+    /// - Polls queued faster than frames shift are dropped by the full TX FIFO, hence the
+    ///   minimum interval
+    ///
+    /// # Panics
+    /// - If `message_size` is above 32 or frames are in flight
+    /// - If `poll.timer` is above 3
+    /// - If `poll.interval` is shorter than [`frame_duration`](Self::frame_duration) or
+    ///   longer than 65535 system clock cycles
+    ///
+    /// # Cancel Safety
+    /// Dropping the future stops the polls; the next transfer resets the state machine to
+    /// discard the frame it was shifting, as after a cancelled
+    /// [`transfer_async`](Self::transfer_async).
+    pub async fn poll_status(&mut self, dma: Peri<'_, impl Channel>, poll: StatusPoll) -> u64 {
+        assert!(self.message_size <= 32, "status polls are at most 32 bits");
+        assert!(poll.timer < DMA_TIMERS, "the DMA has pacing timers 0-3");
+        assert!(
+            poll.interval >= self.frame_duration(),
+            "poll interval shorter than a frame"
+        );
+        let ticks =
+            embassy_rp::clocks::clk_sys_freq() as u64 * poll.interval.as_micros() / 1_000_000;
+        assert!(
+            (1..=u16::MAX as u64).contains(&ticks),
+            "poll interval outside the DMA timer range"
+        );
+        self.recover_if_interrupted();
+        assert!(
+            self.in_flight == 0,
+            "status polls need an idle state machine"
+        );
+        self.claim_fifos();
+
+        let (words, _) = self.format.pack(poll.frame & self.format.tx_mask());
+        // The DMA reads the frame word from here until the guard below stops it
+        let frame = words[0];
+        let pio = pio_index::<PIO>();
+        let regs = pio_regs::<PIO>();
+        let channel = dma.number();
+        critical_section::with(|cs| {
+            let watches = WATCHES.borrow(cs);
+            let mut all = watches.get();
+            all[pio][SM] = Some(Watch {
+                mask: self.response_word(poll.mask),
+                expected: self.response_word(poll.expected & poll.mask),
+                channel,
+                matched: None,
+            });
+            watches.set(all);
+        });
+
+        self.interrupted = true;
+        let guard = PollGuard {
+            pio,
+            sm: SM,
+            regs,
+            channel,
+        };
+        pac::DMA.timer(poll.timer as usize).write(|w| {
+            w.set_x(1);
+            w.set_y(ticks as u16);
+        });
+        let ch = dma.regs();
+        ch.read_addr().write_value(&frame as *const u32 as u32);
+        ch.write_addr().write_value(regs.txf(SM).as_ptr() as u32);
+        ch.trans_count().write(|w| {
+            w.set_mode(TransCountMode::ENDLESS);
+            w.set_count(1);
+        });
+        compiler_fence(Ordering::SeqCst);
+        ch.ctrl_trig().write(|w| {
+            // TIMER0 through TIMER3 follow each other
+            w.set_treq_sel(TreqSel::from(TreqSel::TIMER0 as u8 + poll.timer));
+            w.set_data_size(DataSize::SIZE_WORD);
+            w.set_incr_read(false);
+            w.set_incr_write(false);
+            w.set_chain_to(channel);
+            w.set_irq_quiet(true);
+            w.set_en(true);
+        });
+        self.listen(FifoInterrupt::RxNotEmpty);
+
+        let word = poll_fn(|cx| {
+            WAKERS[pio][SM].register(cx.waker());
+            let matched = critical_section::with(|cs| {
+                WATCHES.borrow(cs).get()[pio][SM].and_then(|watch| watch.matched)
+            });
+            match matched {
+                Some(word) => Poll::Ready(word),
+                None => Poll::Pending,
+            }
+        })
+        .await;
+        drop(guard);
+        compiler_fence(Ordering::SeqCst);
+
+        self.wait_idle_discarding();
+        while self.sm.rx().try_pull().is_some() {}
+        self.interrupted = false;
+        self.release_fifos();
+        self.response_from_word(word)
+    }
+
+    /// Converts response bits, as `transfer` returns them, to their RX FIFO word form
+    fn response_word(&self, value: u64) -> u32 {
+        let value = match self.config.rx_alignment {
+            Alignment::Right => value,
+            Alignment::Left => value >> self.format.padding_bits(),
+        };
+        let value = value & ((1 << self.message_size) - 1);
+        match self.config.rx_bit_order {
+            BitOrder::MsbFirst => value as u32,
+            BitOrder::LsbFirst => (value as u32) << (32 - self.message_size),
+        }
+    }
+
+    /// Converts a raw RX FIFO word to the response `transfer` would return
+    fn response_from_word(&self, word: u32) -> u64 {
+        let value = match self.config.rx_bit_order {
+            BitOrder::MsbFirst => word as u64,
+            BitOrder::LsbFirst => (word >> (32 - self.message_size)) as u64,
+        };
+        match self.config.rx_alignment {
+            Alignment::Right => value,
+            Alignment::Left => value << self.format.padding_bits(),
+        }
+    }
+}
@@ -0,0 +1,261 @@
+//! Bring-up helpers for unknown or undocumented wiring
+//!
+//! [`PioSpiMaster::detect_device`] tells whether anything answers on MISO at all, so an
+//! application can skip an optional peripheral that is not fitted.
+//! [`PioSpiMaster::probe_modes`] finds the SPI mode and bit order a device answers in by
+//! checking a known register under every combination the frame programs support, and
+//! lists the rest as untestable:
+//!
+//! ```ignore
+//! let report = spi.probe_modes(|spi| spi.transfer(READ_WHO_AM_I) & 0xFF == 0x6B);
+//! for mode in report.passing() {
+//!     defmt::info!("device answers in {}", mode);
+//! }
+//! ```
+//!
+//! # Notes
+//! - The frame programs always sample MISO on the trailing edge (CPHA=1), so SPI modes 0
+//!   and 2 are never tried and come out [`Untestable`](ProbeOutcome::Untestable). A
+//!   device that only works in them fails every tried combination, or passes in mode 3 or
+//!   1 with its answer shifted by one bit; a pass therefore does not rule them out
+
+use embassy_rp::pac;
+use embassy_rp::pio::Instance;
+
+use crate::{BitOrder, ClkPolarity, PioSpiMaster};
+
+/// Outcome of [`PioSpiMaster::detect_device`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Presence {
+    /// MISO was driven with the same, not constant, response under both pulls
+    Present,
+    /// MISO followed the pulls: nothing drove it during the frame
+    Absent,
+    /// MISO was driven but the answer proves nothing (all ones or all zeros under both
+    /// pulls, as from a stuck line or a device answering 0x00 / 0xFF), or the two
+    /// responses disagreed
+    Indeterminate,
+}
+
+/// Clock edge data is sampled on (CPHA)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum ClkPhase {
+    /// First edge of each bit (CPHA=0): SPI modes 0 and 2, which the frame programs
+    /// cannot run
+    Leading,
+    /// Second edge of each bit (CPHA=1): SPI modes 1 and 3
+    Trailing,
+}
+
+/// SPI mode and bit order combination reported by [`PioSpiMaster::probe_modes`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct ProbeMode {
+    /// CLK idle level
+    pub clk_polarity: ClkPolarity,
+    /// Sampling edge
+    pub clk_phase: ClkPhase,
+    /// Bit order of both directions
+    pub bit_order: BitOrder,
+}
+
+impl ProbeMode {
+    /// Every combination: the four the frame programs can run, in the order they are
+    /// tried, then the four leading-edge ones
+    pub const ALL: [ProbeMode; 8] = [
+        ProbeMode::new(3, BitOrder::MsbFirst),
+        ProbeMode::new(1, BitOrder::MsbFirst),
+        ProbeMode::new(3, BitOrder::LsbFirst),
+        ProbeMode::new(1, BitOrder::LsbFirst),
+        ProbeMode::new(0, BitOrder::MsbFirst),
+        ProbeMode::new(2, BitOrder::MsbFirst),
+        ProbeMode::new(0, BitOrder::LsbFirst),
+        ProbeMode::new(2, BitOrder::LsbFirst),
+    ];
+
+    /// Returns the combination of SPI mode `mode` (0-3, taken modulo 4) and `bit_order`
+    pub const fn new(mode: u8, bit_order: BitOrder) -> Self {
+        Self {
+            clk_polarity: if mode & 0b10 != 0 {
+                ClkPolarity::IdleHigh
+            } else {
+                ClkPolarity::IdleLow
+            },
+            clk_phase: if mode & 0b01 != 0 {
+                ClkPhase::Trailing
+            } else {
+                ClkPhase::Leading
+            },
+            bit_order,
+        }
+    }
+
+    /// Returns the SPI mode number (0-3)
+    pub const fn spi_mode(&self) -> u8 {
+        let cpol = matches!(self.clk_polarity, ClkPolarity::IdleHigh) as u8;
+        let cpha = matches!(self.clk_phase, ClkPhase::Trailing) as u8;
+        cpol << 1 | cpha
+    }
+
+    /// Returns whether the frame programs can run the combination
+    pub const fn is_testable(&self) -> bool {
+        matches!(self.clk_phase, ClkPhase::Trailing)
+    }
+}
+
+/// Result of one combination in a [`ProbeReport`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum ProbeOutcome {
+    /// The device gave the expected answer
+    Passed,
+    /// The check ran and did not match
+    Failed,
+    /// The combination cannot be run, so nothing is known about it
+    Untestable,
+}
+
+/// Outcome of [`PioSpiMaster::probe_modes`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct ProbeReport {
+    /// Bit `i` is set if [`ProbeMode::ALL`]`[i]` passed
+    passed: u8,
+}
+
+impl ProbeReport {
+    /// Returns what the probe found out about `mode`
+    pub fn outcome(&self, mode: ProbeMode) -> ProbeOutcome {
+        if !mode.is_testable() {
+            return ProbeOutcome::Untestable;
+        }
+        if self.passed(mode) {
+            ProbeOutcome::Passed
+        } else {
+            ProbeOutcome::Failed
+        }
+    }
+
+    /// Returns `true` if the device gave the expected answer in `mode`
+    pub fn passed(&self, mode: ProbeMode) -> bool {
+        ProbeMode::ALL
+            .iter()
+            .position(|&m| m == mode)
+            .is_some_and(|i| self.passed & 1 << i != 0)
+    }
+
+    /// Returns the combinations that could not be tried
+    pub fn untestable(&self) -> impl Iterator<Item = ProbeMode> {
+        ProbeMode::ALL
+            .into_iter()
+            .filter(|mode| !mode.is_testable())
+    }
+
+    /// Returns the combinations the device gave the expected answer in
+    pub fn passing(&self) -> impl Iterator<Item = ProbeMode> + '_ {
+        ProbeMode::ALL
+            .into_iter()
+            .enumerate()
+            .filter(|&(i, _)| self.passed & 1 << i != 0)
+            .map(|(_, mode)| mode)
+    }
+
+    /// Returns the only passing combination, or `None` if none or several passed
+    pub fn unique(&self) -> Option<ProbeMode> {
+        match self.passed.count_ones() {
+            1 => self.passing().next(),
+            _ => None,
+        }
+    }
+}
+
+impl<PIO: Instance, const SM: usize> PioSpiMaster<'_, PIO, SM> {
+    /// Checks whether a device drives MISO in answer to a benign frame
+    ///
+    /// # Arguments
+    /// * `frame` - A frame with no side effects on the device (e.g. a status or ID read)
+    ///
+    /// # Returns
+    /// * `Presence` - Whether a device answered (see [`Presence`] for the criteria)
+    ///
+    /// # Behavior
+    /// Transfers `frame` twice, first with the MISO pad's pull-up enabled and then with its
+    /// pull-down, and compares the responses. An undriven line reads all ones, then all
+    /// zeros; a driving device gives the same answer both times. The pad's pulls are
+    /// restored afterwards.
+    ///
+    /// # Notes
+    /// - With an application-managed CS, assert it around the call
+    /// - Strong external pulls on MISO overpower the internal ones and make an absent
+    ///   device look [`Indeterminate`](Presence::Indeterminate)
+    pub fn detect_device(&mut self, frame: u64) -> Presence {
+        let pad = pac::PADS_BANK0.gpio(self.miso_pin as usize);
+        let pulls = pad.read();
+        let mask = self.format.rx_mask();
+
+        pad.modify(|w| {
+            w.set_pue(true);
+            w.set_pde(false);
+        });
+        let pulled_up = self.transfer(frame) & mask;
+        pad.modify(|w| {
+            w.set_pue(false);
+            w.set_pde(true);
+        });
+        let pulled_down = self.transfer(frame) & mask;
+        pad.modify(|w| {
+            w.set_pue(pulls.pue());
+            w.set_pde(pulls.pde());
+        });
+
+        match (pulled_up, pulled_down) {
+            (up, 0) if up == mask => Presence::Absent,
+            (up, down) if up == down && up != 0 && up != mask => Presence::Present,
+            _ => Presence::Indeterminate,
+        }
+    }
+
+    /// Runs a known-answer check under every SPI mode and bit order combination the frame
+    /// programs can run
+    ///
+    /// # Arguments
+    /// * `test_fn` - Runs one or more transfers against a register with a known value
+    ///   (e.g. WHO_AM_I) and returns `true` if the response matched
+    ///
+    /// # Returns
+    /// * `ProbeReport` - The combinations in which `test_fn` passed; modes 0 and 2 are
+    ///   [`Untestable`](ProbeOutcome::Untestable)
+    ///
+    /// # Behavior
+    /// For each testable [`ProbeMode::ALL`] entry (modes 3 and 1), switches the polarity
+    /// ([`set_clk_polarity`](Self::set_clk_polarity)) and both bit orders
+    /// ([`set_bit_order`](Self::set_bit_order)), then runs `test_fn`. The original
+    /// polarity and bit orders are restored afterwards.
+    ///
+    /// # Notes
+    /// - Polarity switches happen between frames, as [`set_clk_polarity`](Self::set_clk_polarity)
+    ///   requires: with an application-managed CS, keep the device deselected outside
+    ///   `test_fn`
+    /// - A device confused by a frame in the wrong mode may need a CS toggle or reset before
+    ///   it answers correctly again; do that inside `test_fn`
+    pub fn probe_modes<F>(&mut self, mut test_fn: F) -> ProbeReport
+    where
+        F: FnMut(&mut Self) -> bool,
+    {
+        let polarity = self.clk_polarity();
+        let (tx, rx) = (self.config.tx_bit_order, self.config.rx_bit_order);
+
+        let mut passed = 0;
+        for (i, mode) in ProbeMode::ALL.into_iter().enumerate() {
+            if !mode.is_testable() {
+                continue;
+            }
+            self.set_clk_polarity(mode.clk_polarity);
+            self.set_bit_order(mode.bit_order, mode.bit_order);
+            if test_fn(self) {
+                passed |= 1 << i;
+            }
+        }
+
+        self.set_clk_polarity(polarity);
+        self.set_bit_order(tx, rx);
+        ProbeReport { passed }
+    }
+}
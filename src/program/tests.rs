@@ -0,0 +1,1387 @@
+//! Program verification: static checks on every generated variant plus frames simulated
+//! on a cycle-level model of one state machine and an SPI slave.
+
+use std::collections::VecDeque;
+use std::vec::Vec;
+
+use pio::{
+    Instruction, InstructionOperands, JmpCondition, MovDestination, MovSource, OutDestination,
+    SetDestination,
+};
+
+use super::*;
+
+/// Frame sizes covering both program variants and their edges
+const SIZES: [usize; 9] = [8, 16, 17, 24, 31, 32, 33, 50, 60];
+
+/// Cycle limit for one simulated run, catching programs that hang
+const MAX_CYCLES: usize = 100_000;
+
+/// Unjoined FIFO depth
+const FIFO_DEPTH: usize = 4;
+
+/// Shift configuration of the state machine (both directions shift left)
+struct ShiftConfig {
+    autopull: bool,
+    pull_threshold: u32,
+    autopush: bool,
+    push_threshold: u32,
+}
+
+/// Cycle-level model of one state machine driving CLK (side-set), MOSI (OUT), a SET pin
+/// group and sampling MISO (IN)
+struct Sim {
+    program: pio::Program<32>,
+    shift: ShiftConfig,
+    pc: u8,
+    x: u32,
+    y: u32,
+    osr: u32,
+    osr_count: u32,
+    isr: u32,
+    isr_count: u32,
+    delay: u8,
+    exec: Option<u16>,
+    tx: VecDeque<u32>,
+    rx: VecDeque<u32>,
+    clk: bool,
+    mosi: bool,
+    /// Second side-set pin (CLK mirror)
+    clk_copy: bool,
+    /// Second OUT pin (MOSI mirror)
+    mosi_copy: bool,
+    /// Level a faulty MOSI line holds whatever is driven
+    mosi_stuck: Option<bool>,
+    set_pins: u8,
+    cycle: usize,
+    slave: Slave,
+}
+
+/// SPI slave: samples MOSI on CLK rising edges and shifts MISO on falling edges, logging
+/// edge times and SET pin changes
+#[derive(Default)]
+struct Slave {
+    miso: VecDeque<bool>,
+    miso_level: bool,
+    mosi_bits: Vec<bool>,
+    rising_edges: Vec<usize>,
+    falling_edges: Vec<usize>,
+    set_changes: Vec<(usize, u8)>,
+    /// Holds the slave-ready line LOW
+    busy: bool,
+    /// Cycles at which MISO was sampled
+    samples: Vec<usize>,
+}
+
+impl Sim {
+    fn new(program: pio::Program<32>, shift: ShiftConfig) -> Self {
+        Self {
+            program,
+            shift,
+            pc: 0,
+            x: 0,
+            y: 0,
+            osr: 0,
+            osr_count: 32,
+            isr: 0,
+            isr_count: 0,
+            delay: 0,
+            exec: None,
+            tx: VecDeque::new(),
+            rx: VecDeque::new(),
+            clk: true,
+            mosi: false,
+            clk_copy: true,
+            mosi_copy: false,
+            mosi_stuck: None,
+            set_pins: 0b111,
+            cycle: 0,
+            slave: Slave::default(),
+        }
+    }
+
+    /// Runs until `done` holds, panicking if it takes more than [`MAX_CYCLES`]
+    fn run_until(&mut self, mut done: impl FnMut(&Sim) -> bool) {
+        let start = self.cycle;
+        while !done(self) {
+            assert!(self.cycle - start < MAX_CYCLES, "program hung");
+            self.step();
+        }
+    }
+
+    /// Runs a fixed number of cycles
+    fn run_for(&mut self, cycles: usize) {
+        for _ in 0..cycles {
+            self.step();
+        }
+    }
+
+    fn step(&mut self) {
+        self.cycle += 1;
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        let (word, from_exec) = match self.exec.take() {
+            Some(word) => (word, true),
+            None => (self.program.code[self.pc as usize], false),
+        };
+        let instr = Instruction::decode(word, self.program.side_set).expect("valid instruction");
+
+        // Side-set takes effect even when the instruction stalls
+        if let Some(level) = instr.side_set {
+            self.clk_copy = level & 0b10 != 0;
+            self.drive_clk(level & 1 != 0);
+        }
+
+        let Some(jump) = self.execute(instr.operands) else {
+            return; // stalled: retry next cycle
+        };
+        self.delay = instr.delay;
+
+        // An `out exec` instruction already advanced the PC when it ran
+        if from_exec {
+            if let Some(target) = jump {
+                self.pc = target;
+            }
+            return;
+        }
+        self.pc = match jump {
+            Some(target) => target,
+            None if self.pc == self.program.wrap.source => self.program.wrap.target,
+            None => self.pc + 1,
+        };
+    }
+
+    /// Executes one instruction; `None` means stalled, `Some(Some(addr))` a taken jump
+    fn execute(&mut self, operands: InstructionOperands) -> Option<Option<u8>> {
+        match operands {
+            InstructionOperands::JMP { condition, address } => {
+                let taken = match condition {
+                    JmpCondition::Always => true,
+                    JmpCondition::XIsZero => self.x == 0,
+                    JmpCondition::XDecNonZero => {
+                        let taken = self.x != 0;
+                        self.x = self.x.wrapping_sub(1);
+                        taken
+                    }
+                    JmpCondition::YIsZero => self.y == 0,
+                    // The JMP pin is MOSI
+                    JmpCondition::PinHigh => self.mosi,
+                    JmpCondition::OutputShiftRegisterNotEmpty => {
+                        self.osr_count < self.shift.pull_threshold
+                    }
+                    JmpCondition::YDecNonZero => {
+                        let taken = self.y != 0;
+                        self.y = self.y.wrapping_sub(1);
+                        taken
+                    }
+                    other => panic!("unsupported jmp condition {other:?}"),
+                };
+                Some(taken.then_some(address))
+            }
+            InstructionOperands::OUT {
+                destination,
+                bit_count,
+            } => {
+                if self.shift.autopull && self.osr_count >= self.shift.pull_threshold {
+                    self.osr = self.tx.pop_front()?;
+                    self.osr_count = 0;
+                }
+                let bits = if bit_count == 0 { 32 } else { bit_count as u32 };
+                let value = if bits == 32 {
+                    self.osr
+                } else {
+                    self.osr >> (32 - bits)
+                };
+                self.osr = if bits == 32 { 0 } else { self.osr << bits };
+                self.osr_count = (self.osr_count + bits).min(32);
+                match destination {
+                    OutDestination::PC => return Some(Some(value as u8)),
+                    OutDestination::PINS => {
+                        self.mosi = self.mosi_stuck.unwrap_or(value & 1 != 0);
+                        self.mosi_copy = value & 0b10 != 0;
+                    }
+                    OutDestination::X => self.x = value,
+                    OutDestination::Y => self.y = value,
+                    OutDestination::NULL => {}
+                    OutDestination::EXEC => self.exec = Some(value as u16),
+                    other => panic!("unsupported out destination {other:?}"),
+                }
+                Some(None)
+            }
+            InstructionOperands::PULL { if_empty, block } => {
+                let below_threshold = self.osr_count < self.shift.pull_threshold;
+                if (if_empty && below_threshold) || (self.shift.autopull && self.osr_count == 0) {
+                    return Some(None);
+                }
+                match self.tx.pop_front() {
+                    Some(word) => self.osr = word,
+                    None if block => return None,
+                    None => self.osr = self.x,
+                }
+                self.osr_count = 0;
+                Some(None)
+            }
+            InstructionOperands::IN { source, bit_count } => {
+                let bits = if bit_count == 0 { 32 } else { bit_count as u32 };
+                let fills = self.isr_count + bits >= self.shift.push_threshold;
+                // The IN stalls when its autopush cannot complete
+                if self.shift.autopush && fills && self.rx.len() >= FIFO_DEPTH {
+                    return None;
+                }
+                let value = match source {
+                    pio::InSource::PINS => {
+                        self.slave.samples.push(self.cycle);
+                        self.slave.miso_level as u32
+                    }
+                    pio::InSource::X => self.x,
+                    pio::InSource::Y => self.y,
+                    pio::InSource::NULL => 0,
+                    other => panic!("unsupported in source {other:?}"),
+                };
+                self.isr = if bits == 32 {
+                    value
+                } else {
+                    (self.isr << bits) | (value & ((1 << bits) - 1))
+                };
+                self.isr_count = (self.isr_count + bits).min(32);
+                if self.shift.autopush && fills {
+                    self.flush_isr();
+                }
+                Some(None)
+            }
+            InstructionOperands::PUSH { if_full, block } => {
+                if if_full && self.isr_count < self.shift.push_threshold {
+                    return Some(None);
+                }
+                if !self.flush_isr() && block {
+                    return None;
+                }
+                Some(None)
+            }
+            InstructionOperands::MOV {
+                destination,
+                source,
+                ..
+            } => {
+                let value = match source {
+                    MovSource::X => self.x,
+                    MovSource::Y => self.y,
+                    MovSource::NULL => 0,
+                    MovSource::OSR => self.osr,
+                    MovSource::ISR => self.isr,
+                    other => panic!("unsupported mov source {other:?}"),
+                };
+                match destination {
+                    MovDestination::X => self.x = value,
+                    MovDestination::Y => self.y = value,
+                    MovDestination::OSR => {
+                        self.osr = value;
+                        self.osr_count = 0;
+                    }
+                    MovDestination::ISR => {
+                        self.isr = value;
+                        self.isr_count = 0;
+                    }
+                    other => panic!("unsupported mov destination {other:?}"),
+                }
+                Some(None)
+            }
+            InstructionOperands::WAIT {
+                polarity,
+                source: pio::WaitSource::GPIO,
+                ..
+            } => {
+                let level = !self.slave.busy;
+                (level == (polarity != 0)).then_some(None)
+            }
+            InstructionOperands::SET { destination, data } => {
+                match destination {
+                    SetDestination::PINS => {
+                        self.set_pins = data;
+                        self.slave.set_changes.push((self.cycle, data));
+                    }
+                    SetDestination::X => self.x = data as u32,
+                    SetDestination::Y => self.y = data as u32,
+                    other => panic!("unsupported set destination {other:?}"),
+                }
+                Some(None)
+            }
+            other => panic!("unsupported instruction {other:?}"),
+        }
+    }
+
+    /// Moves the ISR to the RX FIFO, returning `false` if the FIFO is full
+    fn flush_isr(&mut self) -> bool {
+        if self.rx.len() >= FIFO_DEPTH {
+            return false;
+        }
+        self.rx.push_back(self.isr);
+        self.isr = 0;
+        self.isr_count = 0;
+        true
+    }
+
+    fn drive_clk(&mut self, level: bool) {
+        if level == self.clk {
+            return;
+        }
+        self.clk = level;
+        if level {
+            self.slave.mosi_bits.push(self.mosi);
+            self.slave.rising_edges.push(self.cycle);
+        } else {
+            self.slave.miso_level = self.slave.miso.pop_front().unwrap_or(false);
+            self.slave.falling_edges.push(self.cycle);
+        }
+    }
+}
+
+/// Bits of `value` MSB first
+fn bits_msb_first(value: u64, width: usize) -> Vec<bool> {
+    (0..width)
+        .rev()
+        .map(|bit| (value >> bit) & 1 != 0)
+        .collect()
+}
+
+/// Packs a frame into TX FIFO words the way `PioSpiMaster` does (high word first)
+fn pack(data: u64, size: usize) -> Vec<u32> {
+    if size <= 32 {
+        return vec![(data << (32 - size)) as u32];
+    }
+    let rest = size - 32;
+    vec![(data >> rest) as u32, (data << (64 - size)) as u32]
+}
+
+/// Reassembles RX FIFO words the way `PioSpiMaster` does (high word first)
+fn unpack(words: &[u32], size: usize) -> u64 {
+    if size <= 32 {
+        return words[0] as u64;
+    }
+    ((words[0] as u64) << (size - 32)) | words[1] as u64
+}
+
+fn frame_sim(program: pio::Program<32>, size: usize) -> Sim {
+    let threshold = size.min(32) as u32;
+    let mut sim = Sim::new(
+        program,
+        ShiftConfig {
+            autopull: true,
+            pull_threshold: threshold,
+            autopush: true,
+            push_threshold: threshold,
+        },
+    );
+    sim.tx.push_back(size as u32 - 1);
+    sim
+}
+
+/// Sends `frames` through a frame program, checking MOSI bits and reassembled responses
+fn check_frames(sim: Sim, size: usize, frames: &[(u64, u64)]) -> Sim {
+    check_frames_after(sim, size, frames, size)
+}
+
+/// Same as [`check_frames`], with the slave's response starting after `turnaround` clocks
+/// of each frame (`size` for the sequential programs, 0 for full duplex)
+fn check_frames_after(mut sim: Sim, size: usize, frames: &[(u64, u64)], turnaround: usize) -> Sim {
+    let mask = (1u64 << size) - 1;
+    for &(data, response) in frames {
+        let mosi_start = sim.slave.mosi_bits.len();
+        sim.slave
+            .miso
+            .extend(std::iter::repeat_n(false, turnaround));
+        sim.slave.miso.extend(bits_msb_first(response & mask, size));
+        sim.tx.extend(pack(data & mask, size));
+
+        let words = size.div_ceil(32);
+        sim.run_until(|sim| sim.rx.len() >= words);
+        let rx: Vec<u32> = sim.rx.drain(..).collect();
+
+        assert_eq!(
+            sim.slave.mosi_bits[mosi_start..mosi_start + size],
+            bits_msb_first(data & mask, size)[..],
+            "MOSI bits of {size}-bit frame {data:#x}"
+        );
+        assert_eq!(unpack(&rx, size), response & mask, "{size}-bit response");
+    }
+    sim
+}
+
+const FRAMES: [(u64, u64); 3] = [
+    (0x0FED_CBA9_8765_4321, 0x0123_4567_89AB_CDEF),
+    (u64::MAX, 0),
+    (0, u64::MAX),
+];
+
+fn cs_variants() -> [CsTiming; 3] {
+    [
+        CsTiming {
+            setup_cycles: 0,
+            hold_cycles: 0,
+            high_time_cycles: 0,
+            lead_in_cycles: 0,
+        },
+        CsTiming {
+            setup_cycles: 5,
+            hold_cycles: 8,
+            high_time_cycles: 9,
+            lead_in_cycles: 0,
+        },
+        CsTiming {
+            setup_cycles: 255,
+            hold_cycles: 100,
+            high_time_cycles: 17,
+            lead_in_cycles: 0,
+        },
+    ]
+}
+
+/// Static checks shared by all variants
+fn check_structure(program: &pio::Program<32>) {
+    let len = program.code.len();
+    assert!(len > 0 && len <= 32, "program has {len} instructions");
+    assert!(
+        (program.wrap.source as usize) < len,
+        "wrap source out of range"
+    );
+    assert!(
+        program.wrap.target <= program.wrap.source,
+        "wrap target after wrap source"
+    );
+    assert!(program.side_set.optional(), "CLK side-set must be optional");
+    assert_eq!(
+        program.side_set.bits(),
+        2,
+        "1 side-set bit plus the opt bit"
+    );
+
+    for &word in program.code.iter() {
+        let instr = Instruction::decode(word, program.side_set).expect("valid instruction");
+        if let InstructionOperands::JMP { address, .. } = instr.operands {
+            assert!((address as usize) < len, "jmp target out of range");
+        }
+    }
+
+    // CLK must be driven HIGH (Mode 3 idle) by the first instruction, which is where the
+    // state machine waits for work
+    let first = Instruction::decode(program.code[0], program.side_set).unwrap();
+    assert_eq!(first.side_set, Some(1), "CLK must idle HIGH while waiting");
+}
+
+#[test]
+fn frame_programs_are_well_formed() {
+    for size in SIZES {
+        check_structure(&get_pio_program(size));
+        for timing in cs_variants() {
+            check_structure(&get_cs_pio_program(size, &timing, false));
+        }
+    }
+    check_structure(&get_transaction_program());
+}
+
+#[test]
+fn frame_program_sizes() {
+    // Frames of up to 32 bits need no fixups; larger ones add `push block` + `out null, 32`
+    assert_eq!(get_pio_program(16).code.len(), 10);
+    assert_eq!(get_pio_program(60).code.len(), 12);
+    assert_eq!(get_transaction_program().code.len(), 29);
+}
+
+/// Asserts a precompiled frame program equals its assembled source
+fn check_fixed(fixed: pio::Program<32>, reference: pio::Program<32>) {
+    assert_eq!(fixed.code, reference.code);
+    assert_eq!(fixed.wrap, reference.wrap);
+    assert_eq!(fixed.origin, reference.origin);
+    assert_eq!(fixed.side_set.optional(), reference.side_set.optional());
+    assert_eq!(fixed.side_set.bits(), reference.side_set.bits());
+    assert_eq!(fixed.version, reference.version);
+}
+
+#[test]
+fn fixed_frame_programs_match_assembler() {
+    let sequential = pio::pio_asm!(
+        ".side_set 1 opt",
+        "pull block side 1",
+        "out y, 32 side 1",
+        ".wrap_target",
+        "mov x, y side 1",
+        "loop_write:",
+        "  out pins, 1 side 0",
+        "  nop side 1",
+        "  jmp x--, loop_write",
+        "mov x, y side 1",
+        "loop_read:",
+        "  nop side 0",
+        "  in pins, 1 side 1",
+        "  jmp x--, loop_read",
+        ".wrap",
+    );
+    check_fixed(get_pio_program(32), sequential.program);
+    let sequential_long = pio::pio_asm!(
+        ".side_set 1 opt",
+        "pull block side 1",
+        "out y, 32 side 1",
+        ".wrap_target",
+        "mov x, y side 1",
+        "loop_write:",
+        "  out pins, 1 side 0",
+        "  nop side 1",
+        "  jmp x--, loop_write",
+        "mov x, y side 1",
+        "loop_read:",
+        "  nop side 0",
+        "  in pins, 1 side 1",
+        "  jmp x--, loop_read",
+        "push block",
+        "out null, 32",
+        ".wrap",
+    );
+    check_fixed(get_pio_program(33), sequential_long.program);
+    let full_duplex = pio::pio_asm!(
+        ".side_set 1 opt",
+        "pull block side 1",
+        "out y, 32 side 1",
+        ".wrap_target",
+        "mov x, y side 1",
+        "loop_bit:",
+        "  out pins, 1 side 0",
+        "  in pins, 1 side 1",
+        "  jmp x--, loop_bit",
+        ".wrap",
+    );
+    check_fixed(get_full_duplex_program(32), full_duplex.program);
+    let full_duplex_long = pio::pio_asm!(
+        ".side_set 1 opt",
+        "pull block side 1",
+        "out y, 32 side 1",
+        ".wrap_target",
+        "mov x, y side 1",
+        "loop_bit:",
+        "  out pins, 1 side 0",
+        "  in pins, 1 side 1",
+        "  jmp x--, loop_bit",
+        "push block",
+        "out null, 32",
+        ".wrap",
+    );
+    check_fixed(get_full_duplex_program(33), full_duplex_long.program);
+}
+
+#[test]
+fn frame_program_shifts_frames() {
+    for size in SIZES {
+        check_frames(frame_sim(get_pio_program(size), size), size, &FRAMES);
+    }
+}
+
+#[test]
+fn frame_program_keeps_clk_idle_high() {
+    let sim = check_frames(frame_sim(get_pio_program(16), 16), 16, &FRAMES[..1]);
+    assert!(sim.clk, "CLK idles HIGH between frames");
+    assert_eq!(
+        sim.slave.rising_edges.len(),
+        32,
+        "one write + one read edge per bit"
+    );
+}
+
+#[test]
+fn full_duplex_programs_shift_frames() {
+    for size in SIZES {
+        for with_cs in [false, true] {
+            let program = if with_cs {
+                get_cs_pio_program(size, &cs_variants()[1], true)
+            } else {
+                get_full_duplex_program(size)
+            };
+            check_structure(&program);
+            let sim = check_frames_after(frame_sim(program, size), size, &FRAMES, 0);
+            assert_eq!(
+                sim.slave.rising_edges.len(),
+                size * FRAMES.len(),
+                "one edge per bit"
+            );
+        }
+    }
+}
+
+#[test]
+fn cs_program_shifts_frames() {
+    for size in SIZES {
+        for timing in cs_variants() {
+            check_frames(
+                frame_sim(get_cs_pio_program(size, &timing, false), size),
+                size,
+                &FRAMES,
+            );
+        }
+    }
+}
+
+#[test]
+fn cs_program_honors_timing() {
+    for size in [16, 50] {
+        for timing in cs_variants() {
+            let mut sim = frame_sim(get_cs_pio_program(size, &timing, false), size);
+            sim.run_for(10);
+            assert!(
+                sim.slave.set_changes.is_empty(),
+                "CS asserted without a frame"
+            );
+
+            // Two back-to-back frames
+            sim = check_frames(sim, size, &FRAMES[..2]);
+            sim.run_for(400);
+            let changes = &sim.slave.set_changes;
+            assert_eq!(changes.len(), 4, "one CS pulse per frame");
+            assert_eq!((changes[0].1, changes[1].1), (0, 1));
+
+            let fall = changes[0].0;
+            let rise = changes[1].0;
+            let edges = &sim.slave.falling_edges;
+            let first_clk = *edges.iter().find(|&&t| t > fall).unwrap();
+            assert_eq!(first_clk - fall, 2 + timing.setup_cycles as usize, "setup");
+
+            let last_clk = *sim.slave.rising_edges.iter().rfind(|&&t| t < rise).unwrap();
+            assert!(rise - last_clk >= 2 + timing.hold_cycles as usize, "hold");
+
+            let next_fall = changes[2].0;
+            assert_eq!(
+                next_fall - rise,
+                2 + timing.high_time_cycles as usize,
+                "high time"
+            );
+        }
+    }
+}
+
+#[test]
+fn stretched_clock_keeps_frames_and_duty() {
+    for size in [16, 50] {
+        for with_cs in [false, true] {
+            for (low, high) in [(0, 0), (4, 1), (7, 7)] {
+                let mut program = if with_cs {
+                    get_cs_pio_program(size, &cs_variants()[1], false)
+                } else {
+                    get_pio_program(size)
+                };
+                stretch_clock(&mut program, low, high);
+                check_structure(&program);
+                let sim = check_frames(frame_sim(program, size), size, &FRAMES);
+
+                let (falls, rises) = (&sim.slave.falling_edges, &sim.slave.rising_edges);
+                for (&fall, &rise) in falls.iter().zip(rises) {
+                    assert_eq!(rise - fall, 1 + low as usize, "LOW time");
+                }
+                for (&rise, &fall) in rises.iter().zip(&falls[1..]) {
+                    assert!(fall - rise >= 2 + high as usize, "HIGH time");
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn cs_program_clocks_lead_in() {
+    for size in [16, 50] {
+        for lead_in in [1, 7, MAX_LEAD_IN_CYCLES] {
+            let timing = CsTiming {
+                setup_cycles: 3,
+                hold_cycles: 0,
+                high_time_cycles: 0,
+                lead_in_cycles: lead_in,
+            };
+            let program = get_cs_pio_program(size, &timing, false);
+            check_structure(&program);
+            let mut sim = frame_sim(program, size);
+
+            let (data, response) = FRAMES[0];
+            let mask = (1u64 << size) - 1;
+            sim.slave
+                .miso
+                .extend(std::iter::repeat_n(false, lead_in as usize + size));
+            sim.slave.miso.extend(bits_msb_first(response & mask, size));
+            sim.tx.extend(pack(data & mask, size));
+            sim.run_until(|sim| sim.rx.len() >= size.div_ceil(32));
+            sim.run_for(20);
+
+            let lead_in = lead_in as usize;
+            let changes = &sim.slave.set_changes;
+            assert_eq!(changes.len(), 2, "one CS pulse");
+            let rises = &sim.slave.rising_edges;
+            assert_eq!(rises.len(), lead_in + 2 * size, "lead-in plus data clocks");
+            assert_eq!(
+                sim.slave.mosi_bits[lead_in..lead_in + size],
+                bits_msb_first(data & mask, size)[..],
+                "data follows the lead-in clocks"
+            );
+            let words: Vec<u32> = sim.rx.drain(..).collect();
+            assert_eq!(unpack(&words, size), response & mask);
+            assert_eq!(sim.slave.falling_edges[0] - changes[0].0, 2 + 3, "setup");
+        }
+    }
+}
+
+#[test]
+fn delay_cycles_is_exact() {
+    for cycles in 0..=255u8 {
+        let mut a = Assembler::<32>::new_with_side_set(SideSet::new(true, 1, false));
+        delay_cycles(&mut a, cycles);
+        a.set(SetDestination::PINS, 1);
+        let program = a.assemble_program();
+        let mut sim = Sim::new(
+            program,
+            ShiftConfig {
+                autopull: false,
+                pull_threshold: 32,
+                autopush: false,
+                push_threshold: 32,
+            },
+        );
+        sim.run_until(|sim| !sim.slave.set_changes.is_empty());
+        assert_eq!(sim.slave.set_changes[0].0, cycles as usize + 1);
+    }
+}
+
+#[test]
+fn clock_program_honors_duty() {
+    for (low, high) in [(1, 1), (1, 2), (3, 5), (16, 16)] {
+        let mut sim = Sim::new(
+            get_clock_program(low, high),
+            ShiftConfig {
+                autopull: false,
+                pull_threshold: 32,
+                autopush: false,
+                push_threshold: 32,
+            },
+        );
+        sim.run_for(10 * (low + high) as usize);
+        let rises = &sim.slave.rising_edges;
+        let falls = &sim.slave.falling_edges;
+        assert!(rises.len() >= 8, "clock keeps running");
+        for (i, &rise) in rises.iter().enumerate().skip(1) {
+            let fall = *falls.iter().rfind(|&&t| t < rise).unwrap();
+            assert_eq!(rise - fall, low as usize, "LOW time");
+            assert_eq!(rise - rises[i - 1], (low + high) as usize, "period");
+        }
+    }
+}
+
+/// Transaction program helpers: header words as built by `Phase::header`
+const READ: u32 = 1 << 15;
+const DUMMY: u32 = 1 << 14;
+const TRANSFER: u32 = 1 << 13;
+
+fn transaction_sim() -> Sim {
+    Sim::new(
+        get_transaction_program(),
+        ShiftConfig {
+            autopull: false,
+            pull_threshold: 8,
+            autopush: false,
+            push_threshold: 32,
+        },
+    )
+}
+
+#[test]
+fn transaction_program_runs_phases() {
+    let mut sim = transaction_sim();
+    let write = [0x9Fu8, 0x00, 0xFF];
+    let read = [0xA5u8, 0x3C];
+
+    // Write phase, then 4 dummy cycles
+    sim.tx.push_back((write.len() as u32 - 1) << 16);
+    for &byte in &write {
+        sim.tx.push_back((byte as u32) << 24);
+    }
+    sim.tx.push_back((3 << 16) | DUMMY);
+    sim.run_until(|sim| sim.slave.rising_edges.len() == 24 + 4);
+    assert!(sim.rx.is_empty(), "write phases push nothing");
+
+    // Read phase: the slave shifts its bytes out from here on
+    for &byte in &read {
+        sim.slave.miso.extend(bits_msb_first(byte as u64, 8));
+    }
+    sim.tx.push_back(((read.len() as u32 - 1) << 16) | READ);
+    sim.run_until(|sim| sim.rx.len() == read.len());
+
+    let mosi: Vec<bool> = write
+        .iter()
+        .flat_map(|&b| bits_msb_first(b as u64, 8))
+        .collect();
+    assert_eq!(sim.slave.mosi_bits[..24], mosi[..]);
+    assert_eq!(sim.slave.rising_edges.len(), 24 + 4 + 16);
+    let rx: Vec<u8> = sim.rx.iter().map(|&w| w as u8).collect();
+    assert_eq!(rx, read);
+}
+
+#[test]
+fn transaction_program_reads_bytes() {
+    let mut sim = transaction_sim();
+    let read = [0xA5u8, 0x3C, 0x01];
+    for &byte in &read {
+        sim.slave.miso.extend(bits_msb_first(byte as u64, 8));
+    }
+    sim.tx.push_back(((read.len() as u32 - 1) << 16) | READ);
+    sim.run_until(|sim| sim.rx.len() == read.len());
+    let rx: Vec<u8> = sim.rx.iter().map(|&w| w as u8).collect();
+    assert_eq!(rx, read);
+    sim.run_for(20);
+    assert!(sim.clk, "CLK idles HIGH after the phase");
+}
+
+#[test]
+fn transaction_program_transfers_bytes() {
+    let mut sim = transaction_sim();
+    let write = [0x61u8, 0x00, 0xFF, 0x5A];
+    let read = [0x0Eu8, 0xA5, 0x3C, 0x81];
+    for &byte in &read {
+        sim.slave.miso.extend(bits_msb_first(byte as u64, 8));
+    }
+    sim.tx
+        .push_back(((write.len() as u32 - 1) << 16) | TRANSFER);
+    for &byte in &write {
+        sim.tx.push_back((byte as u32) << 24);
+    }
+    sim.run_until(|sim| sim.rx.len() == read.len());
+
+    let mosi: Vec<bool> = write
+        .iter()
+        .flat_map(|&b| bits_msb_first(b as u64, 8))
+        .collect();
+    assert_eq!(sim.slave.mosi_bits, mosi);
+    let rx: Vec<u8> = sim.rx.iter().map(|&w| w as u8).collect();
+    assert_eq!(rx, read, "one byte in per byte out");
+    sim.run_for(20);
+    assert_eq!(sim.slave.rising_edges.len(), 32, "no extra clocks");
+    assert!(sim.clk, "CLK idles HIGH after the phase");
+}
+
+#[test]
+fn transaction_program_sets_aux_pins() {
+    let mut sim = transaction_sim();
+    for value in [0b010u8, 0b101, 0b111] {
+        let set_pins = InstructionOperands::SET {
+            destination: SetDestination::PINS,
+            data: value,
+        };
+        sim.tx
+            .push_back(((set_pins.encode() as u32) << 16) | READ | DUMMY);
+    }
+    sim.tx.push_back(0); // 1-byte write phase
+    sim.tx.push_back(0x80 << 24);
+    sim.run_until(|sim| sim.tx.is_empty());
+    sim.run_for(40);
+
+    let values: Vec<u8> = sim.slave.set_changes.iter().map(|&(_, v)| v).collect();
+    assert_eq!(values, [0b010, 0b101, 0b111]);
+    assert_eq!(sim.slave.rising_edges.len(), 8, "aux updates do not clock");
+    assert!(sim.slave.mosi_bits[0], "write phase still follows");
+}
+
+#[test]
+fn delayed_start_shifts_every_edge() {
+    for size in [16, 50] {
+        for with_cs in [false, true] {
+            let program = || {
+                if with_cs {
+                    get_cs_pio_program(size, &cs_variants()[1], false)
+                } else {
+                    get_pio_program(size)
+                }
+            };
+            let reference = check_frames(frame_sim(program(), size), size, &FRAMES);
+            for delay in [1, 2, MAX_START_DELAY] {
+                let mut delayed = program();
+                delay_start(&mut delayed, delay).unwrap();
+                check_structure(&delayed);
+                let sim = check_frames(frame_sim(delayed, size), size, &FRAMES);
+
+                let shifted = |edges: &[usize]| -> Vec<usize> {
+                    edges.iter().map(|edge| edge + delay as usize).collect()
+                };
+                assert_eq!(
+                    sim.slave.falling_edges,
+                    shifted(&reference.slave.falling_edges)
+                );
+                assert_eq!(
+                    sim.slave.rising_edges,
+                    shifted(&reference.slave.rising_edges)
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn byte_mode_packs_four_frames_per_word() {
+    let tx: [u8; 8] = [0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xF0];
+    let rx: [u8; 8] = [0xA5, 0x00, 0xFF, 0x3C, 0x81, 0x7E, 0x01, 0x80];
+    for (duplex, turnaround) in [(false, 8), (true, 0)] {
+        let program = if duplex {
+            get_full_duplex_program(8)
+        } else {
+            get_pio_program(8)
+        };
+        // `transfer_bytes` raises both thresholds to 32 while the frame stays 8 bits
+        let mut sim = Sim::new(
+            program,
+            ShiftConfig {
+                autopull: true,
+                pull_threshold: 32,
+                autopush: true,
+                push_threshold: 32,
+            },
+        );
+        sim.tx.push_back(7);
+        for &byte in &rx {
+            sim.slave
+                .miso
+                .extend(std::iter::repeat_n(false, turnaround));
+            sim.slave.miso.extend(bits_msb_first(byte as u64, 8));
+        }
+        for chunk in tx.chunks(4) {
+            sim.tx
+                .push_back(u32::from_be_bytes(chunk.try_into().unwrap()));
+        }
+        sim.run_until(|sim| sim.rx.len() >= 2);
+
+        // The sequential program's read clocks sample MOSI too; keep each write phase
+        let written: Vec<bool> = sim
+            .slave
+            .mosi_bits
+            .chunks(8 + turnaround)
+            .flat_map(|frame| frame[..8].to_vec())
+            .collect();
+        let mosi: Vec<bool> = tx
+            .iter()
+            .flat_map(|&byte| bits_msb_first(byte as u64, 8))
+            .collect();
+        assert_eq!(written, mosi, "MOSI bytes back to back");
+        let received: Vec<u8> = sim.rx.iter().flat_map(|word| word.to_be_bytes()).collect();
+        assert_eq!(received, rx, "four responses per RX word");
+    }
+}
+
+#[test]
+fn stream_program_is_gapless() {
+    let samples: [u32; 3] = [0x00AB_CDEF, 0x0012_3456, 0x00FF_0001];
+    for threshold in [24, 32] {
+        let mut sim = Sim::new(
+            get_stream_program(true),
+            ShiftConfig {
+                autopull: true,
+                pull_threshold: threshold,
+                autopush: true,
+                push_threshold: threshold,
+            },
+        );
+        for &sample in &samples {
+            sim.tx.push_back(sample << 8);
+            sim.slave
+                .miso
+                .extend(bits_msb_first(!sample as u64, threshold as usize));
+        }
+        sim.run_until(|sim| sim.rx.len() >= samples.len());
+
+        let bits = threshold as usize * samples.len();
+        let edges = &sim.slave.rising_edges[..bits];
+        assert!(
+            edges.windows(2).all(|pair| pair[1] - pair[0] == 3),
+            "3 cycles per bit across frame boundaries"
+        );
+        let mosi: Vec<bool> = samples
+            .iter()
+            .flat_map(|&sample| {
+                bits_msb_first((sample as u64) << 8 >> (32 - threshold), threshold as usize)
+            })
+            .collect();
+        assert_eq!(sim.slave.mosi_bits[..bits], mosi[..]);
+        let mask = (1u64 << threshold) - 1;
+        for (word, &sample) in sim.rx.iter().zip(&samples) {
+            assert_eq!(
+                *word as u64,
+                !sample as u64 & mask,
+                "{threshold}-bit response"
+            );
+        }
+    }
+}
+
+#[test]
+fn ready_wait_gates_every_bit() {
+    for size in [16, 50] {
+        for with_cs in [false, true] {
+            for full_duplex in [false, true] {
+                let mut program = if with_cs {
+                    get_cs_pio_program(size, &cs_variants()[1], full_duplex)
+                } else if full_duplex {
+                    get_full_duplex_program(size)
+                } else {
+                    get_pio_program(size)
+                };
+                stretch_clock(&mut program, 7, 7);
+                wait_for_ready(&mut program, 5).unwrap();
+                check_structure(&program);
+                let turnaround = if full_duplex { 0 } else { size };
+                check_frames_after(frame_sim(program, size), size, &FRAMES, turnaround);
+            }
+        }
+    }
+
+    // A busy slave freezes CLK HIGH mid-frame; the frame completes once it is ready again
+    let size = 16;
+    let mut program = get_pio_program(size);
+    wait_for_ready(&mut program, 5).unwrap();
+    let mut sim = frame_sim(program, size);
+    sim.tx.extend(pack(0xA5C3, size));
+    sim.slave.miso.extend(bits_msb_first(0, size));
+    sim.slave.miso.extend(bits_msb_first(0x3C5A, size));
+    sim.run_until(|sim| sim.slave.rising_edges.len() == 5);
+    sim.slave.busy = true;
+    sim.run_for(3);
+    let edges = sim.slave.rising_edges.len();
+    sim.run_for(200);
+    assert_eq!(sim.slave.rising_edges.len(), edges, "no edges while busy");
+    assert!(sim.clk, "CLK held HIGH while busy");
+    sim.slave.busy = false;
+    sim.run_until(|sim| !sim.rx.is_empty());
+    assert_eq!(
+        sim.slave.mosi_bits[..size],
+        bits_msb_first(0xA5C3, size)[..]
+    );
+    assert_eq!(unpack(&[sim.rx[0]], size), 0x3C5A);
+}
+
+#[test]
+fn delayed_sampling_keeps_frames() {
+    for size in [16, 50] {
+        for full_duplex in [false, true] {
+            for delay in [1, 3, 8] {
+                let mut program = if full_duplex {
+                    get_full_duplex_program(size)
+                } else {
+                    get_pio_program(size)
+                };
+                stretch_clock(&mut program, 2, 1);
+                delay_sampling(&mut program, delay).unwrap();
+                check_structure(&program);
+                let turnaround = if full_duplex { 0 } else { size };
+                let sim = check_frames_after(frame_sim(program, size), size, &FRAMES, turnaround);
+
+                // Each sample follows the latest rising edge by exactly `delay` cycles
+                for &sample in &sim.slave.samples {
+                    let rise = sim.slave.rising_edges.iter().rev().find(|&&r| r <= sample);
+                    assert_eq!(sample - rise.unwrap(), delay as usize, "sample point");
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn trailer_clocks_follow_the_data() {
+    for size in [16, 50] {
+        for with_cs in [false, true] {
+            for full_duplex in [false, true] {
+                for trailer in [1, 5, MAX_TRAILER_CYCLES] {
+                    let mut program = if with_cs {
+                        get_cs_pio_program(size, &cs_variants()[1], full_duplex)
+                    } else if full_duplex {
+                        get_full_duplex_program(size)
+                    } else {
+                        get_pio_program(size)
+                    };
+                    add_trailer(&mut program, trailer).unwrap();
+                    stretch_clock(&mut program, 1, 2);
+                    check_structure(&program);
+                    let mut sim = frame_sim(program, size);
+
+                    let (data, response) = FRAMES[0];
+                    let mask = (1u64 << size) - 1;
+                    let turnaround = if full_duplex { 0 } else { size };
+                    sim.slave
+                        .miso
+                        .extend(std::iter::repeat_n(false, turnaround));
+                    sim.slave.miso.extend(bits_msb_first(response & mask, size));
+                    sim.tx.extend(pack(data & mask, size));
+                    sim.run_until(|sim| sim.rx.len() >= size.div_ceil(32));
+                    sim.run_for(40 * trailer as usize + 40);
+
+                    let words: Vec<u32> = sim.rx.drain(..).collect();
+                    assert_eq!(unpack(&words, size), response & mask, "response");
+                    let data_clocks = if full_duplex { size } else { 2 * size };
+                    let trailer = trailer as usize;
+                    let rises = &sim.slave.rising_edges;
+                    assert_eq!(
+                        rises.len(),
+                        data_clocks + trailer,
+                        "data plus trailer clocks"
+                    );
+                    let last_bit = data & 1 != 0;
+                    assert!(
+                        sim.slave.mosi_bits[data_clocks..]
+                            .iter()
+                            .all(|&bit| bit == last_bit),
+                        "MOSI held through the trailer"
+                    );
+                    if with_cs {
+                        let changes = &sim.slave.set_changes;
+                        assert_eq!(changes.len(), 2, "one CS pulse");
+                        assert!(
+                            changes[1].0 > *rises.last().unwrap(),
+                            "CS held over the trailer"
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn program_generators_accept_every_limit() {
+    // Every combination of the timing options at 0 and at their limits, on every base
+    // program: a program that does not fit is reported, never a panic or a broken program
+    let mut full = 0;
+    for size in [1, 8, 32, 33, 60] {
+        for base in 0..4 {
+            for options in 0..1u32 << 10 {
+                let at_limit =
+                    |bit: u32, limit: u8| if options & 1 << bit != 0 { limit } else { 0 };
+                let timing = CsTiming {
+                    setup_cycles: at_limit(7, u8::MAX),
+                    hold_cycles: at_limit(8, u8::MAX),
+                    high_time_cycles: at_limit(9, u8::MAX),
+                    lead_in_cycles: at_limit(0, MAX_LEAD_IN_CYCLES),
+                };
+                let mut program = match base {
+                    0 => get_pio_program(size),
+                    1 => get_full_duplex_program(size),
+                    _ => get_cs_pio_program(size, &timing, base == 3),
+                };
+                let fits = add_trailer(&mut program, at_limit(1, MAX_TRAILER_CYCLES))
+                    .and_then(|()| {
+                        stretch_clock(
+                            &mut program,
+                            at_limit(2, MAX_CLK_STRETCH),
+                            at_limit(3, MAX_CLK_STRETCH),
+                        );
+                        delay_sampling(&mut program, at_limit(4, MAX_SAMPLE_DELAY))
+                    })
+                    .and_then(|()| match options & 1 << 5 {
+                        0 => Ok(()),
+                        _ => wait_for_ready(&mut program, 31),
+                    })
+                    .and_then(|()| delay_start(&mut program, at_limit(6, MAX_START_DELAY)));
+                match fits {
+                    Ok(()) => check_structure(&program),
+                    Err(ProgramFull) => full += 1,
+                }
+            }
+        }
+    }
+    assert!(full > 0, "the limits include programs that do not fit");
+}
+
+#[test]
+fn multi_size_program_picks_size_per_frame() {
+    let sizes = [16, 24, 8, 27];
+    let program = get_multi_size_program(&sizes);
+    check_structure(&program);
+    assert_eq!(program.code.len(), 2 * sizes.len() + 10);
+    assert_eq!(
+        get_multi_size_program(&[1; MAX_FRAME_SIZES]).code.len(),
+        2 * MAX_FRAME_SIZES + 10
+    );
+
+    let mut sim = Sim::new(
+        program,
+        ShiftConfig {
+            autopull: false,
+            pull_threshold: 32,
+            autopush: false,
+            push_threshold: 32,
+        },
+    );
+    // Slots in an order that jumps back and forth through the table
+    for (slot, data, response) in [
+        (1, 0xAB_CDEF, 0x12_3456),
+        (0, 0xBEEF, 0xCAFE),
+        (3, 0x7FF_FFFF, 0x555_5555),
+        (2, 0x81, 0x7E),
+        (1, 0, 0xFF_FFFF),
+    ] {
+        let size = sizes[slot];
+        let mosi_start = sim.slave.mosi_bits.len();
+        sim.slave.miso.extend(std::iter::repeat_n(false, size));
+        sim.slave.miso.extend(bits_msb_first(response, size));
+        let header = (MULTI_SIZE_TABLE as u32 + 2 * slot as u32) << MAX_MULTI_SIZE_BITS;
+        sim.tx
+            .push_back(header | (data as u32) << (MAX_MULTI_SIZE_BITS - size));
+        sim.run_until(|sim| !sim.rx.is_empty());
+
+        assert_eq!(
+            sim.slave.mosi_bits.len() - mosi_start,
+            2 * size,
+            "{size} write and {size} read clocks"
+        );
+        assert_eq!(
+            sim.slave.mosi_bits[mosi_start..mosi_start + size],
+            bits_msb_first(data, size)[..],
+            "MOSI bits of {size}-bit frame"
+        );
+        assert_eq!(
+            sim.rx.pop_front(),
+            Some(response as u32),
+            "{size}-bit response"
+        );
+    }
+}
+
+#[test]
+fn mirrored_outputs_follow_clk_and_mosi() {
+    assert_eq!(double_bits(0b1011_0001), 0b11_00_11_11_00_00_00_11);
+
+    let size = 12;
+    for full_duplex in [false, true] {
+        let mut program = if full_duplex {
+            get_full_duplex_program(size)
+        } else {
+            get_pio_program(size)
+        };
+        let len = program.code.len();
+        mirror_outputs(&mut program, true, true).unwrap();
+        assert_eq!(program.code.len(), len, "mirroring adds no instructions");
+
+        let mut sim = Sim::new(
+            program,
+            ShiftConfig {
+                autopull: true,
+                pull_threshold: 2 * size as u32,
+                autopush: true,
+                push_threshold: size as u32,
+            },
+        );
+        sim.tx.push_back(size as u32 - 1);
+        let turnaround = if full_duplex { 0 } else { size };
+        for (data, response) in [(0xA5C, 0x3F1), (0xFFF, 0), (0x001, 0x800)] {
+            let mosi_start = sim.slave.mosi_bits.len();
+            sim.slave
+                .miso
+                .extend(std::iter::repeat_n(false, turnaround));
+            sim.slave.miso.extend(bits_msb_first(response, size));
+            sim.tx.push_back(double_bits((data << (16 - size)) as u16));
+            sim.run_until(|sim| {
+                assert_eq!(sim.clk_copy, sim.clk, "CLK copy switches with CLK");
+                assert_eq!(sim.mosi_copy, sim.mosi, "MOSI copy switches with MOSI");
+                !sim.rx.is_empty()
+            });
+
+            assert_eq!(
+                sim.slave.mosi_bits[mosi_start..mosi_start + size],
+                bits_msb_first(data, size)[..],
+                "MOSI bits"
+            );
+            assert_eq!(sim.rx.pop_front(), Some(response as u32), "response");
+        }
+    }
+
+    // The second side-set pin leaves 2 delay bits
+    let mut stretched = get_pio_program(16);
+    stretch_clock(&mut stretched, MAX_MIRRORED_DELAY, MAX_MIRRORED_DELAY);
+    assert_eq!(mirror_outputs(&mut stretched, true, false), Ok(()));
+    let mut stretched = get_pio_program(16);
+    stretch_clock(&mut stretched, MAX_MIRRORED_DELAY + 1, 0);
+    let before = stretched.code.clone();
+    assert_eq!(
+        mirror_outputs(&mut stretched, true, false),
+        Err(DelayTooLong)
+    );
+    assert_eq!(
+        stretched.code, before,
+        "a rejected program is left unchanged"
+    );
+}
+
+#[test]
+fn readback_program_reports_mosi_levels() {
+    let program = get_readback_program();
+    check_structure(&program);
+
+    let size = 12;
+    let mut sim = Sim::new(
+        program,
+        ShiftConfig {
+            autopull: false,
+            pull_threshold: size as u32,
+            autopush: false,
+            push_threshold: 32,
+        },
+    );
+    sim.tx.push_back(size as u32 - 1);
+    for (stuck, data, response) in [
+        (None, 0xA5C, 0x3F1),
+        (None, 0xFFF, 0),
+        (Some(false), 0x9C3, 0x800),
+        (Some(true), 0x001, 0x7FE),
+    ] {
+        sim.mosi_stuck = stuck;
+        let mosi_start = sim.slave.mosi_bits.len();
+        let rising_start = sim.slave.rising_edges.len();
+        sim.slave.miso.extend(std::iter::repeat_n(false, size));
+        sim.slave.miso.extend(bits_msb_first(response, size));
+        sim.tx.push_back((data as u32) << (32 - size));
+        sim.run_until(|sim| sim.rx.len() == 2);
+
+        let seen = match stuck {
+            None => data,
+            Some(false) => 0,
+            Some(true) => 0xFFF,
+        };
+        assert_eq!(
+            sim.slave.mosi_bits[mosi_start..mosi_start + size],
+            bits_msb_first(seen, size)[..],
+            "slave sees the pin level"
+        );
+        assert_eq!(sim.rx.pop_front(), Some(seen as u32), "readback");
+        assert_eq!(sim.rx.pop_front(), Some(response as u32), "response");
+
+        let edges = &sim.slave.rising_edges[rising_start..];
+        assert!(
+            edges[..size]
+                .windows(2)
+                .all(|pair| pair[1] - pair[0] == READBACK_WRITE_CYCLES as usize),
+            "write bits take the same time whatever their level"
+        );
+        assert!(
+            edges[size..].windows(2).all(|pair| pair[1] - pair[0] == 3),
+            "read bits keep the frame program's timing"
+        );
+    }
+}
+
+#[test]
+fn capture_program_samples_every_cycle() {
+    for sample_bits in [1, 2, 4, 8, 16, 32] {
+        let program = get_capture_program(sample_bits);
+        assert_eq!(program.code.len(), 1);
+        assert_eq!((program.wrap.source, program.wrap.target), (0, 0));
+        let instr = Instruction::decode(program.code[0], program.side_set).unwrap();
+        assert_eq!(instr.delay, 0, "one sample per cycle");
+        assert!(
+            matches!(
+                instr.operands,
+                InstructionOperands::IN {
+                    source: pio::InSource::PINS,
+                    bit_count,
+                } if bit_count == sample_bits
+            ),
+            "in pins, {sample_bits}"
+        );
+    }
+}
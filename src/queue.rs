@@ -0,0 +1,250 @@
+//! Background transfer queue
+//!
+//! Lets several tasks share one [`PioSpiMaster`] without serializing access themselves.
+//! Tasks call [`TransferQueue::enqueue`] to submit a frame and get a [`TransferHandle`];
+//! a single runner task owns the master and feeds queued frames to it in submission order
+//! ([`TransferQueue::run`]). Each handle resolves to the response of its own frame.
+//!
+//! ```ignore
+//! static QUEUE: TransferQueue<CriticalSectionRawMutex, 4> = TransferQueue::new();
+//!
+//! #[embassy_executor::task]
+//! async fn spi_runner(mut spi: PioSpiMaster<'static, PIO0, 0>) {
+//!     QUEUE.run(&mut spi).await
+//! }
+//!
+//! // Any task:
+//! let response = QUEUE.enqueue(0xABCD).await.wait().await;
+//! ```
+//!
+//! # Notes
+//! - At most `N` (up to 32) transfers can be outstanding; `enqueue` waits for a free slot
+//! - Dropping a handle without awaiting it is allowed; the frame is still sent and its
+//!   response discarded
+//!
+//! # Priority Frames
+//!
+//! [`TransferQueue::enqueue_urgent`] submits a frame (e.g. an emergency DAC zero) that the
+//! runner sends ahead of every normal frame still waiting in the queue. It cannot overtake
+//! the frame the runner has already taken: that one is in the PIO FIFOs or on the wire and
+//! completes first, so an urgent frame starts at most one frame time later. Urgent frames
+//! keep their submission order among themselves and share the `N` slots with normal ones.
+
+use core::cell::RefCell;
+use core::future::poll_fn;
+use core::task::Poll;
+
+use embassy_rp::pio::Instance;
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::channel::Channel;
+use embassy_sync::signal::Signal;
+use embassy_sync::waitqueue::WakerRegistration;
+
+use crate::PioSpiMaster;
+
+/// Slot bookkeeping shared between submitters, handles and the runner
+struct Slots {
+    /// Slots owned by a pending or unclaimed transfer
+    used: u32,
+    /// Slots whose handle was dropped before the response arrived
+    abandoned: u32,
+    /// Submitter waiting for a free slot
+    waker: WakerRegistration,
+}
+
+/// Queue of frames shared between submitting tasks and one runner
+///
+/// # Type Parameters
+/// * `M` - Mutex kind guarding the queue (e.g. `CriticalSectionRawMutex`)
+/// * `N` - Maximum number of outstanding transfers (1-32)
+pub struct TransferQueue<M: RawMutex, const N: usize> {
+    requests: Channel<M, (u8, u64), N>,
+    urgent: Channel<M, (u8, u64), N>,
+    results: [Signal<M, u64>; N],
+    slots: Mutex<M, RefCell<Slots>>,
+}
+
+impl<M: RawMutex, const N: usize> Default for TransferQueue<M, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M: RawMutex, const N: usize> TransferQueue<M, N> {
+    /// Creates an empty queue (usable in a `static`)
+    ///
+    /// # Panics
+    /// If `N` is 0 or greater than 32
+    pub const fn new() -> Self {
+        assert!(N >= 1 && N <= 32, "queue depth must be 1-32");
+        Self {
+            requests: Channel::new(),
+            urgent: Channel::new(),
+            results: [const { Signal::new() }; N],
+            slots: Mutex::new(RefCell::new(Slots {
+                used: 0,
+                abandoned: 0,
+                waker: WakerRegistration::new(),
+            })),
+        }
+    }
+
+    /// Submits a frame for transfer
+    ///
+    /// # Arguments
+    /// * `frame` - Data to shift out on MOSI (only bits [message_size-1:0] are used)
+    ///
+    /// # Returns
+    /// * `TransferHandle` - Resolves to the frame's response once the runner has sent it
+    ///
+    /// # Behavior
+    /// Waits while `N` transfers are already outstanding, then queues the frame behind
+    /// every previously submitted one.
+    pub async fn enqueue(&self, frame: u64) -> TransferHandle<'_, M, N> {
+        let slot = self.acquire().await;
+        // Every outstanding transfer holds a slot, so the channel always has room
+        let _ = self.requests.try_send((slot, frame));
+        TransferHandle {
+            queue: self,
+            slot,
+            done: false,
+        }
+    }
+
+    /// Submits a frame to be sent before every normal frame still queued
+    ///
+    /// # Arguments
+    /// * `frame` - Data to shift out on MOSI (only bits [message_size-1:0] are used)
+    ///
+    /// # Returns
+    /// * `TransferHandle` - Resolves to the frame's response once the runner has sent it
+    ///
+    /// # Behavior
+    /// Waits for a free slot like [`enqueue`](Self::enqueue), then queues the frame behind
+    /// earlier urgent frames only. The frame the runner is transferring right now is not
+    /// preempted (see the [module docs](self)).
+    pub async fn enqueue_urgent(&self, frame: u64) -> TransferHandle<'_, M, N> {
+        let slot = self.acquire().await;
+        let _ = self.urgent.try_send((slot, frame));
+        TransferHandle {
+            queue: self,
+            slot,
+            done: false,
+        }
+    }
+
+    /// Waits for a free slot and marks it used
+    async fn acquire(&self) -> u8 {
+        poll_fn(|cx| {
+            self.slots.lock(|slots| {
+                let mut slots = slots.borrow_mut();
+                let free = (!slots.used).trailing_zeros() as usize;
+                if free < N {
+                    slots.used |= 1 << free;
+                    Poll::Ready(free as u8)
+                } else {
+                    slots.waker.register(cx.waker());
+                    Poll::Pending
+                }
+            })
+        })
+        .await
+    }
+
+    /// Sends queued frames forever, completing their handles in order
+    ///
+    /// # Arguments
+    /// * `spi` - SPI master the queue drives; run this from the task that owns it
+    ///
+    /// # Behavior
+    /// Takes one frame at a time, urgent frames first, and awaits its response before
+    /// taking the next.
+    pub async fn run<PIO: Instance, const SM: usize>(
+        &self,
+        spi: &mut PioSpiMaster<'_, PIO, SM>,
+    ) -> ! {
+        loop {
+            let (slot, frame) = poll_fn(|cx| match self.urgent.poll_receive(cx) {
+                Poll::Ready(request) => Poll::Ready(request),
+                Poll::Pending => self.requests.poll_receive(cx),
+            })
+            .await;
+            let response = spi.transfer_async(frame).await;
+            self.complete(slot, response);
+        }
+    }
+
+    /// Delivers a response to its handle, or frees the slot if the handle is gone
+    fn complete(&self, slot: u8, response: u64) {
+        self.slots.lock(|slots| {
+            let mut slots = slots.borrow_mut();
+            let bit = 1 << slot;
+            if slots.abandoned & bit != 0 {
+                slots.abandoned &= !bit;
+                slots.used &= !bit;
+                slots.waker.wake();
+            } else {
+                self.results[slot as usize].signal(response);
+            }
+        });
+    }
+
+    /// Returns a slot to the free pool
+    fn release(&self, slot: u8) {
+        self.slots.lock(|slots| {
+            let mut slots = slots.borrow_mut();
+            slots.used &= !(1 << slot);
+            slots.waker.wake();
+        });
+    }
+}
+
+/// Completion handle for a queued transfer
+#[must_use = "the response is lost unless the handle is awaited"]
+pub struct TransferHandle<'q, M: RawMutex, const N: usize> {
+    queue: &'q TransferQueue<M, N>,
+    slot: u8,
+    done: bool,
+}
+
+impl<M: RawMutex, const N: usize> TransferHandle<'_, M, N> {
+    /// Waits for the transfer to complete
+    ///
+    /// # Returns
+    /// * `u64` - Response bits read from MISO
+    pub async fn wait(mut self) -> u64 {
+        let response = self.queue.results[self.slot as usize].wait().await;
+        self.done = true;
+        self.queue.release(self.slot);
+        response
+    }
+
+    /// Returns `true` once the response is available
+    pub fn is_done(&self) -> bool {
+        self.queue.results[self.slot as usize].signaled()
+    }
+}
+
+impl<M: RawMutex, const N: usize> Drop for TransferHandle<'_, M, N> {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        let queue = self.queue;
+        let slot = self.slot;
+        queue.slots.lock(|slots| {
+            let mut slots = slots.borrow_mut();
+            let result = &queue.results[slot as usize];
+            if result.signaled() {
+                // Response already delivered: discard it and free the slot now
+                result.reset();
+                slots.used &= !(1 << slot);
+                slots.waker.wake();
+            } else {
+                // Still in flight: the runner frees the slot when it completes
+                slots.abandoned |= 1 << slot;
+            }
+        });
+    }
+}
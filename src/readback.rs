@@ -0,0 +1,249 @@
+//! MOSI readback for detecting wiring faults in the field
+//!
+//! A MOSI line shorted to a neighbour, stuck at a rail or mangled by a failing level
+//! shifter still produces frames; the slave just receives something else. [`ReadbackSpi`]
+//! runs a frame program that reads the MOSI pin back at every bit of the write phase, while
+//! CLK is HIGH and the slave samples it, and compares the levels against the data sent:
+//!
+//! ```ignore
+//! let mut spi = ReadbackSpi::new(&mut common, sm0, &clk, &mosi, &miso, 8, 16);
+//!
+//! match spi.transfer(WRITE_GAIN | gain) {
+//!     Ok(status) => handle(status),
+//!     Err(fault) if fault.stuck_at().is_some() => raise_alarm(fault),
+//!     Err(fault) => defmt::warn!("MOSI bits {=u32:b} flipped", fault.flipped()),
+//! }
+//! ```
+//!
+//! # Notes
+//! - Frames are sequential (write phase, then read phase, as
+//!   [`Duplex::Half`](crate::Duplex::Half)), MSB first in SPI Mode 3, and at most 32 bits
+//! - Write phase bits take 7 state machine cycles (1 LOW + 6 HIGH) instead of 3, so the
+//!   level read back has passed the pad's input synchronizer; the read phase keeps the
+//!   usual timing
+//! - The level is read at the RP2350's own pad: faults between the pad and the slave
+//!   (a broken trace, a dead level shifter output driving nothing) read back correct
+//! - There is no PIO-managed chip select; drive each device's CS from a GPIO
+
+use embassy_rp::gpio::Level;
+use embassy_rp::pio::{
+    Common, Config, Direction, Instance, LoadedProgram, Pin, ShiftDirection, StateMachine,
+};
+
+use crate::claim::{claim_pins, release_claim};
+use crate::master::{clock_divider, sm_frequency};
+use crate::program::{get_readback_program, MAX_READBACK_BITS, READBACK_WRITE_CYCLES};
+use crate::CYCLES_PER_BIT;
+
+/// A frame whose MOSI levels at the pin differed from the data sent
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct WireFault {
+    /// Frame as sent, in bits [message_size-1:0]
+    pub sent: u32,
+    /// MOSI levels read back, in bits [message_size-1:0]
+    pub seen: u32,
+    /// Response read from MISO; the slave received `seen`, not `sent`
+    pub response: u32,
+    /// Frame size in bits
+    pub message_size: usize,
+}
+
+impl WireFault {
+    /// Returns the bits whose level at the pin differed from the one driven
+    pub fn flipped(&self) -> u32 {
+        self.sent ^ self.seen
+    }
+
+    /// Returns the level MOSI read back at every bit, if it never changed
+    ///
+    /// A line stuck at one level points to a short to a rail or a disconnected level
+    /// shifter; scattered flips point to crosstalk or a marginal edge rate instead.
+    pub fn stuck_at(&self) -> Option<Level> {
+        let mask = u32::MAX >> (32 - self.message_size);
+        match self.seen {
+            0 => Some(Level::Low),
+            seen if seen == mask => Some(Level::High),
+            _ => None,
+        }
+    }
+}
+
+/// SPI master that checks every frame's MOSI levels at the pin
+pub struct ReadbackSpi<'d, PIO: Instance, const SM: usize> {
+    sm: StateMachine<'d, PIO, SM>,
+    program: LoadedProgram<'d, PIO>,
+    message_size: usize,
+    clk_div: u16,
+}
+
+impl<'d, PIO: Instance, const SM: usize> ReadbackSpi<'d, PIO, SM> {
+    /// Loads the readback program and starts the state machine
+    ///
+    /// # Arguments
+    /// * `common` - The PIO peripheral's common interface (for program loading and pin setup)
+    /// * `sm` - State machine (takes ownership)
+    /// * `clk_pin` - Clock pin (side-set/output)
+    /// * `mosi_pin` - MOSI pin (output, also read back through the JMP pin)
+    /// * `miso_pin` - MISO pin (input)
+    /// * `clk_div` - Clock divider setting, as in
+    ///   [`SpiMasterConfig::clk_div`](crate::SpiMasterConfig)
+    /// * `message_size` - Frame size in bits (1-32)
+    ///
+    /// # Panics
+    /// - If `clk_div` is below 2 or `message_size` is out of range
+    /// - If another state machine of the same PIO block already drives CLK or MOSI (see
+    ///   [`PinConflict`](crate::PinConflict))
+    /// - If the program (17 instructions) does not fit the free instruction memory
+    pub fn new(
+        common: &mut Common<'d, PIO>,
+        sm: StateMachine<'d, PIO, SM>,
+        clk_pin: &Pin<'d, PIO>,
+        mosi_pin: &Pin<'d, PIO>,
+        miso_pin: &Pin<'d, PIO>,
+        clk_div: u16,
+        message_size: usize,
+    ) -> Self {
+        assert!(clk_div >= 2, "clk_div must be at least 2");
+        assert!(
+            (1..=MAX_READBACK_BITS).contains(&message_size),
+            "message_size must be 1-32 bits"
+        );
+        claim_pins::<PIO, SM, 2>([Some(clk_pin.pin()), Some(mosi_pin.pin())])
+            .expect("pin already driven by another state machine");
+        let program = common.load_program(&get_readback_program());
+
+        let mut cfg = Config::default();
+        cfg.use_program(&program, &[clk_pin]);
+        cfg.set_out_pins(&[mosi_pin]);
+        cfg.set_in_pins(&[miso_pin]);
+        cfg.set_jmp_pin(mosi_pin);
+        cfg.clock_divider = clock_divider(clk_div);
+        // `jmp !osre` ends the write phase at the threshold; the program pulls and pushes
+        cfg.shift_out.auto_fill = false;
+        cfg.shift_out.threshold = message_size as u8;
+        cfg.shift_out.direction = ShiftDirection::Left;
+        cfg.shift_in.auto_fill = false;
+        cfg.shift_in.threshold = 32;
+        cfg.shift_in.direction = ShiftDirection::Left;
+
+        let mut sm = sm;
+        sm.set_config(&cfg);
+        sm.set_pins(Level::High, &[clk_pin]);
+        sm.set_pin_dirs(Direction::Out, &[clk_pin, mosi_pin]);
+        sm.set_pin_dirs(Direction::In, &[miso_pin]);
+        sm.set_enable(true);
+        sm.tx().push(message_size as u32 - 1);
+
+        Self {
+            sm,
+            program,
+            message_size,
+            clk_div,
+        }
+    }
+
+    /// Returns the frame size in bits
+    pub fn message_size(&self) -> usize {
+        self.message_size
+    }
+
+    /// Returns the SCK frequency of the read phase in Hz at the current system clock
+    ///
+    /// # Notes
+    /// - The write phase runs at 3/7 of this rate
+    pub fn sck_frequency(&self) -> u32 {
+        sm_frequency(self.clk_div) / CYCLES_PER_BIT
+    }
+
+    /// Returns the SCK frequency of the write phase in Hz at the current system clock
+    pub fn write_sck_frequency(&self) -> u32 {
+        sm_frequency(self.clk_div) / READBACK_WRITE_CYCLES
+    }
+
+    /// Shifts one frame out, checks the MOSI levels read back and returns the response
+    ///
+    /// # Arguments
+    /// * `data` - Frame in bits [message_size-1:0]; higher bits are ignored
+    ///
+    /// # Returns
+    /// * `Ok(u32)` - Response in bits [message_size-1:0], the rest zero
+    /// * `Err(WireFault)` - MOSI did not carry `data`; the fault holds the levels seen and
+    ///   the response
+    pub fn transfer(&mut self, data: u32) -> Result<u32, WireFault> {
+        let data = self.push(data);
+        let seen = self.pull();
+        let response = self.pull();
+        self.check(data, seen, response)
+    }
+
+    /// Shifts one frame out and checks it, awaiting FIFO space and the response
+    ///
+    /// Same behavior as [`transfer`](Self::transfer), but yields to the executor while the
+    /// frame shifts. Requires the PIO interrupt handler to be bound.
+    ///
+    /// # Cancel Safety
+    /// Not cancel-safe: a frame dropped after it was queued leaves its readback and
+    /// response in the RX FIFO, where the next transfer would take them. Do not race this
+    /// against a timeout.
+    pub async fn transfer_async(&mut self, data: u32) -> Result<u32, WireFault> {
+        let data = data & self.mask();
+        self.sm
+            .tx()
+            .wait_push(data << (32 - self.message_size))
+            .await;
+        let seen = self.sm.rx().wait_pull().await;
+        let response = self.sm.rx().wait_pull().await;
+        self.check(data, seen, response)
+    }
+
+    /// Stops the state machine and frees the program's instruction memory
+    ///
+    /// # Arguments
+    /// * `common` - The PIO peripheral's common interface the program was loaded with
+    ///
+    /// # Returns
+    /// * `StateMachine` - The stopped state machine, ready to be reused by another driver
+    pub fn free(mut self, common: &mut Common<'d, PIO>) -> StateMachine<'d, PIO, SM> {
+        self.sm.set_enable(false);
+        self.sm.clear_fifos();
+        release_claim::<PIO, SM>();
+        // SAFETY: the program is private to this master, whose state machine is stopped
+        unsafe { common.free_instr(self.program.used_memory) };
+        self.sm
+    }
+
+    /// Returns the mask of a frame's bits
+    fn mask(&self) -> u32 {
+        u32::MAX >> (32 - self.message_size)
+    }
+
+    /// Queues `data` left-justified, returning it masked to the frame size
+    fn push(&mut self, data: u32) -> u32 {
+        let data = data & self.mask();
+        self.sm.tx().push(data << (32 - self.message_size));
+        data
+    }
+
+    /// Busy-waits for the next RX word
+    fn pull(&mut self) -> u32 {
+        loop {
+            if let Some(word) = self.sm.rx().try_pull() {
+                return word;
+            }
+        }
+    }
+
+    /// Compares the readback of a frame against the data sent
+    fn check(&self, sent: u32, seen: u32, response: u32) -> Result<u32, WireFault> {
+        if seen == sent {
+            Ok(response)
+        } else {
+            Err(WireFault {
+                sent,
+                seen,
+                response,
+                message_size: self.message_size,
+            })
+        }
+    }
+}
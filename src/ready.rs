@@ -0,0 +1,162 @@
+//! Awaitable FIFO readiness
+//!
+//! [`transfer_async`](PioSpiMaster::transfer_async) and friends wait for FIFO space and
+//! responses internally. Custom pipelining, or one task serving several masters and other
+//! peripherals from a `select`, needs the waits on their own:
+//! [`wait_tx_space`](PioSpiMaster::wait_tx_space) and
+//! [`wait_rx_ready`](PioSpiMaster::wait_rx_ready) complete once a frame can be started or
+//! a response collected without blocking, and leave the FIFOs untouched:
+//!
+//! ```ignore
+//! // #[interrupt]
+//! fn PIO0_IRQ_1() {
+//!     pio_spi::ready::on_ready_interrupt::<PIO0, 0>();
+//!     pio_spi::ready::on_ready_interrupt::<PIO0, 1>();
+//! }
+//!
+//! loop {
+//!     match select(spi0.wait_rx_ready(), spi1.wait_rx_ready()).await {
+//!         Either::First(()) => handle(spi0.on_interrupt().unwrap()),
+//!         Either::Second(()) => handle(spi1.on_interrupt().unwrap()),
+//!     }
+//! }
+//! ```
+//!
+//! Pair them with the non-blocking [`try_start`](PioSpiMaster::try_start) and
+//! [`on_interrupt`](PioSpiMaster::on_interrupt).
+//!
+//! # Notes
+//! - The waits use the `PIOx_IRQ_1` line, which must be unmasked in the NVIC and forward
+//!   to [`on_ready_interrupt`]; [`crate::poll`] can share the handler. Do not `listen`
+//!   for the same conditions through [`crate::irq`] meanwhile
+//! - The conditions are per word: for frames longer than 32 bits the task may wake once
+//!   more before both words of a frame are there
+
+use core::future::poll_fn;
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::task::Poll;
+
+use embassy_rp::pac;
+use embassy_rp::pio::Instance;
+use embassy_sync::waitqueue::AtomicWaker;
+
+use crate::claim::pio_index;
+use crate::irq::{pio_regs, FifoInterrupt, FIFO_DEPTH};
+use crate::PioSpiMaster;
+
+/// Tasks waiting for a condition, indexed by PIO block, state machine and condition
+static WAKERS: [[[AtomicWaker; 2]; 4]; 3] =
+    [const { [const { [const { AtomicWaker::new() }; 2] }; 4] }; 3];
+
+/// INTE bits enabled by a wait, per PIO block, so the handler leaves others alone
+static ARMED: [AtomicU32; 3] = [const { AtomicU32::new(0) }; 3];
+
+/// Index of a condition in [`WAKERS`]
+fn slot(irq: FifoInterrupt) -> usize {
+    match irq {
+        FifoInterrupt::TxNotFull => 0,
+        FifoInterrupt::RxNotEmpty => 1,
+    }
+}
+
+/// Interrupt handler body for `PIOx_IRQ_1`: wakes the waits of state machine `SM`
+///
+/// # Behavior
+/// For each condition a wait enabled and the hardware asserts, disables its interrupt
+/// and wakes the waiting task, which checks the FIFO level and re-enables it if the
+/// frame does not fit yet. Does nothing for conditions no wait enabled.
+///
+/// # Notes
+/// - Never blocks and touches neither FIFO
+pub fn on_ready_interrupt<PIO: Instance, const SM: usize>() {
+    let regs = pio_regs::<PIO>();
+    let pio = pio_index::<PIO>();
+    let ints = regs.irqs(1).ints().read().0;
+    for irq in [FifoInterrupt::TxNotFull, FifoInterrupt::RxNotEmpty] {
+        let mask = irq.mask(SM);
+        if ints & mask != 0 && ARMED[pio].load(Ordering::Relaxed) & mask != 0 {
+            disarm(regs, pio, mask);
+            WAKERS[pio][SM][slot(irq)].wake();
+        }
+    }
+}
+
+/// Disables a condition's interrupt and forgets that a wait enabled it
+fn disarm(regs: pac::pio::Pio, pio: usize, mask: u32) {
+    // INTE is shared by all state machines of the block, so update it atomically
+    critical_section::with(|_| {
+        regs.irqs(1).inte().modify(|m| m.0 &= !mask);
+        ARMED[pio].fetch_and(!mask, Ordering::Relaxed);
+    });
+}
+
+/// Disables a wait's interrupt when dropped, so a cancelled wait leaves none enabled
+struct Armed {
+    regs: pac::pio::Pio,
+    pio: usize,
+    mask: u32,
+}
+
+impl Drop for Armed {
+    fn drop(&mut self) {
+        disarm(self.regs, self.pio, self.mask);
+    }
+}
+
+impl<PIO: Instance, const SM: usize> PioSpiMaster<'_, PIO, SM> {
+    /// Waits until the TX FIFO has room for a whole frame
+    ///
+    /// # Notes
+    /// - Only waits: start the frame with [`try_start`](Self::try_start) or
+    ///   [`write`](Self::write), which then do not block
+    /// - Completes at once if there is room already
+    ///
+    /// # Cancel Safety
+    /// Cancel-safe: nothing is queued or pulled.
+    pub async fn wait_tx_space(&self) {
+        let words = self.message_size.div_ceil(32) as u8;
+        self.wait_ready(FifoInterrupt::TxNotFull, |(tx, _)| FIFO_DEPTH - tx >= words)
+            .await
+    }
+
+    /// Waits until a whole response is in the RX FIFO
+    ///
+    /// # Notes
+    /// - Only waits: collect the response with [`on_interrupt`](Self::on_interrupt),
+    ///   which then returns it
+    /// - Never completes if no frame is in flight
+    ///
+    /// # Cancel Safety
+    /// Cancel-safe: nothing is queued or pulled.
+    pub async fn wait_rx_ready(&self) {
+        let words = self.message_size.div_ceil(32) as u8;
+        self.wait_ready(FifoInterrupt::RxNotEmpty, |(_, rx)| rx >= words)
+            .await
+    }
+
+    /// Waits until `ready` holds for the (TX, RX) FIFO levels, sleeping on `irq`
+    async fn wait_ready(&self, irq: FifoInterrupt, ready: impl Fn((u8, u8)) -> bool) {
+        let regs = pio_regs::<PIO>();
+        let pio = pio_index::<PIO>();
+        let mask = irq.mask(SM);
+        let _armed = Armed { regs, pio, mask };
+        poll_fn(|cx| {
+            WAKERS[pio][SM][slot(irq)].register(cx.waker());
+            if ready(fifo_levels::<SM>(regs)) {
+                return Poll::Ready(());
+            }
+            critical_section::with(|_| {
+                ARMED[pio].fetch_or(mask, Ordering::Relaxed);
+                regs.irqs(1).inte().modify(|m| m.0 |= mask);
+            });
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+/// Returns the TX and RX FIFO levels of state machine `SM` from `FLEVEL`
+fn fifo_levels<const SM: usize>(regs: pac::pio::Pio) -> (u8, u8) {
+    let levels = regs.flevel().read().0 >> (8 * SM);
+    ((levels & 0xF) as u8, (levels >> 4 & 0xF) as u8)
+}
@@ -0,0 +1,125 @@
+//! Transfers retried under a policy
+//!
+//! Drivers for devices on long or noisy wiring all end up wrapping their transfers in the
+//! same loop: bound the wait for a response, check it (CRC, echo or status bits), back off
+//! and try again. [`PioSpiMaster::transfer_with_retry`] centralizes that loop, driven by a
+//! [`RetryPolicy`].
+//!
+//! ```ignore
+//! fn crc_ok(response: u64) -> bool {
+//!     crc8(response >> 8) == response as u8
+//! }
+//!
+//! let policy = RetryPolicy {
+//!     check: Some(crc_ok),
+//!     ..RetryPolicy::default()
+//! };
+//! let response = spi.transfer_with_retry(READ_TEMP, &policy).await?;
+//! ```
+
+use embassy_rp::pio::Instance;
+use embassy_time::{with_timeout, Duration, Timer};
+
+use crate::PioSpiMaster;
+
+/// When and how often a transfer is retried
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// Attempts in total, including the first (at least 1)
+    pub max_attempts: u8,
+    /// Pause before the first retry
+    pub backoff: Duration,
+    /// Doubles the pause after every further retry when set
+    pub exponential: bool,
+    /// Longest wait for one attempt's response; `None` waits indefinitely
+    pub timeout: Option<Duration>,
+    /// Response check (CRC, echo, status bits); `None` accepts every response
+    pub check: Option<fn(u64) -> bool>,
+    /// Retry attempts that timed out
+    pub retry_timeout: bool,
+    /// Retry responses rejected by `check`
+    pub retry_invalid: bool,
+}
+
+impl Default for RetryPolicy {
+    /// 3 attempts, 1 ms apart, 10 ms timeout, both failure kinds retried
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            backoff: Duration::from_millis(1),
+            exponential: false,
+            timeout: Some(Duration::from_millis(10)),
+            check: None,
+            retry_timeout: true,
+            retry_invalid: true,
+        }
+    }
+}
+
+/// Failure of the last attempt of [`PioSpiMaster::transfer_with_retry`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum RetryError {
+    /// The response did not arrive within [`RetryPolicy::timeout`]
+    Timeout,
+    /// [`RetryPolicy::check`] rejected the response, which is included
+    Invalid(u64),
+}
+
+impl<PIO: Instance, const SM: usize> PioSpiMaster<'_, PIO, SM> {
+    /// Transfers a frame, retrying failed attempts as `policy` allows
+    ///
+    /// # Arguments
+    /// * `data` - Frame to send on every attempt
+    /// * `policy` - Attempt limit, backoff, timeout, response check and retryable failures
+    ///
+    /// # Returns
+    /// * `Ok(u64)` - First response that arrived in time and passed the check
+    /// * `Err(RetryError)` - Failure of the last attempt: all attempts were used up, or the
+    ///   failure kind is not retryable under `policy`
+    ///
+    /// # Notes
+    /// - A timed-out attempt is cancelled like a dropped [`transfer_async`](Self::transfer_async)
+    ///   future; the next attempt resets the state machine first. Toggle the slave's chip
+    ///   select between attempts if it needs to see a fresh frame
+    pub async fn transfer_with_retry(
+        &mut self,
+        data: u64,
+        policy: &RetryPolicy,
+    ) -> Result<u64, RetryError> {
+        let mut backoff = policy.backoff;
+        let mut attempt = 1;
+        loop {
+            let error = match self.attempt(data, policy).await {
+                Ok(response) => return Ok(response),
+                Err(error) => error,
+            };
+            let retryable = match error {
+                RetryError::Timeout => policy.retry_timeout,
+                RetryError::Invalid(_) => policy.retry_invalid,
+            };
+            if !retryable || attempt >= policy.max_attempts {
+                return Err(error);
+            }
+
+            Timer::after(backoff).await;
+            if policy.exponential {
+                backoff *= 2;
+            }
+            attempt += 1;
+        }
+    }
+
+    /// Runs one attempt of [`transfer_with_retry`](Self::transfer_with_retry)
+    async fn attempt(&mut self, data: u64, policy: &RetryPolicy) -> Result<u64, RetryError> {
+        let response = match policy.timeout {
+            Some(timeout) => with_timeout(timeout, self.transfer_async(data))
+                .await
+                .map_err(|_| RetryError::Timeout)?,
+            None => self.transfer_async(data).await,
+        };
+        match policy.check {
+            Some(check) if !check(response) => Err(RetryError::Invalid(response)),
+            _ => Ok(response),
+        }
+    }
+}
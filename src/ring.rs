@@ -0,0 +1,146 @@
+//! Interrupt-fed RX ring buffer
+//!
+//! The RX FIFO holds only four words, so a task that streams frames with
+//! [`PioSpiMaster::write`] must collect their responses within a few frame times or the
+//! state machine stalls. [`RxRing`] moves that deadline into the PIO interrupt: the handler
+//! drains the FIFO into a `static` ring buffer, and the consuming task picks responses up
+//! with [`RxRing::read_available`] whenever it gets scheduled.
+//!
+//! ```ignore
+//! static RX: RxRing<CriticalSectionRawMutex, 64> = RxRing::new();
+//!
+//! // init
+//! spi.listen(FifoInterrupt::RxNotEmpty);
+//!
+//! // #[task(binds = PIO0_IRQ_1, shared = [spi])]
+//! RX.collect(spi);
+//!
+//! // Consumer
+//! let mut responses = [0u64; 16];
+//! let count = RX.read_available(&mut responses);
+//! ```
+//!
+//! # Notes
+//! - Use a mutex that is safe to lock from the interrupt (e.g. `CriticalSectionRawMutex`)
+//! - When the ring is full, newly collected responses are discarded and counted
+//!   (see [`RxRing::dropped`]); responses already buffered are never overwritten
+
+use core::cell::RefCell;
+
+use embassy_rp::pio::Instance;
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+use crate::PioSpiMaster;
+
+/// Ring storage and its read/write positions
+struct Ring<const N: usize> {
+    buf: [u64; N],
+    /// Index of the oldest buffered response
+    head: usize,
+    /// Number of buffered responses
+    len: usize,
+    /// Responses discarded because the ring was full
+    dropped: u32,
+}
+
+/// Ring buffer of frame responses, filled from an interrupt handler
+///
+/// # Type Parameters
+/// * `M` - Mutex kind guarding the ring; must be lockable from the interrupt
+/// * `N` - Capacity in responses
+pub struct RxRing<M: RawMutex, const N: usize> {
+    ring: Mutex<M, RefCell<Ring<N>>>,
+}
+
+impl<M: RawMutex, const N: usize> Default for RxRing<M, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M: RawMutex, const N: usize> RxRing<M, N> {
+    /// Creates an empty ring (usable in a `static`)
+    ///
+    /// # Panics
+    /// If `N` is 0
+    pub const fn new() -> Self {
+        assert!(N >= 1, "ring capacity must be at least 1");
+        Self {
+            ring: Mutex::new(RefCell::new(Ring {
+                buf: [0; N],
+                head: 0,
+                len: 0,
+                dropped: 0,
+            })),
+        }
+    }
+
+    /// Interrupt handler body: moves every complete response from the RX FIFO into the ring
+    ///
+    /// # Arguments
+    /// * `spi` - SPI master whose `PIOx_IRQ_1` fired (see [`crate::irq`])
+    ///
+    /// # Returns
+    /// * `usize` - Number of responses taken from the FIFO, including any dropped
+    ///
+    /// # Notes
+    /// - Never blocks; frames whose words have not all arrived are left for the next call
+    pub fn collect<PIO: Instance, const SM: usize>(
+        &self,
+        spi: &mut PioSpiMaster<'_, PIO, SM>,
+    ) -> usize {
+        let mut count = 0;
+        while let Some(response) = spi.on_interrupt() {
+            self.ring.lock(|ring| {
+                let mut ring = ring.borrow_mut();
+                if ring.len == N {
+                    ring.dropped = ring.dropped.saturating_add(1);
+                } else {
+                    let tail = (ring.head + ring.len) % N;
+                    ring.buf[tail] = response;
+                    ring.len += 1;
+                }
+            });
+            count += 1;
+        }
+        count
+    }
+
+    /// Copies buffered responses out, oldest first, without waiting
+    ///
+    /// # Arguments
+    /// * `buf` - Destination for the responses
+    ///
+    /// # Returns
+    /// * `usize` - Number of responses stored in `buf` (0 if the ring is empty)
+    pub fn read_available(&self, buf: &mut [u64]) -> usize {
+        self.ring.lock(|ring| {
+            let mut ring = ring.borrow_mut();
+            let count = ring.len.min(buf.len());
+            for slot in buf[..count].iter_mut() {
+                *slot = ring.buf[ring.head];
+                ring.head = (ring.head + 1) % N;
+            }
+            ring.len -= count;
+            count
+        })
+    }
+
+    /// Returns the number of buffered responses
+    pub fn len(&self) -> usize {
+        self.ring.lock(|ring| ring.borrow().len)
+    }
+
+    /// Returns `true` if no response is buffered
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns how many responses were discarded because the ring was full, and resets
+    /// the count
+    pub fn dropped(&self) -> u32 {
+        self.ring
+            .lock(|ring| core::mem::take(&mut ring.borrow_mut().dropped))
+    }
+}
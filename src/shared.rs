@@ -0,0 +1,84 @@
+//! Sharing one master between tasks
+//!
+//! [`PioSpiMaster`] is `Send`, so it can be moved into a spawned task, but every transfer
+//! takes `&mut self`: a frame's words and its response must not interleave with another
+//! task's. [`SharedSpi`] puts the master behind an async mutex so several tasks can hold
+//! `&'static SharedSpi` and transfer without racing on the FIFOs; the compiler rejects
+//! any other way of reaching the master from two tasks.
+//!
+//! ```ignore
+//! static SPI: StaticCell<SharedSpi<'static, CriticalSectionRawMutex, PIO0, 0>> = StaticCell::new();
+//! let spi = SPI.init(SharedSpi::new(PioSpiMaster::new(&mut common, sm0, &clk, &mosi, &miso, config)));
+//!
+//! // Any task holding `spi: &'static SharedSpi<..>`:
+//! let response = spi.transfer(0xABCD).await;
+//! let mut master = spi.lock().await; // several frames without another task in between
+//! master.write(0x0102);
+//! let status = master.transfer_async(0x0300).await;
+//! ```
+//!
+//! # Notes
+//! - The mutex kind decides where the master may be used: `ThreadModeRawMutex` for tasks
+//!   of thread-mode executors, `CriticalSectionRawMutex` across interrupt executors too
+//! - A transfer cancelled while holding the lock is cleaned up by the next transfer (see
+//!   [`PioSpiMaster::transfer_async`])
+//! - For fire-and-forget submission from many tasks, see [`crate::queue`]
+
+use embassy_rp::pio::Instance;
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::mutex::{Mutex, MutexGuard};
+
+use crate::PioSpiMaster;
+
+/// SPI master shared between tasks through an async mutex
+///
+/// # Type Parameters
+/// * `M` - Mutex kind guarding the master
+pub struct SharedSpi<'d, M: RawMutex, PIO: Instance, const SM: usize> {
+    spi: Mutex<M, PioSpiMaster<'d, PIO, SM>>,
+}
+
+impl<'d, M: RawMutex, PIO: Instance, const SM: usize> SharedSpi<'d, M, PIO, SM> {
+    /// Wraps `spi` for shared use (takes ownership)
+    pub const fn new(spi: PioSpiMaster<'d, PIO, SM>) -> Self {
+        Self {
+            spi: Mutex::new(spi),
+        }
+    }
+
+    /// Waits for exclusive access to the master
+    ///
+    /// # Returns
+    /// * `MutexGuard` - Dereferences to the master; other tasks wait until it is dropped
+    pub async fn lock(&self) -> MutexGuard<'_, M, PioSpiMaster<'d, PIO, SM>> {
+        self.spi.lock().await
+    }
+
+    /// Performs one frame with exclusive access, as
+    /// [`transfer_async`](PioSpiMaster::transfer_async) does
+    ///
+    /// # Arguments
+    /// * `data` - Data to shift out on MOSI (only bits [message_size-1:0] are used)
+    ///
+    /// # Returns
+    /// * `u64` - Response bits read from MISO
+    pub async fn transfer(&self, data: u64) -> u64 {
+        self.lock().await.transfer_async(data).await
+    }
+
+    /// Returns the master
+    pub fn release(self) -> PioSpiMaster<'d, PIO, SM> {
+        self.spi.into_inner()
+    }
+}
+
+// The drivers must stay movable into spawned tasks; a field that is not `Send` fails here
+// rather than in user code
+const _: () = {
+    const fn assert_send<T: Send>() {}
+    assert_send::<PioSpiMaster<'static, embassy_rp::peripherals::PIO0, 0>>();
+    #[cfg(feature = "phases")]
+    assert_send::<crate::transaction::PioSpiBus<'static, embassy_rp::peripherals::PIO0, 0>>();
+    #[cfg(feature = "clock-out")]
+    assert_send::<crate::clock::ClockOut<'static, embassy_rp::peripherals::PIO0, 0>>();
+};
@@ -0,0 +1,117 @@
+//! Minimum-latency frames of up to 8 bits
+//!
+//! Encoders, trigger inputs and similar devices use frames of a few bits where the time
+//! from the call to the first CLK edge matters more than throughput. The short-frame path
+//! takes and returns the frame right-justified in a `u8` and moves exactly one FIFO word
+//! each way, with none of the masking, alignment or two-word branches of
+//! [`transfer`](PioSpiMaster::transfer):
+//!
+//! ```ignore
+//! // message_size: 4
+//! let position = spi.transfer_short(READ_POSITION);
+//!
+//! // Latency-critical: pack and queue the frame ahead of the event
+//! spi.arm_short(TRIGGER);
+//! wait_for_event();
+//! spi.fire_short(); // a single register write away from the first edge
+//! let status = spi.finish_short();
+//! ```
+//!
+//! # Latency
+//!
+//! [`benchmark_short_latency`](PioSpiMaster::benchmark_short_latency) (`phases` feature)
+//! measures the call-to-first-edge time of both paths on the target by watching the CLK
+//! pad. Beyond the CPU cycles it reports, the state machine adds up to one divider period
+//! (`clk_div` system clock cycles) of jitter, as the divider keeps running while the state
+//! machine waits, plus any configured `lead_in_cycles`, `start_delay_cycles` or CS setup.
+
+use embassy_rp::pio::Instance;
+
+use crate::{BitOrder, PioSpiMaster};
+
+impl<PIO: Instance, const SM: usize> PioSpiMaster<'_, PIO, SM> {
+    /// Performs a write-then-read transfer of a frame of up to 8 bits
+    ///
+    /// # Arguments
+    /// * `data` - Frame in bits [message_size-1:0]; higher bits are ignored
+    ///
+    /// # Returns
+    /// * `u8` - Response in bits [message_size-1:0], the rest zero
+    ///
+    /// # Notes
+    /// - Frames are right-justified whatever `tx_alignment` / `rx_alignment` say
+    ///
+    /// # Panics
+    /// In debug builds, if `message_size` is above 8
+    pub fn transfer_short(&mut self, data: u8) -> u8 {
+        self.push_short(data);
+        self.pull_short()
+    }
+
+    /// Queues a frame of up to 8 bits with the state machine paused, ready for
+    /// [`fire_short`](Self::fire_short)
+    ///
+    /// # Arguments
+    /// * `data` - Frame in bits [message_size-1:0]; higher bits are ignored
+    ///
+    /// # Behavior
+    /// Waits for frames in flight to finish shifting, disables the state machine at the
+    /// point where it waits for its next frame, and pushes the frame. Nothing reaches the
+    /// wire until [`fire_short`](Self::fire_short).
+    ///
+    /// # Notes
+    /// - Every other transfer waits on a state machine that is not running: fire or
+    ///   [`start`](Self::start) it first
+    ///
+    /// # Panics
+    /// In debug builds, if `message_size` is above 8
+    pub fn arm_short(&mut self, data: u8) {
+        self.wait_idle();
+        self.sm.set_enable(false);
+        self.push_short(data);
+    }
+
+    /// Starts the frame queued by [`arm_short`](Self::arm_short)
+    ///
+    /// # Behavior
+    /// Only enables the state machine, so the first CLK edge follows within a divider
+    /// period. Collect the response with [`finish_short`](Self::finish_short).
+    pub fn fire_short(&mut self) {
+        self.sm.set_enable(true);
+    }
+
+    /// Waits for the response of the frame started by [`fire_short`](Self::fire_short)
+    ///
+    /// # Returns
+    /// * `u8` - Response in bits [message_size-1:0], the rest zero
+    pub fn finish_short(&mut self) -> u8 {
+        self.pull_short()
+    }
+
+    /// Pushes a short frame as its single TX FIFO word
+    pub(crate) fn push_short(&mut self, data: u8) {
+        debug_assert!(self.message_size <= 8, "short frames are at most 8 bits");
+        self.recover_if_interrupted();
+        self.claim_fifos();
+        // OUT consumes message_size bits per autopull, so bits above the frame never
+        // reach the wire and need no masking
+        let word = match self.config.tx_bit_order {
+            BitOrder::MsbFirst => (data as u32) << (32 - self.message_size),
+            BitOrder::LsbFirst => data as u32,
+        };
+        self.sm.tx().push(self.format.mirror_word(word));
+        self.in_flight += 1;
+    }
+
+    /// Pulls the single RX FIFO word of a short frame
+    pub(crate) fn pull_short(&mut self) -> u8 {
+        self.in_flight = self.in_flight.saturating_sub(1);
+        let word = self.pull_blocking();
+        self.release_fifos();
+        // The ISR starts empty for every frame, so only the frame's bits are set
+        match self.config.rx_bit_order {
+            BitOrder::MsbFirst => word as u8,
+            BitOrder::LsbFirst => (word >> (32 - self.message_size)) as u8,
+        }
+    }
+}
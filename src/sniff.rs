@@ -0,0 +1,199 @@
+//! Hardware CRC of DMA-streamed phases
+//!
+//! The DMA block's sniffer computes a checksum over the data one channel moves, at no CPU
+//! cost. [`PioSpiBus::write_dma_crc`] and [`PioSpiBus::read_dma_crc`] run a write or read
+//! phase through the bus's DMA channel (see [`PioSpiBus::with_dma`]) with the sniffer on
+//! it, so flash programming and link protocols get the payload CRC for free:
+//!
+//! ```ignore
+//! let crc = bus.write_dma_crc(page, SnifferCrc::Crc16CcittFalse);
+//! bus.write(&(crc as u16).to_be_bytes());
+//!
+//! let mut reply = [0u8; 66]; // 64 data bytes + CRC-16
+//! // The CRC of data followed by its own CRC is zero
+//! let ok = bus.read_dma_crc(&mut reply, SnifferCrc::Crc16CcittFalse) == 0;
+//! ```
+//!
+//! # Notes
+//! - The RP2350 has one sniffer for all DMA channels: these calls take it over for their
+//!   duration, so nothing else may use it at the same time
+//! - Bytes are streamed as they are, MSB first, whatever the bus bit order (like
+//!   [`PioSpiBus::write_static`])
+
+use core::sync::atomic::{compiler_fence, Ordering};
+
+use embassy_rp::dma::Channel;
+use embassy_rp::pac;
+use embassy_rp::pac::dma::vals::{Calc, DataSize, TreqSel};
+use embassy_rp::pio::Instance;
+
+use crate::claim::pio_index;
+use crate::irq::pio_regs;
+use crate::transaction::{Phase, PioSpiBus};
+
+/// Checksum the DMA sniffer computes
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum SnifferCrc {
+    /// CRC-16/CCITT-FALSE: polynomial 0x1021, initial value 0xFFFF, not reflected (as used
+    /// by [`crate::link`])
+    Crc16CcittFalse,
+    /// CRC-32/ISO-HDLC, the Ethernet and zlib CRC-32: polynomial 0x04C11DB7, initial value
+    /// 0xFFFFFFFF, reflected, inverted result
+    Crc32,
+    /// CRC-32/MPEG-2: polynomial 0x04C11DB7, initial value 0xFFFFFFFF, not reflected, no
+    /// final inversion
+    Crc32Mpeg2,
+}
+
+impl SnifferCrc {
+    /// Seeds the sniffer and attaches it to DMA channel `channel`
+    fn attach(self, channel: u8) {
+        let (calc, reflect) = match self {
+            SnifferCrc::Crc16CcittFalse => (Calc::CRC16, false),
+            SnifferCrc::Crc32 => (Calc::CRC32R, true),
+            SnifferCrc::Crc32Mpeg2 => (Calc::CRC32, false),
+        };
+        pac::DMA.sniff_data().write_value(match self {
+            SnifferCrc::Crc16CcittFalse => 0xFFFF,
+            SnifferCrc::Crc32 | SnifferCrc::Crc32Mpeg2 => 0xFFFF_FFFF,
+        });
+        pac::DMA.sniff_ctrl().write(|w| {
+            w.set_dmach(channel);
+            w.set_calc(calc);
+            w.set_out_rev(reflect);
+            w.set_out_inv(reflect);
+            w.set_en(true);
+        });
+    }
+
+    /// Returns the checksum of no data
+    fn empty(self) -> u32 {
+        match self {
+            SnifferCrc::Crc16CcittFalse => 0xFFFF,
+            SnifferCrc::Crc32 => 0,
+            SnifferCrc::Crc32Mpeg2 => 0xFFFF_FFFF,
+        }
+    }
+
+    /// Reads the finished checksum and detaches the sniffer
+    fn take(self) -> u32 {
+        let crc = pac::DMA.sniff_data().read();
+        pac::DMA.sniff_ctrl().write(|w| w.set_en(false));
+        match self {
+            SnifferCrc::Crc16CcittFalse => crc & 0xFFFF,
+            SnifferCrc::Crc32 | SnifferCrc::Crc32Mpeg2 => crc,
+        }
+    }
+}
+
+/// Direction of a sniffed FIFO transfer
+enum Fifo {
+    /// Memory to the TX FIFO
+    Tx,
+    /// RX FIFO to memory
+    Rx,
+}
+
+impl<PIO: Instance, const SM: usize> PioSpiBus<'_, PIO, SM> {
+    /// Writes bytes in a single write phase fed by DMA, returning their CRC
+    ///
+    /// # Arguments
+    /// * `data` - Bytes to send
+    /// * `crc` - Checksum to compute over `data`
+    ///
+    /// # Returns
+    /// * `u32` - Checksum of `data` (16-bit checksums in the low half)
+    ///
+    /// # Behavior
+    /// Same as [`write`](Self::write) with the bytes fed by the DMA channel and summed by
+    /// the sniffer on the way to the TX FIFO. Returns once the phase has completed.
+    ///
+    /// # Panics
+    /// - If no DMA channel is attached (see [`with_dma`](Self::with_dma))
+    /// - If `data` is longer than 65536 bytes
+    pub fn write_dma_crc(&mut self, data: &[u8], crc: SnifferCrc) -> u32 {
+        let Some(header) = Phase::Write(data).header() else {
+            return crc.empty();
+        };
+        self.begin();
+        self.push(header);
+        let fifo = pio_regs::<PIO>().txf(SM).as_ptr() as u32;
+        self.start_sniffed(Fifo::Tx, data.as_ptr() as u32, fifo, data.len(), crc);
+        let sum = self.finish_sniffed(crc);
+        self.end();
+        sum
+    }
+
+    /// Reads bytes in a single read phase collected by DMA, returning their CRC
+    ///
+    /// # Arguments
+    /// * `buf` - Destination
+    /// * `crc` - Checksum to compute over the received bytes
+    ///
+    /// # Returns
+    /// * `u32` - Checksum of `buf` (16-bit checksums in the low half). If `buf` ends with
+    ///   the sender's CRC of the rest, the non-inverted CRCs ([`SnifferCrc::Crc16CcittFalse`]
+    ///   and [`SnifferCrc::Crc32Mpeg2`]) come out 0 when the data is intact
+    ///
+    /// # Behavior
+    /// Same as [`read`](Self::read), including the [status byte](Self::last_status), with
+    /// the bytes collected by the DMA channel and summed by the sniffer on the way from the
+    /// RX FIFO.
+    ///
+    /// # Panics
+    /// - If no DMA channel is attached (see [`with_dma`](Self::with_dma))
+    /// - If `buf` is longer than 65536 bytes
+    pub fn read_dma_crc(&mut self, buf: &mut [u8], crc: SnifferCrc) -> u32 {
+        let Some(header) = Phase::Read(buf).header() else {
+            self.last_status = None;
+            return crc.empty();
+        };
+        self.begin();
+        let fifo = pio_regs::<PIO>().rxf(SM).as_ptr() as u32;
+        // The channel waits on the RX DREQ, so it can be armed before the phase starts
+        self.start_sniffed(Fifo::Rx, fifo, buf.as_mut_ptr() as u32, buf.len(), crc);
+        self.push(header);
+        let sum = self.finish_sniffed(crc);
+        self.end();
+        self.last_status = buf.first().copied();
+        sum
+    }
+
+    /// Attaches the sniffer to the bus's DMA channel and starts a byte transfer between
+    /// memory and a FIFO
+    fn start_sniffed(&mut self, fifo: Fifo, from: u32, to: u32, len: usize, crc: SnifferCrc) {
+        let dma = self
+            .dma
+            .as_ref()
+            .expect("sniffed transfers require a DMA channel (see with_dma)");
+        let (regs, number) = (dma.regs(), dma.number());
+        let dreq = (pio_index::<PIO>() * 8 + SM) as u8;
+        let (dreq, to_fifo) = match fifo {
+            Fifo::Tx => (dreq, true),
+            Fifo::Rx => (dreq + 4, false),
+        };
+
+        crc.attach(number);
+        regs.read_addr().write_value(from);
+        regs.write_addr().write_value(to);
+        regs.trans_count().write(|w| w.set_count(len as u32));
+        compiler_fence(Ordering::SeqCst);
+        regs.ctrl_trig().write(|w| {
+            w.set_treq_sel(TreqSel::from(dreq));
+            w.set_data_size(DataSize::SIZE_BYTE);
+            w.set_incr_read(to_fifo);
+            w.set_incr_write(!to_fifo);
+            w.set_chain_to(number);
+            w.set_sniff_en(true);
+            w.set_irq_quiet(true);
+            w.set_en(true);
+        });
+    }
+
+    /// Waits for the sniffed transfer to finish and returns its checksum
+    fn finish_sniffed(&mut self, crc: SnifferCrc) -> u32 {
+        self.wait_dma();
+        compiler_fence(Ordering::SeqCst);
+        crc.take()
+    }
+}
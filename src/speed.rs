@@ -0,0 +1,221 @@
+//! Speed class presets
+//!
+//! Running a bus reliably at a given rate takes more than a clock divider: slow buses over
+//! long wires want weak, slow-slewing outputs and a Schmitt trigger on MISO, fast ones want
+//! strong, fast edges and the least input latency. [`SpeedClass`] bundles those choices
+//! per rate band and checks that the system clock can reach the band:
+//!
+//! ```ignore
+//! // At compile time, for a known system clock
+//! const ADC_DIV: u16 = SpeedClass::Fast10M.clk_div_at(150_000_000).unwrap();
+//!
+//! // At construction, against the running system clock
+//! let spi = PioSpiMaster::try_new_with_speed(
+//!     &mut common, sm0, &mut clk, &mut mosi, &mut miso, SpeedClass::Standard1M, config,
+//! )?;
+//! ```
+//!
+//! # Bands
+//!
+//! | Class | SCK | Drive | Slew | MISO Schmitt | MISO synchronizer |
+//! |-------|-----|-------|------|--------------|-------------------|
+//! | [`Slow100k`](SpeedClass::Slow100k) | 75-100 kHz | 2 mA | slow | on | on |
+//! | [`Standard1M`](SpeedClass::Standard1M) | 0.75-1 MHz | 4 mA | slow | on | on |
+//! | [`Fast10M`](SpeedClass::Fast10M) | 7.5-10 MHz | 8 mA | fast | on | on |
+//! | [`Turbo`](SpeedClass::Turbo) | 37.5-50 MHz | 12 mA | fast | off | bypassed |
+//!
+//! The clock divider is the smallest that keeps SCK at or below the top of the band, so a
+//! device is never clocked faster than its class; a system clock that cannot reach at
+//! least three quarters of it is rejected.
+//!
+//! # Notes
+//! - Every class runs the side-set frame program, the only CLK variant the crate has; the
+//!   class only picks the divider and the pads
+//! - Bypassing the MISO synchronizer saves two system clock cycles of input latency, which
+//!   at Turbo rates is most of a bit; the slave must then meet the pad's setup time, as it
+//!   does at those rates anyway
+//! - Pad settings outlive the master: [`free`](PioSpiMaster::free) leaves them as set
+
+use embassy_rp::gpio::{Drive, SlewRate};
+use embassy_rp::pio::{Common, Instance, Pin, StateMachine};
+
+use crate::{InitError, PioSpiMaster, SpiMasterConfig, CYCLES_PER_BIT};
+
+/// Rate band of a bus, with the divider and pad settings that suit it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum SpeedClass {
+    /// Up to 100 kHz: long or unshielded wiring, slow sensors
+    Slow100k,
+    /// Up to 1 MHz: the common default of SPI peripherals
+    Standard1M,
+    /// Up to 10 MHz: ADCs, DACs and flash on short board traces
+    Fast10M,
+    /// Up to 50 MHz: the frame program's full rate at the RP2350's default 150 MHz clock
+    Turbo,
+}
+
+/// Reason a [`SpeedClass`] could not be applied
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum SpeedError {
+    /// The system clock cannot reach the class's band; `sck_hz` is the fastest SCK it
+    /// gives
+    OutOfBand { sck_hz: u32 },
+    /// The master could not be built (see [`PioSpiMaster::try_new`])
+    Init(InitError),
+}
+
+impl From<InitError> for SpeedError {
+    fn from(error: InitError) -> Self {
+        SpeedError::Init(error)
+    }
+}
+
+impl SpeedClass {
+    /// Returns the top of the class's SCK band in Hz
+    pub const fn max_hz(self) -> u32 {
+        match self {
+            SpeedClass::Slow100k => 100_000,
+            SpeedClass::Standard1M => 1_000_000,
+            SpeedClass::Fast10M => 10_000_000,
+            SpeedClass::Turbo => 50_000_000,
+        }
+    }
+
+    /// Returns the bottom of the class's SCK band in Hz: three quarters of the top
+    pub const fn min_hz(self) -> u32 {
+        self.max_hz() / 4 * 3
+    }
+
+    /// Returns the `clk_div` putting SCK in the class's band at a given system clock
+    ///
+    /// # Arguments
+    /// * `sys_hz` - System clock in Hz
+    ///
+    /// # Returns
+    /// * `Some(u16)` - The smallest divider keeping SCK at or below [`max_hz`](Self::max_hz)
+    /// * `None` - SCK would fall below [`min_hz`](Self::min_hz) (or need a divider above
+    ///   the 16-bit range)
+    ///
+    /// # Notes
+    /// - A `const fn`, so `clk_div_at(SYS_HZ).unwrap()` in a `const` item rejects an
+    ///   unreachable class at compile time
+    /// - Assumes the plain [`CYCLES_PER_BIT`] period, without CLK stretching
+    pub const fn clk_div_at(self, sys_hz: u32) -> Option<u16> {
+        let per_bit = self.max_hz() as u64 * CYCLES_PER_BIT as u64;
+        let divider = (sys_hz as u64).div_ceil(per_bit);
+        let divider = if divider == 0 { 1 } else { divider };
+        if divider >= u16::MAX as u64 {
+            return None;
+        }
+        let sck_hz = sys_hz as u64 / divider / CYCLES_PER_BIT as u64;
+        if sck_hz < self.min_hz() as u64 {
+            return None;
+        }
+        Some(divider as u16 + 1)
+    }
+
+    /// Returns the `clk_div` putting SCK in the class's band at the current system clock
+    ///
+    /// # Returns
+    /// * `Ok(u16)` - As [`clk_div_at`](Self::clk_div_at)
+    /// * `Err(SpeedError::OutOfBand)` - The system clock is too slow for the class
+    pub fn clk_div(self) -> Result<u16, SpeedError> {
+        let sys_hz = embassy_rp::clocks::clk_sys_freq();
+        self.clk_div_at(sys_hz).ok_or(SpeedError::OutOfBand {
+            sck_hz: sys_hz / CYCLES_PER_BIT,
+        })
+    }
+
+    /// Returns `config` with the class's clock divider at the current system clock
+    ///
+    /// # Returns
+    /// * `Ok(SpiMasterConfig)` - `config` with `clk_div` replaced
+    /// * `Err(SpeedError::OutOfBand)` - The system clock is too slow for the class
+    pub fn apply(self, config: SpiMasterConfig) -> Result<SpiMasterConfig, SpeedError> {
+        Ok(SpiMasterConfig {
+            clk_div: self.clk_div()?,
+            ..config
+        })
+    }
+
+    /// Returns the drive strength of CLK and MOSI
+    pub fn drive(self) -> Drive {
+        match self {
+            SpeedClass::Slow100k => Drive::_2mA,
+            SpeedClass::Standard1M => Drive::_4mA,
+            SpeedClass::Fast10M => Drive::_8mA,
+            SpeedClass::Turbo => Drive::_12mA,
+        }
+    }
+
+    /// Returns the slew rate of CLK and MOSI
+    pub fn slew_rate(self) -> SlewRate {
+        match self {
+            SpeedClass::Slow100k | SpeedClass::Standard1M => SlewRate::Slow,
+            SpeedClass::Fast10M | SpeedClass::Turbo => SlewRate::Fast,
+        }
+    }
+
+    /// Returns whether MISO's Schmitt trigger is on, filtering slow or noisy edges
+    pub const fn miso_schmitt(self) -> bool {
+        !matches!(self, SpeedClass::Turbo)
+    }
+
+    /// Returns whether MISO bypasses the PIO input synchronizer
+    pub const fn bypass_miso_sync(self) -> bool {
+        matches!(self, SpeedClass::Turbo)
+    }
+
+    /// Sets the pads of the bus pins for the class
+    ///
+    /// # Arguments
+    /// * `clk_pin` / `mosi_pin` - Outputs: drive strength and slew rate
+    /// * `miso_pin` - Input: Schmitt trigger and synchronizer bypass
+    pub fn configure_pins<'d, PIO: Instance>(
+        self,
+        clk_pin: &mut Pin<'d, PIO>,
+        mosi_pin: &mut Pin<'d, PIO>,
+        miso_pin: &mut Pin<'d, PIO>,
+    ) {
+        for pin in [clk_pin, mosi_pin] {
+            pin.set_drive_strength(self.drive());
+            pin.set_slew_rate(self.slew_rate());
+        }
+        miso_pin.set_schmitt(self.miso_schmitt());
+        miso_pin.set_input_sync_bypass(self.bypass_miso_sync());
+    }
+}
+
+impl<'d, PIO: Instance, const SM: usize> PioSpiMaster<'d, PIO, SM> {
+    /// Creates a master running in a speed class, with the class's divider and pads
+    ///
+    /// # Arguments
+    /// * `common` / `sm` / `clk_pin` / `mosi_pin` / `miso_pin` - As for [`try_new`](Self::try_new)
+    /// * `speed` - Rate band of the bus
+    /// * `config` - SPI configuration; its `clk_div` is replaced by the class's
+    ///
+    /// # Returns
+    /// * `Ok(PioSpiMaster)` - The master, running
+    /// * `Err(SpeedError::OutOfBand)` - The current system clock cannot reach the class;
+    ///   nothing was touched
+    /// * `Err(SpeedError::Init)` - As [`try_new`](Self::try_new); the pads were not changed
+    ///
+    /// # Behavior
+    /// Validates the class against the system clock, builds the master with
+    /// [`try_new`](Self::try_new), then sets the pads with
+    /// [`SpeedClass::configure_pins`].
+    pub fn try_new_with_speed(
+        common: &mut Common<'d, PIO>,
+        sm: StateMachine<'d, PIO, SM>,
+        clk_pin: &mut Pin<'d, PIO>,
+        mosi_pin: &mut Pin<'d, PIO>,
+        miso_pin: &mut Pin<'d, PIO>,
+        speed: SpeedClass,
+        config: SpiMasterConfig,
+    ) -> Result<Self, SpeedError> {
+        let config = speed.apply(config)?;
+        let spi = Self::try_new(common, sm, clk_pin, mosi_pin, miso_pin, config)?;
+        speed.configure_pins(clk_pin, mosi_pin, miso_pin);
+        Ok(spi)
+    }
+}
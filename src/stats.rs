@@ -0,0 +1,101 @@
+//! CPU cycles spent waiting on the FIFOs
+//!
+//! A blocking transfer spins while the TX FIFO is full or the response has not arrived.
+//! That is harmless for an occasional register read and a waste for a hot loop that could
+//! run from DMA or an async task instead. With the `cycle-stats` feature, every master
+//! times its blocking pushes and pulls with the Cortex-M33 cycle counter (DWT `CYCCNT`) and
+//! `PioSpiMaster::cycle_stats` reports how often and how long they waited:
+//!
+//! ```ignore
+//! spi.reset_cycle_stats();
+//! for channel in 0..8 {
+//!     samples[channel] = spi.transfer(read_channel(channel));
+//! }
+//! let stats = spi.cycle_stats();
+//! defmt::info!("{} cycles per pull", stats.pull.mean_cycles());
+//! ```
+//!
+//! # Notes
+//! - Counted: the pushes and pulls of the master's own blocking `transfer*`, `write*` and
+//!   `pipeline` paths; async transfers yield instead of spinning, and the extension
+//!   modules' fast paths (byte streaming, short frames, ISR handles, DMA) are not timed
+//! - A call that finds the FIFO ready costs a level check; only waits read the counter
+//! - The cycle counter is enabled on first use and left running
+//! - Without the feature, nothing is recorded and the stats take no memory; the types stay
+//!   available
+
+#[cfg(any(feature = "cycle-stats", feature = "phases"))]
+use cortex_m::peripheral::DWT;
+#[cfg(feature = "cycle-stats")]
+use embassy_rp::pio::Instance;
+
+#[cfg(feature = "cycle-stats")]
+use crate::PioSpiMaster;
+
+/// Waits of one kind of FIFO access
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct WaitStats {
+    /// Accesses made
+    pub calls: u32,
+    /// Accesses that found the FIFO not ready and spun
+    pub blocked_calls: u32,
+    /// System clock cycles spent spinning, over all accesses
+    pub blocked_cycles: u64,
+    /// Longest single wait in system clock cycles
+    pub max_cycles: u32,
+}
+
+impl WaitStats {
+    /// Returns the mean system clock cycles spent spinning per access, waits or not
+    pub fn mean_cycles(&self) -> u32 {
+        (self.blocked_cycles / self.calls.max(1) as u64) as u32
+    }
+
+    /// Counts an access that did not wait
+    #[cfg(feature = "cycle-stats")]
+    pub(crate) fn ready(&mut self) {
+        self.calls = self.calls.saturating_add(1);
+    }
+
+    /// Counts an access that spun for `cycles`
+    #[cfg(feature = "cycle-stats")]
+    pub(crate) fn blocked(&mut self, cycles: u32) {
+        self.ready();
+        self.blocked_calls = self.blocked_calls.saturating_add(1);
+        self.blocked_cycles = self.blocked_cycles.saturating_add(cycles as u64);
+        self.max_cycles = self.max_cycles.max(cycles);
+    }
+}
+
+/// Waits of a master's blocking FIFO accesses, as `PioSpiMaster::cycle_stats` reports them
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct CycleStats {
+    /// TX FIFO pushes, waiting for space
+    pub push: WaitStats,
+    /// RX FIFO pulls, waiting for a response word
+    pub pull: WaitStats,
+}
+
+#[cfg(feature = "cycle-stats")]
+impl<PIO: Instance, const SM: usize> PioSpiMaster<'_, PIO, SM> {
+    /// Returns the FIFO waits counted since the master was built or the stats were reset
+    pub fn cycle_stats(&self) -> CycleStats {
+        self.cycles
+    }
+
+    /// Clears the counted FIFO waits
+    pub fn reset_cycle_stats(&mut self) {
+        self.cycles = CycleStats::default();
+    }
+}
+
+/// Enables the cycle counter if needed and returns its current value
+#[cfg(any(feature = "cycle-stats", feature = "phases"))]
+pub(crate) fn cycle_count() -> u32 {
+    // SAFETY: only the trace and cycle counter enable bits are set, which nothing else in
+    // a typical application relies on being clear
+    let mut core = unsafe { cortex_m::Peripherals::steal() };
+    core.DCB.enable_trace();
+    core.DWT.enable_cycle_counter();
+    DWT::cycle_count()
+}
@@ -0,0 +1,224 @@
+//! `embedded-io` byte stream over fixed-size frames
+//!
+//! [`ByteStream`] turns a [`PioSpiMaster`] into an `embedded_io` / `embedded_io_async`
+//! reader and writer, so byte-stream protocols (simple inter-MCU links, line-based
+//! consoles, COBS or postcard framing) can run over SPI unchanged.
+//!
+//! # Framing
+//!
+//! Every transfer carries one frame of `message_size / 8` bytes in each direction. The
+//! first byte on the wire is a header, the rest is payload:
+//! - **Master → slave header**: bit 7 = the master can accept a full payload in the
+//!   response, bits [6:0] = number of valid payload bytes that follow
+//! - **Slave → master header**: bits [6:0] = number of valid payload bytes that follow;
+//!   must be 0 unless the master's header had bit 7 set
+//!
+//! Unused payload bytes are zero. The slave answers in the read phase of the same frame,
+//! after it has seen the master's header.
+//!
+//! ```ignore
+//! let mut stream = ByteStream::new(&mut spi); // message_size: 56 -> 6 payload bytes
+//! stream.write_all(b"ping\n")?;
+//! let n = stream.read(&mut buf)?;
+//! ```
+//!
+//! # Notes
+//! - The master is the only side that can clock frames, so [`read`](embedded_io::Read::read)
+//!   polls the slave with empty frames until it answers with data
+//! - Writes are complete when they return; `flush` has nothing to do
+//! - The master must use [`Duplex::Half`] frames, so the slave sees the header before it
+//!   answers; on full-duplex frames the response would overlap it
+
+use embassy_rp::pio::Instance;
+
+use crate::{Duplex, PioSpiMaster};
+
+/// Header bit announcing that the master can accept a payload in the response
+const HEADER_RX_READY: u8 = 0x80;
+/// Header bits holding the payload length
+const HEADER_LEN_MASK: u8 = 0x7F;
+
+/// Error reported by [`ByteStream`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum StreamError {
+    /// The slave announced more payload bytes than a frame holds, or sent data the master
+    /// had not asked for
+    InvalidHeader(u8),
+}
+
+impl embedded_io::Error for StreamError {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        embedded_io::ErrorKind::InvalidData
+    }
+}
+
+/// Byte stream adapter over a [`PioSpiMaster`]
+pub struct ByteStream<'a, 'd, PIO: Instance, const SM: usize> {
+    spi: &'a mut PioSpiMaster<'d, PIO, SM>,
+    /// Bytes per frame, header included
+    frame_bytes: usize,
+    /// Payload of the last response not yet handed to the reader
+    rx: [u8; 8],
+    rx_pos: usize,
+    rx_len: usize,
+}
+
+impl<'a, 'd, PIO: Instance, const SM: usize> ByteStream<'a, 'd, PIO, SM> {
+    /// Wraps a master whose frames carry whole bytes
+    ///
+    /// # Arguments
+    /// * `spi` - SPI master; its `message_size` sets the frame size
+    ///
+    /// # Panics
+    /// If `message_size` is not a multiple of 8 of at least 16 bits, or the frames are not
+    /// [`Duplex::Half`]
+    pub fn new(spi: &'a mut PioSpiMaster<'d, PIO, SM>) -> Self {
+        let size = spi.message_size();
+        assert!(
+            size >= 16 && size.is_multiple_of(8),
+            "message_size must be a multiple of 8 of at least 16"
+        );
+        assert_eq!(
+            spi.duplex(),
+            Duplex::Half,
+            "byte stream frames must be write-then-read"
+        );
+        Self {
+            spi,
+            frame_bytes: size / 8,
+            rx: [0; 8],
+            rx_pos: 0,
+            rx_len: 0,
+        }
+    }
+
+    /// Returns the number of payload bytes per frame
+    pub fn payload_size(&self) -> usize {
+        self.frame_bytes - 1
+    }
+
+    /// Builds the frame sending the first payload bytes of `data`
+    ///
+    /// Returns the frame, how many bytes of `data` it carries and whether it tells the
+    /// slave that a response payload can be accepted.
+    fn encode(&self, data: &[u8]) -> (u64, usize, bool) {
+        let count = data.len().min(self.payload_size());
+        let rx_ready = self.rx_pos == self.rx_len;
+        let mut header = count as u8;
+        if rx_ready {
+            header |= HEADER_RX_READY;
+        }
+
+        let mut frame = header as u64;
+        for &byte in &data[..count] {
+            frame = (frame << 8) | byte as u64;
+        }
+        // Zero the unused payload bytes
+        frame <<= 8 * (self.payload_size() - count);
+        (frame, count, rx_ready)
+    }
+
+    /// Checks a response frame and stores its payload for the reader
+    fn accept(&mut self, frame: u64, rx_ready: bool) -> Result<(), StreamError> {
+        let payload = self.payload_size();
+        let header = (frame >> (8 * payload)) as u8;
+        let len = (header & HEADER_LEN_MASK) as usize;
+        if len == 0 {
+            return Ok(());
+        }
+        if len > payload || !rx_ready {
+            return Err(StreamError::InvalidHeader(header));
+        }
+
+        for (i, byte) in self.rx[..len].iter_mut().enumerate() {
+            *byte = (frame >> (8 * (payload - 1 - i))) as u8;
+        }
+        self.rx_pos = 0;
+        self.rx_len = len;
+        Ok(())
+    }
+
+    /// Copies buffered response bytes into `buf`, returning how many were copied
+    fn take_buffered(&mut self, buf: &mut [u8]) -> usize {
+        let count = (self.rx_len - self.rx_pos).min(buf.len());
+        buf[..count].copy_from_slice(&self.rx[self.rx_pos..self.rx_pos + count]);
+        self.rx_pos += count;
+        count
+    }
+
+    /// Sends one frame carrying the start of `data` and keeps the response payload
+    fn exchange(&mut self, data: &[u8]) -> Result<usize, StreamError> {
+        let (frame, count, rx_ready) = self.encode(data);
+        let response = self.spi.transfer(frame);
+        self.accept(response, rx_ready)?;
+        Ok(count)
+    }
+
+    /// Async variant of [`exchange`](Self::exchange)
+    async fn exchange_async(&mut self, data: &[u8]) -> Result<usize, StreamError> {
+        let (frame, count, rx_ready) = self.encode(data);
+        let response = self.spi.transfer_async(frame).await;
+        self.accept(response, rx_ready)?;
+        Ok(count)
+    }
+}
+
+impl<PIO: Instance, const SM: usize> embedded_io::ErrorType for ByteStream<'_, '_, PIO, SM> {
+    type Error = StreamError;
+}
+
+impl<PIO: Instance, const SM: usize> embedded_io::Read for ByteStream<'_, '_, PIO, SM> {
+    /// Returns buffered bytes, polling the slave with empty frames until it sends some
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, StreamError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let count = self.take_buffered(buf);
+            if count > 0 {
+                return Ok(count);
+            }
+            self.exchange(&[])?;
+        }
+    }
+}
+
+impl<PIO: Instance, const SM: usize> embedded_io::Write for ByteStream<'_, '_, PIO, SM> {
+    /// Sends up to one frame's payload, returning how many bytes were sent
+    fn write(&mut self, buf: &[u8]) -> Result<usize, StreamError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.exchange(buf)
+    }
+
+    fn flush(&mut self) -> Result<(), StreamError> {
+        Ok(())
+    }
+}
+
+impl<PIO: Instance, const SM: usize> embedded_io_async::Read for ByteStream<'_, '_, PIO, SM> {
+    /// Async variant of the blocking `read`, awaiting each polling frame
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, StreamError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let count = self.take_buffered(buf);
+            if count > 0 {
+                return Ok(count);
+            }
+            self.exchange_async(&[]).await?;
+        }
+    }
+}
+
+impl<PIO: Instance, const SM: usize> embedded_io_async::Write for ByteStream<'_, '_, PIO, SM> {
+    /// Async variant of the blocking `write`
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, StreamError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.exchange_async(buf).await
+    }
+}
@@ -0,0 +1,261 @@
+//! Gapless 24-bit frame streaming
+//!
+//! Audio DACs and many sigma-delta ADCs take a continuous stream of 24-bit frames. The
+//! frame program spends a `mov x, y` cycle (and, for the sequential program, a whole read
+//! phase) per frame; [`Stream24`] runs a loop-free program instead, where the FIFO
+//! thresholds delimit the frames, so consecutive frames leave no gap and the bit rate is
+//! exactly the SCK rate.
+//!
+//! ```ignore
+//! let config = Stream24Config { justify: Justify::Left, ..Stream24Config::default() };
+//! let mut dac = Stream24::new(&mut common, sm1, &clk, &mosi, &miso, config);
+//! dac.write(&samples); // right-justified 24-bit samples in u32s
+//! ```
+//!
+//! # Frames
+//!
+//! Samples are passed right-justified in `u32`s (bits [23:0], MSB first on the wire); the
+//! [`Justify`] setting decides how they sit in the frame on the wire.
+//!
+//! # Notes
+//! - Frames are full duplex: with `capture`, MISO is sampled in the same clocks
+//! - CLK parks LOW whenever the TX FIFO runs dry (e.g. between bursts) and the next frame
+//!   starts with the rising edge of its first bit; keep the FIFO fed for a steady clock
+
+use embassy_rp::gpio::Level;
+use embassy_rp::pio::{
+    Common, Config, Direction, Instance, LoadedProgram, Pin, ShiftDirection, StateMachine,
+};
+
+use crate::claim::{claim_pins, release_claim};
+use crate::master::{clock_divider, sm_frequency};
+use crate::program::get_stream_program;
+use crate::CYCLES_PER_BIT;
+
+/// Bits of one sample
+const SAMPLE_BITS: u32 = 24;
+
+/// Placement of a 24-bit sample in its frame
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub enum Justify {
+    /// 24-clock frames holding only the sample
+    #[default]
+    Packed,
+    /// 32-clock slots: the sample, then 8 zero bits
+    Left,
+    /// 32-clock slots: 8 zero bits, then the sample
+    Right,
+}
+
+impl Justify {
+    /// Returns the clocks per frame
+    pub fn frame_bits(self) -> u32 {
+        match self {
+            Self::Packed => SAMPLE_BITS,
+            Self::Left | Self::Right => 32,
+        }
+    }
+}
+
+/// Streaming configuration
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Stream24Config {
+    /// Clock divider setting, as in [`SpiMasterConfig::clk_div`](crate::SpiMasterConfig)
+    pub clk_div: u16,
+    /// Sample placement in the frame
+    pub justify: Justify,
+    /// Sample MISO and return the responses; without it the RX FIFO is never used
+    pub capture: bool,
+}
+
+impl Default for Stream24Config {
+    /// `clk_div` 8, packed frames, write only
+    fn default() -> Self {
+        Self {
+            clk_div: 8,
+            justify: Justify::default(),
+            capture: false,
+        }
+    }
+}
+
+/// Gapless stream of 24-bit frames
+pub struct Stream24<'d, PIO: Instance, const SM: usize> {
+    sm: StateMachine<'d, PIO, SM>,
+    program: LoadedProgram<'d, PIO>,
+    config: Stream24Config,
+}
+
+impl<'d, PIO: Instance, const SM: usize> Stream24<'d, PIO, SM> {
+    /// Loads the streaming program and starts the state machine
+    ///
+    /// # Arguments
+    /// * `common` - The PIO peripheral's common interface (for program loading and pin setup)
+    /// * `sm` - State machine (takes ownership)
+    /// * `clk_pin` - Clock pin (side-set/output)
+    /// * `mosi_pin` - MOSI pin (output)
+    /// * `miso_pin` - MISO pin (input; only sampled with `capture`)
+    /// * `config` - Rate, justification and capture
+    ///
+    /// # Panics
+    /// If `clk_div` is below 2 or another state machine of the same PIO block already
+    /// drives CLK or MOSI (see [`PinConflict`](crate::PinConflict))
+    pub fn new(
+        common: &mut Common<'d, PIO>,
+        sm: StateMachine<'d, PIO, SM>,
+        clk_pin: &Pin<'d, PIO>,
+        mosi_pin: &Pin<'d, PIO>,
+        miso_pin: &Pin<'d, PIO>,
+        config: Stream24Config,
+    ) -> Self {
+        assert!(config.clk_div >= 2, "clk_div must be at least 2");
+        claim_pins::<PIO, SM, 2>([Some(clk_pin.pin()), Some(mosi_pin.pin())])
+            .expect("pin already driven by another state machine");
+        let program = common.load_program(&get_stream_program(config.capture));
+
+        let mut cfg = Config::default();
+        cfg.use_program(&program, &[clk_pin]);
+        cfg.set_out_pins(&[mosi_pin]);
+        cfg.set_in_pins(&[miso_pin]);
+        cfg.clock_divider = clock_divider(config.clk_div);
+        // The thresholds are the frame length; samples are left-aligned in the OSR and
+        // arrive right-justified in the ISR
+        let threshold = config.justify.frame_bits() as u8;
+        cfg.shift_out.auto_fill = true;
+        cfg.shift_out.threshold = threshold;
+        cfg.shift_out.direction = ShiftDirection::Left;
+        cfg.shift_in.auto_fill = config.capture;
+        cfg.shift_in.threshold = threshold;
+        cfg.shift_in.direction = ShiftDirection::Left;
+
+        let mut sm = sm;
+        sm.set_config(&cfg);
+        sm.set_pins(Level::High, &[clk_pin]);
+        sm.set_pin_dirs(Direction::Out, &[clk_pin, mosi_pin]);
+        sm.set_pin_dirs(Direction::In, &[miso_pin]);
+        sm.set_enable(true);
+
+        Self {
+            sm,
+            program,
+            config,
+        }
+    }
+
+    /// Returns the active configuration
+    pub fn config(&self) -> Stream24Config {
+        self.config
+    }
+
+    /// Returns the frame rate in Hz at the current system clock
+    pub fn frame_rate(&self) -> u32 {
+        sm_frequency(self.config.clk_div) / (CYCLES_PER_BIT * self.config.justify.frame_bits())
+    }
+
+    /// Streams samples out, blocking while the TX FIFO is full
+    ///
+    /// # Arguments
+    /// * `samples` - Right-justified 24-bit samples (bits above 23 are ignored)
+    ///
+    /// # Notes
+    /// - Returns once the last sample is queued, up to 4 frames before it is on the wire
+    /// - With `capture`, the responses are discarded
+    pub fn write(&mut self, samples: &[u32]) {
+        for &sample in samples {
+            let word = self.pack(sample);
+            while !self.sm.tx().try_push(word) {
+                self.drop_responses();
+            }
+            self.drop_responses();
+        }
+    }
+
+    /// Streams samples out, awaiting FIFO space
+    ///
+    /// Same behavior as [`write`](Self::write), but yields to the executor while the TX
+    /// FIFO is full. Requires the PIO interrupt handler to be bound.
+    ///
+    /// # Panics
+    /// If the stream was created with `capture`: responses piling up while the task waits
+    /// would stall the state machine and with it the TX FIFO
+    pub async fn write_async(&mut self, samples: &[u32]) {
+        assert!(!self.config.capture, "write_async requires capture off");
+        for &sample in samples {
+            let word = self.pack(sample);
+            self.sm.tx().wait_push(word).await;
+        }
+    }
+
+    /// Streams samples out and replaces each with the 24 bits sampled from MISO in its frame
+    ///
+    /// # Arguments
+    /// * `samples` - Right-justified 24-bit samples, overwritten with the responses
+    ///   (right-justified, from the same 24 clocks as the sample)
+    ///
+    /// # Notes
+    /// - The TX FIFO is kept up to 4 frames ahead of the responses, so the stream only
+    ///   pauses if the CPU falls behind
+    ///
+    /// # Panics
+    /// If the stream was created without `capture`
+    pub fn transfer(&mut self, samples: &mut [u32]) {
+        assert!(self.config.capture, "transfer requires capture");
+        self.drop_responses();
+        let (mut sent, mut received) = (0, 0);
+        while received < samples.len() {
+            if sent < samples.len() {
+                let word = self.pack(samples[sent]);
+                if self.sm.tx().try_push(word) {
+                    sent += 1;
+                }
+            }
+            if let Some(word) = self.sm.rx().try_pull() {
+                samples[received] = self.unpack(word);
+                received += 1;
+            }
+        }
+    }
+
+    /// Stops the stream and frees the program's instruction memory
+    ///
+    /// # Arguments
+    /// * `common` - The PIO peripheral's common interface the program was loaded with
+    ///
+    /// # Returns
+    /// * `StateMachine` - The stopped state machine, ready to be reused by another driver
+    ///
+    /// # Notes
+    /// - Queued frames that have not been shifted yet are dropped
+    pub fn free(mut self, common: &mut Common<'d, PIO>) -> StateMachine<'d, PIO, SM> {
+        self.sm.set_enable(false);
+        self.sm.clear_fifos();
+        release_claim::<PIO, SM>();
+        // SAFETY: the program is private to this stream, whose state machine is stopped
+        unsafe { common.free_instr(self.program.used_memory) };
+        self.sm
+    }
+
+    /// Places a sample in its TX FIFO word (left-aligned, as OUT takes the OSR's MSB first)
+    fn pack(&self, sample: u32) -> u32 {
+        let sample = sample & 0x00FF_FFFF;
+        match self.config.justify {
+            Justify::Packed | Justify::Left => sample << 8,
+            Justify::Right => sample,
+        }
+    }
+
+    /// Extracts the sample from an RX FIFO word
+    fn unpack(&self, word: u32) -> u32 {
+        match self.config.justify {
+            Justify::Packed | Justify::Right => word & 0x00FF_FFFF,
+            Justify::Left => word >> 8,
+        }
+    }
+
+    /// Discards captured responses nobody asked for, so the RX FIFO cannot stall the stream
+    fn drop_responses(&mut self) {
+        if self.config.capture {
+            while self.sm.rx().try_pull().is_some() {}
+        }
+    }
+}
@@ -0,0 +1,77 @@
+//! Synchronized start of several masters
+//!
+//! Masters on different state machines of one PIO block normally start whenever their
+//! constructors enable them, so their clocks drift apart by however long the CPU took in
+//! between. [`start_synchronized`] enables a set of masters built with
+//! [`PioSpiMaster::new_disabled`] in a single write to the block's control register,
+//! restarting their clock dividers in the same cycle, so multi-lane or multi-device
+//! sampling is phase-aligned.
+//!
+//! ```ignore
+//! let mut lane0 = PioSpiMaster::new_disabled(&mut common, sm0, &clk0, &mosi0, &miso0, config);
+//! let mut lane1 = PioSpiMaster::new_disabled(&mut common, sm1, &clk1, &mosi1, &miso1, config);
+//! lane0.write(first0); // frames queued before the start begin on the same cycle
+//! lane1.write(first1);
+//! start_synchronized(&mut common, &mut [&mut lane0, &mut lane1]);
+//! ```
+//!
+//! # Phase offset
+//!
+//! A master whose config sets `start_delay_cycles` idles that many state machine cycles
+//! after the common start, so its CLK edges trail the others by a fixed amount. With
+//! [`half_period_cycles`](crate::SpiMasterConfig::half_period_cycles) the lagging master
+//! samples halfway between the leading master's edges, e.g. for ping-pong sampling of one
+//! ADC or interleaved DACs:
+//!
+//! ```ignore
+//! let lead = SpiMasterConfig { clk_low_cycles: 1, ..config }; // 4 cycles per bit
+//! let lag = SpiMasterConfig {
+//!     start_delay_cycles: lead.half_period_cycles().unwrap(),
+//!     ..lead
+//! };
+//! ```
+//!
+//! # Notes
+//! - Masters with the same `clk_div`, CLK phases and message size stay aligned for as
+//!   long as their TX FIFOs never run dry; a frame queued after the FIFO emptied starts
+//!   whenever the CPU pushes it, and the offset is lost
+//! - Only masters of the same PIO block can be started together
+
+use embassy_rp::pio::{Common, Instance, PioBatch};
+
+use crate::PioSpiMaster;
+
+/// Driver whose state machine can join a [`start_synchronized`] batch
+pub trait SyncStart<'d, PIO: Instance> {
+    /// Adds a clock divider restart and enable of this driver's state machine to `batch`
+    fn add_to_batch(&mut self, batch: &mut PioBatch<'d, PIO>);
+}
+
+impl<'d, PIO: Instance, const SM: usize> SyncStart<'d, PIO> for PioSpiMaster<'d, PIO, SM> {
+    fn add_to_batch(&mut self, batch: &mut PioBatch<'d, PIO>) {
+        batch.restart(&mut self.sm);
+        batch.set_enable(&mut self.sm, true);
+    }
+}
+
+/// Enables several state machines of one PIO block in the same cycle
+///
+/// # Arguments
+/// * `common` - The PIO peripheral's common interface the masters were built with
+/// * `masters` - Masters to start, typically built with
+///   [`new_disabled`](PioSpiMaster::new_disabled)
+///
+/// # Behavior
+/// Restarts every clock divider and sets every enable bit in one write to the block's
+/// `CTRL` register, so all state machines execute their first instruction together.
+/// Masters that are already running are restarted in phase with the others.
+pub fn start_synchronized<'d, PIO: Instance>(
+    common: &mut Common<'d, PIO>,
+    masters: &mut [&mut dyn SyncStart<'d, PIO>],
+) {
+    common.apply_sm_batch(|batch| {
+        for master in masters.iter_mut() {
+            master.add_to_batch(batch);
+        }
+    });
+}
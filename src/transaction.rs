@@ -0,0 +1,1033 @@
+//! Phase-based SPI transactions
+//!
+//! The frame program in the crate root always clocks `message_size` bits out and then the
+//! same number of bits in. Flash, FRAM, PSRAM and most byte-oriented peripherals instead
+//! need a *sequence* of differently sized phases (opcode, address, dummy clocks, data)
+//! within one chip-select frame. [`PioSpiBus`] runs a dedicated PIO program that executes
+//! such phase sequences.
+//!
+//! # Phase Protocol
+//!
+//! Each phase is announced by a header word pushed to the TX FIFO:
+//! - **Bits [31:16]**: Count - 1 (bytes for data phases, clock cycles for dummy phases)
+//! - **Bit 15**: Read flag (shift bytes in from MISO)
+//! - **Bit 14**: Dummy flag (clock without shifting data)
+//! - **Bit 13**: Transfer flag (shift bytes out and in at once; read and dummy flags clear)
+//!
+//! A header with both flags set is an auxiliary pin update instead: bits [31:16] hold a
+//! `set pins` instruction that the state machine executes in sequence with the phases.
+//!
+//! Write and transfer phases are followed by one TX FIFO word per byte (byte in bits
+//! [31:24]); read and transfer phases produce one RX FIFO word per byte (byte in bits
+//! [7:0]). Bytes are shifted MSB first and timing matches the frame program (SPI Mode 3).
+//!
+//! # DMA From Flash
+//!
+//! With a DMA channel attached ([`PioSpiBus::with_dma`]), [`PioSpiBus::write_static`] streams
+//! `&'static` data (display init sequences, lookup tables) straight from flash to the TX FIFO
+//! without copying it to RAM, and returns while the transfer runs in the background.
+//!
+//! # DMA Chaining
+//!
+//! With a second, control channel attached as well ([`PioSpiBus::with_dma_chaining`]),
+//! [`PioSpiBus::transaction`] sends each run of consecutive [`Phase::Write`] entries (e.g.
+//! header, payload and CRC) as a single write phase: the control channel walks a list of
+//! segment descriptors and restarts the data channel on the next buffer as each one
+//! finishes, so the CPU does nothing between segments and the wire shows no gap.
+//!
+//! ```ignore
+//! let mut bus = bus.with_dma(p.DMA_CH0).with_dma_chaining(p.DMA_CH1);
+//! bus.transaction(&mut [Phase::Write(&header), Phase::Write(payload), Phase::Write(&crc)]);
+//! ```
+//!
+//! # Auxiliary Pins
+//!
+//! [`PioSpiBus::new_with_aux`] maps up to 3 consecutive pins (e.g. CS, D/C, RESET) to the
+//! state machine's SET group. [`Phase::Aux`] entries change them between phases with exact
+//! timing relative to the clock, so display and radio drivers can sequence their whole pin
+//! set from one transaction.
+//!
+//! # Hooks
+//!
+//! [`PioSpiBus::set_hooks`] registers [`TransactionHooks`] called before a transaction's
+//! first phase is queued and after its last phase has finished on the wire, e.g. to
+//! enable a level shifter or power the slave only around its transactions. Every method
+//! that clocks the wire runs them once per call, however many phases or chunks it queues.
+//! As the calls come from the bus itself, the `before` hook has returned before an
+//! auxiliary CS is asserted, and the `after` hook only runs once the CS deassert phase is
+//! done.
+//!
+//! # Notes
+//! - Chip select is not driven by the bus unless it is an auxiliary pin; otherwise hold it
+//!   asserted around [`PioSpiBus::transaction`]
+//! - The program uses 29 instructions, so it cannot share a PIO block with the frame program
+
+use core::sync::atomic::{compiler_fence, Ordering};
+
+use embassy_rp::dma::{AnyChannel, Channel};
+use embassy_rp::gpio::Level;
+use embassy_rp::pac::dma::regs::CtrlTrig;
+use embassy_rp::pac::dma::vals::{DataSize, TreqSel};
+use embassy_rp::pio::{
+    Common, Config, Direction, Instance, LoadedProgram, Pin, ShiftDirection, StateMachine,
+};
+use embassy_rp::Peri;
+use pio::SetDestination;
+
+use crate::claim::{claim_pins, pio_index};
+use crate::irq::pio_regs;
+use crate::master::{clock_divider, reset_to_origin, set_output_enable};
+use crate::program::get_transaction_program;
+use crate::BitOrder;
+
+/// Header bit marking a read phase
+const HEADER_READ: u32 = 1 << 15;
+/// Header bit marking a dummy phase
+const HEADER_DUMMY: u32 = 1 << 14;
+/// Header bit marking a transfer phase
+const HEADER_TRANSFER: u32 = 1 << 13;
+
+/// Bytes of a transfer phase kept in flight, so the RX FIFO never fills up
+const TRANSFER_DEPTH: usize = 4;
+
+/// Most write phases [`PioSpiBus::transaction`] chains into one DMA write phase
+pub const MAX_CHAINED_SEGMENTS: usize = 8;
+
+/// One phase of a transaction
+pub enum Phase<'a> {
+    /// Shift bytes out on MOSI
+    Write(&'a [u8]),
+    /// Shift bytes in from MISO
+    Read(&'a mut [u8]),
+    /// Shift the buffer's bytes out on MOSI while shifting bytes in from MISO, replacing
+    /// each byte with the one received during it (e.g. a radio's status byte during its
+    /// command byte)
+    Transfer(&'a mut [u8]),
+    /// Clock cycles with MOSI held and MISO ignored
+    Dummy(u16),
+    /// Drive the auxiliary pins to this value (bit 0 = first pin), CLK held HIGH
+    Aux(u8),
+}
+
+impl Phase<'_> {
+    /// Returns the number of bytes or cycles the phase covers
+    fn len(&self) -> usize {
+        match self {
+            Phase::Write(data) => data.len(),
+            Phase::Read(buf) | Phase::Transfer(buf) => buf.len(),
+            Phase::Dummy(cycles) => *cycles as usize,
+            Phase::Aux(_) => 1,
+        }
+    }
+
+    /// Returns the header word announcing the phase, or `None` for empty phases
+    ///
+    /// # Panics
+    /// If the phase covers more than 65536 bytes or cycles
+    pub(crate) fn header(&self) -> Option<u32> {
+        if let Phase::Aux(value) = self {
+            let set_pins = pio::InstructionOperands::SET {
+                destination: SetDestination::PINS,
+                data: value & 0b111,
+            };
+            return Some(((set_pins.encode() as u32) << 16) | HEADER_READ | HEADER_DUMMY);
+        }
+
+        let count = count_field(self.len())?;
+        Some(match self {
+            Phase::Write(_) => count,
+            Phase::Read(_) => count | HEADER_READ,
+            Phase::Dummy(_) => count | HEADER_DUMMY,
+            Phase::Transfer(_) => count | HEADER_TRANSFER,
+            Phase::Aux(_) => unreachable!(),
+        })
+    }
+}
+
+/// Returns the header count field for a phase of `len` bytes or cycles, or `None` if empty
+///
+/// # Panics
+/// If `len` exceeds 65536
+fn count_field(len: usize) -> Option<u32> {
+    if len == 0 {
+        return None;
+    }
+    assert!(len <= 1 << 16, "phase too long");
+    Some(((len - 1) as u32) << 16)
+}
+
+/// Word type of the word-slice APIs ([`PioSpiBus::write_words`], [`PioSpiBus::read_words`])
+///
+/// Words travel most significant byte first, so a `u16` display command appears on the
+/// wire exactly as the device datasheet draws it.
+pub trait Word: Copy {
+    /// Byte array holding one word
+    type Bytes: AsRef<[u8]> + AsMut<[u8]> + Default;
+
+    /// Splits the word into bytes in wire order
+    fn to_be_bytes(self) -> Self::Bytes;
+
+    /// Assembles a word from bytes in wire order
+    fn from_be_bytes(bytes: Self::Bytes) -> Self;
+}
+
+impl Word for u8 {
+    type Bytes = [u8; 1];
+
+    fn to_be_bytes(self) -> Self::Bytes {
+        [self]
+    }
+
+    fn from_be_bytes(bytes: Self::Bytes) -> Self {
+        bytes[0]
+    }
+}
+
+impl Word for u16 {
+    type Bytes = [u8; 2];
+
+    fn to_be_bytes(self) -> Self::Bytes {
+        u16::to_be_bytes(self)
+    }
+
+    fn from_be_bytes(bytes: Self::Bytes) -> Self {
+        u16::from_be_bytes(bytes)
+    }
+}
+
+impl Word for u32 {
+    type Bytes = [u8; 4];
+
+    fn to_be_bytes(self) -> Self::Bytes {
+        u32::to_be_bytes(self)
+    }
+
+    fn from_be_bytes(bytes: Self::Bytes) -> Self {
+        u32::from_be_bytes(bytes)
+    }
+}
+
+/// Configuration for [`PioSpiBus`]
+pub struct SpiBusConfig {
+    pub clk_div: u16,
+}
+
+/// Clock dividers for the two directions of a [`PioSpiBus::transaction_with_speeds`] call
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct PhaseSpeeds {
+    /// Divider while shifting write phases out
+    pub write_clk_div: u16,
+    /// Divider while clocking read and dummy phases
+    pub read_clk_div: u16,
+}
+
+/// Callbacks run around every transaction of a [`PioSpiBus`]
+///
+/// Every bus method that clocks the wire runs the pair once around all of its phases,
+/// including [`read_dynamic`](PioSpiBus::read_dynamic)'s header and payload, the chunks
+/// of [`write_large`](PioSpiBus::write_large) and a daisy chain's whole shift.
+///
+/// # Notes
+/// - The `after` hook of a [`write_static`](PioSpiBus::write_static) phase runs once it
+///   has been clocked out, from [`flush`](PioSpiBus::flush) or the next bus operation
+/// - A cancelled [`transaction_async`](PioSpiBus::transaction_async) runs `after` at the
+///   start of the next bus operation, once the state machine has been reset
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TransactionHooks {
+    /// Called before the first phase is queued
+    pub before: Option<fn()>,
+    /// Called once the last phase has completed
+    pub after: Option<fn()>,
+}
+
+/// Byte-oriented SPI bus executing phase sequences
+pub struct PioSpiBus<'d, PIO: Instance, const SM: usize> {
+    sm: StateMachine<'d, PIO, SM>,
+    program: LoadedProgram<'d, PIO>,
+    pub(crate) dma: Option<Peri<'d, AnyChannel>>,
+    /// Channel reloading `dma` from a segment list, for chained write phases
+    chain_dma: Option<Peri<'d, AnyChannel>>,
+    clk_div: u16,
+    bit_order: BitOrder,
+    /// GPIO numbers of CLK, MOSI and the auxiliary pins, for tri-stating the bus
+    out_pins: [Option<u8>; 5],
+    /// First byte received by the last transaction (see [`last_status`](Self::last_status))
+    pub(crate) last_status: Option<u8>,
+    /// Set while an async transaction is in progress; still set on entry means it was cancelled
+    interrupted: bool,
+    /// Set while a [`write_static`](Self::write_static) phase is due its `after` hook
+    static_pending: bool,
+    hooks: TransactionHooks,
+}
+
+impl<'d, PIO: Instance, const SM: usize> PioSpiBus<'d, PIO, SM> {
+    /// Creates a new phase-based PIO SPI bus
+    ///
+    /// # Arguments
+    /// * `common` - The PIO peripheral's common interface (for program loading and pin setup)
+    /// * `sm` - State machine (takes ownership)
+    /// * `clk_pin` - Clock pin (side-set/output)
+    /// * `mosi_pin` - MOSI pin (output)
+    /// * `miso_pin` - MISO pin (input)
+    /// * `config` - Bus configuration
+    pub fn new(
+        common: &mut Common<'d, PIO>,
+        sm: StateMachine<'d, PIO, SM>,
+        clk_pin: &Pin<'d, PIO>,
+        mosi_pin: &Pin<'d, PIO>,
+        miso_pin: &Pin<'d, PIO>,
+        config: SpiBusConfig,
+    ) -> Self {
+        Self::new_with_aux(common, sm, clk_pin, mosi_pin, miso_pin, &[], config)
+    }
+
+    /// Creates a new phase-based PIO SPI bus with auxiliary output pins
+    ///
+    /// # Arguments
+    /// * `common` - The PIO peripheral's common interface (for program loading and pin setup)
+    /// * `sm` - State machine (takes ownership)
+    /// * `clk_pin` - Clock pin (side-set/output)
+    /// * `mosi_pin` - MOSI pin (output)
+    /// * `miso_pin` - MISO pin (input)
+    /// * `aux_pins` - Up to 3 consecutive GPIOs driven by [`Phase::Aux`] (set/output);
+    ///   `aux_pins[0]` is bit 0 of the value
+    /// * `config` - Bus configuration
+    ///
+    /// # Notes
+    /// - Auxiliary pins start HIGH (inactive for active-low CS and RESET lines)
+    ///
+    /// # Panics
+    /// If more than 3 auxiliary pins are given or they are not consecutive, or another state
+    /// machine of the same PIO block already drives CLK, MOSI or an auxiliary pin
+    /// (see [`PinConflict`](crate::PinConflict))
+    pub fn new_with_aux(
+        common: &mut Common<'d, PIO>,
+        sm: StateMachine<'d, PIO, SM>,
+        clk_pin: &Pin<'d, PIO>,
+        mosi_pin: &Pin<'d, PIO>,
+        miso_pin: &Pin<'d, PIO>,
+        aux_pins: &[&Pin<'d, PIO>],
+        config: SpiBusConfig,
+    ) -> Self {
+        assert!(aux_pins.len() <= 3, "at most 3 auxiliary pins");
+        let mut out_pins = [Some(clk_pin.pin()), Some(mosi_pin.pin()), None, None, None];
+        for (slot, pin) in out_pins[2..].iter_mut().zip(aux_pins) {
+            *slot = Some(pin.pin());
+        }
+        claim_pins::<PIO, SM, 5>(out_pins).expect("pin already driven by another state machine");
+
+        let program = get_transaction_program();
+        let program = common.load_program(&program);
+
+        let mut cfg = Config::default();
+        cfg.use_program(&program, &[clk_pin]);
+        cfg.set_out_pins(&[mosi_pin]);
+        cfg.set_in_pins(&[miso_pin]);
+        cfg.set_set_pins(aux_pins);
+        cfg.clock_divider = clock_divider(config.clk_div);
+
+        // Headers and data bytes are pulled/pushed explicitly, so no auto-fill.
+        // Shifting left makes OUT take the MSB first and IN leave bytes right-justified.
+        // The write loop runs until 8 bits have left the OSR.
+        cfg.shift_out.direction = ShiftDirection::Left;
+        cfg.shift_out.threshold = 8;
+        cfg.shift_in.direction = ShiftDirection::Left;
+
+        let mut sm = sm;
+        sm.set_config(&cfg);
+        sm.set_pins(Level::High, aux_pins);
+        sm.set_pin_dirs(Direction::Out, aux_pins);
+        sm.set_pin_dirs(Direction::Out, &[clk_pin, mosi_pin]);
+        sm.set_pin_dirs(Direction::In, &[miso_pin]);
+        sm.set_enable(true);
+
+        Self {
+            sm,
+            program,
+            dma: None,
+            chain_dma: None,
+            out_pins,
+            last_status: None,
+            clk_div: config.clk_div,
+            bit_order: BitOrder::MsbFirst,
+            interrupted: false,
+            static_pending: false,
+            hooks: TransactionHooks::default(),
+        }
+    }
+
+    /// Attaches a DMA channel used by [`write_static`](Self::write_static)
+    ///
+    /// # Arguments
+    /// * `dma` - DMA channel (takes ownership for the lifetime of the bus)
+    pub fn with_dma(mut self, dma: Peri<'d, impl Channel>) -> Self {
+        self.dma = Some(dma.into());
+        self
+    }
+
+    /// Attaches a second DMA channel that chains consecutive write phases (see the
+    /// [module docs](self))
+    ///
+    /// # Arguments
+    /// * `control` - DMA channel reprogramming the [`with_dma`](Self::with_dma) channel
+    ///   between segments (takes ownership for the lifetime of the bus)
+    ///
+    /// # Notes
+    /// - Chaining only happens once both channels are attached, and only in
+    ///   [`transaction`](Self::transaction) with [`BitOrder::MsbFirst`] data; other paths
+    ///   and LSB-first buses keep sending one phase at a time
+    pub fn with_dma_chaining(mut self, control: Peri<'d, impl Channel>) -> Self {
+        self.chain_dma = Some(control.into());
+        self
+    }
+
+    /// Selects the bit order of data bytes in write and read phases
+    ///
+    /// # Arguments
+    /// * `bit_order` - [`BitOrder::LsbFirst`] for devices that expect bit 0 of each byte
+    ///   first; the default is [`BitOrder::MsbFirst`]
+    ///
+    /// # Notes
+    /// - LSB-first bytes are reversed by the CPU as they are queued or collected, using one
+    ///   `RBIT` instruction per byte (see [`crate::bits`])
+    /// - Word-slice APIs also reverse the byte order, so bit 0 of each word goes first
+    /// - [`write_static`](Self::write_static) streams data untouched and is always MSB first;
+    ///   store such data pre-reversed (see [`crate::bits::reverse_bits_in_bytes`])
+    pub fn set_bit_order(&mut self, bit_order: BitOrder) {
+        self.bit_order = bit_order;
+    }
+
+    /// Returns the current data bit order
+    pub fn bit_order(&self) -> BitOrder {
+        self.bit_order
+    }
+
+    /// Registers callbacks run around every transaction (see [`TransactionHooks`])
+    ///
+    /// # Arguments
+    /// * `hooks` - Callbacks; [`TransactionHooks::default`] removes them
+    pub fn set_hooks(&mut self, hooks: TransactionHooks) {
+        self.hooks = hooks;
+    }
+
+    /// Executes a sequence of phases back to back
+    ///
+    /// # Arguments
+    /// * `phases` - Phases to execute in order; empty phases are skipped
+    ///
+    /// # Behavior
+    /// For each phase, pushes its header, then streams write bytes to the TX FIFO or
+    /// collects read bytes from the RX FIFO. Returns once the last phase has completed.
+    ///
+    /// With [DMA chaining](Self::with_dma_chaining), runs of two or more write phases (up
+    /// to [`MAX_CHAINED_SEGMENTS`] and 65536 bytes together) are instead announced as one
+    /// write phase and fed by DMA, segment after segment.
+    ///
+    /// # Panics
+    /// If a phase covers more than 65536 bytes or cycles
+    pub fn transaction(&mut self, phases: &mut [Phase<'_>]) {
+        self.begin();
+        let mut next = 0;
+        while next < phases.len() {
+            match self.chain_writes(&phases[next..]) {
+                0 => {
+                    self.run_phase(&mut phases[next]);
+                    next += 1;
+                }
+                chained => next += chained,
+            }
+        }
+        self.end();
+        self.capture_status(phases);
+    }
+
+    /// Executes a sequence of phases, clocking write and read phases at different speeds
+    ///
+    /// # Arguments
+    /// * `phases` - Phases to execute in order; empty phases are skipped
+    /// * `speeds` - Clock dividers for write phases and for read/dummy phases
+    ///
+    /// # Behavior
+    /// Whenever the next phase needs a different divider, waits for the state machine to
+    /// finish the previous phase and park at its header pull (CLK idling HIGH), then
+    /// reprograms the divider before announcing the phase. The bus divider from
+    /// [`SpiBusConfig`] is restored afterwards.
+    ///
+    /// # Notes
+    /// - Dummy phases count as read phases, since dummy cycles usually belong to the
+    ///   device's read latency; transfer phases do too, as they sample MISO
+    /// - Each speed change drains the FIFOs, adding a short gap between the two phases
+    ///
+    /// # Panics
+    /// If a phase covers more than 65536 bytes or cycles
+    pub fn transaction_with_speeds(&mut self, phases: &mut [Phase<'_>], speeds: PhaseSpeeds) {
+        self.begin();
+        let mut current = self.clk_div;
+        for phase in phases.iter_mut() {
+            let clk_div = match phase {
+                Phase::Write(_) => speeds.write_clk_div,
+                Phase::Read(_) | Phase::Transfer(_) | Phase::Dummy(_) => speeds.read_clk_div,
+                Phase::Aux(_) => current,
+            };
+            if clk_div != current && phase.len() != 0 {
+                self.wait_idle();
+                self.apply_clk_div(clk_div);
+                current = clk_div;
+            }
+            self.run_phase(phase);
+        }
+        self.wait_idle();
+        if current != self.clk_div {
+            self.apply_clk_div(self.clk_div);
+        }
+        self.end();
+        self.capture_status(phases);
+    }
+
+    /// Returns the first byte received by the most recent transaction
+    ///
+    /// Radios such as the nRF24 and SX127x shift a status byte out while their command
+    /// byte arrives. Sending the command as a [`Phase::Transfer`] samples it; the bus keeps
+    /// the first byte of the first non-empty transfer or read phase of each transaction
+    /// (including [`read`](Self::read) and [`read_words`](Self::read_words)), so drivers
+    /// can check the status after a slice transfer without splitting the buffer:
+    ///
+    /// ```ignore
+    /// bus.transaction(&mut [Phase::Transfer(&mut [R_RX_PAYLOAD]), Phase::Read(&mut payload)]);
+    /// let status = bus.last_status().unwrap();
+    /// ```
+    ///
+    /// # Returns
+    /// * `Some(u8)` - First byte on MISO of the last transaction
+    /// * `None` - The last transaction had no transfer or read phase
+    pub fn last_status(&self) -> Option<u8> {
+        self.last_status
+    }
+
+    /// Records the first byte of the first non-empty transfer or read phase as the status
+    /// byte
+    fn capture_status(&mut self, phases: &[Phase<'_>]) {
+        self.last_status = phases.iter().find_map(|phase| match phase {
+            Phase::Read(buf) | Phase::Transfer(buf) => buf.first().copied(),
+            _ => None,
+        });
+    }
+
+    /// Announces one phase and streams its data, skipping empty phases
+    pub(crate) fn run_phase(&mut self, phase: &mut Phase<'_>) {
+        let Some(header) = phase.header() else {
+            return;
+        };
+        self.push(header);
+
+        match phase {
+            Phase::Write(data) => {
+                for &byte in data.iter() {
+                    self.push(self.tx_word(byte));
+                }
+            }
+            Phase::Read(buf) => {
+                for byte in buf.iter_mut() {
+                    *byte = self.pull_byte();
+                }
+            }
+            Phase::Transfer(buf) => {
+                let mut sent = 0;
+                for received in 0..buf.len() {
+                    while sent < buf.len() && sent - received < TRANSFER_DEPTH {
+                        self.push(self.tx_word(buf[sent]));
+                        sent += 1;
+                    }
+                    buf[received] = self.pull_byte();
+                }
+            }
+            Phase::Dummy(_) | Phase::Aux(_) => {}
+        }
+    }
+
+    /// Sends the run of write phases at the start of `phases` as one DMA-chained write phase
+    ///
+    /// # Returns
+    /// * `usize` - Phases sent; 0 if chaining is unavailable or the run is shorter than two
+    ///   phases, leaving the first phase to [`run_phase`](Self::run_phase)
+    ///
+    /// # Behavior
+    /// Builds one `[count, read address]` descriptor per non-empty segment, ended by a
+    /// zero descriptor. The control channel writes each descriptor to the data channel's
+    /// `AL3_TRANS_COUNT` / `AL3_READ_ADDR_TRIG` pair, which starts it on that segment; the
+    /// data channel chains back to the control channel when the segment is done. The zero
+    /// read address of the final descriptor is a null trigger, ending the chain. Waits
+    /// until every segment has been fed to the TX FIFO, as the descriptors and segments are
+    /// only borrowed.
+    fn chain_writes(&mut self, phases: &[Phase<'_>]) -> usize {
+        if self.bit_order != BitOrder::MsbFirst {
+            return 0;
+        }
+        let (Some(data), Some(control)) = (&self.dma, &self.chain_dma) else {
+            return 0;
+        };
+        let (data, control, control_number) = (data.regs(), control.regs(), control.number());
+
+        let mut descriptors = [[0u32; 2]; MAX_CHAINED_SEGMENTS + 1];
+        let (mut run, mut segments, mut total) = (0, 0, 0);
+        for phase in phases.iter().take(MAX_CHAINED_SEGMENTS) {
+            let Phase::Write(bytes) = phase else {
+                break;
+            };
+            if total + bytes.len() > 1 << 16 {
+                break;
+            }
+            if !bytes.is_empty() {
+                descriptors[segments] = [bytes.len() as u32, bytes.as_ptr() as u32];
+                segments += 1;
+            }
+            total += bytes.len();
+            run += 1;
+        }
+        if run < 2 {
+            return 0;
+        }
+        let Some(header) = count_field(total) else {
+            return run;
+        };
+        self.push(header);
+
+        let mut data_ctrl = CtrlTrig(0);
+        data_ctrl.set_treq_sel(TreqSel::from((pio_index::<PIO>() * 8 + SM) as u8));
+        data_ctrl.set_data_size(DataSize::SIZE_BYTE);
+        data_ctrl.set_incr_read(true);
+        data_ctrl.set_chain_to(control_number);
+        data_ctrl.set_irq_quiet(true);
+        data_ctrl.set_en(true);
+        data.write_addr()
+            .write_value(pio_regs::<PIO>().txf(SM).as_ptr() as u32);
+        data.al1_ctrl().write_value(data_ctrl.0);
+
+        // Two words per descriptor, wrapping within the 8-byte AL3 count/address pair
+        let list_end = descriptors.as_ptr() as u32 + 8 * (segments as u32 + 1);
+        control.read_addr().write_value(descriptors.as_ptr() as u32);
+        control
+            .write_addr()
+            .write_value(data.al3_trans_count().as_ptr() as u32);
+        control.trans_count().write(|w| w.set_count(2));
+        compiler_fence(Ordering::SeqCst);
+        control.ctrl_trig().write(|w| {
+            w.set_treq_sel(TreqSel::PERMANENT);
+            w.set_data_size(DataSize::SIZE_WORD);
+            w.set_incr_read(true);
+            w.set_incr_write(true);
+            w.set_ring_size(3);
+            w.set_ring_sel(true);
+            w.set_chain_to(control_number);
+            w.set_irq_quiet(true);
+            w.set_en(true);
+        });
+
+        // A segment hand-over briefly leaves both channels idle, so also wait for the
+        // control channel to have read the final descriptor
+        while control.read_addr().read() != list_end
+            || control.ctrl_trig().read().busy()
+            || data.ctrl_trig().read().busy()
+        {}
+        compiler_fence(Ordering::SeqCst);
+        run
+    }
+
+    /// Reprograms the state machine clock divider while it is parked at the header pull
+    fn apply_clk_div(&mut self, clk_div: u16) {
+        self.sm.set_clock_divider(clock_divider(clk_div));
+        self.sm.clkdiv_restart();
+    }
+
+    /// Executes a sequence of phases back to back, awaiting FIFO space and data
+    ///
+    /// Same behavior as [`transaction`](Self::transaction), but yields to the executor while
+    /// the TX FIFO is full or the RX FIFO is empty. Requires the PIO interrupt handler to be
+    /// bound. The final wait for the last write bits to leave the shift register is short
+    /// (at most the FIFO depth) and busy-waits.
+    ///
+    /// # Cancel Safety
+    /// Dropping the future mid-transaction may leave a phase half-executed. The next call on
+    /// this bus detects this and resets the state machine to its header pull with empty
+    /// FIFOs, so stale bytes are never taken for headers. The device saw a truncated
+    /// transaction; deassert chip select before starting a new one.
+    pub async fn transaction_async(&mut self, phases: &mut [Phase<'_>]) {
+        self.begin_async();
+        self.run_phases_async(phases).await;
+        self.end();
+        self.capture_status(phases);
+    }
+
+    /// Reads a length-prefixed response whose size is only known once it starts arriving
+    ///
+    /// # Arguments
+    /// * `lead` - Phases run first, e.g. a [`Phase::Aux`] asserting an auxiliary chip
+    ///   select and the command; `&mut []` for none
+    /// * `buf` - Destination; its length is the maximum response size (length byte included)
+    /// * `trail` - Phases run after the payload, e.g. the [`Phase::Aux`] deasserting chip
+    ///   select; `&mut []` for none
+    ///
+    /// # Returns
+    /// * `usize` - Number of bytes stored in `buf`, including the length byte in `buf[0]`
+    ///
+    /// # Behavior
+    /// 1. Runs `lead`, then reads the 1-byte length header and awaits it
+    /// 2. Clamps the announced payload length to the space left in `buf`
+    /// 3. Reads the payload as a second read phase, then runs `trail`
+    ///
+    /// All phases form one transaction: the clock idles HIGH between the header and the
+    /// payload while the state machine waits for the next phase, so an auxiliary chip
+    /// select asserted in `lead` (or one held by the caller) stays asserted throughout.
+    ///
+    /// # Cancel Safety
+    /// As [`transaction_async`](Self::transaction_async)
+    pub async fn read_dynamic(
+        &mut self,
+        lead: &mut [Phase<'_>],
+        buf: &mut [u8],
+        trail: &mut [Phase<'_>],
+    ) -> usize {
+        let Some((header, payload)) = buf.split_first_mut() else {
+            return 0;
+        };
+        let header = core::slice::from_mut(header);
+        let len = self
+            .read_prefixed(lead, header, |header| header[0] as usize, payload, trail)
+            .await;
+        // Unless `lead` read a status, the response's first byte is its length header
+        self.last_status.get_or_insert(header[0]);
+        1 + len
+    }
+
+    /// Reads a header and a payload whose length the header announces, in one transaction
+    ///
+    /// # Arguments
+    /// * `lead` / `trail` - As for [`read_dynamic`](Self::read_dynamic)
+    /// * `header` - Destination of the fixed-size header
+    /// * `length` - Returns the payload length announced by the received header
+    /// * `payload` - Destination of the payload; longer announcements are clamped to it
+    ///
+    /// # Returns
+    /// * `usize` - Number of payload bytes stored in `payload`
+    pub(crate) async fn read_prefixed(
+        &mut self,
+        lead: &mut [Phase<'_>],
+        header: &mut [u8],
+        length: impl FnOnce(&[u8]) -> usize,
+        payload: &mut [u8],
+        trail: &mut [Phase<'_>],
+    ) -> usize {
+        self.begin_async();
+        self.run_phases_async(lead).await;
+        self.run_phases_async(&mut [Phase::Read(&mut *header)])
+            .await;
+
+        let len = length(header).min(payload.len());
+        self.run_phases_async(&mut [Phase::Read(&mut payload[..len])])
+            .await;
+        self.run_phases_async(trail).await;
+        self.end();
+        self.capture_status(lead);
+        len
+    }
+
+    /// Announces each phase and streams its data, awaiting FIFO space and data
+    pub(crate) async fn run_phases_async(&mut self, phases: &mut [Phase<'_>]) {
+        for phase in phases.iter_mut() {
+            let Some(header) = phase.header() else {
+                continue;
+            };
+            self.sm.tx().wait_push(header).await;
+
+            match phase {
+                Phase::Write(data) => {
+                    for &byte in data.iter() {
+                        let word = self.tx_word(byte);
+                        self.sm.tx().wait_push(word).await;
+                    }
+                }
+                Phase::Read(buf) => {
+                    for byte in buf.iter_mut() {
+                        let word = self.sm.rx().wait_pull().await;
+                        *byte = self.rx_byte(word);
+                    }
+                }
+                Phase::Transfer(buf) => {
+                    let mut sent = 0;
+                    for received in 0..buf.len() {
+                        while sent < buf.len() && sent - received < TRANSFER_DEPTH {
+                            let word = self.tx_word(buf[sent]);
+                            self.sm.tx().wait_push(word).await;
+                            sent += 1;
+                        }
+                        let word = self.sm.rx().wait_pull().await;
+                        buf[received] = self.rx_byte(word);
+                    }
+                }
+                Phase::Dummy(_) | Phase::Aux(_) => {}
+            }
+        }
+    }
+
+    /// Writes bytes in a single write phase
+    pub fn write(&mut self, data: &[u8]) {
+        self.transaction(&mut [Phase::Write(data)]);
+    }
+
+    /// Reads bytes in a single read phase
+    pub fn read(&mut self, buf: &mut [u8]) {
+        self.transaction(&mut [Phase::Read(buf)]);
+    }
+
+    /// Writes `u8`, `u16` or `u32` words in a single write phase
+    ///
+    /// # Arguments
+    /// * `data` - Words to send, each most significant byte first (see [`Word`])
+    ///
+    /// # Panics
+    /// If `data` covers more than 65536 bytes
+    pub fn write_words<W: Word>(&mut self, data: &[W]) {
+        let Some(count) = count_field(core::mem::size_of_val(data)) else {
+            return;
+        };
+        self.begin();
+        self.push(count);
+        for &word in data {
+            let mut bytes = word.to_be_bytes();
+            if self.bit_order == BitOrder::LsbFirst {
+                bytes.as_mut().reverse();
+            }
+            for &byte in bytes.as_ref() {
+                self.push(self.tx_word(byte));
+            }
+        }
+        self.end();
+    }
+
+    /// Reads `u8`, `u16` or `u32` words in a single read phase
+    ///
+    /// # Arguments
+    /// * `buf` - Destination; each word is assembled most significant byte first
+    ///
+    /// # Panics
+    /// If `buf` covers more than 65536 bytes
+    pub fn read_words<W: Word>(&mut self, buf: &mut [W]) {
+        let Some(count) = count_field(core::mem::size_of_val(buf)) else {
+            return;
+        };
+        self.begin();
+        self.push(count | HEADER_READ);
+        for word in buf.iter_mut() {
+            let mut bytes = W::Bytes::default();
+            for byte in bytes.as_mut() {
+                *byte = self.pull_byte();
+                self.last_status.get_or_insert(*byte);
+            }
+            if self.bit_order == BitOrder::LsbFirst {
+                bytes.as_mut().reverse();
+            }
+            *word = W::from_be_bytes(bytes);
+        }
+        self.end();
+    }
+
+    /// Writes a buffer of any size as consecutive write phases, reporting progress
+    ///
+    /// # Arguments
+    /// * `data` - Bytes to send (e.g. a display frame or a flash page run)
+    /// * `chunk_size` - Bytes per write phase; clamped to the 65536-byte phase limit
+    /// * `progress` - Called after each chunk with `(bytes_sent, total_bytes)`
+    ///
+    /// # Behavior
+    /// Each chunk is announced as its own write phase and queued right behind the previous
+    /// one, so CLK only idles HIGH for the few cycles it takes to pull the next header.
+    /// Chip select (held by the caller or set with a preceding [`Phase::Aux`]) stays asserted
+    /// across all chunks. Returns once the last byte has been clocked out.
+    ///
+    /// # Notes
+    /// - `progress` runs when a chunk has been handed to the state machine; at most the
+    ///   FIFO depth of its bytes may still be shifting out
+    /// - Smaller chunks report (and let a watchdog be fed) more often at the cost of one
+    ///   header per chunk
+    ///
+    /// # Panics
+    /// If `chunk_size` is 0
+    pub fn write_large<F>(&mut self, data: &[u8], chunk_size: usize, mut progress: F)
+    where
+        F: FnMut(usize, usize),
+    {
+        assert!(chunk_size > 0, "chunk_size must be non-zero");
+        self.begin();
+
+        let mut sent = 0;
+        for chunk in data.chunks(chunk_size.min(1 << 16)) {
+            self.run_phase(&mut Phase::Write(chunk));
+            sent += chunk.len();
+            progress(sent, data.len());
+        }
+        self.end();
+    }
+
+    /// Writes `&'static` data in a single write phase fed by DMA, without waiting for it
+    ///
+    /// # Arguments
+    /// * `data` - Bytes to send; may live in flash (XIP) and need no particular alignment
+    ///
+    /// # Behavior
+    /// 1. Waits for any previous background write to finish feeding the FIFO
+    /// 2. Pushes the phase header
+    /// 3. Starts a byte-wide DMA from `data` to the TX FIFO, paced by the state machine's
+    ///    TX DREQ, and returns immediately
+    ///
+    /// The DMA reads `data` in place, so nothing is copied to RAM. Byte-sized bus writes are
+    /// replicated across all four byte lanes, which puts each byte in bits [31:24] as the
+    /// program expects; there is no word alignment requirement on `data`.
+    ///
+    /// Every other bus operation first waits for the background write to drain, so calls
+    /// stay ordered. Call [`flush`](Self::flush) before deasserting chip select. The
+    /// `after` [hook](TransactionHooks) runs once the phase has been clocked out, from
+    /// `flush` or the next bus operation.
+    ///
+    /// # Panics
+    /// - If no DMA channel is attached (see [`with_dma`](Self::with_dma))
+    /// - If `data` is longer than 65536 bytes
+    pub fn write_static(&mut self, data: &'static [u8]) {
+        let Some(header) = Phase::Write(data).header() else {
+            return;
+        };
+        self.begin();
+        self.push(header);
+
+        let dma = self
+            .dma
+            .as_mut()
+            .expect("write_static requires a DMA channel (see with_dma)");
+        let transfer = self.sm.tx().dma_push(dma.reborrow(), data, false);
+        // Dropping the transfer would abort it; the bus tracks completion through the
+        // channel's busy flag instead.
+        core::mem::forget(transfer);
+        self.static_pending = true;
+    }
+
+    /// Waits until every queued phase, including background writes, has been clocked out
+    pub fn flush(&mut self) {
+        self.wait_dma();
+        self.wait_idle();
+    }
+
+    /// Starts a transaction: lets a background write finish, recovers from a cancelled
+    /// async transaction, clears the [status byte](Self::last_status) until a transfer or
+    /// read phase sets it and runs the `before` hook
+    pub(crate) fn begin(&mut self) {
+        self.wait_dma();
+        self.recover_if_interrupted();
+        self.last_status = None;
+        run_hook(self.hooks.before);
+    }
+
+    /// Starts a transaction whose phases are awaited, marking it in progress until
+    /// [`end`](Self::end) so a cancellation is detected
+    pub(crate) fn begin_async(&mut self) {
+        self.begin();
+        self.interrupted = true;
+    }
+
+    /// Ends a transaction: waits for its last phase to complete and runs the `after` hook
+    pub(crate) fn end(&mut self) {
+        self.wait_idle();
+        self.interrupted = false;
+        run_hook(self.hooks.after);
+    }
+
+    /// Resets the state machine if an async transaction was cancelled part-way, then runs
+    /// the `after` hook its `before` hook is still waiting for
+    pub(crate) fn recover_if_interrupted(&mut self) {
+        if !self.interrupted {
+            return;
+        }
+        reset_to_origin(&mut self.sm, self.program.origin);
+        self.sm.set_enable(true);
+        self.interrupted = false;
+        run_hook(self.hooks.after);
+    }
+
+    /// Waits for a background DMA write to finish feeding the TX FIFO, ending its
+    /// transaction
+    pub(crate) fn wait_dma(&mut self) {
+        if let Some(dma) = &self.dma {
+            while dma.regs().ctrl_trig().read().busy() {}
+        }
+        if self.static_pending {
+            self.static_pending = false;
+            self.end();
+        }
+    }
+
+    /// Returns the TX FIFO word that shifts `byte` out in the configured bit order
+    fn tx_word(&self, byte: u8) -> u32 {
+        match self.bit_order {
+            BitOrder::MsbFirst => (byte as u32) << 24,
+            // One 32-bit reversal both mirrors the byte and moves it to bits [31:24]
+            BitOrder::LsbFirst => (byte as u32).reverse_bits(),
+        }
+    }
+
+    /// Returns the byte held in bits [7:0] of an RX FIFO word, in the configured bit order
+    fn rx_byte(&self, word: u32) -> u8 {
+        match self.bit_order {
+            BitOrder::MsbFirst => word as u8,
+            BitOrder::LsbFirst => (word.reverse_bits() >> 24) as u8,
+        }
+    }
+
+    /// Pushes a word to the TX FIFO, waiting for space
+    pub(crate) fn push(&mut self, word: u32) {
+        while !self.sm.tx().try_push(word) {}
+    }
+
+    /// Pulls a read-phase byte from the RX FIFO, waiting for data
+    fn pull_byte(&mut self) -> u8 {
+        let word = self.pull();
+        self.rx_byte(word)
+    }
+
+    /// Pulls a word from the RX FIFO, waiting for data
+    fn pull(&mut self) -> u32 {
+        loop {
+            if let Some(word) = self.sm.rx().try_pull() {
+                return word;
+            }
+        }
+    }
+
+    /// Tri-states CLK, MOSI and the auxiliary pins so another controller can drive them
+    ///
+    /// # Behavior
+    /// Waits until every queued phase has run, then overrides the pads' output enables
+    /// off. The PIO configuration is untouched; [`reclaim_pins`](Self::reclaim_pins)
+    /// gives the pins back.
+    ///
+    /// # Notes
+    /// - See [`PioSpiMaster::release_pins`](crate::PioSpiMaster::release_pins)
+    pub fn release_pins(&mut self) {
+        self.wait_idle();
+        set_output_enable(self.out_pins, false);
+    }
+
+    /// Resumes driving the pins released by [`release_pins`](Self::release_pins)
+    pub fn reclaim_pins(&mut self) {
+        set_output_enable(self.out_pins, true);
+    }
+
+    /// Waits until the program has consumed every queued phase and is back at its header pull
+    pub(crate) fn wait_idle(&mut self) {
+        while !self.sm.tx().empty() || self.sm.get_addr() != self.program.origin {}
+    }
+}
+
+/// Calls a registered hook
+fn run_hook(hook: Option<fn()>) {
+    if let Some(hook) = hook {
+        hook();
+    }
+}
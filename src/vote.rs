@@ -0,0 +1,54 @@
+//! Majority vote over repeated responses
+//!
+//! [`crate::oversample`] reads a frame several times over a noisy link and lets
+//! [`vote`] settle on one response. The vote is kept apart from the bus so host tests can
+//! check it on any set of responses.
+
+#[cfg(all(test, feature = "std"))]
+mod tests;
+
+/// Outcome of a vote over repeated responses, see [`vote`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "hal", derive(defmt::Format))]
+pub struct Vote {
+    /// Median response: the majority value when there is one
+    pub value: u64,
+    /// More than half of the responses equal `value`
+    pub majority: bool,
+    /// Not every response equals `value`
+    pub disagreement: bool,
+    /// Number of responses equal to `value`
+    pub agreeing: u32,
+}
+
+/// Votes on repeated responses to the same frame
+///
+/// # Arguments
+/// * `responses` - Responses to vote on (at least 1); sorted in place
+///
+/// # Returns
+/// * `Vote` - The median response, whether it holds a majority and whether any
+///   response differed
+///
+/// # Behavior
+/// Sorts the responses and takes the middle one (the lower of the two middle ones for an
+/// even count): when more than half of the responses agree it is their value, otherwise
+/// a single glitched response still cannot pull it to an extreme.
+///
+/// # Panics
+/// If `responses` is empty
+pub fn vote(responses: &mut [u64]) -> Vote {
+    assert!(!responses.is_empty(), "vote needs at least one response");
+    responses.sort_unstable();
+    let value = responses[(responses.len() - 1) / 2];
+    let agreeing = responses
+        .iter()
+        .filter(|&&response| response == value)
+        .count();
+    Vote {
+        value,
+        majority: agreeing > responses.len() / 2,
+        disagreement: agreeing < responses.len(),
+        agreeing: agreeing as u32,
+    }
+}
@@ -0,0 +1,75 @@
+//! Votes on hand-picked response sets: agreement, a single glitch, no majority, and odd
+//! and even counts.
+
+use super::*;
+
+#[test]
+fn all_equal_responses_agree() {
+    for n in 1..=8 {
+        let mut responses = [0xA5A5u64; 8];
+        assert_eq!(
+            vote(&mut responses[..n]),
+            Vote {
+                value: 0xA5A5,
+                majority: true,
+                disagreement: false,
+                agreeing: n as u32,
+            }
+        );
+    }
+}
+
+#[test]
+fn single_glitch_is_outvoted() {
+    for glitch in [0, 0x1234, u64::MAX] {
+        for position in 0..3 {
+            let mut responses = [0x1200u64; 3];
+            responses[position] = glitch;
+            let result = vote(&mut responses);
+            assert_eq!(result.value, 0x1200, "glitch {glitch:#x} at {position}");
+            assert!(result.majority);
+            assert!(result.disagreement);
+            assert_eq!(result.agreeing, 2);
+        }
+    }
+}
+
+#[test]
+fn odd_count_takes_the_median() {
+    let mut responses = [9, 1, 5, 7, 3];
+    let result = vote(&mut responses);
+    assert_eq!(result.value, 5);
+    assert_eq!(result.agreeing, 1);
+    assert!(!result.majority);
+    assert!(result.disagreement);
+}
+
+#[test]
+fn even_count_takes_the_lower_middle() {
+    let mut responses = [40, 10, 30, 20];
+    let result = vote(&mut responses);
+    assert_eq!(result.value, 20);
+    assert!(!result.majority);
+
+    // Three of four agree: the lower middle is theirs
+    let mut responses = [7, 7, 100, 7];
+    let result = vote(&mut responses);
+    assert_eq!((result.value, result.agreeing), (7, 3));
+    assert!(result.majority);
+}
+
+#[test]
+fn even_split_is_no_majority() {
+    let mut responses = [2, 8, 8, 2];
+    let result = vote(&mut responses);
+    assert_eq!(result.value, 2);
+    assert_eq!(result.agreeing, 2);
+    assert!(!result.majority);
+    assert!(result.disagreement);
+}
+
+#[test]
+#[should_panic]
+fn empty_responses_panic() {
+    vote(&mut []);
+}